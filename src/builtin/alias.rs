@@ -14,8 +14,7 @@ pub fn execute<'a>(alias_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 
 	let ctx_redirs = slash.ctx_mut().take_redirs();
 	if !ctx_redirs.is_empty() {
-		let mut redirs = slash.ctx_mut().consume_redirs();
-		redirs.activate()?;
+		slash.activate_redirs()?;
 	}
 
 	while let Some(arg) = args.pop_front() {
@@ -23,8 +22,13 @@ pub fn execute<'a>(alias_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 			Rule::arg_assign => {
 				let mut assign_inner = arg.into_inner();
 				let alias = assign_inner.next().unpack()?.as_str();
-				let body = assign_inner.next().map(|pair| pair.as_str()).unwrap_or_default();
-				helper::write_alias(slash, alias, &body.trim_quotes())?;
+				let body = match assign_inner.next() {
+					// ANSI-C quoting is the only way to get both kinds of quote into a single word
+					Some(word) if word.scry(Rule::ansi_quoted).is_some() => helper::try_expansion(slash, word)?,
+					Some(word) => word.as_str().trim_quotes(),
+					None => String::new()
+				};
+				helper::write_alias(slash, alias, &body)?;
 			}
 			Rule::word => {
 				let alias = slash.logic().get_alias(arg.as_str());
@@ -52,7 +56,7 @@ pub fn unalias<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 
 #[cfg(test)]
 mod tests {
-	use crate::execute;
+	use crate::{execute, test_util::{capture, output_of}};
 
 use super::*;
 
@@ -63,4 +67,30 @@ use super::*;
 		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
 		assert!(slash.logic().get_alias("foo").is_some_and(|al| &al == "bar"))
 	}
+
+	#[test]
+	fn test_expand_aliases_shopt() {
+		let mut slash = Slash::new();
+		// Outside of an interactive shell, aliases are left alone by default
+		execute::dispatch::exec_input("alias greet_alias_test='echo hi'".to_string(), &mut slash).unwrap();
+		let (unexpanded, _) = capture("greet_alias_test 2> /dev/null", &mut slash);
+		let unexpanded_code = slash.get_status();
+		execute::dispatch::exec_input("shopt -s expand_aliases".to_string(), &mut slash).unwrap();
+		let expanded = output_of("greet_alias_test", &mut slash);
+
+		unexpanded.unwrap();
+		assert_eq!(unexpanded_code, 127);
+		assert_eq!(expanded, "hi\n");
+	}
+
+	#[test]
+	fn test_alias_command_position() {
+		let mut slash = Slash::new();
+		// Like in bash, an alias is only used on the lines after the one that defines it
+		execute::dispatch::exec_input("shopt -s expand_aliases; alias pos_alias_test='echo expanded'".to_string(), &mut slash).unwrap();
+		let output = output_of("pos_alias_test first; echo pos_alias_test; echo x | pos_alias_test piped; true && pos_alias_test chained", &mut slash);
+
+		// Only the words where a command is expected are expanded
+		assert_eq!(output, "expanded first\npos_alias_test\nexpanded piped\nexpanded chained\n");
+	}
 }
@@ -10,7 +10,7 @@ pub fn execute<'a>(assign: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()>
 	while let Some(arg) = argv.pop_front() {
 		match arg.as_rule() {
 			Rule::arg_assign => {
-				let var_name = arg.scry(Rule::var_ident).unpack()?;
+				let var_name = helper::assign_target(&arg, blame.clone())?;
 				if let Some(val) = arg.scry(&[Rule::word,Rule::array][..]) {
 					let rule = val.as_rule();
 					let val = helper::try_expansion(slash,val)?;
@@ -0,0 +1,69 @@
+use crate::{helper, prelude::*, utils};
+
+/// Prints the line, function, and file that the nth frame of the call stack was called from
+/// With no argument, prints the line and file of the current frame's call site
+/// Sets the exit code to 1 if there is no such frame
+pub fn execute<'a>(caller_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = caller_call.clone();
+	let mut argv = helper::prepare_argv(caller_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(caller_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let depth = match argv.pop_front() {
+		Some(arg) => Some(arg.parse::<usize>().map_err(|_| High(SlashErrHigh::syntax_err(format!("Invalid frame number: {}",arg), blame)))?),
+		None => None
+	};
+
+	let stack = slash.meta().call_stack();
+	let Some(index) = stack.len().checked_sub(depth.unwrap_or(0) + 1) else {
+		slash.set_code(1);
+		return Ok(())
+	};
+	let frame = &stack[index];
+	let file = frame.file.clone()
+		.or_else(|| slash.vars().get_param("0"))
+		.unwrap_or_else(|| "main".into());
+
+	match depth {
+		Some(_) => {
+			// The function that made the call is the next frame down
+			let caller_name = match index.checked_sub(1).map(|i| &stack[i]) {
+				Some(below) if below.is_source => "source".to_string(),
+				Some(below) => below.name.clone(),
+				None => "main".to_string()
+			};
+			writeln!(stdout, "{} {} {}", frame.line, caller_name, file)?;
+		}
+		None => writeln!(stdout, "{} {}", frame.line, file)?
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::output_of};
+
+use super::*;
+
+	#[test]
+	fn test_funcname() {
+		let mut slash = Slash::new();
+		let output = output_of("inner() { echo $FUNCNAME; echo \"${FUNCNAME}\"; echo ${FUNCNAME[@]}; }\nouter() { inner; }\nouter", &mut slash);
+		assert_eq!(output, "inner\ninner\ninner outer\n");
+	}
+
+	#[test]
+	fn test_caller() {
+		let mut slash = Slash::new();
+		let output = output_of("inner() { caller 0; }\nouter() {\n\ttrue\n\tinner\n}\nouter", &mut slash);
+
+		// `inner` is on the second line of the body of `outer`
+		assert_eq!(output, "2 outer main\n");
+
+		execute::dispatch::exec_input("caller".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+	}
+}
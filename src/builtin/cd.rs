@@ -1,15 +1,38 @@
 use crate::prelude::*;
 
-use crate::{helper::{self}, shellenv::Slash, SlashResult, pest_ext::Rule};
+use crate::{builtin::dir_stack, error::SlashErrExt, helper::{self}, shellenv::{EnvFlags, Slash}, utils, SlashResult, pest_ext::Rule};
 
+/// Changes the current directory
+/// By default `PWD` is worked out from the old `PWD` and the argument, so symlinks stay in it and `..` goes back out of them.
+/// -P resolves the symlinks, and sets `PWD` to the physical path instead. `cd +n` and `cd -n` rotate the directory stack
 pub fn execute<'a>(cd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let blame = cd_call.clone();
-	let mut argv = helper::prepare_argv(cd_call,slash)?;
+	let mut argv = helper::prepare_argv(cd_call.clone(),slash)?;
+	let redirs = helper::prepare_redirs(cd_call)?;
 	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut physical = false;
+	while let Some(flag) = argv.front() {
+		match flag.as_str() {
+			"-L" => physical = false,
+			"-P" => physical = true,
+			"--" => {
+				argv.pop_front();
+				break
+			}
+			_ => break
+		}
+		argv.pop_front();
+	}
 	let new_pwd;
+	let mut rotated = None;
 	match argv.pop_front() {
 		Some(arg) => {
-			if arg.as_str() == "-" {
+			if dir_stack::is_stack_index(&arg) {
+				let (target, stack) = dir_stack::rotate(&arg, slash).blame(blame)?;
+				new_pwd = target.to_string_lossy().to_string();
+				rotated = Some(stack);
+			} else if arg.as_str() == "-" {
 				new_pwd = slash.vars().get_evar("OLDPWD").unwrap_or("/".into());
 			} else {
 				new_pwd = arg.as_str().into();
@@ -19,8 +42,141 @@ pub fn execute<'a>(cd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()>
 			new_pwd = env::var("HOME").unwrap_or("/".into());
 		}
 	}
-	slash.vars_mut().export_var("OLDPWD", &env::var("PWD").unwrap_or_default());
-	env::set_current_dir(new_pwd)?;
-	slash.vars_mut().export_var("PWD", env::current_dir().unwrap().to_str().unwrap());
+	let old_pwd = env::var("PWD").unwrap_or_default();
+	let mut dest = if physical { PathBuf::from(&new_pwd) } else { logical_path(&old_pwd, &new_pwd) };
+	if let Err(e) = env::set_current_dir(&dest) {
+		let cdspell = slash.meta().flags().contains(EnvFlags::INTERACTIVE) &&
+			slash.meta().get_shopt("core.cdspell")?.parse::<bool>().unwrap_or(false);
+		match spell_correct(&new_pwd).filter(|_| cdspell) {
+			Some(corrected) => {
+				dest = if physical { corrected.clone() } else { logical_path(&old_pwd, &corrected.to_string_lossy()) };
+				env::set_current_dir(&dest)?;
+				let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+				writeln!(stdout, "{}", corrected.display())?;
+			}
+			None => return Err(e.into())
+		}
+	}
+	if physical {
+		dest = env::current_dir()?;
+	}
+	if let Some(stack) = rotated {
+		slash.meta_mut().set_dir_stack(stack);
+	}
+	slash.vars_mut().export_var("OLDPWD", &old_pwd);
+	slash.vars_mut().export_var("PWD", &dest.to_string_lossy());
 	Ok(())
 }
+
+/// Joins `path` onto the logical `pwd` and removes the `.` and `..` components without looking at the filesystem
+fn logical_path(pwd: &str, path: &str) -> PathBuf {
+	let base = match pwd {
+		_ if path.starts_with('/') => PathBuf::from("/"),
+		"" => env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
+		_ => PathBuf::from(pwd)
+	};
+	let mut result = PathBuf::from("/");
+	for component in base.join(path).components() {
+		match component {
+			std::path::Component::ParentDir => { result.pop(); }
+			std::path::Component::Normal(name) => result.push(name),
+			_ => {}
+		}
+	}
+	result
+}
+
+/// Looks for a directory next to `path` whose name is one transposed, missing, or extra character away from it
+fn spell_correct(path: &str) -> Option<PathBuf> {
+	let path = Path::new(path);
+	let name = path.file_name()?.to_str()?;
+	let parent = match path.parent() {
+		Some(parent) if !parent.as_os_str().is_empty() => parent,
+		_ => Path::new(".")
+	};
+	let mut candidates = std::fs::read_dir(parent).ok()?
+		.flatten()
+		.filter(|entry| entry.path().is_dir())
+		.filter_map(|entry| entry.file_name().into_string().ok())
+		.collect::<Vec<String>>();
+	candidates.sort();
+	candidates.into_iter()
+		.find(|candidate| is_close_match(name, candidate))
+		.map(|candidate| parent.join(candidate))
+}
+
+fn is_close_match(typed: &str, candidate: &str) -> bool {
+	let typed = typed.chars().collect::<Vec<char>>();
+	let candidate = candidate.chars().collect::<Vec<char>>();
+	match typed.len() as isize - candidate.len() as isize {
+		0 => {
+			let diffs = (0..typed.len()).filter(|&i| typed[i] != candidate[i]).collect::<Vec<usize>>();
+			diffs.len() == 2 &&
+			diffs[1] == diffs[0] + 1 &&
+			typed[diffs[0]] == candidate[diffs[1]] &&
+			typed[diffs[1]] == candidate[diffs[0]]
+		}
+		1 => (0..typed.len()).any(|i| typed[..i] == candidate[..i] && typed[i + 1..] == candidate[i..]),
+		-1 => (0..candidate.len()).any(|i| candidate[..i] == typed[..i] && candidate[i + 1..] == typed[i..]),
+		_ => false
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{capture, lock_process, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_cdspell() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let root = temp_path("cdspell");
+		let target = root.join("foo");
+		std::fs::create_dir_all(&target).unwrap();
+		let root_str = root.to_str().unwrap();
+		let orig_dir = env::current_dir().unwrap();
+
+		slash.meta_mut().mod_flags(|flags| *flags |= EnvFlags::INTERACTIVE);
+		assert!(execute::dispatch::exec_input(format!("cd {root_str}/fooo"), &mut slash).is_err());
+
+		execute::dispatch::exec_input("setopt core.cdspell=true".to_string(), &mut slash).unwrap();
+		for typo in ["fooo", "fo", "ofo"] {
+			capture(&format!("cd {root_str}/{typo}"), &mut slash).0.unwrap();
+			assert_eq!(env::current_dir().unwrap(), target.canonicalize().unwrap());
+		}
+		assert!(execute::dispatch::exec_input(format!("cd {root_str}/bar"), &mut slash).is_err());
+
+		env::set_current_dir(orig_dir).unwrap();
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+
+	#[test]
+	fn test_cd_logical_physical() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let root = temp_path("cd_link");
+		std::fs::create_dir_all(&root).unwrap();
+		let root = root.canonicalize().unwrap();
+		let real = root.join("real");
+		let link = root.join("link");
+		std::fs::create_dir_all(&real).unwrap();
+		std::os::unix::fs::symlink(&real, &link).unwrap();
+		let (root_str, link_str) = (root.to_str().unwrap(), link.to_str().unwrap());
+		let orig_dir = env::current_dir().unwrap();
+
+		execute::dispatch::exec_input(format!("cd {link_str}"), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_evar("PWD").unwrap(), link_str);
+		execute::dispatch::exec_input(format!("cd {link_str}/.."), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_evar("PWD").unwrap(), root_str);
+		execute::dispatch::exec_input(format!("cd -L {root_str}/./link"), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_evar("PWD").unwrap(), link_str);
+		execute::dispatch::exec_input(format!("cd -P {link_str}"), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_evar("PWD").unwrap(), real.to_str().unwrap());
+		assert_eq!(env::current_dir().unwrap(), real);
+
+		env::set_current_dir(orig_dir).unwrap();
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+}
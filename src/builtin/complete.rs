@@ -0,0 +1,179 @@
+use crate::{helper, prelude::*, prompt::comp::{self, CompSpec, CompType}, utils};
+
+/// Registers how the arguments of each named command are completed, like bash's `complete`
+/// With `-p`, or without any completion options, the specs for the names are printed instead, or every spec if no names are given
+pub fn execute<'a>(complete_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = complete_call.clone();
+	let mut argv = helper::prepare_argv(complete_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(complete_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let CompArgs { spec, print, remove, mut names } = parse_args("complete", argv, blame.clone())?;
+
+	if remove {
+		if names.is_empty() {
+			slash.logic_mut().clear_comp_specs();
+		}
+		for name in &names {
+			slash.logic_mut().remove_comp_spec(name);
+		}
+	} else if print || spec == CompSpec::default() {
+		if names.is_empty() {
+			names = slash.logic().borrow_comp_specs().keys().cloned().collect();
+			names.sort();
+		}
+		for name in names {
+			let Some(spec) = slash.logic().get_comp_spec(&name) else {
+				return Err(High(SlashErrHigh::exec_err(format!("complete: {}: no completion specification",name), blame)))
+			};
+			writeln!(stdout, "complete {} {}", spec, name)?;
+		}
+	} else {
+		for name in &names {
+			slash.logic_mut().set_comp_spec(name, spec.clone());
+		}
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+/// Prints the candidates that a spec would offer for a word, one per line, like bash's `compgen`
+/// The spec is given with the same options as `complete`. Without a word, everything the spec offers is printed
+pub fn compgen<'a>(compgen_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = compgen_call.clone();
+	let mut argv = helper::prepare_argv(compgen_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(compgen_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let CompArgs { spec, print, remove, mut names } = parse_args("compgen", argv, blame.clone())?;
+	if print || remove {
+		return Err(High(SlashErrHigh::syntax_err("compgen: -p and -r only apply to `complete'", blame)))
+	}
+	if names.len() > 1 {
+		return Err(High(SlashErrHigh::syntax_err("compgen: too many arguments", blame)))
+	}
+	let word = names.pop().unwrap_or_default();
+	let candidates = comp::cycle_order(spec.candidates(&word, slash).into_iter()
+		.filter(|candidate| candidate.starts_with(word.as_str()))
+		.collect());
+	for candidate in &candidates {
+		writeln!(stdout, "{}", candidate)?;
+	}
+	slash.set_code(if candidates.is_empty() { 1 } else { 0 });
+	Ok(())
+}
+
+/// The options shared by `complete` and `compgen`, and the names after them
+struct CompArgs {
+	spec: CompSpec,
+	print: bool,
+	remove: bool,
+	names: Vec<String>
+}
+
+fn parse_args<'a>(name: &str, mut argv: VecDeque<String>, blame: Pair<'a,Rule>) -> SlashResult<CompArgs> {
+	let mut spec = CompSpec::default();
+	let mut print = false;
+	let mut remove = false;
+	let mut names = vec![];
+	while let Some(arg) = argv.pop_front() {
+		if arg == "--" {
+			names.extend(argv.drain(..));
+			break
+		}
+		let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+			names.push(arg);
+			continue
+		};
+		let mut flags = flags.chars();
+		while let Some(flag) = flags.next() {
+			match flag {
+				'p' => print = true,
+				'r' => remove = true,
+				'f' => spec.add_action(CompType::Paths),
+				'd' => spec.add_action(CompType::Directories),
+				'c' => spec.add_action(CompType::Commands),
+				'W' | 'A' => {
+					// The value can be attached to the flag, like `-Afunction`
+					let rest = flags.as_str();
+					let value = if rest.is_empty() { argv.pop_front() } else { Some(rest.to_string()) };
+					let Some(value) = value else {
+						return Err(High(SlashErrHigh::syntax_err(format!("{}: -{}: option requires an argument",name,flag), blame)))
+					};
+					if flag == 'W' {
+						spec.words.extend(value.split_whitespace().map(|word| word.to_string()));
+					} else {
+						let Some(action) = CompType::from_action(&value) else {
+							return Err(High(SlashErrHigh::syntax_err(format!("{}: {}: invalid action name",name,value), blame)))
+						};
+						spec.add_action(action);
+					}
+					break
+				}
+				_ => return Err(High(SlashErrHigh::syntax_err(format!("Invalid flag in `{}' call: -{}",name,flag), blame)))
+			}
+		}
+	}
+	Ok(CompArgs { spec, print, remove, names })
+}
+
+#[cfg(test)]
+mod tests {
+	use rustyline::{completion::{Candidate, Completer}, history::DefaultHistory, Context};
+
+	use crate::{execute, prompt::prompt::SlashHelper, test_util::{output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_complete_specs() {
+		let mut slash = Slash::new();
+		let dir = temp_path("complete");
+		std::fs::create_dir_all(dir.join("alpha")).unwrap();
+		std::fs::create_dir_all(dir.join("beta")).unwrap();
+		std::fs::write(dir.join("gamma"), "").unwrap();
+		let dir_str = dir.to_str().unwrap();
+
+		let output = output_of("complete -W 'start stop status' fakectl; complete -d fakecd; complete -p", &mut slash);
+		assert_eq!(output, "complete -d fakecd\ncomplete -W 'start stop status' fakectl\n");
+
+		// The fuzzy finder needs a terminal
+		slash.meta_mut().set_shopt("prompt.completion_style", "menu").unwrap();
+		let helper = SlashHelper::new(&mut slash);
+		let history = DefaultHistory::new();
+		let ctx = Context::new(&history);
+		let complete = |line: &str| {
+			let (start, candidates) = helper.complete(line, line.len(), &ctx).unwrap();
+			(start, candidates.iter().map(|candidate| candidate.replacement().to_string()).collect::<Vec<_>>())
+		};
+		assert_eq!(complete("fakectl st"), (8, vec!["start".to_string(), "status".to_string(), "stop".to_string()]));
+		assert_eq!(complete("fakectl sta"), (8, vec!["start".to_string(), "status".to_string()]));
+		let line = format!("fakecd {dir_str}/");
+		assert_eq!(complete(&line), (7, vec![format!("{dir_str}/alpha/"), format!("{dir_str}/beta/")]));
+		let line = format!("fakecd {dir_str}/b");
+		assert_eq!(complete(&line), (7, vec![format!("{dir_str}/beta/")]));
+
+		assert!(execute::dispatch::exec_input("complete -A bogus foo".to_string(), &mut slash).is_err());
+		execute::dispatch::exec_input("complete -r fakectl".to_string(), &mut slash).unwrap();
+		assert!(slash.logic().get_comp_spec("fakectl").is_none());
+		assert!(execute::dispatch::exec_input("complete -p fakectl".to_string(), &mut slash).is_err());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_compgen() {
+		let mut slash = Slash::new();
+		assert_eq!(output_of("compgen -W 'a ab abc b' ab", &mut slash), "ab\nabc\n");
+
+		// Builtins, functions and aliases are commands too, not just what is in $PATH
+		let output = output_of("slash_compgen_fn() { true; }; alias slash_compgen_alias=ls; compgen -c slash_compgen; compgen -c hist", &mut slash);
+		assert_eq!(output, "slash_compgen_alias\nslash_compgen_fn\nhistory\n");
+
+		execute::dispatch::exec_input("compgen -W 'a b' c".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+	}
+}
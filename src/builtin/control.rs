@@ -51,3 +51,32 @@ pub fn loop_break<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()>
 pub fn loop_continue<'a>() -> SlashResult<()> {
 	Err(Low(SlashErrLow::LoopCont))
 }
+
+/// `:` and `true` succeed and `false` fails, after expanding their arguments for any side effects
+pub fn no_op<'a>(pair: Pair<'a,Rule>, slash: &mut Slash, code: i32) -> SlashResult<()> {
+	let redirs = helper::prepare_redirs(pair.clone())?;
+	helper::prepare_argv(pair, slash)?;
+	slash.consume_redirs(redirs)?;
+	slash.set_code(code);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, shellenv::SlashVal};
+
+use super::*;
+
+	#[test]
+	fn test_no_op_builtins() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input(": ${X:=hi}".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("X"), Some(SlashVal::String("hi".into())));
+		assert_eq!(slash.get_status(), 0);
+
+		execute::dispatch::exec_input("false ignored args".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+		execute::dispatch::exec_input("true".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 0);
+	}
+}
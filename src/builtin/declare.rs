@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+
+use crate::{helper, prelude::*, shellenv::{SlashVal, VarFlags}, utils};
+
+/// Handles the function flags `-f` and `-F`, and declaring variables with attributes
+/// `-f` prints function definitions in a form that can be fed back into the shell, `-F` prints only their names
+/// `-a`, `-A`, `-i`, `-r`, and `-x` give each name an attribute, and `-g` sets it globally even inside of a function
+/// `-p` prints each variable as a `declare` command that recreates it, or every variable if no names are given
+pub fn execute<'a>(declare_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = declare_call.clone();
+	let mut argv = helper::prepare_argv(declare_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(declare_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let mut names_only = false;
+	let mut funcs = false;
+	let mut print = false;
+	let mut global = false;
+	let mut flags = VarFlags::empty();
+	let mut names = vec![];
+	while let Some(arg) = argv.pop_front() {
+		if arg == "--" {
+			names.extend(argv.drain(..));
+			break
+		}
+		let Some(opts) = arg.strip_prefix('-').filter(|opts| !opts.is_empty()) else {
+			names.push(arg);
+			continue
+		};
+		for opt in opts.chars() {
+			match opt {
+				'f' => funcs = true,
+				'F' => { funcs = true; names_only = true }
+				'p' => print = true,
+				'g' => global = true,
+				'a' => flags |= VarFlags::ARRAY,
+				'A' => flags |= VarFlags::ASSOC,
+				'i' => flags |= VarFlags::INTEGER,
+				'r' => flags |= VarFlags::READONLY,
+				'x' => flags |= VarFlags::EXPORT,
+				_ => return Err(High(SlashErrHigh::syntax_err(format!("Invalid flag in `declare' call: {}",arg), blame)))
+			}
+		}
+	}
+
+	if funcs {
+		if names.is_empty() {
+			names = slash.logic().borrow_functions().keys().cloned().collect();
+			names.sort();
+		}
+		for name in names {
+			let Some(body) = slash.logic().get_func(&name) else {
+				return Err(High(SlashErrHigh::exec_err(format!("declare: {}: not found",name), blame)))
+			};
+			if names_only {
+				writeln!(stdout, "{}", name)?;
+			} else {
+				writeln!(stdout, "{}", render_func(&name, &body, &slash.logic().get_func_params(&name)))?;
+			}
+		}
+		slash.set_code(0);
+		return Ok(())
+	}
+
+	if print || names.is_empty() {
+		if names.is_empty() {
+			names = slash.vars().vars().keys()
+				.filter(|name| flags.is_empty() || slash.vars().get_flags(name).contains(flags))
+				.cloned()
+				.collect();
+			names.sort();
+		}
+		let mut code = 0;
+		for name in names {
+			match slash.vars().get_var(&name) {
+				Some(val) => writeln!(stdout, "{}", render_var(&name, &val, slash.vars().get_flags(&name)))?,
+				None => {
+					let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+					writeln!(stderr, "declare: {}: not found",name)?;
+					code = 1;
+				}
+			}
+		}
+		slash.set_code(code);
+		return Ok(())
+	}
+
+	let in_func = slash.meta().call_stack().iter().any(|frame| !frame.is_source);
+	for arg in names {
+		let (name, val) = match arg.split_once('=') {
+			Some((name, val)) => (name.to_string(), Some(val.to_string())),
+			None => (arg, None)
+		};
+		helper::check_ident(&name, blame.clone())?;
+		if val.is_some() && slash.vars().is_readonly(&name) {
+			return Err(High(SlashErrHigh::exec_err(format!("declare: {}: readonly variable",name), blame)))
+		}
+		let existing = slash.vars().get_var(&name);
+		let val = match val {
+			Some(val) if flags.contains(VarFlags::INTEGER) => {
+				let Ok(int) = val.parse::<i32>() else {
+					return Err(High(SlashErrHigh::exec_err(format!("declare: {}: expected an integer, got `{}'",name,val), blame)))
+				};
+				SlashVal::Int(int)
+			}
+			Some(val) => SlashVal::parse(&val)?,
+			// Declaring an existing variable again leaves its value alone, unless the attribute changes its type
+			None if flags.contains(VarFlags::ASSOC) => match existing {
+				Some(SlashVal::Dict(dict)) => SlashVal::Dict(dict),
+				_ => SlashVal::Dict(BTreeMap::new())
+			},
+			None if flags.contains(VarFlags::ARRAY) => match existing {
+				Some(SlashVal::Array(arr)) => SlashVal::Array(arr),
+				_ => SlashVal::Array(vec![])
+			},
+			None => existing.unwrap_or_else(|| SlashVal::String(String::new()))
+		};
+		if global && in_func {
+			slash.vars_mut().set_global(&name, val, flags);
+		} else {
+			slash.vars_mut().declare_var(&name, val, flags);
+		}
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+/// Renders a variable as a `declare` command that recreates it with the same attributes
+/// Associative arrays are followed by an assignment for each of their elements
+pub fn render_var(name: &str, val: &SlashVal, flags: VarFlags) -> String {
+	render_var_with(name, val, flags, quote_val)
+}
+
+/// Like `render_var()`, with `quote` used on each value in place of the double quoting that `declare -p` prints
+pub fn render_var_with(name: &str, val: &SlashVal, flags: VarFlags, quote: fn(&str) -> String) -> String {
+	let opts = [
+		(VarFlags::ARRAY, 'a'),
+		(VarFlags::ASSOC, 'A'),
+		(VarFlags::INTEGER, 'i'),
+		(VarFlags::READONLY, 'r'),
+		(VarFlags::EXPORT, 'x'),
+	].into_iter().filter(|(flag, _)| flags.contains(*flag)).map(|(_, opt)| opt).collect::<String>();
+	let opts = if opts.is_empty() { "-".to_string() } else { opts };
+	match val {
+		SlashVal::Dict(dict) => {
+			let mut source = format!("declare -{} {}", opts, name);
+			for (key, elem) in dict {
+				source.push_str(&format!("; {}[{}]={}", name, key.replace(']', "\\]"), quote(&elem.to_string())));
+			}
+			source
+		}
+		SlashVal::Array(arr) => {
+			let elems = arr.iter().map(|elem| elem.to_string().replace(',', "\\,")).collect::<Vec<_>>();
+			format!("declare -{} {}=[{}]", opts, name, elems.join(","))
+		}
+		_ => format!("declare -{} {}={}", opts, name, quote(&val.to_string()))
+	}
+}
+
+fn quote_val(val: &str) -> String {
+	let mut quoted = String::from('"');
+	for ch in val.chars() {
+		if matches!(ch, '"' | '\\' | '$' | '`') {
+			quoted.push('\\');
+		}
+		quoted.push(ch);
+	}
+	quoted.push('"');
+	quoted
+}
+
+/// Rebuilds the source of a function from its stored body
+/// Functions with named parameters are printed in the `fn name(params)` form, which is the only one that can declare them
+/// The body is printed as it was written, since re-indenting a line of a multi-line string or here-doc would change its contents
+pub fn render_func(name: &str, body: &str, params: &[String]) -> String {
+	let header = if params.is_empty() {
+		format!("{}()", name)
+	} else {
+		format!("fn {}({})", name, params.join(" "))
+	};
+	format!("{} {{\n\t{}\n}}", header, body)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, pest_ext::SlashParse, test_util::output_of};
+	use pest::Parser;
+
+use super::*;
+
+	#[test]
+	fn test_declare_f() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("greet() { echo hello $1; echo bye; }".to_string(), &mut slash).unwrap();
+		let source = output_of("declare -f greet", &mut slash);
+		assert!(SlashParse::parse(Rule::main, &source).is_ok());
+
+		// Defining the function again from the output should give the same body
+		let mut fresh = Slash::new();
+		execute::dispatch::exec_input(source, &mut fresh).unwrap();
+		assert_eq!(fresh.logic().get_func("greet"), slash.logic().get_func("greet"));
+
+		assert_eq!(output_of("declare -F", &mut slash), "greet\n");
+
+		// Indentation inside a quoted string and a here-doc is part of the value, so it survives the round trip
+		let input = "lines() {\n\techo \"a\n   b\"\n\tcat <<EOF\n  c\nEOF\n}";
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+		let source = output_of("declare -f lines", &mut slash);
+		let mut fresh = Slash::new();
+		execute::dispatch::exec_input(source, &mut fresh).unwrap();
+		assert_eq!(fresh.logic().get_func("lines"), slash.logic().get_func("lines"));
+		assert_eq!(output_of("lines", &mut fresh), "a\n   b\n  c\n");
+	}
+
+	#[test]
+	fn test_declare_assoc() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("declare -A colors; colors[apple]=red; k=banana; colors[$k]=yellow".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("colors['lime green']=green".to_string(), &mut slash).unwrap();
+		assert!(matches!(slash.vars().get_var("colors"), Some(SlashVal::Dict(dict)) if dict.len() == 3));
+
+		execute::dispatch::exec_input("a=${colors[apple]}; b=${colors[$k]}; c=${colors[cherry]}".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("a"), Some(SlashVal::String("red".into())));
+		assert_eq!(slash.vars().get_var("b"), Some(SlashVal::String("yellow".into())));
+		assert_eq!(slash.vars().get_var("c"), Some(SlashVal::String("".into())));
+
+		execute::dispatch::exec_input("vals=\"${colors[@]}\"; keys=\"${!colors[@]}\"".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("vals"), Some(SlashVal::String("red yellow green".into())));
+		assert_eq!(slash.vars().get_var("keys"), Some(SlashVal::String("apple banana lime green".into())));
+
+		// Assigning to an undeclared array creates it
+		execute::dispatch::exec_input("sizes[small]=1; s=${sizes[small]}".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("s"), Some(SlashVal::Int(1)));
+	}
+
+	#[test]
+	fn test_declare_global() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("f() { declare -g outer=1; declare inner=2; g; }; g() { declare -gi nested=3; }; f".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("outer"), Some(SlashVal::Int(1)));
+		assert_eq!(slash.vars().get_var("inner"), None);
+		assert_eq!(slash.vars().get_var("nested"), Some(SlashVal::Int(3)));
+		assert!(slash.vars().get_flags("nested").contains(VarFlags::INTEGER));
+	}
+
+	#[test]
+	fn test_declare_p_round_trip() {
+		let mut slash = Slash::new();
+		let source = output_of("declare -ir x=5; declare -x SLASH_DECLARE_X='a b'; arr list=[one,two]; declare -p x SLASH_DECLARE_X list", &mut slash);
+		assert_eq!(source.lines().next(), Some("declare -ir x=\"5\""));
+		assert_eq!(env::var("SLASH_DECLARE_X").unwrap(), "a b");
+		assert!(execute::dispatch::exec_input("x=6".to_string(), &mut slash).is_err());
+		// Missing names are reported on stderr, and the ones that exist still get printed
+		assert_eq!(output_of("declare -p nope x 2>&1; echo $?", &mut slash), "declare: nope: not found\ndeclare -ir x=\"5\"\n1\n");
+
+		let mut fresh = Slash::new();
+		execute::dispatch::exec_input(source.replace('\n', "; "), &mut fresh).unwrap();
+		for name in ["x", "SLASH_DECLARE_X", "list"] {
+			assert_eq!(fresh.vars().get_var(name), slash.vars().get_var(name), "{}", name);
+			assert_eq!(fresh.vars().get_flags(name), slash.vars().get_flags(name), "{}", name);
+		}
+	}
+}
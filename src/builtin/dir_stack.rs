@@ -1,29 +1,55 @@
 use crate::prelude::*;
 
-use crate::{helper, shellenv::Slash, SlashResult};
+use crate::{error::SlashErrExt, helper, shellenv::Slash, utils, SlashResult};
 
+/// Removes a directory from the stack
+/// Without an index, the top of the stack is removed and the shell changes to the directory under it
+/// `+n` or `-n` removes the nth entry instead, counting like `dirs -v` does, and only changes directory if that entry is the top one
+/// A bare count pops that many entries. `-n` removes the entry under the top without changing directory
 pub fn popd<'a>(popd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = popd_call.clone();
 	let mut argv = helper::prepare_argv(popd_call.clone(),slash)?;
 	argv.pop_front();
+	let no_cd = argv.front().is_some_and(|arg| arg == "-n");
+	if no_cd {
+		argv.pop_front();
+	}
 	let arg = argv.pop_front();
+	if let Some(index) = arg.as_deref().filter(|arg| is_stack_index(arg)) {
+		let entries = dir_entries(slash);
+		let position = stack_position(index, entries.len()).blame(blame.clone())?;
+		if position > 0 {
+			// The stack is stored bottom first, without the current directory
+			let mut stack = slash.meta().dir_stack().to_vec();
+			stack.remove(entries.len() - 1 - position);
+			slash.meta_mut().set_dir_stack(stack);
+			return Ok(())
+		}
+	} else if no_cd {
+		if slash.meta_mut().pop_dir().is_none() {
+			return Err(High(SlashErrHigh::exec_err("`popd` called with an empty directory stack", blame)))
+		}
+		return Ok(())
+	}
 	let mut path = None;
-	if let Some(arg) = arg {
-		match arg.as_str().parse::<usize>() {
-			Ok(count) => {
-				for _ in 0..count {
-					let popped = slash.meta_mut().pop_dir();
-					if let Some(popped) = popped {
-						path = Some(popped);
+	match arg.filter(|arg| !is_stack_index(arg)) {
+		Some(arg) => {
+			match arg.as_str().parse::<usize>() {
+				Ok(count) => {
+					for _ in 0..count {
+						let popped = slash.meta_mut().pop_dir();
+						if let Some(popped) = popped {
+							path = Some(popped);
+						}
 					}
 				}
-			}
-			Err(_) => {
-				let msg = "`popd` expects a positive integer";
-				return Err(High(SlashErrHigh::syntax_err(msg, popd_call)))
+				Err(_) => {
+					let msg = "`popd` expects a positive integer";
+					return Err(High(SlashErrHigh::syntax_err(msg, popd_call)))
+				}
 			}
 		}
-	} else {
-		path = slash.meta_mut().pop_dir();
+		None => path = slash.meta_mut().pop_dir()
 	}
 	match path {
 		Some(path) => {
@@ -45,16 +71,43 @@ pub fn popd<'a>(popd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()>
 	Ok(())
 }
 
+/// Pushes the current directory onto the stack and changes to `dir`, or swaps the top two entries without an argument
+/// `+n` or `-n` rotates the stack so that the nth entry is on top, and changes to it
+/// `-n` leaves the current directory alone: a new directory goes under the top of the stack, and a rotation only moves the entries under it
 pub fn pushd<'a>(pushd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let blame = pushd_call.clone();
 	let mut argv = helper::prepare_argv(pushd_call,slash)?;
 	argv.pop_front();
+	let no_cd = argv.front().is_some_and(|arg| arg == "-n");
+	if no_cd {
+		argv.pop_front();
+	}
+	let cwd = slash.vars().get_evar("PWD").map(PathBuf::from).unwrap_or(env::current_dir()?);
 	match argv.pop_front() {
+		Some(index) if is_stack_index(&index) => {
+			if no_cd {
+				let mut entries = dir_entries(slash);
+				let position = stack_position(&index, entries.len()).blame(blame)?;
+				entries.remove(0);
+				// Taking the current directory back out of the rotated stack leaves the rest one step less rotated
+				entries.rotate_left(position.saturating_sub(1));
+				slash.meta_mut().set_dir_stack(entries.into_iter().rev().collect());
+			} else {
+				let (target, stack) = rotate(&index, slash).blame(blame)?;
+				slash.change_dir(&target)?;
+				slash.meta_mut().set_dir_stack(stack);
+			}
+		}
 		Some(arg) => {
 			let path = Path::new(arg.as_str());
 			if path.exists() {
 				if path.is_dir() {
-					slash.change_dir(path)?;
+					if no_cd {
+						slash.meta_mut().push_dir(path.to_path_buf());
+					} else {
+						slash.change_dir(path)?;
+						slash.meta_mut().push_dir(cwd);
+					}
 				} else {
 					return Err(High(SlashErrHigh::syntax_err("Path is not a directory", blame)))
 				}
@@ -62,7 +115,174 @@ pub fn pushd<'a>(pushd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()
 				return Err(High(SlashErrHigh::syntax_err("Path does not exist", blame)))
 			}
 		}
-		None => return Err(High(SlashErrHigh::syntax_err("Expected a directory path in pushd args", blame)))
+		None => {
+			let Some(next) = slash.meta_mut().pop_dir() else {
+				return Err(High(SlashErrHigh::exec_err("pushd: no other directory", blame)))
+			};
+			if no_cd {
+				slash.meta_mut().push_dir(next);
+			} else {
+				if let Err(e) = slash.change_dir(&next) {
+					slash.meta_mut().push_dir(next);
+					return Err(e)
+				}
+				slash.meta_mut().push_dir(cwd);
+			}
+		}
+	}
+	Ok(())
+}
+
+/// The whole directory stack in the order `dirs` prints it, starting with the current directory
+pub fn dir_entries(slash: &Slash) -> Vec<PathBuf> {
+	let cwd = slash.vars().get_evar("PWD").map(PathBuf::from).or_else(|| env::current_dir().ok()).unwrap_or_default();
+	std::iter::once(cwd).chain(slash.meta().dir_stack().iter().rev().cloned()).collect()
+}
+
+/// Whether `arg` is a `+n` or `-n` index into the directory stack
+pub fn is_stack_index(arg: &str) -> bool {
+	arg.len() > 1 && arg.starts_with(['+', '-']) && arg[1..].chars().all(|ch| ch.is_ascii_digit())
+}
+
+/// Where a `+n` or `-n` index points in a stack of `len` entries, counting from the top
+/// `+n` counts from the top of the stack and `-n` counts from the bottom, both starting at zero
+pub fn stack_position(index: &str, len: usize) -> SlashResult<usize> {
+	let n = index[1..].parse::<usize>().unwrap_or(usize::MAX);
+	let position = if index.starts_with('+') { Some(n) } else { len.checked_sub(n.saturating_add(1)) };
+	position.filter(|pos| *pos < len)
+		.ok_or_else(|| Low(SlashErrLow::ExecFailed(format!("{}: directory stack index out of range",index))))
+}
+
+/// Rotates the directory stack so that the entry at `index` is on top, like `cd +n` and `cd -n` in bash
+/// Returns the directory to change to and the directories that are left on the stack under it
+pub fn rotate(index: &str, slash: &Slash) -> SlashResult<(PathBuf, Vec<PathBuf>)> {
+	let mut entries = dir_entries(slash);
+	let position = stack_position(index, entries.len())?;
+	entries.rotate_left(position);
+	let target = entries.remove(0);
+	Ok((target, entries.into_iter().rev().collect()))
+}
+
+/// Prints the directory stack, starting with the current directory
+/// -v numbers the entries one per line, -p prints them one per line, -l leaves `$HOME` unabbreviated, and -c clears the stack
+pub fn dirs<'a>(dirs_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = dirs_call.clone();
+	let mut argv = helper::prepare_argv(dirs_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(dirs_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let (mut verbose, mut per_line, mut long) = (false, false, false);
+	while let Some(arg) = argv.pop_front() {
+		let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+			return Err(High(SlashErrHigh::syntax_err(format!("Invalid argument in `dirs' call: {}",arg), blame)))
+		};
+		for flag in flags.chars() {
+			match flag {
+				'v' => verbose = true,
+				'p' => per_line = true,
+				'l' => long = true,
+				'c' => {
+					slash.meta_mut().set_dir_stack(vec![]);
+					slash.set_code(0);
+					return Ok(())
+				}
+				_ => return Err(High(SlashErrHigh::syntax_err(format!("Invalid flag in `dirs' call: -{}",flag), blame)))
+			}
+		}
 	}
+
+	let home = slash.vars().get_evar("HOME").unwrap_or_default();
+	let entries = dir_entries(slash).into_iter().map(|dir| {
+		let dir = dir.to_string_lossy().to_string();
+		match dir.strip_prefix(&home) {
+			Some(rest) if !long && !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => format!("~{}",rest),
+			_ => dir
+		}
+	}).collect::<Vec<_>>();
+	if verbose {
+		for (i, dir) in entries.iter().enumerate() {
+			writeln!(stdout, "{:>2}  {}", i, dir)?;
+		}
+	} else if per_line {
+		for dir in &entries {
+			writeln!(stdout, "{}", dir)?;
+		}
+	} else {
+		writeln!(stdout, "{}", entries.join(" "))?;
+	}
+	slash.set_code(0);
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{lock_process, output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_cd_stack_index() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let root = temp_path("dir_stack");
+		for dir in ["a", "b", "c"] {
+			std::fs::create_dir_all(root.join(dir)).unwrap();
+		}
+		let root = root.canonicalize().unwrap();
+		let root_str = root.to_str().unwrap();
+		let orig_dir = env::current_dir().unwrap();
+
+		let input = format!("cd {root_str}; pushd {root_str}/a; pushd {root_str}/b; pushd {root_str}/c; cd +2; dirs -lv");
+		let output = output_of(&input, &mut slash);
+
+		assert_eq!(slash.vars().get_evar("PWD").unwrap(), format!("{root_str}/a"));
+		let expected = format!(" 0  {root_str}/a\n 1  {root_str}\n 2  {root_str}/c\n 3  {root_str}/b\n");
+		assert_eq!(output, expected);
+
+		// -0 is the bottom of the stack
+		execute::dispatch::exec_input("cd -0".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_evar("PWD").unwrap(), format!("{root_str}/b"));
+		assert!(execute::dispatch::exec_input("cd +4".to_string(), &mut slash).is_err());
+		assert!(execute::dispatch::exec_input("cd -4".to_string(), &mut slash).is_err());
+
+		env::set_current_dir(orig_dir).unwrap();
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+
+	#[test]
+	fn test_pushd_popd_rotation() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let root = temp_path("pushd_rotate");
+		for dir in ["a", "b", "c"] {
+			std::fs::create_dir_all(root.join(dir)).unwrap();
+		}
+		let root = root.canonicalize().unwrap();
+		let root_str = root.to_str().unwrap();
+		let orig_dir = env::current_dir().unwrap();
+
+		let input = format!("cd {root_str}; pushd {root_str}/a; pushd {root_str}/b; pushd {root_str}/c; pushd +1; dirs -lv; popd +2; dirs -lv");
+		let output = output_of(&input, &mut slash);
+
+		assert_eq!(slash.vars().get_evar("PWD").unwrap(), format!("{root_str}/b"));
+		let expected = format!(" 0  {root_str}/b\n 1  {root_str}/a\n 2  {root_str}\n 3  {root_str}/c\n 0  {root_str}/b\n 1  {root_str}/a\n 2  {root_str}/c\n");
+		assert_eq!(output, expected);
+
+		// -n only touches the entries under the top, so the current directory stays put
+		let input = format!("pushd -n {root_str}; pushd -n +2; dirs -l; popd -n; pushd; dirs -l");
+		let output = output_of(&input, &mut slash);
+
+		let expected = format!("{root_str}/b {root_str}/a {root_str}/c {root_str}\n{root_str}/c {root_str}/b {root_str}\n");
+		assert_eq!(output, expected);
+		assert_eq!(slash.vars().get_evar("PWD").unwrap(), format!("{root_str}/c"));
+
+		assert!(execute::dispatch::exec_input("pushd +3".to_string(), &mut slash).is_err());
+		assert!(execute::dispatch::exec_input("popd -3".to_string(), &mut slash).is_err());
+		assert_eq!(slash.meta().dir_stack().len(), 2);
+
+		env::set_current_dir(orig_dir).unwrap();
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+}
@@ -1,4 +1,4 @@
-use crate::{prelude::*, utils};
+use crate::{expand, prelude::*, shellenv::EnvFlags, utils};
 
 use crate::{error::{SlashErr::*, SlashErrHigh}, helper::{self, StrExtension}, shellenv::{write_jobs, ChildProc, JobBuilder, Slash}, SlashResult, pest_ext::Rule};
 
@@ -17,43 +17,47 @@ pub fn execute<'a>(echo_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 	let blame = echo_call.clone();
 	let mut argv = helper::prepare_argv(echo_call.clone(),slash)?;
 	argv.pop_front();
-	let mut arg_buffer = vec![];
 	let redirs = helper::prepare_redirs(echo_call)?;
 
-	while let Some(arg) = argv.pop_front() {
-		if arg.as_str().starts_with('-') {
-			let mut options = arg.as_str().strip_prefix('-').unwrap().chars();
-			let mut new_flags = EchoFlags::empty();
-			while let Some(opt) = options.next() {
-				match opt {
-					'e' => {
-						if new_flags.contains(EchoFlags::NO_ESCAPE) {
-							new_flags &= !EchoFlags::NO_ESCAPE
-						}
-						new_flags |= EchoFlags::USE_ESCAPE
-					}
-					'r' => new_flags |= EchoFlags::STDERR,
-					'n' => new_flags |= EchoFlags::NO_NEWLINE,
-					'P' => new_flags |= EchoFlags::EXPAND_OX_ESC,
-					'E' => {
-						if new_flags.contains(EchoFlags::USE_ESCAPE) {
-							new_flags &= !EchoFlags::USE_ESCAPE
-						}
-						new_flags |= EchoFlags::NO_ESCAPE
-					}
-					_ => break
-				}
+	// With xpg_echo, escapes are expanded unless -E is given
+	// In POSIX mode, it also takes no flags at all, like System V echo, so `\c` is the only way to leave off the newline
+	let xpg_echo = slash.meta().get_shopt("core.xpg_echo")?.parse::<bool>().unwrap_or(false);
+	if xpg_echo {
+		flags |= EchoFlags::USE_ESCAPE;
+	}
+	let spec = if xpg_echo && slash.meta().flags().contains(EnvFlags::POSIX) { "" } else { "neErP" };
+	// Like bash, anything that isn't a flag echo knows is printed, including `--`
+	let (opts, operands) = helper::parse_flags(spec, argv, false).map_err(|e| e.into_err("echo", blame.clone()))?;
+	for (opt, _) in opts.iter() {
+		match opt {
+			'e' => {
+				flags.remove(EchoFlags::NO_ESCAPE);
+				flags |= EchoFlags::USE_ESCAPE
 			}
-			if new_flags.is_empty() {
-				arg_buffer.push(arg.as_str().trim_quotes().to_string());
-			} else {
-				flags |= new_flags;
+			'E' => {
+				flags.remove(EchoFlags::USE_ESCAPE);
+				flags |= EchoFlags::NO_ESCAPE
 			}
-		} else {
-			arg_buffer.push(arg.as_str().trim_quotes().to_string());
+			'r' => flags |= EchoFlags::STDERR,
+			'n' => flags |= EchoFlags::NO_NEWLINE,
+			'P' => flags |= EchoFlags::EXPAND_OX_ESC,
+			_ => unreachable!()
+		}
+	}
+	let mut arg_buffer = vec![];
+	for arg in operands {
+		let arg = arg.as_str().trim_quotes().to_string();
+		if !flags.contains(EchoFlags::USE_ESCAPE) {
+			arg_buffer.push(arg);
+			continue
+		}
+		let (expanded, stop) = expand::string::expand_echo_escapes(&arg);
+		arg_buffer.push(expanded);
+		if stop {
+			flags |= EchoFlags::NO_NEWLINE;
+			break
 		}
 	}
-
 	let output = arg_buffer.join(" ");
 
 	let newline = !flags.contains(EchoFlags::NO_NEWLINE);
@@ -84,7 +88,11 @@ pub fn execute<'a>(echo_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 			std::process::exit(0);
 		}
 		Ok(ForkResult::Parent { child }) => {
-			setpgid(child, child).map_err(|_| High(SlashErrHigh::io_err(blame.clone())))?;
+			// The child may have already exited and been reaped, in which case there is no group to move it into
+			match setpgid(child, child) {
+				Ok(_) | Err(nix::errno::Errno::ESRCH) => {}
+				Err(_) => return Err(High(SlashErrHigh::io_err(blame)))
+			}
 			let children = vec![
 				ChildProc::new(child, Some("echo"), None)?
 			];
@@ -94,6 +102,7 @@ pub fn execute<'a>(echo_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 				.build();
 
 			if slash.ctx().flags().contains(utils::ExecFlags::BACKGROUND) {
+				slash.vars_mut().set_param("!", &child.to_string());
 				write_jobs(|j| j.insert_job(job,false))??;
 			} else {
 				helper::handle_fg(slash,job)?;
@@ -104,3 +113,41 @@ pub fn execute<'a>(echo_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::output_of};
+
+use super::*;
+
+	#[test]
+	fn test_echo_flags() {
+		let mut slash = Slash::new();
+		let output = output_of("echo -n a; echo b -n; echo -nx --", &mut slash);
+
+		// Flags end at the first operand, and echo prints any word it doesn't take as a flag
+		assert_eq!(output, "ab -n\n-nx --\n");
+	}
+
+	#[test]
+	fn test_echo_literal_words() {
+		let mut slash = Slash::new();
+		let output = output_of("echo -n hi; echo -- -n; echo -xyz; echo -en -a -n", &mut slash);
+
+		// A word with any flag echo doesn't know is printed whole, and so is everything after it
+		assert_eq!(output, "hi-- -n\n-xyz\n-a -n");
+	}
+
+	#[test]
+	fn test_xpg_echo() {
+		let mut slash = Slash::new();
+		assert_eq!(output_of("echo 'a\\tb'; echo -e 'a\\tb\\c' 'c'", &mut slash), "a\\tb\na\tb");
+
+		execute::dispatch::exec_input("shopt -s xpg_echo".to_string(), &mut slash).unwrap();
+		assert_eq!(output_of("echo 'a\\tb'; echo -E 'a\\tb'; echo -n 'c'", &mut slash), "a\tb\na\\tb\nc");
+
+		// System V echo prints its flags
+		execute::dispatch::exec_input("set -o posix".to_string(), &mut slash).unwrap();
+		assert_eq!(output_of("echo -n 'a\\tb\\c'", &mut slash), "-n a\tb");
+	}
+}
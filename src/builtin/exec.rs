@@ -14,6 +14,21 @@ pub fn run_exec<'a>(exec_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 			}
 			Rule::redir => {
 				// Mess with file descriptors
+				// Redirections given to exec are permanent, so the fds are kept open instead of being closed after the command
+				let mut redirs = helper::prepare_redirs(exec_call.clone())?;
+				slash.expand_heredocs(&mut redirs)?;
+				let closed = redirs.iter().filter(|redir| redir.redir_type() == Rule::close_fd).map(|redir| redir.our_fd()).collect::<Vec<_>>();
+				let noclobber = slash.noclobber();
+				let mut cmd_redirs = utils::CmdRedirs::new(redirs);
+				cmd_redirs.activate(noclobber).blame(exec_call.clone())?;
+				for fd in cmd_redirs.persist() {
+					if fd > 2 {
+						slash.meta_mut().track_fd(fd);
+					}
+				}
+				for fd in closed {
+					slash.meta_mut().untrack_fd(fd);
+				}
 			}
 			_ => unreachable!()
 		}
@@ -22,3 +37,30 @@ pub fn run_exec<'a>(exec_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{capture, lock_process, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_exec_numbered_fd() {
+		// fd 3 belongs to the whole process until it is closed again
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let path = temp_path("exec_fd");
+		let path_str = path.to_str().unwrap();
+
+		execute::dispatch::exec_input(format!("exec 3>{path_str}"), &mut slash).unwrap();
+		assert!(slash.meta().open_fds().contains(&3));
+		for _ in 0..2 {
+			capture("echo hi >&3", &mut slash).0.unwrap();
+		}
+		execute::dispatch::exec_input("exec 3>&-".to_string(), &mut slash).unwrap();
+		assert!(!slash.meta().open_fds().contains(&3));
+
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "hi\nhi\n");
+		std::fs::remove_file(&path).unwrap();
+	}
+}
@@ -1,39 +1,59 @@
-use crate::prelude::*;
-
-use crate::{helper, pest_ext::ARG_RULES, shellenv::Slash, SlashResult};
+use crate::{builtin::declare, helper, prelude::*, shellenv::{SlashVal, VarFlags}, utils};
 
+/// Exports variables to the environment of child processes, giving them a value first with `name=value`
+/// `-n` takes the names back out of the environment, keeping them as shell variables, and `-p` prints the exported variables
 pub fn execute<'a>(export_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
-	let mut argv = export_call.filter(&ARG_RULES[..]);
-	while let Some(arg) = argv.pop_front() {
-		match arg.as_rule() {
-			Rule::cmd_name => continue,
-			Rule::arg_assign => {
-				let mut assign_inner = arg.into_inner();
-				let var_name = assign_inner.next().unpack()?.as_str();
-				let val = match assign_inner.next() {
-					Some(pair) => helper::try_expansion(slash,pair)?,
-					None => String::new()
-				};
-				slash.vars_mut().export_var(var_name, &val);
-			}
-			_ => {
-				let msg = String::from("Expected an assignment in export args, got this");
-				return Err(High(SlashErrHigh::syntax_err(msg, arg)))
-			}
+	let blame = export_call.clone();
+	let mut argv = helper::prepare_argv(export_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(export_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let (flags, names) = helper::parse_flags("np", argv, true).map_err(|e| e.into_err("export", blame.clone()))?;
+
+	if flags.has('p') || (names.is_empty() && !flags.has('n')) {
+		let mut evars = slash.vars().borrow_evars().iter().collect::<Vec<_>>();
+		evars.sort();
+		for (name, val) in evars {
+			writeln!(stdout, "{}", declare::render_var(name, &SlashVal::String(val.clone()), VarFlags::EXPORT))?;
 		}
+		slash.set_code(0);
+		return Ok(())
 	}
 
+	for arg in names {
+		let (name, val) = match arg.split_once('=') {
+			Some((name, val)) => (name.to_string(), Some(val.to_string())),
+			None => (arg, None)
+		};
+		helper::check_ident(&name, blame.clone())?;
+		if flags.has('n') {
+			if let Some(val) = val.or_else(|| slash.vars().get_evar(&name)) {
+				slash.vars_mut().set_var(&name, SlashVal::String(val));
+			}
+			slash.vars_mut().unset_evar(&name);
+			continue
+		}
+		// Without a value, the variable's current value is exported
+		let Some(val) = val.or_else(|| slash.vars().get_var(&name).map(|val| val.to_string())) else {
+			continue
+		};
+		slash.vars_mut().export_var(&name, &val);
+	}
+	slash.set_code(0);
 	Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-	use crate::execute;
+	use crate::{execute, test_util::{lock_process, output_of}};
 
 use super::*;
 
 	#[test]
 	fn test_export() {
+		let _lock = lock_process();
 		let mut slash = Slash::new();
 		let input = "export VAR=\"foo bar\"";
 
@@ -44,4 +64,19 @@ use super::*;
 		assert_eq!(external_var, "foo bar".to_string());
 		assert_eq!(internal_var, "foo bar".to_string());
 	}
+
+	#[test]
+	fn test_export_flags() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let output = output_of("SLASH_EXPORT_A=1; export -- SLASH_EXPORT_A SLASH_EXPORT_B=2; export -p", &mut slash);
+		assert!(output.contains("declare -x SLASH_EXPORT_A=\"1\"\ndeclare -x SLASH_EXPORT_B=\"2\"\n"));
+
+		execute::dispatch::exec_input("export -n SLASH_EXPORT_B".to_string(), &mut slash).unwrap();
+		assert!(env::var("SLASH_EXPORT_B").is_err());
+		assert_eq!(slash.vars().get_var("SLASH_EXPORT_B"), Some(SlashVal::String("2".into())));
+
+		let bogus = execute::dispatch::exec_input("export -x FOO=1".to_string(), &mut slash);
+		assert!(bogus.is_err_and(|e| e.to_string().contains("Invalid flag in `export' call: -x")));
+	}
 }
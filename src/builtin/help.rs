@@ -0,0 +1,135 @@
+use crate::{builtin::{BUILTINS, HIDDEN_BUILTINS}, helper, prelude::*, utils};
+
+/// (name, synopsis, description)
+pub const HELP_TEXT: [(&str, &str, &str); 59] = [
+	("try", "try; commands; except; commands; done", "Run commands, falling back to the except block if any of them fail."),
+	("except", "try; commands; except; commands; done", "Begin the fallback block of a try statement."),
+	("return", "return [n]", "Return from a function with exit status n, or the status of the last command."),
+	("break", "break [n]", "Exit from n enclosing for, while, or until loops."),
+	("continue", "continue", "Skip to the next iteration of the enclosing loop."),
+	("exit", "exit [n]", "Exit the shell with status n, or the status of the last command."),
+	("command", "command name [args ...]", "Run name as a command, ignoring any function or alias of the same name."),
+	("pushd", "pushd [-n] [dir | +n | -n]", "Push dir onto the directory stack and change to it, or swap the top two directories when no dir is given. `pushd +n' or `pushd -n' rotates the nth entry of the stack to the top, and the -n flag skips the change of directory."),
+	("popd", "popd [-n] [+n | -n]", "Pop the top directory off of the directory stack and change to the new top. `popd +n' or `popd -n' removes the nth entry of the stack instead, and the -n flag removes the entry under the top without changing directory."),
+	("dirs", "dirs [-clpv]", "Print the directory stack, starting with the current directory. -v numbers the entries, -p prints one per line, -l leaves HOME unabbreviated, and -c clears the stack."),
+	("setopt", "setopt key=value ...", "Set shell options, like `setopt core.autocd=true'."),
+	("getopt", "getopt key ...", "Print the current value of shell options."),
+	("type", "type name ...", "Describe how each name would be interpreted as a command."),
+	("string", "string name=value ...", "Declare string variables."),
+	("int", "int name=value ...", "Declare integer variables."),
+	("bool", "bool name=value ...", "Declare boolean variables."),
+	("arr", "arr name=(values ...) ...", "Declare array variables."),
+	("float", "float name=value ...", "Declare floating point variables."),
+	("dict", "dict name=value ...", "Declare dictionary variables."),
+	("expr", "expr expression", "Evaluate an arithmetic expression."),
+	("echo", "echo [-neErP] [args ...]", "Write the arguments to standard output, separated by spaces. -n leaves off the newline, -e expands escapes and -E does not, and -r writes to standard error instead. With the xpg_echo shopt, escapes are expanded by default, and in POSIX mode no flags are taken."),
+	("jobs", "jobs [-lpnrs]", "List the jobs in the job table."),
+	("unset", "unset [-fv] name ...", "Remove variables, or functions with -f. Without a flag, a name that is not a variable is removed as a function. Inside a function, unsetting a local brings back the value it was hiding."),
+	("fg", "fg [jobspec]", "Move a job to the foreground."),
+	("bg", "bg [jobspec]", "Resume a stopped job in the background."),
+	("set", "set [-+evxC] [-+o name] [--] [args ...]", "Turn shell flags on with -x or off with +x, or by name with -o name, and replace the positional parameters with args."),
+	("builtin", "builtin name [args ...]", "Run a shell builtin, ignoring any function or alias of the same name."),
+	("test", "test expression", "Evaluate a conditional expression, exiting with 0 if it is true and 1 if it is false."),
+	("[", "[ expression ]", "Evaluate a conditional expression, like `test'. The last argument must be `]'."),
+	("shift", "shift [n]", "Shift the positional parameters to the left by n."),
+	("unalias", "unalias name ...", "Remove aliases."),
+	("alias", "alias [name[=value] ...]", "Define aliases, or print the value of existing ones."),
+	("export", "export [-np] [name[=value] ...]", "Export variables to the environment of child processes. -n removes them from the environment but keeps them as shell variables, and -p prints the exported variables."),
+	("cd", "cd [-L|-P] [dir | +n | -n]", "Change the current directory to dir, or to HOME if no directory is given. `cd -' returns to OLDPWD, and `cd +n' or `cd -n' rotates the nth entry of the directory stack to the top. PWD keeps symlinks in the path unless -P is given."),
+	("readonly", "readonly name[=value] ...", "Mark variables as read-only."),
+	("declare", "declare [-fFpgaAirx] [name[=value] ...]", "Print function definitions with -f, or only function names with -F. -a, -A, -i, -r, and -x give each name the array, associative array, integer, readonly, or export attribute, and -g sets it globally from inside a function. -p prints the variables as declare commands."),
+	("local", "local name[=value] ...", "Declare variables local to the current function."),
+	("trap", "trap [-p] [command signal ...]", "Run command when the shell reaches a signal: DEBUG before each command, with the command in BASH_COMMAND, or RETURN when a function or sourced file finishes. `trap - signal' removes the trap, and -p prints the traps as trap commands."),
+	("node", "node", "Print the parse tree of a command."),
+	("exec", "exec [command [args ...]] [redirections]", "Replace the shell with command, or apply redirections to the shell itself."),
+	("source", "source file [args ...]", "Execute commands from file in the current shell."),
+	("read_func", "read_func file", "Read function definitions from file."),
+	("wait", "wait [-n] [-p var] [jobspec ...]", "Wait for jobs to finish, or for the next job to finish with -n. -p stores the pid of the job that was waited for in var, which is left unset if there was none."),
+	("ulimit", "ulimit [-HSa] [-cfnsu] [limit]", "Print or set resource limits for the shell and its children."),
+	("times", "times", "Print the user and system time used by the shell, and then by its children."),
+	("help", "help [-s] [name ...]", "Print documentation for builtins, or list them all when no name is given."),
+	("caller", "caller [n]", "Print the line, function, and file that the nth function call on the stack came from."),
+	("read", "read [-ers] [-d delim] [-n count] [-t timeout] [-p prompt] [-u fd] [name ...]", "Read a line from standard input, or from fd with -u, and split it into the named variables, or REPLY if none are given. -d reads up to delim instead of a newline, or up to a NUL byte if delim is empty. -e reads the line with the editor when standard input is a terminal."),
+	("shopt", "shopt [-pqsu] [name ...]", "Turn bash-style shell options on with -s or off with -u, or print their state. -q only sets the exit status."),
+	("history", "history [n] | -d offset | -d start-end | --dedup", "Print the command history, or the last n entries. -d deletes an entry, a range of them, or with a negative offset an entry counted back from the newest, and --dedup removes every entry that is repeated later on. Entries are prefixed with their time if HISTTIMEFORMAT is set and core.hist_timestamps was on when they were saved."),
+	("fc", "fc -s [old=new] [prefix]", "Run the last command again, or the last one starting with prefix, after replacing each old with new. `fc -e -' does the same."),
+	("r", "r [old=new] [prefix]", "Run the last command again, like `fc -s'."),
+	("complete", "complete [-pr] [-cdf] [-W wordlist] [-A action] [name ...]", "Set how the arguments of each name are completed: from the words in wordlist, or from file (-f), directory (-d), or command (-c) names. -A takes file, directory, command, function, or variable. -p prints the specs, and -r removes them."),
+	("compgen", "compgen [-cdf] [-W wordlist] [-A action] [word]", "Print the completions that the options, which are the same as for `complete', would offer for word, one per line."),
+	("oxdebug", "oxdebug ast command", "Print the parse tree of command without running it, or where parsing failed. This is meant for debugging the shell, and is left out of the list of builtins."),
+	("oxdump", "oxdump", "Print the shell options, aliases, variables, exported environment, and functions as commands that recreate them when the output is sourced."),
+	(":", ": [args ...]", "Do nothing and succeed. The arguments are still expanded, so `: ${VAR:=default}' assigns a default."),
+	("true", "true", "Do nothing and succeed."),
+	("false", "false", "Do nothing and fail."),
+];
+
+pub fn execute<'a>(help_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = help_call.clone();
+	let mut argv = helper::prepare_argv(help_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(help_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let mut synopsis_only = false;
+	let mut names = vec![];
+	while let Some(arg) = argv.pop_front() {
+		match arg.as_str() {
+			"-s" => synopsis_only = true,
+			_ if arg.starts_with('-') => {
+				return Err(High(SlashErrHigh::syntax_err(format!("Invalid flag in `help' call: {}",arg), blame)))
+			}
+			_ => names.push(arg)
+		}
+	}
+
+	if names.is_empty() {
+		let mut names = BUILTINS.into_iter().filter(|name| !HIDDEN_BUILTINS.contains(name)).collect::<Vec<_>>();
+		names.sort();
+		names.dedup();
+		write!(stdout, "{}", helper::fmt_columns(&names, slash.term_width()))?;
+		return Ok(())
+	}
+
+	for name in names {
+		let Some((_, synopsis, desc)) = HELP_TEXT.iter().find(|entry| entry.0 == name) else {
+			let msg = match helper::closest_match(&name, HELP_TEXT.iter().map(|entry| entry.0), 2, false) {
+				Some(suggestion) => format!("help: no help topic for `{}' (did you mean `{}'?)",name,suggestion),
+				None => format!("help: no help topic for `{}'",name)
+			};
+			return Err(High(SlashErrHigh::exec_err(msg, blame)))
+		};
+		writeln!(stdout, "{}: {}", name, synopsis)?;
+		if !synopsis_only {
+			writeln!(stdout, "    {}", desc)?;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::output_of};
+
+use super::*;
+
+	#[test]
+	fn test_help_documents_all_builtins() {
+		for name in BUILTINS {
+			assert!(HELP_TEXT.iter().any(|entry| entry.0 == name), "No help text for {}", name);
+		}
+	}
+
+	#[test]
+	fn test_help_cd() {
+		let mut slash = Slash::new();
+		let output = output_of("help cd", &mut slash);
+		assert!(output.starts_with("cd: cd [-L|-P] [dir | +n | -n]\n"));
+		assert!(output.lines().nth(1).is_some_and(|desc| !desc.trim().is_empty()));
+
+		let bogus = execute::dispatch::exec_input("help bogus".to_string(), &mut slash);
+		assert!(bogus.is_err());
+		let typo = execute::dispatch::exec_input("help ulimt".to_string(), &mut slash);
+		assert!(typo.is_err_and(|e| e.to_string().contains("did you mean `ulimit'")));
+	}
+}
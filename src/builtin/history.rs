@@ -0,0 +1,214 @@
+use crate::{execute, helper, prelude::*, prompt::history, utils};
+
+/// Prints the commands in the history file, numbered from the oldest, or only the last `n` of them
+/// If `$HISTTIMEFORMAT` is set, entries that were saved with a timestamp are prefixed with their time
+/// `-d` deletes an entry or a `start-end` range of them, and `--dedup` keeps only the latest copy of each command
+pub fn execute<'a>(history_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = history_call.clone();
+	let mut argv = helper::prepare_argv(history_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(history_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let hist_path = history::hist_path(slash);
+	let mut entries = history::read_hist_file(&hist_path).unwrap_or_default();
+	match argv.front().map(|arg| arg.as_str()) {
+		Some("-d") => {
+			argv.pop_front();
+			let Some(arg) = argv.pop_front() else {
+				return Err(High(SlashErrHigh::syntax_err("history: -d: option requires an argument", blame)))
+			};
+			let Some((start, end)) = hist_range(&arg, entries.len()) else {
+				return Err(High(SlashErrHigh::exec_err(format!("history: {}: history position out of range",arg), blame)))
+			};
+			// Both ends are numbered as they were before anything was deleted
+			entries.drain(start - 1..end);
+			history::write_hist_file(&hist_path, &entries)?;
+			slash.set_code(0);
+			return Ok(())
+		}
+		Some("--dedup") => {
+			history::dedup_entries(&mut entries);
+			history::write_hist_file(&hist_path, &entries)?;
+			slash.set_code(0);
+			return Ok(())
+		}
+		_ => {}
+	}
+
+	let count = match argv.pop_front() {
+		Some(arg) => Some(arg.parse::<usize>().map_err(|_| High(SlashErrHigh::syntax_err(format!("Invalid history count: {}",arg), blame)))?),
+		None => None
+	};
+
+	let time_fmt = slash.vars().get_var("HISTTIMEFORMAT").map(|fmt| fmt.to_string());
+	let skip = count.map(|count| entries.len().saturating_sub(count)).unwrap_or(0);
+	for (i, entry) in entries.iter().enumerate().skip(skip) {
+		let time = match (&time_fmt, entry.time) {
+			(Some(fmt), Some(time)) => history::format_time(time, fmt),
+			_ => String::new()
+		};
+		writeln!(stdout, "{:>5}  {}{}", i + 1, time, entry.command)?;
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+/// The first and last history numbers that a `-d` argument covers, if they are all in the history
+/// The argument is a single number, a `start-end` range, or a negative offset that counts back from the newest entry
+fn hist_range(arg: &str, len: usize) -> Option<(usize, usize)> {
+	let (start, end) = match arg.split_once('-') {
+		Some((start, end)) if !start.is_empty() => (start.parse::<usize>().ok()?, end.parse::<usize>().ok()?),
+		Some((_, back)) => {
+			let pos = (len + 1).checked_sub(back.parse::<usize>().ok()?)?;
+			(pos, pos)
+		}
+		None => {
+			let pos = arg.parse::<usize>().ok()?;
+			(pos, pos)
+		}
+	};
+	(1 <= start && start <= end && end <= len).then_some((start, end))
+}
+
+/// Runs the most recent history entry again, or the most recent one starting with a prefix, like `fc -s` or `r`
+/// An `old=new` argument replaces every `old` in the command first. The command is echoed to stderr before it runs
+pub fn reexec<'a>(fc_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = fc_call.clone();
+	let mut argv = helper::prepare_argv(fc_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(fc_call)?;
+	let name = argv.pop_front().unwrap_or_default();
+	if name == "fc" {
+		match argv.pop_front().as_deref() {
+			Some("-s") => {}
+			Some("-e") if argv.front().is_some_and(|editor| editor == "-") => { argv.pop_front(); }
+			_ => return Err(High(SlashErrHigh::syntax_err("fc: only `fc -s' and `fc -e -' are supported", blame)))
+		}
+	}
+
+	let mut subs = vec![];
+	let mut prefix = None;
+	while let Some(arg) = argv.pop_front() {
+		match arg.split_once('=') {
+			Some((old, new)) if prefix.is_none() => subs.push((old.to_string(), new.to_string())),
+			_ if prefix.is_none() => prefix = Some(arg),
+			_ => return Err(High(SlashErrHigh::syntax_err(format!("{}: too many arguments",name), blame)))
+		}
+	}
+
+	let entries = history::read_hist_file(&history::hist_path(slash)).unwrap_or_default();
+	// The line that called this is usually the newest entry, and running it again would never end
+	let is_reexec = |cmd: &str| matches!(cmd.split_whitespace().next(), Some("r" | "fc"));
+	let Some(entry) = entries.iter().rev()
+		.filter(|entry| !is_reexec(&entry.command))
+		.find(|entry| prefix.as_ref().is_none_or(|prefix| entry.command.starts_with(prefix.as_str()))) else {
+		let msg = match prefix {
+			Some(prefix) => format!("{}: no command found starting with `{}'",name,prefix),
+			None => format!("{}: no command found in history",name)
+		};
+		return Err(High(SlashErrHigh::exec_err(msg, blame)))
+	};
+	let mut command = entry.command.clone();
+	for (old, new) in subs.iter().filter(|(old,_)| !old.is_empty()) {
+		command = command.replace(old.as_str(), new);
+	}
+
+	slash.consume_redirs(redirs)?;
+	let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+	writeln!(stderr, "{}", command)?;
+	execute::dispatch::exec_input(command, slash)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{prompt::history::HistEntry, test_util::{lock_process, output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_history_time_format() {
+		// HIST_FILE is exported, which changes it for the whole process
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let hist_path = temp_path("history");
+		let entries = [
+			HistEntry { time: None, dir: None, command: "ls".into() },
+			HistEntry { time: Some(1700000000), dir: None, command: "pwd".into() },
+		];
+		history::write_hist_file(&hist_path, &entries).unwrap();
+		let saved_hist_file = slash.vars().get_evar("HIST_FILE").unwrap_or_default();
+		slash.vars_mut().export_var("HIST_FILE", hist_path.to_str().unwrap());
+
+		let output = output_of("HISTTIMEFORMAT='%s '; history", &mut slash);
+		slash.vars_mut().export_var("HIST_FILE", &saved_hist_file);
+
+		assert_eq!(output, "    1  ls\n    2  1700000000 pwd\n");
+		std::fs::remove_file(&hist_path).unwrap();
+	}
+
+	#[test]
+	fn test_history_delete_and_dedup() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let hist_path = temp_path("history_dedup");
+		let entry = |command: &str| HistEntry { time: None, dir: None, command: command.into() };
+		let mut entries = ["ls", "pwd", "ls", "cd src", "pwd", "echo hi"].map(entry).to_vec();
+		entries[4].time = Some(1700000000);
+		history::write_hist_file(&hist_path, &entries).unwrap();
+		let saved_hist_file = slash.vars().get_evar("HIST_FILE").unwrap_or_default();
+		slash.vars_mut().export_var("HIST_FILE", hist_path.to_str().unwrap());
+		let commands = || history::read_hist_file(&hist_path).unwrap().into_iter().map(|entry| entry.command).collect::<Vec<_>>();
+
+		let dedup = execute::dispatch::exec_input("history --dedup".to_string(), &mut slash);
+		let deduped = history::read_hist_file(&hist_path).unwrap();
+		let range = execute::dispatch::exec_input("history -d 2-3".to_string(), &mut slash);
+		let after_range = commands();
+		let last = execute::dispatch::exec_input("history -d -1".to_string(), &mut slash);
+		let after_last = commands();
+		let out_of_range = execute::dispatch::exec_input("history -d 2".to_string(), &mut slash);
+		slash.vars_mut().export_var("HIST_FILE", &saved_hist_file);
+		dedup.unwrap();
+		range.unwrap();
+		last.unwrap();
+
+		// The last copy of each command is the one that stays, along with its timestamp
+		assert_eq!(deduped.iter().map(|entry| entry.command.as_str()).collect::<Vec<_>>(), ["ls", "cd src", "pwd", "echo hi"]);
+		assert_eq!(deduped[2].time, Some(1700000000));
+		assert_eq!(after_range, ["ls", "echo hi"]);
+		assert_eq!(after_last, ["ls"]);
+		assert!(out_of_range.is_err());
+		assert_eq!(hist_range("3-2", 5), None);
+		assert_eq!(hist_range("0", 5), None);
+		std::fs::remove_file(&hist_path).unwrap();
+	}
+
+	#[test]
+	fn test_reexec_substitution() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let (hist_path, out_path) = (temp_path("reexec"), temp_path("reexec_out"));
+		let out_str = out_path.to_str().unwrap();
+		let entries = [
+			HistEntry { time: None, dir: None, command: format!("echo hello > {out_str}") },
+			HistEntry { time: None, dir: None, command: "true".into() },
+			HistEntry { time: None, dir: None, command: "r".into() },
+		];
+		history::write_hist_file(&hist_path, &entries).unwrap();
+		let saved_hist_file = slash.vars().get_evar("HIST_FILE").unwrap_or_default();
+		slash.vars_mut().export_var("HIST_FILE", hist_path.to_str().unwrap());
+
+		// The command is echoed to stderr before it runs
+		let echoed = output_of("r hello=bye echo 2>&1", &mut slash);
+		assert_eq!(std::fs::read_to_string(&out_path).unwrap(), "bye\n");
+		assert_eq!(echoed, format!("echo bye > {out_str}\n"));
+
+		history::write_hist_file(&hist_path, &[]).unwrap();
+		let empty = execute::dispatch::exec_input("fc -s".to_string(), &mut slash);
+		slash.vars_mut().export_var("HIST_FILE", &saved_hist_file);
+		assert!(empty.is_err());
+		for path in [hist_path, out_path] {
+			std::fs::remove_file(&path).unwrap();
+		}
+	}
+}
@@ -76,46 +76,98 @@ pub fn jobs<'a>(jobs_call: Pair<'a,Rule>,slash: &mut Slash) -> SlashResult<()> {
 		}
 	}
 
-	read_jobs(|j| j.print_jobs(&flags, stdout))??;
+	let width = slash.term_width();
+	read_jobs(|j| j.print_jobs(&flags, width, stdout))??;
 
 	Ok(())
 }
 
-fn parse_job_id<'a>(arg: &str, blame: Pair<'a,Rule>) -> SlashResult<usize> {
+pub fn parse_job_id<'a>(arg: &str, blame: Pair<'a,Rule>) -> SlashResult<usize> {
 	if arg.starts_with('%') {
-		let arg = arg.strip_prefix('%').unwrap();
-		if arg.chars().all(|ch| ch.is_ascii_digit()) {
-			Ok(arg.parse::<usize>().unwrap())
-		} else {
-			let result = write_jobs(|j| {
-				let query_result = j.query(JobID::Command(arg.into()));
-				query_result.map(|job| job.table_id().unwrap())
-			})?;
-			match result {
-				Some(id) => Ok(id),
-				None => Err(High(SlashErrHigh::internal_err("Found a job but no table id in parse_job_id()", blame)))
-			}
-		}
+		read_jobs(|j| j.resolve_jobspec(arg))?.blame(blame)
 	} else if arg.chars().all(|ch| ch.is_ascii_digit()) {
-		let result = write_jobs(|j| {
-			let pgid_query_result = j.query(JobID::Pgid(Pid::from_raw(arg.parse::<i32>().unwrap())));
-			if let Some(job) = pgid_query_result {
-				return Some(job.table_id().unwrap())
-			}
-
-			if arg.parse::<i32>().unwrap() > 0 {
-				let table_id_query_result = j.query(JobID::TableID(arg.parse::<usize>().unwrap()));
-				return table_id_query_result.map(|job| job.table_id().unwrap());
-			}
-
-			None
+		// A bare number is a pid or pgid if one matches, and a job number otherwise
+		let pid = Pid::from_raw(arg.parse::<i32>().map_err(|_| High(SlashErrHigh::syntax_err(format!("Invalid job id: {}",arg), blame.clone())))?);
+		let result = read_jobs(|j| {
+			j.query(JobID::Pgid(pid))
+				.or_else(|| j.query(JobID::Pid(pid)))
+				.and_then(|job| job.table_id())
+				.map(Ok)
+				.unwrap_or_else(|| j.resolve_jobspec(&format!("%{}",arg)))
 		})?;
+		result.blame(blame)
+	} else {
+		Err(High(SlashErrHigh::syntax_err(format!("Invalid job id: {}",arg), blame)))
+	}
+}
 
-		match result {
-			Some(id) => Ok(id),
-			None => Err(High(SlashErrHigh::internal_err("Found a job but no table id in parse_job_id()", blame)))
+#[cfg(test)]
+mod tests {
+	use crate::shellenv::{ChildProc, JobBuilder, JobTable};
+
+use super::*;
+
+	fn synthetic_table() -> JobTable {
+		let mut table = JobTable::new();
+		let cmds = ["sleep 10", "vim notes.txt", "sleep 20"];
+		for (i, cmd) in cmds.iter().enumerate() {
+			// Pids that can't exist, so nothing real gets touched
+			let pid = Pid::from_raw(1_000_000_000 + i as i32);
+			let child = ChildProc::new(pid, Some(cmd), None).unwrap();
+			let job = JobBuilder::new().with_pgid(pid).with_children(vec![child]).build();
+			table.insert_job(job, true).unwrap();
 		}
-	} else {
-		Err(High(SlashErrHigh::syntax_err(format!("Invalid fg argument: {}",arg), blame)))
+		table
+	}
+
+	#[test]
+	fn test_jobs_columns() {
+		let table = synthetic_table();
+		let mut wide = vec![];
+		table.print_jobs(&JobCmdFlags::empty(), 200, &mut wide).unwrap();
+		let wide = String::from_utf8(wide).unwrap();
+		assert_eq!(wide.lines().count(), 1);
+		assert!(wide.contains("sleep 10") && wide.contains("vim notes.txt") && wide.contains("sleep 20"));
+
+		// Too narrow for two jobs side by side
+		let mut narrow = vec![];
+		table.print_jobs(&JobCmdFlags::empty(), 20, &mut narrow).unwrap();
+		assert_eq!(String::from_utf8(narrow).unwrap().lines().count(), 3);
+	}
+
+	#[test]
+	fn test_jobspec_number() {
+		let table = synthetic_table();
+		assert_eq!(table.resolve_jobspec("%1").unwrap(), 0);
+		assert_eq!(table.resolve_jobspec("%3").unwrap(), 2);
+		assert!(table.resolve_jobspec("%0").is_err());
+		assert!(table.resolve_jobspec("%4").is_err());
+	}
+
+	#[test]
+	fn test_jobspec_current_and_previous() {
+		let table = synthetic_table();
+		assert_eq!(table.resolve_jobspec("%+").unwrap(), 2);
+		assert_eq!(table.resolve_jobspec("%%").unwrap(), 2);
+		assert_eq!(table.resolve_jobspec("%").unwrap(), 2);
+		assert_eq!(table.resolve_jobspec("%-").unwrap(), 1);
+		assert!(JobTable::new().resolve_jobspec("%+").is_err());
+	}
+
+	#[test]
+	fn test_jobspec_prefix() {
+		let table = synthetic_table();
+		assert_eq!(table.resolve_jobspec("%vim").unwrap(), 1);
+		assert!(table.resolve_jobspec("%sleep").is_err_and(|e| e.to_string().contains("ambiguous")));
+		assert!(table.resolve_jobspec("%notes").is_err());
+	}
+
+	#[test]
+	fn test_jobspec_substring() {
+		let table = synthetic_table();
+		assert_eq!(table.resolve_jobspec("%?notes").unwrap(), 1);
+		assert_eq!(table.resolve_jobspec("%?20").unwrap(), 2);
+		assert!(table.resolve_jobspec("%?sleep").is_err_and(|e| e.to_string().contains("ambiguous")));
+		assert!(table.resolve_jobspec("%?xyz").is_err());
 	}
 }
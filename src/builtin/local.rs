@@ -0,0 +1,103 @@
+use crate::{helper, pest_ext::ARG_RULES, prelude::*, shellenv::SlashVal};
+
+/// Declares variables in the current function, giving each one its value or an empty string
+/// A function's variables are all thrown away when it returns, so this is what puts back the caller's `IFS`, `PATH`, and so on
+pub fn execute<'a>(local_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = local_call.clone();
+	if !slash.meta().call_stack().iter().any(|frame| !frame.is_source) {
+		return Err(High(SlashErrHigh::exec_err("local: can only be used in a function", blame)))
+	}
+	let mut argv = local_call.filter(&ARG_RULES[..]);
+	while let Some(arg) = argv.pop_front() {
+		match arg.as_rule() {
+			Rule::arg_assign => {
+				let var_name = helper::assign_target(&arg, blame.clone())?;
+				helper::check_ident(var_name.as_str(), blame.clone())?;
+				check_readonly(var_name.as_str(), blame.clone(), slash)?;
+				let val = match arg.scry(&[Rule::word,Rule::array][..]) {
+					Some(val) if val.as_rule() == Rule::array => SlashVal::parse(helper::try_expansion(slash,val)?.as_str())?,
+					Some(val) => SlashVal::String(helper::try_expansion(slash,val)?.trim_quotes().to_string()),
+					None => SlashVal::String(String::new())
+				};
+				let prev = slash.vars().get_var(var_name.as_str());
+				slash.meta_mut().shadow_var(var_name.as_str(), prev);
+				slash.vars_mut().set_var(var_name.as_str(), val);
+			}
+			Rule::word if arg.as_str() == "local" => continue,
+			Rule::word => {
+				let var_name = arg.as_str();
+				helper::check_ident(var_name, blame.clone())?;
+				check_readonly(var_name, blame.clone(), slash)?;
+				let prev = slash.vars().get_var(var_name);
+				slash.meta_mut().shadow_var(var_name, prev);
+				if slash.vars().get_var(var_name).is_none() {
+					slash.vars_mut().set_var(var_name, SlashVal::String(String::new()));
+				}
+			}
+			_ => {
+				let msg = String::from("Expected a variable name in local args, got this");
+				return Err(High(SlashErrHigh::syntax_err(msg, arg)))
+			}
+		}
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+/// A local can't shadow a readonly variable, since it would be assigned when the function returns
+fn check_readonly<'a>(name: &str, blame: Pair<'a,Rule>, slash: &Slash) -> SlashResult<()> {
+	if slash.vars().is_readonly(name) {
+		return Err(High(SlashErrHigh::exec_err(format!("local: {}: readonly variable",name), blame)))
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{lock_process, output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_local_ifs_restored() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		slash.vars_mut().export_var("SLASH_LOCAL_TEST", "outer");
+
+		let output = output_of("IFS=' '; f() { local IFS=:; set -- a b c; echo \"$*\"; }; f; g() { local SLASH_LOCAL_TEST=inner; return 3; }; g", &mut slash);
+
+		assert_eq!(output, "a:b:c\n");
+		assert_eq!(slash.vars().get_var("IFS"), Some(SlashVal::String(" ".into())));
+		// An exported variable has to be put back in the process environment too, even after an early return
+		assert_eq!(slash.get_status(), 3);
+		assert_eq!(env::var("SLASH_LOCAL_TEST").unwrap(), "outer");
+		assert_eq!(slash.vars().get_evar("SLASH_LOCAL_TEST"), Some("outer".into()));
+
+		assert!(execute::dispatch::exec_input("local foo=bar".to_string(), &mut slash).is_err());
+	}
+
+	#[test]
+	fn test_local_path() {
+		use std::os::unix::fs::PermissionsExt;
+
+		// The function's PATH is exported into the process environment while it runs
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let dir = temp_path("local_path");
+		let dir_str = dir.to_str().unwrap();
+		std::fs::create_dir_all(&dir).unwrap();
+		let cmd_path = dir.join("slash_fake_cmd");
+		std::fs::write(&cmd_path, "#!/bin/sh\necho fake\n").unwrap();
+		std::fs::set_permissions(&cmd_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+		let orig_path = env::var("PATH").unwrap();
+
+		let input = format!("f() {{ local PATH={dir_str}:$PATH; slash_fake_cmd; echo \"$PATH\"; }}; f; slash_fake_cmd 2> /dev/null; echo $?");
+		let output = output_of(&input, &mut slash);
+
+		// Once the function returns, the command can't be found anymore
+		assert_eq!(output, format!("fake\n{dir_str}:{orig_path}\n127\n"));
+		assert_eq!(env::var("PATH").unwrap(), orig_path);
+		assert_eq!(slash.vars().get_evar("PATH"), Some(orig_path));
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -13,7 +13,25 @@ pub mod control;
 pub mod job;
 pub mod cmd_override;
 pub mod exec;
+pub mod wait;
+pub mod ulimit;
+pub mod declare;
+pub mod help;
+pub mod caller;
+pub mod set;
+pub mod read;
+pub mod history;
+pub mod local;
+pub mod times;
+pub mod complete;
+pub mod unset;
+pub mod trap;
+pub mod oxdebug;
+pub mod oxdump;
 
-pub const BUILTINS: [&str; 43] = [
-	"try", "except", "return", "break", "continue", "exit", "command", "pushd", "popd", "setopt", "getopt", "type", "string", "int", "bool", "arr", "float", "dict", "expr", "echo", "jobs", "unset", "fg", "bg", "set", "builtin", "test", "[", "shift", "unalias", "alias", "export", "cd", "readonly", "declare", "local", "unset", "trap", "node", "exec", "source", "read_func", "wait",
+pub const BUILTINS: [&str; 60] = [
+	"try", "except", "return", "break", "continue", "exit", "command", "pushd", "popd", "dirs", "setopt", "getopt", "type", "string", "int", "bool", "arr", "float", "dict", "expr", "echo", "jobs", "unset", "fg", "bg", "set", "builtin", "test", "[", "shift", "unalias", "alias", "export", "cd", "readonly", "declare", "local", "unset", "trap", "node", "exec", "source", "read_func", "wait", "ulimit", "times", "help", "caller", "read", "shopt", "history", "fc", "r", "complete", "compgen", "oxdebug", "oxdump", ":", "true", "false",
 ];
+
+/// Builtins for debugging the shell, which `help` leaves out of its listing
+pub const HIDDEN_BUILTINS: [&str; 1] = ["oxdebug"];
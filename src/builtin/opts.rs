@@ -2,13 +2,13 @@ use crate::pest_ext::ARG_RULES;
 use crate::prelude::*;
 
 use crate::utils::SmartFD;
-use crate::{error::{SlashErr::*, SlashErrHigh}, helper::{self}, shellenv::Slash, SlashResult};
+use crate::{error::{SlashErr::*, SlashErrExt, SlashErrHigh}, helper::{self}, shellenv::Slash, SlashResult};
 
 pub fn setopt<'a>(setopt_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let mut argv = setopt_call.filter(&ARG_RULES[..]);
 	while let Some(arg) = argv.pop_front() {
 		if arg.as_rule() == Rule::arg_assign {
-			let opt_path = arg.scry(Rule::var_ident).unpack()?.as_str();
+			let opt_path = arg.scry(&[Rule::opt_path,Rule::var_ident][..]).unpack()?.as_str();
 			let val = match arg.scry(Rule::word) {
 				Some(pair) => helper::try_expansion(slash,pair)?,
 				None => String::new()
@@ -35,9 +35,72 @@ pub fn getopt<'a>(getopt_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 	Ok(())
 }
 
+/// Bash option names understood by `shopt`, and the shopt keys they map onto
+/// There is no `histappend`, since each command is added to the end of the history file as soon as it runs
+const BASH_SHOPTS: [(&str, &str); 8] = [
+	("autocd", "core.autocd"),
+	("cdspell", "core.cdspell"),
+	("dotglob", "core.dotglob"),
+	("expand_aliases", "core.expand_aliases"),
+	("extglob", "core.extglob"),
+	("nocaseglob", "core.nocaseglob"),
+	("nullglob", "core.nullglob"),
+	("xpg_echo", "core.xpg_echo"),
+];
+
+/// Bash-style `shopt`
+/// `-s` and `-u` turn options on and off, `-q` only sets the exit status, and `-p` prints options as `shopt` commands
+/// Without `-s` or `-u`, the exit status is 0 only if every named option is on
+pub fn shopt<'a>(shopt_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = shopt_call.clone();
+	let mut argv = helper::prepare_argv(shopt_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(shopt_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = SmartFD::new(1)?;
+
+	let (flags, names) = helper::parse_flags("supq", argv, true).map_err(|e| e.into_err("shopt", blame.clone()))?;
+	// Whichever of `-s` and `-u` comes last wins
+	let enable = flags.iter().filter_map(|(flag,_)| match flag {
+		's' => Some(true),
+		'u' => Some(false),
+		_ => None
+	}).last();
+	let quiet = flags.has('q');
+	let reusable = flags.has('p');
+
+	let names = if names.is_empty() {
+		BASH_SHOPTS.iter().map(|(name,_)| name.to_string()).collect()
+	} else {
+		names
+	};
+	let mut all_on = true;
+	for name in names {
+		let Some((_, key)) = BASH_SHOPTS.iter().find(|(opt,_)| *opt == name) else {
+			return Err(High(SlashErrHigh::exec_err(format!("shopt: {}: invalid shell option name",name), blame)))
+		};
+		if let Some(val) = enable {
+			slash.meta_mut().set_shopt(key, &val.to_string()).blame(blame.clone())?;
+			continue
+		}
+		let is_on = slash.meta().get_shopt(key)?.parse::<bool>().unwrap_or(false);
+		all_on &= is_on;
+		if quiet {
+			continue
+		}
+		if reusable {
+			writeln!(stdout,"shopt {} {}",if is_on { "-s" } else { "-u" },name)?;
+		} else {
+			writeln!(stdout,"{:<15}\t{}",name,if is_on { "on" } else { "off" })?;
+		}
+	}
+	slash.set_code(if all_on { 0 } else { 1 });
+	Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-	use crate::execute;
+	use crate::{execute, test_util::{output_of, temp_path}};
 
 use super::*;
 
@@ -51,4 +114,39 @@ use super::*;
 
 		assert_eq!(opt,"bar".to_string())
 	}
+
+	#[test]
+	fn test_shopt() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("shopt -s dotglob".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("shopt -q dotglob".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 0);
+		assert_eq!(slash.meta().get_shopt("core.dotglob").unwrap(), "true");
+
+		execute::dispatch::exec_input("shopt -u dotglob".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("shopt -q dotglob".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+
+		assert_eq!(output_of("shopt -p dotglob autocd", &mut slash), "shopt -u dotglob\nshopt -s autocd\n");
+
+		assert!(execute::dispatch::exec_input("shopt -s bogus".to_string(), &mut slash).is_err());
+	}
+
+	#[test]
+	fn test_glob_shopts() {
+		let mut slash = Slash::new();
+		let dir = temp_path("glob_shopts");
+		std::fs::create_dir(&dir).unwrap();
+		std::fs::write(dir.join("Foo.txt"), "").unwrap();
+		let dir = dir.to_str().unwrap();
+
+		let input = format!("count() {{ echo $#; }}; count {dir}/*.none; count {dir}/f*.TXT; echo {dir}/f*.TXT", dir = dir);
+		assert_eq!(output_of(&input, &mut slash), format!("1\n1\n{}/f*.TXT\n", dir));
+
+		execute::dispatch::exec_input("shopt -s nullglob nocaseglob".to_string(), &mut slash).unwrap();
+		let input = format!("count() {{ echo $#; }}; count {dir}/*.none; count \"{dir}/*.none\"; echo {dir}/f*.TXT", dir = dir);
+		assert_eq!(output_of(&input, &mut slash), format!("0\n1\n{}/Foo.txt\n", dir));
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
 }
@@ -0,0 +1,73 @@
+use crate::{helper, prelude::*, utils};
+
+/// Tools for looking into the shell itself, which are left out of the `help` listing
+/// `oxdebug ast 'command'` prints the parse tree of the command without running it
+pub fn execute<'a>(oxdebug_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = oxdebug_call.clone();
+	let mut argv = helper::prepare_argv(oxdebug_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(oxdebug_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	match argv.pop_front().as_deref() {
+		Some("ast") => {
+			if argv.is_empty() {
+				return Err(High(SlashErrHigh::syntax_err("oxdebug: ast: expected a command to parse", blame)))
+			}
+			let input = Vec::from(argv).join(" ");
+			// The error from pest points at the spot in the input where parsing failed
+			let tree = SlashParse::parse(Rule::main, &input).map_err(|e| Low(SlashErrLow::Parse(e.to_string())))?;
+			for pair in tree {
+				write!(stdout, "{}", fmt_tree(pair, 0))?;
+			}
+		}
+		Some(cmd) => {
+			return Err(High(SlashErrHigh::syntax_err(format!("oxdebug: unknown subcommand `{}', expected `ast'",cmd), blame)))
+		}
+		None => return Err(High(SlashErrHigh::syntax_err("oxdebug: expected a subcommand", blame)))
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+/// Prints a pair and everything under it, one rule per line and indented by depth
+/// Rules without any inner pairs are printed with the text that they matched
+fn fmt_tree(pair: Pair<Rule>, depth: usize) -> String {
+	if pair.as_rule() == Rule::EOI {
+		return String::new()
+	}
+	let indent = "  ".repeat(depth);
+	let mut inner = pair.clone().into_inner().peekable();
+	if inner.peek().is_none() {
+		return format!("{}{:?} {:?}\n", indent, pair.as_rule(), pair.as_str())
+	}
+	let mut output = format!("{}{:?}\n", indent, pair.as_rule());
+	for child in inner {
+		output.push_str(&fmt_tree(child, depth + 1));
+	}
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_oxdebug_ast() {
+		let mut slash = Slash::new();
+		let path = temp_path("oxdebug");
+		let path_str = path.to_str().unwrap();
+
+		// The command is only printed, so the redirection inside of it never creates the file
+		let output = output_of(&format!("oxdebug ast 'a | b > {path_str}'"), &mut slash);
+		assert!(output.lines().any(|line| line.trim() == "pipeline"), "{}", output);
+		assert!(output.contains("word \"a\""), "{}", output);
+		assert!(!path.exists());
+
+		let bad = execute::dispatch::exec_input("oxdebug ast 'if true; then'".to_string(), &mut slash);
+		assert!(bad.is_err_and(|e| e.to_string().contains("1:")));
+	}
+}
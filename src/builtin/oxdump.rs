@@ -0,0 +1,108 @@
+use crate::{builtin::declare, expand::misc::quote_for_reuse, helper, prelude::*, shellenv::SlashVal, shopt::SHOPT_KEYS, utils};
+
+/// Prints the state of the shell as commands that recreate it when they are sourced
+/// This covers the shell options, aliases, variables with their attributes, the exported environment, and functions
+pub fn execute<'a>(oxdump_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = oxdump_call.clone();
+	let mut argv = helper::prepare_argv(oxdump_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(oxdump_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	if let Some(arg) = argv.pop_front() {
+		return Err(High(SlashErrHigh::syntax_err(format!("oxdump: unexpected argument `{}'",arg), blame)))
+	}
+	write!(stdout, "{}", dump_state(slash)?)?;
+	slash.set_code(0);
+	Ok(())
+}
+
+fn dump_state(slash: &Slash) -> SlashResult<String> {
+	let mut dump = String::new();
+	// Every option is printed as `setopt`, which covers the ones `shopt` knows about along with the rest
+	let shopts = slash.meta().borrow_shopts();
+	let mut opts = SHOPT_KEYS.iter().map(|key| Ok((key.to_string(), shopts.get(key)?))).collect::<SlashResult<Vec<_>>>()?;
+	opts.extend(shopts.prompt.custom.entries());
+	for (key, val) in opts {
+		let val = match val {
+			SlashVal::String(val) => quote_for_reuse(&val),
+			_ => val.to_string()
+		};
+		dump.push_str(&format!("setopt {}={}\n", key, val));
+	}
+
+	let aliases = slash.logic().borrow_aliases();
+	let mut names = aliases.keys().collect::<Vec<_>>();
+	names.sort();
+	for name in names {
+		dump.push_str(&format!("alias {}={}\n", name, quote_for_reuse(&aliases[name])));
+	}
+
+	let vars = slash.vars().vars();
+	let mut names = vars.keys().collect::<Vec<_>>();
+	names.sort();
+	for name in &names {
+		dump.push_str(&declare::render_var_with(name, &vars[*name], slash.vars().get_flags(name), quote_for_reuse));
+		dump.push('\n');
+	}
+
+	// Exported shell variables were already printed with their attributes
+	let evars = slash.vars().borrow_evars();
+	let mut env_names = evars.keys()
+		.filter(|name| !vars.contains_key(*name) && helper::is_valid_ident(name))
+		.collect::<Vec<_>>();
+	env_names.sort();
+	for name in env_names {
+		dump.push_str(&format!("export {}={}\n", name, quote_for_reuse(&evars[name])));
+	}
+
+	let funcs = slash.logic().borrow_functions();
+	let mut names = funcs.keys().collect::<Vec<_>>();
+	names.sort();
+	for name in names {
+		dump.push_str(&declare::render_func(name, &funcs[name], &slash.logic().get_func_params(name)));
+		dump.push('\n');
+	}
+	Ok(dump)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, shellenv::SlashVal, test_util::{lock_process, output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_oxdump_round_trip() {
+		// The exported variable goes into the environment of the whole process
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let path = temp_path("oxdump");
+		let path_str = path.to_str().unwrap();
+
+		let dump = output_of("shopt -s extglob; setopt prompt.edit_mode=emacs; setopt core.max_hist=50; \
+			alias ll='ls -l'; alias both=$'echo \\'it\\' \"x\"'; declare -i count=3; msg='say \"hi\" $5'; arr list=[one,two]; \
+			export SLASH_OXDUMP_X=exported; greet() { echo \"hello $1\"; }; oxdump", &mut slash);
+		std::fs::write(&path, dump).unwrap();
+
+		// Nothing carries over into a new shell except the environment of the process, so the exported variable is taken out of it first
+		let mut fresh = Slash::new();
+		fresh.vars_mut().unset_evar("SLASH_OXDUMP_X");
+		execute::dispatch::exec_input(format!("source {path_str}"), &mut fresh).unwrap();
+		for name in ["count", "msg", "list"] {
+			assert_eq!(fresh.vars().get_var(name), slash.vars().get_var(name), "{}", name);
+			assert_eq!(fresh.vars().get_flags(name), slash.vars().get_flags(name), "{}", name);
+		}
+		assert_eq!(fresh.vars().get_var("msg"), Some(SlashVal::String("say \"hi\" $5".into())));
+		assert_eq!(fresh.vars().get_evar("SLASH_OXDUMP_X"), Some("exported".into()));
+		assert_eq!(fresh.logic().get_alias("ll"), Some("ls -l".into()));
+		assert_eq!(fresh.logic().get_alias("both"), slash.logic().get_alias("both"));
+		assert_eq!(fresh.logic().get_alias("both"), Some("echo 'it' \"x\"".into()));
+		assert_eq!(fresh.logic().get_func("greet"), slash.logic().get_func("greet"));
+		assert_eq!(fresh.meta().get_shopt("core.extglob").unwrap(), "true");
+		assert_eq!(fresh.meta().get_shopt("prompt.edit_mode").unwrap(), "emacs");
+		assert_eq!(fresh.meta().get_shopt("core.max_hist").unwrap(), "50");
+		std::fs::remove_file(&path).unwrap();
+	}
+}
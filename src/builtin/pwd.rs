@@ -10,8 +10,7 @@ pub fn execute<'a>(pwd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()
 
 	let redirs = slash.ctx_mut().take_redirs();
 	if !redirs.is_empty() {
-		let mut redirs = slash.ctx_mut().consume_redirs();
-		redirs.activate()?;
+		slash.activate_redirs()?;
 	}
 
 	if let Ok(pwd) = env::var("PWD") {
@@ -0,0 +1,364 @@
+use std::{os::fd::{BorrowedFd, RawFd}, time::{Duration, Instant}};
+
+use nix::{poll::{poll, PollFd, PollFlags, PollTimeout}, sys::termios::{self, LocalFlags, SetArg, SpecialCharacterIndices}, unistd::isatty};
+use rustyline::error::ReadlineError;
+
+use crate::{helper, prelude::*, prompt::rl_init, shellenv::SlashVal, utils};
+
+/// Exit status for a read that runs out of time, matching bash
+const TIMEOUT_CODE: i32 = 128 + 14;
+
+struct ReadOpts {
+	raw: bool,
+	silent: bool,
+	/// Whether to read the line with the line editor, which only happens if stdin is a terminal
+	edit: bool,
+	timeout: Option<Duration>,
+	max_chars: Option<usize>,
+	prompt: Option<String>,
+	/// The byte that ends the input, which is a newline unless `-d` is given
+	delim: u8,
+	/// The file descriptor to read from, which is stdin unless `-u` is given
+	fd: RawFd
+}
+
+enum ReadEnd {
+	Delim,
+	Count,
+	Eof,
+	Timeout,
+	Interrupted
+}
+
+/// Reads a line from stdin and splits it into the given variables, or `REPLY` if none are given
+/// `-t` gives up after a number of seconds, `-n` returns after a number of characters, and `-s` turns off echo
+/// `-u` reads from another file descriptor instead, like one opened with `exec 3<file`
+/// `-d` reads up to another delimiter instead of a newline, and `-d ''` reads up to a NUL byte, for input from things like `find -print0`
+/// `-e` reads the line with the same editor as the prompt, with its history and completion, as long as stdin is a terminal
+pub fn execute<'a>(read_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = read_call.clone();
+	let mut argv = helper::prepare_argv(read_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(read_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let (flags, names) = helper::parse_flags("rset:n:p:u:d:", argv, true).map_err(|e| e.into_err("read", blame.clone()))?;
+	let names = Vec::from(names);
+	let mut opts = ReadOpts { raw: flags.has('r'), silent: flags.has('s'), edit: flags.has('e'), timeout: None, max_chars: None, prompt: None, delim: b'\n', fd: STDIN_FILENO };
+	for (flag, val) in flags.iter() {
+		let Some(val) = val else {
+			continue
+		};
+		let invalid = || High(SlashErrHigh::syntax_err(format!("Invalid value for `-{}' in `read' call: {}",flag,val), blame.clone()));
+		match flag {
+			't' => {
+				let secs = val.parse::<f64>().ok().filter(|secs| *secs >= 0.0).ok_or_else(invalid)?;
+				opts.timeout = Some(Duration::from_secs_f64(secs));
+			}
+			'n' => opts.max_chars = Some(val.parse::<usize>().map_err(|_| invalid())?),
+			'u' => {
+				let fd = val.parse::<RawFd>().map_err(|_| invalid())?;
+				// Any fd that is open will do, whether it came from `exec` or a redirection on an enclosing command
+				if fcntl(fd, F_GETFD).is_err() {
+					return Err(High(SlashErrHigh::exec_err(format!("read: {}: invalid file descriptor",fd), blame)))
+				}
+				opts.fd = fd;
+			}
+			// Only the first byte is used as the delimiter, and an empty one means NUL
+			'd' => opts.delim = val.bytes().next().unwrap_or(0),
+			_ => opts.prompt = Some(val.clone())
+		}
+	}
+
+	// Nothing is read if any of the names can't be assigned to
+	if let Some(name) = names.iter().find(|name| slash.vars().is_readonly(name)) {
+		return Err(High(SlashErrHigh::exec_err(format!("read: {}: readonly variable",name), blame)))
+	}
+
+	// `read -t 0` only checks whether there is any input waiting
+	if opts.timeout.is_some_and(|timeout| timeout.is_zero()) {
+		let code = if input_ready(opts.fd, PollTimeout::ZERO)? { 0 } else { 1 };
+		slash.set_code(code);
+		return Ok(())
+	}
+
+	// The editor can't count characters, hide what is typed, or give up after a timeout, so those reads are left to the terminal
+	let use_editor = opts.edit && !opts.silent && opts.max_chars.is_none() && opts.timeout.is_none()
+		&& opts.fd == STDIN_FILENO && isatty(STDIN_FILENO).unwrap_or(false);
+	let (line, end) = if use_editor {
+		read_with_editor(&opts, slash)?
+	} else {
+		if let Some(prompt) = &opts.prompt {
+			if isatty(opts.fd).unwrap_or(false) {
+				let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+				write!(stderr, "{}", prompt)?;
+			}
+		}
+
+		let saved_termios = set_read_mode(&opts);
+		let result = read_input(&opts);
+		if let Some(termios) = saved_termios {
+			termios::tcsetattr(std::io::stdin(), SetArg::TCSANOW, &termios).ok();
+		}
+		result?
+	};
+
+	assign_fields(&line, names, slash);
+	let code = match end {
+		ReadEnd::Delim | ReadEnd::Count => 0,
+		ReadEnd::Eof => 1,
+		ReadEnd::Timeout => TIMEOUT_CODE,
+		ReadEnd::Interrupted => 130
+	};
+	slash.set_code(code);
+	Ok(())
+}
+
+/// Takes the terminal out of canonical mode for `-n` and turns off echo for `-s`
+/// Returns the original settings so that they can be restored
+fn set_read_mode(opts: &ReadOpts) -> Option<termios::Termios> {
+	if (opts.max_chars.is_none() && !opts.silent) || opts.fd != STDIN_FILENO || !isatty(STDIN_FILENO).unwrap_or(false) {
+		return None
+	}
+	let saved = termios::tcgetattr(std::io::stdin()).ok()?;
+	let mut new = saved.clone();
+	if opts.max_chars.is_some() {
+		new.local_flags &= !LocalFlags::ICANON;
+		new.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+		new.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+	}
+	if opts.silent {
+		new.local_flags &= !LocalFlags::ECHO;
+	}
+	termios::tcsetattr(std::io::stdin(), SetArg::TCSANOW, &new).ok()?;
+	Some(saved)
+}
+
+fn input_ready(fd: RawFd, timeout: PollTimeout) -> SlashResult<bool> {
+	// The fd was checked to be open before the read started, and outlives this call
+	let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+	let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+	loop {
+		match poll(&mut fds, timeout) {
+			Ok(ready) => return Ok(ready > 0),
+			Err(nix::errno::Errno::EINTR) => continue,
+			Err(e) => return Err(Low(SlashErrLow::ErrNo(e)))
+		}
+	}
+}
+
+/// Reads one byte at a time so that nothing past the delimiter is consumed, leaving the rest for whatever reads the fd next
+fn read_byte(fd: RawFd, deadline: Option<Instant>) -> SlashResult<Option<u8>> {
+	if let Some(deadline) = deadline {
+		let remaining = deadline.saturating_duration_since(Instant::now());
+		let timeout = PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX);
+		if remaining.is_zero() || !input_ready(fd, timeout)? {
+			return Err(Low(SlashErrLow::ErrNo(nix::errno::Errno::ETIMEDOUT)))
+		}
+	}
+	let mut buf = [0u8; 1];
+	loop {
+		match nix::unistd::read(fd, &mut buf) {
+			Ok(0) => return Ok(None),
+			Ok(_) => return Ok(Some(buf[0])),
+			Err(nix::errno::Errno::EINTR) => continue,
+			Err(e) => return Err(Low(SlashErrLow::ErrNo(e)))
+		}
+	}
+}
+
+/// Reads a line with a fresh copy of the prompt's editor, so the line can be edited and completed like a command
+/// The line is not saved to the history file
+fn read_with_editor(opts: &ReadOpts, slash: &Slash) -> SlashResult<(String, ReadEnd)> {
+	let mut slash_clone = slash.clone();
+	let mut rl = rl_init::init_prompt(&mut slash_clone)?;
+	match rl.readline(opts.prompt.as_deref().unwrap_or("")) {
+		Ok(line) => {
+			if opts.raw {
+				return Ok((line, ReadEnd::Delim))
+			}
+			// The editor already ended the line, so a backslash only escapes the character after it
+			let mut unescaped = String::new();
+			let mut chars = line.chars();
+			while let Some(ch) = chars.next() {
+				match ch {
+					'\\' => unescaped.extend(chars.next()),
+					_ => unescaped.push(ch)
+				}
+			}
+			Ok((unescaped, ReadEnd::Delim))
+		}
+		Err(ReadlineError::Eof) => Ok((String::new(), ReadEnd::Eof)),
+		Err(ReadlineError::Interrupted) => Ok((String::new(), ReadEnd::Interrupted)),
+		Err(e) => Err(Low(SlashErrLow::InternalErr(format!("rustyline error: {}",e))))
+	}
+}
+
+fn read_input(opts: &ReadOpts) -> SlashResult<(String, ReadEnd)> {
+	let deadline = opts.timeout.map(|timeout| Instant::now() + timeout);
+	let mut bytes = vec![];
+	let mut num_chars = 0;
+	let mut escaped = false;
+	let end = loop {
+		if opts.max_chars.is_some_and(|max| num_chars >= max) {
+			break ReadEnd::Count
+		}
+		let byte = match read_byte(opts.fd, deadline) {
+			Ok(Some(byte)) => byte,
+			Ok(None) => break ReadEnd::Eof,
+			Err(Low(SlashErrLow::ErrNo(nix::errno::Errno::ETIMEDOUT))) => break ReadEnd::Timeout,
+			Err(e) => return Err(e)
+		};
+		if escaped {
+			escaped = false;
+			// A backslash before a newline continues the line
+			if byte == b'\n' {
+				continue
+			}
+		} else if byte == b'\\' && !opts.raw {
+			escaped = true;
+			continue
+		} else if byte == opts.delim {
+			break ReadEnd::Delim
+		} else if byte == 0 {
+			// Like bash, NUL bytes can only be delimiters, since variables can't hold them
+			continue
+		}
+		bytes.push(byte);
+		// Continuation bytes of a multi-byte character don't count towards `-n`
+		if byte & 0xC0 != 0x80 {
+			let width = match byte {
+				0xF0.. => 3,
+				0xE0.. => 2,
+				0xC0.. => 1,
+				_ => 0
+			};
+			for _ in 0..width {
+				match read_byte(opts.fd, deadline) {
+					Ok(Some(byte)) => bytes.push(byte),
+					Ok(None) | Err(_) => break
+				}
+			}
+			num_chars += 1;
+		}
+	};
+	Ok((String::from_utf8_lossy(&bytes).to_string(), end))
+}
+
+/// Splits `line` on `$IFS`, giving each name one field and the last name the rest of the line
+fn assign_fields(line: &str, mut names: Vec<String>, slash: &mut Slash) {
+	if names.is_empty() {
+		// REPLY gets the line exactly as it was read
+		slash.vars_mut().set_var("REPLY", SlashVal::String(line.to_string()));
+		return
+	}
+	let ifs = slash.vars().get_var("IFS").map(|ifs| ifs.to_string()).unwrap_or_else(|| " \t\n".into());
+	let is_sep = |ch: char| ifs.contains(ch);
+	let last = names.pop().unwrap();
+	let mut rest = line.trim_start_matches(is_sep);
+	for name in names {
+		let (field, remainder) = rest.split_once(is_sep).unwrap_or((rest, ""));
+		slash.vars_mut().set_var(&name, SlashVal::String(field.to_string()));
+		rest = remainder.trim_start_matches(is_sep);
+	}
+	slash.vars_mut().set_var(&last, SlashVal::String(rest.trim_end_matches(is_sep).to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{capture, lock_process, output_of, temp_path}};
+	use nix::unistd::{pipe, write};
+	use std::os::fd::AsRawFd;
+
+use super::*;
+
+	#[test]
+	fn test_read_timeout() {
+		let mut slash = Slash::new();
+		let (read_end, write_end) = pipe().unwrap();
+		// The pipe is only stdin for the length of each group
+		let read = |cmd: &str, slash: &mut Slash| capture(&format!("{{ {cmd}; }} <&{}", read_end.as_raw_fd()), slash).0;
+
+		// Nothing has been written yet, so `read -t 0` should fail right away
+		let no_input = read("read -t 0 var", &mut slash);
+		let no_input_code = slash.get_status();
+		let started = Instant::now();
+		let timed_out = read("read -t 0.1 var", &mut slash);
+		let timed_out_code = slash.get_status();
+		let waited = started.elapsed();
+
+		write(&write_end, b"foo bar baz\nabc").unwrap();
+		let line = read("read first rest", &mut slash);
+		let count = read("read -n 2 -t 1 chars", &mut slash);
+
+		no_input.unwrap();
+		assert_eq!(no_input_code, 1);
+		timed_out.unwrap();
+		assert_eq!(timed_out_code, TIMEOUT_CODE);
+		assert!(waited < Duration::from_secs(2));
+		line.unwrap();
+		assert_eq!(slash.vars().get_var("first"), Some(SlashVal::String("foo".into())));
+		assert_eq!(slash.vars().get_var("rest"), Some(SlashVal::String("bar baz".into())));
+		count.unwrap();
+		assert_eq!(slash.vars().get_var("chars"), Some(SlashVal::String("ab".into())));
+	}
+
+	#[test]
+	fn test_read_nul_delim() {
+		let mut slash = Slash::new();
+		let (read_end, write_end) = pipe().unwrap();
+		let long_field = "x".repeat(10000);
+		write(&write_end, format!("one\0two words\0{long_field}\0last").as_bytes()).unwrap();
+		drop(write_end);
+
+		let input = format!("{{ read -d '' a; read -d '' b c; read -d '' long; read -d '' d; }} <&{}", read_end.as_raw_fd());
+		capture(&input, &mut slash).0.unwrap();
+
+		assert_eq!(slash.vars().get_var("a"), Some(SlashVal::String("one".into())));
+		assert_eq!(slash.vars().get_var("b"), Some(SlashVal::String("two".into())));
+		assert_eq!(slash.vars().get_var("c"), Some(SlashVal::String("words".into())));
+		assert_eq!(slash.vars().get_var("long"), Some(SlashVal::String(long_field)));
+		// The last record has no delimiter after it, so it is still assigned, but read fails
+		assert_eq!(slash.vars().get_var("d"), Some(SlashVal::String("last".into())));
+		assert_eq!(slash.get_status(), 1);
+	}
+
+	#[test]
+	fn test_read_from_fd() {
+		// fd 5 belongs to the whole process until it is closed again
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let in_path = temp_path("read_fd");
+		std::fs::write(&in_path, "first line\nsecond line\n").unwrap();
+
+		let input = format!("exec 5<{}; while read -u 5 line; do echo \"got $line\"; done; exec 5>&-", in_path.display());
+		assert_eq!(output_of(&input, &mut slash), "got first line\ngot second line\n");
+		assert_eq!(slash.get_status(), 0);
+		// Nothing in the tests opens an fd this high
+		assert!(execute::dispatch::exec_input("read -u 999 line".to_string(), &mut slash).is_err());
+
+		// The fd can also come from a redirection on the loop, which is only opened once
+		let input = format!("while read -u 3 line; do echo \"got $line\"; done 3<{}", in_path.display());
+		assert_eq!(output_of(&input, &mut slash), "got first line\ngot second line\n");
+		std::fs::remove_file(&in_path).unwrap();
+	}
+
+	#[test]
+	fn test_read_edit_from_pipe() {
+		let mut slash = Slash::new();
+		let (read_end, write_end) = pipe().unwrap();
+		write(&write_end, b"one two\\ three\nnext\n").unwrap();
+		drop(write_end);
+
+		// A pipe is not a terminal, so `-e` falls back to reading the fd, and nothing past the line is taken
+		let read = |cmd: &str, slash: &mut Slash| capture(&format!("{{ {cmd}; }} <&{} 2> /dev/null", read_end.as_raw_fd()), slash).0;
+		let edited = read("read -e -p '> ' first rest", &mut slash);
+		let next = read("read -e", &mut slash);
+
+		edited.unwrap();
+		assert_eq!(slash.vars().get_var("first"), Some(SlashVal::String("one".into())));
+		assert_eq!(slash.vars().get_var("rest"), Some(SlashVal::String("two three".into())));
+		next.unwrap();
+		assert_eq!(slash.vars().get_var("REPLY"), Some(SlashVal::String("next".into())));
+	}
+}
@@ -0,0 +1,148 @@
+use crate::{helper, prelude::*, shellenv::EnvFlags};
+
+/// Maps `set` flag letters to the shell flags they toggle
+/// Flags that the shell doesn't act on are left out, so that setting one is an error instead of doing nothing
+fn flag_for(letter: char) -> Option<EnvFlags> {
+	let flag = match letter {
+		'e' => EnvFlags::EXIT_ON_ERROR,
+		'v' => EnvFlags::PRINT_INPUT,
+		'x' => EnvFlags::STACK_TRACE,
+		'C' => EnvFlags::NO_OVERWRITE,
+		_ => return None
+	};
+	Some(flag)
+}
+
+/// Maps the long names used with `set -o` to the shell flags they toggle
+fn flag_for_name(name: &str) -> Option<EnvFlags> {
+	let flag = match name {
+		"errexit" => EnvFlags::EXIT_ON_ERROR,
+		"verbose" => EnvFlags::PRINT_INPUT,
+		"xtrace" => EnvFlags::STACK_TRACE,
+		"noclobber" => EnvFlags::NO_OVERWRITE,
+		"pipefail" => EnvFlags::PIPEFAIL,
+		"posix" => EnvFlags::POSIX,
+		_ => return None
+	};
+	Some(flag)
+}
+
+/// `-x` turns a flag on and `+x` turns it off, and `-o name` or `+o name` does the same by name
+/// Any arguments after the flags, or after `--`, replace the positional parameters
+pub fn execute<'a>(set_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = set_call.clone();
+	let mut argv = helper::prepare_argv(set_call, slash)?;
+	argv.pop_front();
+
+	// A trailing `--` with nothing after it still clears the positional parameters
+	let ends_with_dashes = argv.back().is_some_and(|arg| arg == "--");
+	let (flags, params) = helper::parse_flags("+evxCo:", argv, true).map_err(|e| e.into_err("set", blame.clone()))?;
+	for (enable, (letter, name)) in flags.iter_signed() {
+		let flag = match name {
+			Some(name) => flag_for_name(name),
+			None => flag_for(*letter)
+		};
+		let Some(flag) = flag else {
+			let name = name.clone().unwrap_or(letter.to_string());
+			return Err(High(SlashErrHigh::syntax_err(format!("Invalid option name in `set' call: {}",name), blame)))
+		};
+		slash.meta_mut().mod_flags(|flags| flags.set(flag, enable));
+	}
+
+	if !params.is_empty() || ends_with_dashes {
+		slash.vars_mut().set_pos_params(params);
+	}
+	Ok(())
+}
+
+/// Drops the first `n` positional parameters, or the first one if no count is given
+/// Fails without changing anything if there are fewer than `n` parameters
+pub fn shift<'a>(shift_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = shift_call.clone();
+	let mut argv = helper::prepare_argv(shift_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(shift_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let count = match argv.pop_front() {
+		Some(arg) => arg.parse::<usize>().map_err(|_| High(SlashErrHigh::syntax_err(format!("Invalid shift count: {}",arg), blame)))?,
+		None => 1
+	};
+	if count > slash.vars().borrow_pos_params().len() {
+		slash.set_code(1);
+		return Ok(())
+	}
+	for _ in 0..count {
+		slash.vars_mut().pos_param_popfront();
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_shift_after_set() {
+		let mut slash = Slash::new();
+		let output = output_of("set -- x y z; shift; echo \"$@\"; f() { shift 2; echo \"$@\" $#; }; f a b c d", &mut slash);
+		assert_eq!(output, "y z\nc d 2\n");
+
+		// The function's parameters don't leak out, and shifting too far changes nothing
+		assert_eq!(slash.vars().get_param("@"), Some("y z".into()));
+		execute::dispatch::exec_input("shift 3".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+		assert_eq!(slash.vars().get_param("#"), Some("2".into()));
+		execute::dispatch::exec_input("set --".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_param("#"), Some("0".into()));
+	}
+
+	#[test]
+	fn test_pipefail_status() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("false | true | false".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+		execute::dispatch::exec_input("false | true".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 0);
+
+		execute::dispatch::exec_input("set -o pipefail".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("false | true".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+		execute::dispatch::exec_input("sh -c 'exit 3' | false | true".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+		execute::dispatch::exec_input("false | sh -c 'exit 3' | true".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 3);
+
+		execute::dispatch::exec_input("set +o pipefail".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("false | true".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 0);
+	}
+
+	#[test]
+	fn test_set_flags() {
+		let mut slash = Slash::new();
+		assert_eq!(output_of("{ set -x; echo 'a b' c; set +x; } 2>&1; echo d", &mut slash), "+ echo 'a b' c\na b c\n+ set +x\nd\n");
+
+		// Flags that wouldn't do anything are refused
+		for input in ["set -k", "set -o monitor", "set -u", "set -o noglob"] {
+			assert!(execute::dispatch::exec_input(input.to_string(), &mut slash).is_err(), "{}", input);
+		}
+	}
+
+	#[test]
+	fn test_verbose_input() {
+		let mut slash = Slash::new();
+		let script = temp_path("verbose");
+		std::fs::write(&script, "X=\"$HOME\"\necho hi # not expanded\nf() {\n\techo in f\n}\nset +v\necho hidden\n").unwrap();
+
+		let output = output_of(&format!("{{ set -v; source {}; }} 2>&1", script.to_str().unwrap()), &mut slash);
+
+		// The lines come out as they were written, before their comments are stripped or their variables expanded,
+		// and each command's lines come out right before it runs, so nothing after `set +v` is echoed
+		assert_eq!(output, "X=\"$HOME\"\necho hi # not expanded\nhi\nf() {\n\techo in f\n}\nset +v\nhidden\n");
+		std::fs::remove_file(&script).unwrap();
+	}
+}
@@ -17,3 +17,24 @@ pub fn execute<'a>(src_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()
 	}
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::test_util::{output_of, temp_path};
+
+use super::*;
+
+	#[test]
+	fn test_source_lineno() {
+		let mut slash = Slash::new();
+		let script = temp_path("source_lineno");
+		std::fs::write(&script, "true\n# a comment\necho $LINENO\n").unwrap();
+
+		let input = format!("true\nsource {}\ntrue\necho $LINENO", script.to_str().unwrap());
+		let output = output_of(&input, &mut slash);
+
+		// Lines in the sourced file count from its own first line, and the caller's count picks up where it left off
+		assert_eq!(output, "3\n4\n");
+		std::fs::remove_file(&script).unwrap();
+	}
+}
@@ -0,0 +1,56 @@
+use crate::{helper, prelude::*, utils};
+
+/// Prints the user and system time used by the shell, then by its children, like bash's `times`
+pub fn execute<'a>(times_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = times_call.clone();
+	let mut argv = helper::prepare_argv(times_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(times_call)?;
+	argv.pop_front();
+	if let Some(arg) = argv.pop_front() {
+		return Err(High(SlashErrHigh::syntax_err(format!("times: unexpected argument: {}",arg), blame)))
+	}
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+		let usage = read_rusage(who)?;
+		writeln!(stdout, "{} {}", fmt_time(usage.ru_utime), fmt_time(usage.ru_stime))?;
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+pub fn read_rusage(who: libc::c_int) -> SlashResult<libc::rusage> {
+	let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+	if unsafe { libc::getrusage(who, &mut usage) } != 0 {
+		return Err(Low(SlashErrLow::from_io()))
+	}
+	Ok(usage)
+}
+
+/// Formats a time like `1m2.345s`
+fn fmt_time(time: libc::timeval) -> String {
+	let millis = time.tv_usec / 1000;
+	format!("{}m{}.{:03}s", time.tv_sec / 60, time.tv_sec % 60, millis)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::test_util::output_of;
+
+use super::*;
+
+	#[test]
+	fn test_times_fields() {
+		let mut slash = Slash::new();
+		let output = output_of("times", &mut slash);
+		assert_eq!(slash.get_status(), 0);
+
+		let fields = output.split_whitespace().collect::<Vec<_>>();
+		assert_eq!(fields.len(), 4);
+		for field in fields {
+			let (mins, secs) = field.strip_suffix('s').and_then(|time| time.split_once('m')).unwrap();
+			assert!(mins.parse::<u64>().is_ok() && secs.parse::<f64>().is_ok(), "Bad time field: {}", field);
+		}
+	}
+}
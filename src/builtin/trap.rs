@@ -0,0 +1,137 @@
+use crate::{helper, prelude::*, shellenv::EnvFlags, signal, utils};
+
+/// Pseudo-signals raised by the shell itself rather than the kernel
+const PSEUDO_SIGNALS: [&str; 4] = ["EXIT", "ERR", "DEBUG", "RETURN"];
+
+/// The name a trap is kept under, like `USR1` for `SIGUSR1`, `usr1`, or `10`, along with the real signal behind it if there is one
+fn parse_signal(arg: &str) -> Option<(String, Option<Signal>)> {
+	if arg == "0" {
+		return Some(("EXIT".into(), None))
+	}
+	let upper = arg.to_uppercase();
+	let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+	if PSEUDO_SIGNALS.contains(&name) {
+		return Some((name.to_string(), None))
+	}
+	let sig = match arg.parse::<i32>() {
+		Ok(num) => Signal::try_from(num).ok()?,
+		Err(_) => format!("SIG{}",name).parse::<Signal>().ok()?
+	};
+	Some((sig.as_str()["SIG".len()..].to_string(), Some(sig)))
+}
+
+/// Sets the command that runs when the shell reaches each signal, or removes it with `trap - signal ...`
+/// With no arguments, or with `-p`, the traps are printed as the commands that would set them again
+pub fn execute<'a>(trap_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = trap_call.clone();
+	let mut argv = helper::prepare_argv(trap_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(trap_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let (flags, mut argv) = helper::parse_flags("p", argv, true).map_err(|e| e.into_err("trap", blame.clone()))?;
+	let print = flags.has('p');
+	if print || argv.is_empty() {
+		let mut signals = if argv.is_empty() {
+			slash.logic().borrow_traps().keys().cloned().collect::<Vec<_>>()
+		} else {
+			argv.into_iter().filter_map(|arg| parse_signal(&arg).map(|(name,_)| name)).collect()
+		};
+		signals.sort();
+		for signal in signals {
+			if let Some(handler) = slash.logic().get_trap(&signal) {
+				writeln!(stdout, "trap -- '{}' {}", handler.replace('\'', "'\\''"), signal)?;
+			}
+		}
+		slash.set_code(0);
+		return Ok(())
+	}
+
+	let handler = argv.pop_front().unwrap_or_default();
+	if argv.is_empty() {
+		return Err(High(SlashErrHigh::syntax_err("trap: expected a signal after the command", blame)))
+	}
+	let interactive = slash.meta().flags().contains(EnvFlags::INTERACTIVE);
+	for arg in argv {
+		let Some((name, sig)) = parse_signal(&arg) else {
+			return Err(High(SlashErrHigh::exec_err(format!("trap: {}: invalid signal specification",arg), blame)))
+		};
+		// The shell needs SIGCHLD to keep track of its jobs
+		if matches!(sig, Some(Signal::SIGKILL | Signal::SIGSTOP | Signal::SIGCHLD)) {
+			return Err(High(SlashErrHigh::exec_err(format!("trap: {}: cannot be trapped",arg), blame)))
+		}
+		if handler == "-" {
+			slash.logic_mut().remove_trap(&name);
+			if let Some(sig) = sig {
+				signal::reset_trap_handler(sig, interactive);
+			}
+		} else {
+			slash.logic_mut().set_trap(&name, &handler);
+			if let Some(sig) = sig {
+				signal::set_trap_handler(sig, handler.is_empty());
+			}
+		}
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::output_of};
+
+use super::*;
+
+	#[test]
+	fn test_debug_trap() {
+		let mut slash = Slash::new();
+		// The handler's own echo would set the trap off again if it were not guarded
+		let output = output_of("trap 'echo \"$BASH_COMMAND\"' DEBUG; true; echo hi | cat > /dev/null; false; trap - DEBUG; true", &mut slash);
+
+		assert_eq!(output, "true\necho hi | cat > /dev/null\nfalse\ntrap - DEBUG\n");
+		assert!(slash.logic().get_trap("DEBUG").is_none());
+
+		assert!(execute::dispatch::exec_input("trap 'true' BOGUS".to_string(), &mut slash).is_err());
+		assert!(execute::dispatch::exec_input("trap 'true' KILL".to_string(), &mut slash).is_err());
+	}
+
+	#[test]
+	fn test_debug_trap_count() {
+		let mut slash = Slash::new();
+		// One tick for each of `true`, `false`, `test`, and the `trap` that removes the handler
+		let output = output_of("ticks=; trap 'ticks=\"${ticks}.\"' DEBUG; true; false; test -n hi; trap - DEBUG; echo \"$ticks\"", &mut slash);
+		assert_eq!(output, "....\n");
+	}
+
+	#[test]
+	fn test_err_and_exit_traps() {
+		let mut slash = Slash::new();
+		// Conditions and the commands before `&&` or `||` don't count as failures
+		let output = output_of("trap 'echo err' ERR; false; true; if false; then true; fi; false || true; trap - ERR; false", &mut slash);
+		assert_eq!(output, "err\n");
+
+		let output = output_of("( trap 'echo bye' EXIT; echo hi ); echo after", &mut slash);
+		assert_eq!(output, "hi\nbye\nafter\n");
+		assert!(slash.logic().get_trap("EXIT").is_none());
+	}
+
+	#[test]
+	fn test_signal_trap() {
+		let mut slash = Slash::new();
+		// The handler runs before the next command once the signal has arrived
+		let output = output_of("trap 'echo got usr1' USR1; sh -c 'kill -USR1 $PPID'; echo after; trap - usr1", &mut slash);
+		assert_eq!(output, "got usr1\nafter\n");
+		assert!(slash.logic().get_trap("USR1").is_none());
+		assert_eq!(output_of("trap '' USR2; trap -p SIGUSR2; trap - 12", &mut slash), "trap -- '' USR2\n");
+	}
+
+	#[test]
+	fn test_return_trap() {
+		let mut slash = Slash::new();
+		let output = output_of("trap 'echo \"returned $X\"' RETURN; f() { X=inner; false; }; f; trap - RETURN; f", &mut slash);
+
+		// The handler runs before the function returns, so it still sees the function's variables
+		assert_eq!(output, "returned inner\n");
+	}
+}
@@ -0,0 +1,114 @@
+use crate::{helper, prelude::*, utils};
+
+/// (flag, description, resource, unit size in bytes)
+const LIMITS: [(char, &str, libc::__rlimit_resource_t, u64); 5] = [
+	('c', "core file size (blocks)", libc::RLIMIT_CORE, 1024),
+	('f', "file size (blocks)", libc::RLIMIT_FSIZE, 1024),
+	('n', "open files", libc::RLIMIT_NOFILE, 1),
+	('s', "stack size (kbytes)", libc::RLIMIT_STACK, 1024),
+	('u', "max user processes", libc::RLIMIT_NPROC, 1),
+];
+
+pub fn execute<'a>(ulimit_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = ulimit_call.clone();
+	let mut argv = helper::prepare_argv(ulimit_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(ulimit_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let (flags, operands) = helper::parse_flags("HSacfnsu", argv, true).map_err(|e| e.into_err("ulimit", blame.clone()))?;
+	let hard = flags.has('H');
+	let soft = flags.has('S');
+	let all = flags.has('a');
+	// The last resource flag given is the one that is used
+	let resource = flags.iter().filter_map(|(flag,_)| LIMITS.iter().position(|limit| limit.0 == *flag)).last();
+	let mut new_limit = None;
+	for arg in operands {
+		if arg == "unlimited" {
+			new_limit = Some(libc::RLIM_INFINITY);
+		} else if let Ok(value) = arg.parse::<u64>() {
+			new_limit = Some(value);
+		} else {
+			return Err(High(SlashErrHigh::syntax_err(format!("Invalid limit: {}",arg), blame)))
+		}
+	}
+
+	// File size is the default, like in bash
+	let index = resource.unwrap_or(1);
+
+	if all {
+		for (flag, desc, res, unit) in LIMITS {
+			let limit = get_limit(res, hard).blame(blame.clone())?;
+			writeln!(stdout, "{:<32}(-{}) {}", desc, flag, fmt_limit(limit, unit))?;
+		}
+		return Ok(())
+	}
+
+	let (_, _, res, unit) = LIMITS[index];
+	match new_limit {
+		Some(value) => {
+			let value = if value == libc::RLIM_INFINITY { value } else { value.saturating_mul(unit) };
+			// Neither flag given means both limits are set
+			let (set_soft, set_hard) = if !hard && !soft { (true, true) } else { (soft, hard) };
+			set_limit(res, value, set_soft, set_hard).blame(blame)?;
+		}
+		None => {
+			let limit = get_limit(res, hard).blame(blame)?;
+			writeln!(stdout, "{}", fmt_limit(limit, unit))?;
+		}
+	}
+	Ok(())
+}
+
+fn fmt_limit(limit: u64, unit: u64) -> String {
+	if limit == libc::RLIM_INFINITY {
+		"unlimited".into()
+	} else {
+		(limit / unit).to_string()
+	}
+}
+
+fn read_rlimit(resource: libc::__rlimit_resource_t) -> SlashResult<libc::rlimit> {
+	let mut rlim = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+	if unsafe { libc::getrlimit(resource, &mut rlim) } != 0 {
+		return Err(Low(SlashErrLow::from_io()))
+	}
+	Ok(rlim)
+}
+
+pub fn get_limit(resource: libc::__rlimit_resource_t, hard: bool) -> SlashResult<u64> {
+	let rlim = read_rlimit(resource)?;
+	Ok(if hard { rlim.rlim_max } else { rlim.rlim_cur })
+}
+
+pub fn set_limit(resource: libc::__rlimit_resource_t, value: u64, soft: bool, hard: bool) -> SlashResult<()> {
+	let mut rlim = read_rlimit(resource)?;
+	if soft && !hard && value > rlim.rlim_max {
+		return Err(Low(SlashErrLow::ExecFailed(format!("Soft limit {} exceeds the hard limit {}",value,fmt_limit(rlim.rlim_max, 1)))))
+	}
+	if soft {
+		rlim.rlim_cur = value;
+	}
+	if hard {
+		rlim.rlim_max = value;
+	}
+	if unsafe { libc::setrlimit(resource, &rlim) } != 0 {
+		return Err(Low(SlashErrLow::ExecFailed(format!("Failed to set limit: {}",io::Error::last_os_error()))))
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::test_util::output_of;
+
+use super::*;
+
+	#[test]
+	fn test_ulimit_nofile() {
+		let mut slash = Slash::new();
+		let expected = fmt_limit(get_limit(libc::RLIMIT_NOFILE, false).unwrap(), 1);
+		assert_eq!(output_of("ulimit -n", &mut slash).trim(), expected);
+	}
+}
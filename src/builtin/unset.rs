@@ -0,0 +1,53 @@
+use crate::{helper, prelude::*};
+
+/// Removes variables, or functions with `-f`
+/// Without a flag, a name that isn't a variable is taken to be a function, like in bash
+/// Inside a function, unsetting one of its locals brings back the value that the local was hiding
+pub fn execute<'a>(unset_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = unset_call.clone();
+	let mut argv = helper::prepare_argv(unset_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(unset_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let (flags, names) = helper::parse_flags("vf", argv, true).map_err(|e| e.into_err("unset", blame.clone()))?;
+	let (vars, funcs) = (flags.has('v'), flags.has('f'));
+
+	for name in names {
+		let is_var = slash.vars().get_var(&name).is_some() || slash.vars().get_evar(&name).is_some();
+		if funcs || (!vars && !is_var && slash.logic().get_func(&name).is_some()) {
+			slash.logic_mut().remove_func(&name);
+			continue
+		}
+		if slash.vars().is_readonly(&name) {
+			return Err(High(SlashErrHigh::exec_err(format!("unset: {}: cannot unset: readonly variable",name), blame)))
+		}
+		match slash.meta_mut().unshadow_var(&name) {
+			Some(Some(hidden)) => slash.vars_mut().set_var(&name, hidden),
+			_ => helper::unset_var_conflicts(slash, &name)?
+		}
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, shellenv::SlashVal, test_util::output_of};
+
+use super::*;
+
+	#[test]
+	fn test_unset_local_reveals_global() {
+		let mut slash = Slash::new();
+		let output = output_of("X=global; f() { local X=inner; echo \"$X\"; unset X; echo \"$X\"; unset X; echo \"[$X]\"; }; f", &mut slash);
+
+		// The second unset has no local left to remove, so it takes the global, which comes back once the function returns
+		assert_eq!(output, "inner\nglobal\n[]\n");
+		assert_eq!(slash.vars().get_var("X"), Some(SlashVal::String("global".into())));
+
+		execute::dispatch::exec_input("g() { true; }; unset g; unset Y; declare -r Z=1".to_string(), &mut slash).unwrap();
+		assert!(slash.logic().get_func("g").is_none());
+		assert!(execute::dispatch::exec_input("unset Z".to_string(), &mut slash).is_err());
+	}
+}
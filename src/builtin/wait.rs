@@ -0,0 +1,153 @@
+use nix::sys::signal::SigSet;
+
+use crate::{builtin::job, helper, prelude::*, shellenv::{disable_reaping, enable_reaping, write_jobs, EnvFlags, Job, JobID, SlashVal}, signal};
+
+/// Waits for background jobs to finish, setting `$?` to the exit code of the job that was waited for
+/// `-n` returns as soon as any one of them finishes, and `-p var` stores the pid of that job in `var`
+pub fn execute<'a>(wait_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = wait_call.clone();
+	let mut argv = helper::prepare_argv(wait_call.clone(), slash)?;
+	let redirs = helper::prepare_redirs(wait_call)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let (flags, operands) = helper::parse_flags("np:", argv, true).map_err(|e| e.into_err("wait", blame.clone()))?;
+	let wait_any = flags.has('n');
+	let pid_var = flags.value('p').map(|var| var.to_string());
+	if let Some(var) = &pid_var {
+		if !helper::is_valid_ident(var) {
+			return Err(High(SlashErrHigh::syntax_err(format!("wait: -p: `{}' is not a valid identifier",var), blame)))
+		}
+		if slash.vars().is_readonly(var) {
+			return Err(High(SlashErrHigh::exec_err(format!("wait: {}: readonly variable",var), blame)))
+		}
+	}
+	let targets = operands.iter().map(|arg| job::parse_job_id(arg, blame.clone())).collect::<SlashResult<Vec<_>>>()?;
+
+	// The SIGCHLD handler would race us for the exit statuses, so we poll the children ourselves
+	// SIGCHLD stays blocked between polls, and is only let through while we sleep waiting for it
+	let pipefail = slash.meta().flags().contains(EnvFlags::PIPEFAIL);
+	disable_reaping();
+	let mask = signal::block_sigchld();
+	let result = if wait_any {
+		wait_next(&targets, pipefail, mask)
+	} else {
+		wait_all(&targets, pipefail, mask)
+	};
+	signal::restore_sigmask(mask);
+	enable_reaping()?;
+
+	let (code, pid) = result?;
+	if let Some(var) = pid_var {
+		// Like bash, the variable is left unset when there was no job to wait for
+		match pid {
+			Some(pid) => slash.vars_mut().set_var(&var, SlashVal::Int(pid.as_raw())),
+			None => slash.vars_mut().unset_var(&var)
+		}
+	}
+	slash.set_code(code);
+	Ok(())
+}
+
+/// Polls the job table once, returning the jobs that have finished and whether any job has yet to finish
+/// Stopped jobs haven't finished, since they can still be continued
+fn poll_jobs(targets: &[usize]) -> SlashResult<(Vec<usize>,bool)> {
+	write_jobs(|j| {
+		let mut finished = vec![];
+		let mut pending = false;
+		for job in j.mut_jobs().iter_mut().flatten() {
+			let id = job.table_id().unwrap();
+			if !targets.is_empty() && !targets.contains(&id) {
+				continue
+			}
+			job.poll_children()?;
+			if job.is_done() {
+				finished.push(id);
+			} else {
+				pending = true;
+			}
+		}
+		Ok((finished,pending))
+	})?
+}
+
+fn take_job(id: usize) -> SlashResult<Option<Job>> {
+	write_jobs(|j| j.remove_job(JobID::TableID(id)))
+}
+
+/// The exit code of a finished job, and the pid of its last process, which is what `$!` held for it
+fn job_result(job: Option<Job>, pipefail: bool) -> (i32, Option<Pid>) {
+	match job {
+		Some(job) => (job.get_exit_code(pipefail), job.get_pids().last().copied()),
+		None => (0, None)
+	}
+}
+
+/// Blocks until any one of the targeted jobs finishes, and returns its exit code and pid
+/// Only the job that finished is removed from the job table
+fn wait_next(targets: &[usize], pipefail: bool, mask: SigSet) -> SlashResult<(i32, Option<Pid>)> {
+	loop {
+		let (finished,pending) = poll_jobs(targets)?;
+		if let Some(id) = finished.first() {
+			let job = take_job(*id)?;
+			return Ok(job_result(job, pipefail))
+		}
+		if !pending {
+			return Ok((127, None))
+		}
+		signal::suspend_until_sigchld(mask);
+	}
+}
+
+/// Blocks until all of the targeted jobs have finished
+/// Returns the exit code and pid of the last targeted job, or zero and no pid if no jobs were given
+fn wait_all(targets: &[usize], pipefail: bool, mask: SigSet) -> SlashResult<(i32, Option<Pid>)> {
+	while poll_jobs(targets)?.1 {
+		signal::suspend_until_sigchld(mask);
+	}
+	let mut result = (0, None);
+	for id in poll_jobs(targets)?.0 {
+		let job = take_job(id)?;
+		if targets.last() == Some(&id) {
+			result = job_result(job, pipefail);
+		}
+	}
+	Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, Instant};
+
+	use crate::{execute, shellenv::read_jobs};
+
+use super::*;
+
+	#[test]
+	fn test_wait_next() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("sleep 0.1 &".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("sleep 2 &".to_string(), &mut slash).unwrap();
+		let slow_pid = slash.vars().get_param("!").unwrap().parse::<i32>().unwrap();
+		let start = Instant::now();
+		execute::dispatch::exec_input("wait -n".to_string(), &mut slash).unwrap();
+		assert!(start.elapsed() < Duration::from_secs(1));
+		assert_eq!(slash.get_status(), 0);
+		assert!(read_jobs(|j| j.query(JobID::Pid(Pid::from_raw(slow_pid))).is_some()).unwrap());
+		execute::dispatch::exec_input("wait".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("wait -n".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 127);
+
+		// The jobs are targeted by pid, so jobs started elsewhere can't be the ones that finish
+		execute::dispatch::exec_input("sleep 2 &".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("slow=$!".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("sh -c 'sleep 0.2; exit 3' &".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("fast=$!; wait -n -p finished $slow $fast".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 3);
+		assert_eq!(slash.vars().get_var("finished").unwrap().to_string(), slash.vars().get_var("fast").unwrap().to_string());
+		execute::dispatch::exec_input("wait -p finished $slow".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("finished").unwrap().to_string(), slash.vars().get_var("slow").unwrap().to_string());
+		execute::dispatch::exec_input("wait -n -p finished".to_string(), &mut slash).unwrap();
+		assert!(slash.vars().get_var("finished").is_none());
+	}
+}
@@ -105,6 +105,7 @@ pub enum SlashErrLow {
 
 	// Not actual errors, used to propagate logic from commands like `exit` and `return`
 	CleanExit(i32),
+	Interrupted, // Ctrl-C while the shell itself was running commands
 	FuncReturn(i32),
 	LoopCont,
 	LoopBreak(i32),
@@ -133,6 +134,7 @@ impl Display for SlashErrLow {
 			SlashErrLow::LoopCont => write!(f, "Found continue outside of loop"),
 			SlashErrLow::LoopBreak(_) => write!(f, "Found break outside of loop"),
 			SlashErrLow::CleanExit(_) => write!(f, ""),
+			SlashErrLow::Interrupted => write!(f, ""),
 		}
 	}
 }
@@ -198,3 +200,29 @@ pub fn infer_parse_err(input: &str, err: pest::error::Error<Rule>) -> String {
 
 	err_msg
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, shellenv::Slash};
+
+use super::*;
+
+	#[test]
+	fn test_errors_render_alike() {
+		let mut slash = Slash::new();
+		// A builtin blames the command it was called with
+		let builtin_err = execute::dispatch::exec_input("shift abc".to_string(), &mut slash).unwrap_err();
+		// Input that doesn't parse has no pair to blame, but pest still points at where it went wrong
+		let parse_err = execute::dispatch::exec_input("echo $(".to_string(), &mut slash).unwrap_err();
+		assert!(matches!(builtin_err, High(_)));
+		assert!(matches!(parse_err, Low(SlashErrLow::Parse(_))));
+
+		let builtin_msg = builtin_err.to_string();
+		let parse_msg = parse_err.to_string();
+		assert!(builtin_msg.contains("--> 1:1"), "{}", builtin_msg);
+		assert!(builtin_msg.contains("shift abc"));
+		assert!(parse_msg.starts_with("Parse Error: "), "{}", parse_msg);
+		assert!(parse_msg.contains("--> 1:"));
+		assert!(parse_msg.contains("echo $("));
+	}
+}
@@ -1,17 +1,27 @@
+use std::collections::BTreeMap;
+
 use crate::{error::{SlashErr::*, SlashErrExt}, expand, helper, prelude::*, shellenv::SlashVal};
 
 use super::dispatch;
 
 pub fn exec_assignment<'a>(ass: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	if let Some(elem) = ass.scry(Rule::elem_assign) {
+		return exec_elem_assign(elem, slash)
+	}
 	let cmd = ass.scry(Rule::cmd_list);
 	let blame = ass.clone();
 	let var_name: String = ass.scry(Rule::var_ident).unpack()?.as_str().to_string();
+	helper::check_ident(&var_name, blame.clone())?;
+	if slash.vars().is_readonly(&var_name) {
+		return Err(High(SlashErrHigh::exec_err(format!("{}: readonly variable",var_name), blame)))
+	}
 	let assign_type = ass.scry(&[
 		Rule::increment,
 		Rule::decrement,
 		Rule::plus_assign,
 		Rule::minus_assign,
 		Rule::std_assign][..]).unpack()?;
+	// The right hand side is expanded, but never word split or globbed
 	let val = ass.scry(Rule::word).map(|pr| helper::try_expansion(slash,pr).unwrap_or_default()).unwrap_or_default();
 	let vars = slash.vars_mut();
 	match assign_type.as_rule() {
@@ -83,3 +93,132 @@ pub fn exec_assignment<'a>(ass: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult
 	slash.set_code(0);
 	Ok(())
 }
+
+/// Handles `name[key]=value`, creating an indexed array if `name` is unset and the key is a number, and an associative array otherwise
+/// Indexed arrays can be assigned to as well, as long as the key is a number
+fn exec_elem_assign<'a>(elem: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = elem.clone();
+	let name = elem.scry(Rule::var_ident_plain).unpack()?.as_str().to_string();
+	helper::check_ident(&name, blame.clone())?;
+	if slash.vars().is_readonly(&name) {
+		return Err(High(SlashErrHigh::exec_err(format!("{}: readonly variable",name), blame)))
+	}
+	let key = elem.scry(Rule::assoc_key).unpack()?.as_str().to_string();
+	let key = expand::misc::expand_key(&key, slash)?;
+	let val = match elem.scry(Rule::word) {
+		Some(pair) => helper::try_expansion(slash,pair)?,
+		None => String::new()
+	};
+	let val = SlashVal::parse(&val)?;
+
+	let vars = slash.vars_mut();
+	match vars.get_var_mut(&name) {
+		Some(SlashVal::Dict(dict)) => {
+			dict.insert(key, val);
+		}
+		Some(SlashVal::Array(arr)) => {
+			let Ok(idx) = key.parse::<usize>() else {
+				let msg = format!("Invalid index for array '{}': {}",name,key);
+				return Err(High(SlashErrHigh::syntax_err(msg, blame)))
+			};
+			if idx >= arr.len() {
+				arr.resize(idx + 1, SlashVal::default());
+			}
+			arr[idx] = val;
+		}
+		Some(_) => {
+			let msg = format!("'{}' is not an array",name);
+			return Err(High(SlashErrHigh::exec_err(msg, blame)))
+		}
+		None => {
+			let new_var = match key.parse::<usize>() {
+				Ok(idx) => {
+					let mut arr = vec![SlashVal::default(); idx];
+					arr.push(val);
+					SlashVal::Array(arr)
+				}
+				Err(_) => SlashVal::Dict(BTreeMap::from([(key, val)]))
+			};
+			vars.set_var(&name, new_var);
+		}
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{self, lock_process, output_of}};
+
+use super::*;
+
+	#[test]
+	fn test_elem_assign() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("idx_arr[0]=x; idx_arr[2]=z".to_string(), &mut slash).unwrap();
+		let expected = SlashVal::Array(vec![SlashVal::String("x".into()), SlashVal::default(), SlashVal::String("z".into())]);
+		assert_eq!(slash.vars().get_var("idx_arr"), Some(expected.clone()));
+
+		// A failed expansion fails the assignment instead of storing an empty value
+		let result = execute::dispatch::exec_input("set -u; idx_arr[1]=$no_such_var".to_string(), &mut slash);
+		assert!(result.is_err());
+		assert_eq!(slash.vars().get_var("idx_arr"), Some(expected));
+	}
+
+	#[test]
+	fn test_readonly_assignments() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("declare -r RO_VAR=1".to_string(), &mut slash).unwrap();
+
+		for input in ["RO_VAR=2 true", "f() { local RO_VAR=2; }; f", "f() { local RO_VAR; }; f", "read RO_VAR < /dev/null", "for RO_VAR < 2 3; do true; done"] {
+			let result = execute::dispatch::exec_input(input.to_string(), &mut slash);
+			assert!(result.is_err_and(|e| e.to_string().contains("readonly variable")), "{} should have failed", input);
+			assert_eq!(slash.vars().get_var("RO_VAR"), Some(SlashVal::Int(1)), "{}", input);
+		}
+	}
+
+	#[test]
+	fn test_assign_no_word_split() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("x='a b'; y=$x".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("y"), Some(SlashVal::String("a b".into())));
+
+		assert_eq!(output_of("echo \"[$y]\"", &mut slash), "[a b]\n");
+	}
+
+	#[test]
+	fn test_assign_no_glob() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("z=*".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("z"), Some(SlashVal::String("*".into())));
+
+		execute::dispatch::exec_input("pat='*.rs'; w=$pat".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("w"), Some(SlashVal::String("*.rs".into())));
+	}
+
+	#[test]
+	fn test_invalid_identifiers() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("_under=1; export SLASH_IDENT_OK=2; declare -i n2=3; f() { local _l=4; }; f".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("_under"), Some(SlashVal::Int(1)));
+		assert_eq!(env::var("SLASH_IDENT_OK").unwrap(), "2");
+		assert_eq!(slash.vars().get_var("n2"), Some(SlashVal::Int(3)));
+
+		for input in ["export 1FOO=1", "export 'a b'=1", "declare a-b=1", "export a.b=1", "f() { local 1x=1; }; f", "f() { local a.b=1; }; f"] {
+			let result = execute::dispatch::exec_input(input.to_string(), &mut slash);
+			assert!(result.is_err_and(|e| e.to_string().contains("not a valid identifier")), "{} should have failed", input);
+		}
+		assert!(env::var("1FOO").is_err());
+		assert!(slash.vars().get_var("a-b").is_none());
+
+		// The grammar doesn't read these as assignments, so they are looked up as commands
+		for input in ["1abc=1", "a-b=1", "'x=1'"] {
+			let (_, output) = test_util::capture(&format!("{} 2>&1", input), &mut slash);
+			assert!(output.contains("Command not found"), "{} should have run as a command", input);
+		}
+
+		// A dot ends the variable name
+		assert_eq!(test_util::output_of("n=5; echo \"$n.\"", &mut slash), "5.\n");
+	}
+}
@@ -12,9 +12,26 @@ pub fn exec_cmd<'a>(cmd: Pair<Rule>, slash: &mut Slash) -> SlashResult<()> {
 	if helper::validate_autocd(slash,&argv)? {
 		let arg = argv.pop_front().unwrap();
 		let dir = PathBuf::from(&arg);
+		slash.ctx_mut().take_redirs();
 		return slash.change_dir(&dir)
 	}
 
+	if slash.is_func("command_not_found_handle")? {
+		let cmd_name = argv.front().unwrap();
+		if !cmd_name.contains('/') && helper::which(slash, cmd_name).is_none() {
+			// The handler stands in for the command, so the command's redirections apply to it
+			let saved_fds = utils::save_fds()?;
+			let result = slash.activate_redirs().and_then(|_| super::func::call_func("command_not_found_handle", argv, slash));
+			utils::restore_fds(saved_fds, slash)?;
+			return result
+		}
+	}
+
+	let cmd_name = argv.front().unwrap().clone();
+	let cmd_suggest = !cmd_name.contains('/') && slash.meta().get_shopt("core.cmd_suggest")?.parse::<bool>().unwrap_or(false);
+	// Only looked for once the command has turned out not to exist, since it reads every directory in PATH
+	let suggestion = |slash: &Slash| if cmd_suggest { helper::suggest_cmd(slash, &cmd_name) } else { None };
+
 	let argv = argv.into_iter().map(|arg| CString::new(arg).unwrap()).collect::<Vec<_>>();
 
 
@@ -28,15 +45,15 @@ pub fn exec_cmd<'a>(cmd: Pair<Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let env_vars = env::vars().into_iter().collect::<Vec<(String,String)>>();
 	let envp = env_vars.iter().map(|var| CString::new(format!("{}={}",var.0,var.1)).unwrap()).collect::<Vec<_>>();
 
-	slash.ctx_mut().activate_redirs()?;
+	slash.activate_redirs()?;
 
 	if slash.ctx_mut().flags().contains(utils::ExecFlags::NO_FORK) {
-		utils::exec_external(command, argv, envp, blame);
+		utils::exec_external(command, argv, envp, || suggestion(&*slash), blame);
 	}
 
 	match unsafe { fork() } {
 		Ok(ForkResult::Child) => {
-			utils::exec_external(command, argv, envp, blame);
+			utils::exec_external(command, argv, envp, || suggestion(&*slash), blame);
 		}
 		Ok(ForkResult::Parent { child }) => {
 			utils::handle_parent_process(child, command.to_str().unwrap().to_string(),slash)?;
@@ -46,3 +63,55 @@ pub fn exec_cmd<'a>(cmd: Pair<Rule>, slash: &mut Slash) -> SlashResult<()> {
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{lock_process, output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_command_not_found_handle() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("command_not_found_handle() { echo missing $1 $2; }".to_string(), &mut slash).unwrap();
+
+		let output = output_of("definitely_not_a_command_xyz arg", &mut slash);
+		assert_eq!(output, "missing definitely_not_a_command_xyz arg\n");
+
+		let output = output_of("definitely_not_a_command_xyz arg > /dev/null; echo after", &mut slash);
+		assert_eq!(output, "after\n");
+	}
+
+	#[test]
+	fn test_autocd() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let dir = temp_path("autocd");
+		std::fs::create_dir_all(&dir).unwrap();
+		let dir = dir.canonicalize().unwrap();
+		let dir_str = dir.to_str().unwrap();
+		let orig_dir = env::current_dir().unwrap();
+
+		execute::dispatch::exec_input("setopt core.autocd=false".to_string(), &mut slash).unwrap();
+		let _ = execute::dispatch::exec_input(dir_str.to_string(), &mut slash);
+		assert_ne!(slash.vars().get_evar("PWD").as_deref(), Some(dir_str));
+
+		execute::dispatch::exec_input("setopt core.autocd=true".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input(dir_str.to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_evar("PWD").as_deref(), Some(dir_str));
+
+		env::set_current_dir(orig_dir).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_cmd_suggestion() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("frobnicate() { echo hi; }".to_string(), &mut slash).unwrap();
+
+		assert!(output_of("frobnicaet 2>&1", &mut slash).contains("(did you mean: frobnicate?)"));
+
+		execute::dispatch::exec_input("setopt core.cmd_suggest=false".to_string(), &mut slash).unwrap();
+		assert!(!output_of("frobnicaet 2>&1", &mut slash).contains("did you mean"));
+	}
+}
@@ -1,10 +1,11 @@
-use crate::{builtin::{self, BUILTINS}, error::SlashErrExt, expand, helper, prelude::*, script, utils::{ExecFlags, Redir}};
+use crate::{builtin::{self, BUILTINS}, error::SlashErrExt, expand, helper, prelude::*, script, shellenv::EnvFlags, signal, utils::{self, ExecFlags, Redir}};
 
-use super::{pipeline, command, func};
+use super::{pipeline, command, func, time};
 
 pub fn dispatch_exec<'a>(node: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 		match node.as_rule() {
 			Rule::simple_cmd => {
+				begin_command(&node, slash)?;
 				let command_name = node.clone().into_inner().find(|pair| pair.as_rule() == Rule::cmd_name).unpack()?.as_str();
 				if !slash.ctx().flags().contains(ExecFlags::IGN_FUNC) && slash.is_func(command_name)? {
 					func::exec_func(node,slash)?;
@@ -21,25 +22,62 @@ pub fn dispatch_exec<'a>(node: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 					let redir = Redir::from_pair(shell_cmd_inner.pop_front().unpack()?)?;
 					slash.ctx_mut().push_redir(redir);
 				}
+				if shell_cmd.as_rule() == Rule::assignment {
+					begin_command(&shell_cmd, slash)?;
+				}
 				match shell_cmd.as_rule() {
 					Rule::for_cmd => script::fordo::exec_for_cmd(shell_cmd, slash)?,
 					Rule::match_cmd => script::matchdo::exec_match_cmd(shell_cmd, slash)?,
 					Rule::loop_cmd => script::loopdo::exec_loop_cmd(shell_cmd, slash)?,
 					Rule::if_cmd => script::ifthen::exec_if_cmd(shell_cmd, slash)?,
 					Rule::subshell => super::subshell::exec_subshell(shell_cmd, slash)?,
-					Rule::brace_grp => todo!(),
+					Rule::cmd_group => super::group::exec_group(shell_cmd, slash)?,
 					Rule::assignment => super::assignment::exec_assignment(shell_cmd, slash)?,
 					Rule::func_def => super::func::exec_func_def(shell_cmd, slash)?,
 					_ => unreachable!()
 				};
 			}
-			Rule::pipeline => { pipeline::exec_pipeline(node, slash)?; },
+			Rule::pipeline => {
+				// The stages are forked with NO_FORK, so the trap only runs once for the whole pipeline
+				begin_command(&node, slash)?;
+				pipeline::exec_pipeline(node, slash)?;
+			}
+			Rule::negation => {
+				let blame = node.clone();
+				let mut inner = node.into_inner().filter(|pair| pair.as_rule() != Rule::bang).collect::<VecDeque<_>>();
+				if blame.clone().into_inner().filter(|pair| pair.as_rule() == Rule::bang).count() > 1 {
+					return Err(High(SlashErrHigh::syntax_err("`!' cannot be applied twice", blame)))
+				}
+				dispatch_exec(inner.pop_front().unpack()?, slash)?;
+				let code = if slash.get_status() == 0 { 1 } else { 0 };
+				// `!` only changes `$?`, so `$PIPESTATUS` keeps the status of each stage
+				let pipe_status = slash.vars().get_var("PIPESTATUS");
+				slash.set_code(code);
+				if let Some(pipe_status) = pipe_status {
+					slash.vars_mut().set_var("PIPESTATUS", pipe_status);
+				}
+			}
+			Rule::timed => time::exec_timed(node, slash)?,
 			Rule::EOI => { /* Do nothing */ }
 			_ => todo!("Support for rule '{:?}' is unimplemented",node.as_rule())
 		}
 		Ok(())
 }
 
+/// Records a command as the one being run, then runs the `DEBUG` trap before it
+/// Commands run by a trap handler leave `$BASH_COMMAND` alone, so that the handler sees the command that set it off
+/// Forked children, like pipeline stages and command substitutions, leave the trap to the shell that forked them
+fn begin_command(node: &Pair<Rule>, slash: &mut Slash) -> SlashResult<()> {
+	if slash.meta().flags().contains(EnvFlags::IN_TRAP) {
+		return Ok(())
+	}
+	slash.vars_mut().set_current_command(node.as_str().trim());
+	if slash.ctx().flags().contains(ExecFlags::NO_FORK) {
+		return Ok(())
+	}
+	slash.run_trap("DEBUG")
+}
+
 pub fn descend(mut node_stack: VecDeque<Pair<Rule>>, slash: &mut Slash) -> SlashResult<()> {
 	slash.ctx_mut().descend()?; // Increment depth counter
 	while let Some(node) = node_stack.pop_front() {
@@ -82,13 +120,80 @@ pub fn descend(mut node_stack: VecDeque<Pair<Rule>>, slash: &mut Slash) -> Slash
 	Ok(())
 }
 
+/// Runs input the way it is read from a script or the prompt, one complete command at a time,
+/// taking as many lines as it needs to finish each one
+/// With `set -v` the lines of each command are echoed right before it runs, so turning it on or off partway through takes effect on the next line
+pub fn exec_read_input(input: String, slash: &mut Slash) -> SlashResult<()> {
+	let line_base = slash.meta().line_base();
+	let mut lines_done = 0;
+	let mut pending = String::new();
+	let mut pending_lines = 0;
+	let mut lines = input.split_inclusive('\n').peekable();
+	while let Some(line) = lines.next() {
+		pending.push_str(line);
+		pending_lines += 1;
+		if lines.peek().is_some() && !is_complete(&pending, slash) {
+			continue
+		}
+		let chunk = std::mem::take(&mut pending);
+		slash.print_input(&chunk)?;
+		// $LINENO still counts from the top of the input
+		slash.meta_mut().set_line_base(line_base + lines_done);
+		lines_done += std::mem::take(&mut pending_lines);
+		let result = exec_input(chunk, slash);
+		slash.meta_mut().set_line_base(line_base);
+		result?;
+	}
+	Ok(())
+}
+
+/// Whether `input` is made of whole commands, with no here-doc body, quote, or line continuation left open
+fn is_complete(input: &str, slash: &Slash) -> bool {
+	let (input, pending_heredocs) = expand::misc::collect_heredocs(input);
+	if !pending_heredocs.is_empty() {
+		return false
+	}
+	let input = if slash.comments_enabled() { expand::misc::strip_comments(&input) } else { input };
+	// A continuation at the very end has no later line to put its newline back after
+	let joined = expand::misc::join_continuations(&input);
+	if joined.matches('\n').count() < input.matches('\n').count() {
+		return false
+	}
+	SlashParse::parse(Rule::main, &joined).is_ok()
+}
+
+/// The grammar always accepts pattern groups like `@(a|b)`, but without extglob they are a syntax error, as they are in bash
+/// They are rejected before anything in the input runs, so turning extglob on only affects the lines after it
+fn check_extglob<'a>(lists: &VecDeque<Pair<'a,Rule>>, slash: &Slash) -> SlashResult<()> {
+	if slash.meta().get_shopt("core.extglob")?.parse::<bool>().unwrap_or(false) {
+		return Ok(())
+	}
+	let group = lists.iter().flat_map(|list| list.clone().into_inner().flatten()).find(|pair| pair.as_rule() == Rule::extglob_group);
+	match group {
+		Some(group) => Err(High(SlashErrHigh::syntax_err("syntax error near unexpected token `('", group))),
+		None => Ok(())
+	}
+}
+
 pub fn exec_input(mut input: String, slash: &mut Slash) -> SlashResult<()> {
-	input = expand::dispatch::expand_aliases(input, 0, vec![],slash)?;
+	// Here-doc bodies go first, since nothing in them is a comment or a line continuation
+	// A body that runs off the end of the input just ends there, like in bash
+	input = expand::misc::collect_heredocs(&input).0;
+	if slash.comments_enabled() {
+		input = expand::misc::strip_comments(&input);
+	}
+	input = expand::misc::join_continuations(&input);
+	// Scripts only get aliases if they ask for them with `shopt -s expand_aliases`
+	if slash.meta().get_shopt("core.expand_aliases")?.parse::<bool>().unwrap_or(false) {
+		input = expand::dispatch::expand_aliases(input, &mut vec![], slash)?;
+	}
 	let mut lists = SlashParse::parse(Rule::main, &input).map_err(|e| Low(SlashErrLow::Parse(e.to_string())))?.next().unwrap().into_inner().collect::<VecDeque<_>>();
 	lists.pop_back();
+	check_extglob(&lists, slash)?;
+	let line_base = slash.meta().line_base();
 	// Chew through the input one list at a time
 	while let Some(list) = lists.pop_front() {
-		let mut cmds = list.into_inner();
+		let mut cmds = list.into_inner().peekable();
 		while let Some(cmd) = cmds.next() {
 			if cmd.as_rule() == Rule::op {
 				let op = cmd.scry(&[Rule::and,Rule::or][..]).unpack()?;
@@ -110,14 +215,51 @@ pub fn exec_input(mut input: String, slash: &mut Slash) -> SlashResult<()> {
 					_ => unreachable!()
 				}
 			}
+			slash.run_pending_traps()?;
+			// Ctrl-C stops whatever the shell was running, but not the shell itself
+			if signal::take_interrupt() {
+				slash.set_code(utils::SIG_EXIT_OFFSET + Signal::SIGINT as i32);
+				return Err(Low(SlashErrLow::Interrupted))
+			}
 			let blame = cmd.clone();
+			// Anything executed by this command, like a loop body, counts lines from here
+			let lineno = line_base + cmd.line_col().0;
+			slash.vars_mut().set_param("LINENO", &lineno.to_string());
+			slash.meta_mut().set_line_base(lineno - 1);
+			// Only the last command of an `&&`/`||` list can trigger errexit, and never a negated one
+			let guarded = cmds.peek().is_some_and(|next| next.as_rule() == Rule::op) || cmd.as_rule() == Rule::negation;
+			let saved_flags = slash.ctx().flags();
+			if guarded {
+				*slash.ctx_mut().flags_mut() |= ExecFlags::IN_COND;
+			}
 			let node_stack = VecDeque::from([cmd]);
-			descend(node_stack, slash).blame_no_overwrite(blame)?;
+			let result = descend(node_stack, slash).blame_no_overwrite(blame);
+			*slash.ctx_mut().flags_mut() = saved_flags;
+			slash.meta_mut().set_line_base(line_base);
+			result?;
+			if !guarded {
+				// The ERR trap goes off for the same failures that errexit does, and before the shell exits for one
+				if slash.get_status() != 0 && !slash.ctx().flags().contains(ExecFlags::IN_COND) {
+					slash.run_trap("ERR")?;
+				}
+				check_errexit(slash)?;
+			}
 		}
 	}
 	Ok(())
 }
 
+/// Exits the shell if `set -e` is active and the last command failed outside of a condition
+fn check_errexit(slash: &Slash) -> SlashResult<()> {
+	let status = slash.get_status();
+	let errexit = slash.meta().flags().contains(EnvFlags::EXIT_ON_ERROR);
+	let in_cond = slash.ctx().flags().contains(ExecFlags::IN_COND);
+	if status != 0 && errexit && !in_cond {
+		return Err(Low(SlashErrLow::CleanExit(status)))
+	}
+	Ok(())
+}
+
 pub fn exec_builtin(cmd: Pair<Rule>, name: &str, slash: &mut Slash) -> SlashResult<()> {
 	let blame = cmd.clone();
 	match name {
@@ -138,20 +280,42 @@ pub fn exec_builtin(cmd: Pair<Rule>, name: &str, slash: &mut Slash) -> SlashResu
 		"fg" => builtin::job::continue_job(cmd, slash, true)?,
 		"bg" => builtin::job::continue_job(cmd, slash, false)?,
 		"jobs" => builtin::job::jobs(cmd, slash)?,
+		"wait" => return builtin::wait::execute(cmd, slash),
+		"caller" => return builtin::caller::execute(cmd, slash),
+		"read" => return builtin::read::execute(cmd, slash),
+		"ulimit" => builtin::ulimit::execute(cmd, slash)?,
+		"times" => return builtin::times::execute(cmd, slash),
+		"set" => builtin::set::execute(cmd, slash)?,
+		"shift" => return builtin::set::shift(cmd, slash),
+		"declare" => return builtin::declare::execute(cmd, slash),
+		"help" => builtin::help::execute(cmd, slash)?,
 		"return" => builtin::control::func_return(cmd, slash)?,
 		"break" => builtin::control::loop_break(cmd, slash)?,
 		"continue" => builtin::control::loop_continue()?,
 		"pushd" => builtin::dir_stack::pushd(cmd, slash)?,
 		"source" => builtin::source::execute(cmd, slash)?,
 		"popd" => builtin::dir_stack::popd(cmd, slash)?,
+		"dirs" => return builtin::dir_stack::dirs(cmd, slash),
 		"setopt" => builtin::opts::setopt(cmd, slash)?,
 		"getopt" => builtin::opts::getopt(cmd, slash)?,
+		"shopt" => return builtin::opts::shopt(cmd, slash),
+		"history" => return builtin::history::execute(cmd, slash),
+		"fc" | "r" => return builtin::history::reexec(cmd, slash),
+		"complete" => return builtin::complete::execute(cmd, slash),
+		"compgen" => return builtin::complete::compgen(cmd, slash),
+		"oxdebug" => return builtin::oxdebug::execute(cmd, slash),
+		"oxdump" => return builtin::oxdump::execute(cmd, slash),
+		":" | "true" => return builtin::control::no_op(cmd, slash, 0),
+		"false" => return builtin::control::no_op(cmd, slash, 1),
 		"exit" => builtin::control::exit(cmd, slash)?,
 		"cd" => builtin::cd::execute(cmd, slash)?,
 		"alias" => builtin::alias::execute(cmd, slash)?,
 		"unalias" => builtin::alias::unalias(cmd, slash)?,
 		"pwd" => builtin::pwd::execute(cmd, slash)?,
 		"export" => builtin::export::execute(cmd, slash)?,
+		"local" => return builtin::local::execute(cmd, slash),
+		"unset" => return builtin::unset::execute(cmd, slash),
+		"trap" => return builtin::trap::execute(cmd, slash),
 		"echo" => builtin::echo::execute(cmd, slash)?,
 		"builtin" => builtin::cmd_override::execute(cmd, slash, true)?,
 		"command" => builtin::cmd_override::execute(cmd, slash, false)?,
@@ -160,3 +324,72 @@ pub fn exec_builtin(cmd: Pair<Rule>, name: &str, slash: &mut Slash) -> SlashResu
 	slash.set_code(0);
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::test_util::{capture, output_of};
+
+use super::*;
+
+	#[test]
+	fn test_lineno() {
+		let mut slash = Slash::new();
+		assert_eq!(output_of("true\necho $LINENO\ntrue", &mut slash), "2\n");
+		// A continuation line is still a line
+		assert_eq!(output_of("echo a \\\n\tb\necho $LINENO", &mut slash), "a b\n3\n");
+		assert_eq!(output_of("f() {\n\techo a \\\n\t\tb\n\techo $LINENO\n}\nf", &mut slash), "a b\n3\n");
+
+		exec_input("true\ntrue\ntrue".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_param("LINENO"), Some("3".into()));
+	}
+
+	#[test]
+	fn test_errexit_exemptions() {
+		let mut slash = Slash::new();
+		assert_eq!(output_of("set -e; false || true; false && true; if false; then true; fi; echo ok", &mut slash), "ok\n");
+
+		let (result, output) = capture("false; echo no", &mut slash);
+		// The exit comes back out of the group that `capture()` wraps the input in
+		assert!(matches!(result, Err(High(ref err)) if matches!(err.get_err(), SlashErrLow::CleanExit(1))));
+		assert!(output.is_empty());
+
+		exec_input("set +e; false".to_string(), &mut slash).unwrap();
+	}
+
+	#[test]
+	fn test_negation() {
+		let mut slash = Slash::new();
+		exec_input("! false".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 0);
+		exec_input("! true".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+
+		// A negated command can't trip errexit
+		exec_input("set -e; ! true; set +e".to_string(), &mut slash).unwrap();
+		assert!(exec_input("! ! true".to_string(), &mut slash).is_err());
+	}
+
+	#[test]
+	fn test_current_command() {
+		let mut slash = Slash::new();
+		exec_input("shopt -s expand_aliases; alias greet_cmd_test='echo hi'".to_string(), &mut slash).unwrap();
+		exec_input("trap 'echo \"$OX_COMMAND\" >&2' DEBUG".to_string(), &mut slash).unwrap();
+		let output = output_of("{ greet_cmd_test $HOME > /dev/null; X=1; trap - DEBUG; } 2>&1", &mut slash);
+
+		// Aliases are already expanded, but nothing else is
+		assert_eq!(output, "echo hi $HOME > /dev/null\nX=1\ntrap - DEBUG\n");
+		assert_eq!(slash.vars().get_var("BASH_COMMAND").unwrap().to_string(), "trap - DEBUG");
+	}
+
+	#[test]
+	fn test_heredocs() {
+		let mut slash = Slash::new();
+		// Two here-docs on one line, then a function whose here-doc is only expanded when it runs
+		let input = "X=val; cat <<A; cat <<-'B'\nhi $X \"q\" \\$X $(echo sub)\nA\n\tlit $X\n\tB\nf() { cat <<E\nin $X\nE\n}; X=two; f\n";
+		assert_eq!(output_of(input, &mut slash), "hi val \"q\" $X sub\nlit $X\nin two\n");
+
+		// A quoted body is passed along as a `$'...'` word, which the comment and continuation passes have to see through
+		assert_eq!(output_of("cat <<'EOF'\nit's\n# x\nEOF", &mut slash), "it's\n# x\n");
+		assert_eq!(output_of("cat <<'EOF'\na\\\nb\nEOF", &mut slash), "a\\\nb\n");
+	}
+}
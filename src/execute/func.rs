@@ -1,35 +1,76 @@
-use crate::{helper, prelude::*};
+use crate::{helper, prelude::*, shellenv::{SlashVal, StackFrame}};
 
 use super::dispatch;
 
 pub fn exec_func_def<'a>(func_def: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let blame = func_def.clone();
-	let func_name = func_def.scry(Rule::func_name).unpack()?;
+	// `fn name { ... }` gives the name as a plain word
+	let func_name = func_def.scry(&[Rule::func_name,Rule::word][..]).unpack()?;
+	let func_name = func_name.as_str().trim_end_matches("()");
 	let body = func_def.scry(Rule::brace_grp).unpack()?;
-	helper::write_func(
-		slash,
-		func_name.as_str().trim_end_matches("()"),
-		body.as_str().trim_matches(['{','}']).trim()
-	)?;
+	helper::write_func(slash, func_name, body.as_str().trim_matches(['{','}']).trim())?;
+	if let Some(params) = func_def.scry(Rule::func_params) {
+		let params = params.into_inner().map(|param| param.as_str().trim().to_string()).collect();
+		slash.logic_mut().set_func_params(func_name, params);
+	}
 	slash.set_code(0);
 	Ok(())
 }
 
 pub fn exec_func(cmd: Pair<Rule>,slash: &mut Slash) -> SlashResult<()> {
-	let blame = cmd.clone();
 	let mut argv = helper::prepare_argv(cmd,slash)?;
 	let func_name = argv.pop_front().unwrap();
-	let body = slash.logic().get_func(&func_name).unwrap();
+	call_func(&func_name, argv, slash)
+}
+
+/// Calls the function `func_name` with `argv` as its positional parameters
+pub fn call_func(func_name: &str, argv: VecDeque<String>, slash: &mut Slash) -> SlashResult<()> {
+	let body = slash.logic().get_func(func_name).unwrap();
 	let mut var_table = slash.vars().clone();
 	let snapshot = slash.clone();
 
 	var_table.reset_params();
-	for arg in argv {
-		var_table.pos_param_pushback(&arg);
+	// Named parameters are locals, so unsetting one brings back whatever it was hiding
+	let mut shadowed = HashMap::new();
+	for (i, param) in slash.logic().get_func_params(func_name).into_iter().enumerate() {
+		shadowed.insert(param.clone(), slash.vars().get_var(&param));
+		var_table.set_var(&param, SlashVal::String(argv.get(i).cloned().unwrap_or_default()));
 	}
+	var_table.set_pos_params(argv);
+
+	let frame = StackFrame {
+		name: func_name.to_string(),
+		line: slash.vars().get_param("LINENO").and_then(|line| line.parse::<usize>().ok()).unwrap_or(0),
+		file: slash.meta().current_file(),
+		is_source: false,
+		shadowed
+	};
+	slash.enter_frame(frame)?;
+	// Innermost function first, like bash
+	let func_names = slash.meta().call_stack().iter().rev()
+		.filter(|frame| !frame.is_source)
+		.map(|frame| SlashVal::String(frame.name.clone()))
+		.collect();
+	var_table.set_var("FUNCNAME", SlashVal::Array(func_names));
 	*slash.vars_mut() = var_table;
+	// $LINENO counts from the start of the function body
+	slash.meta_mut().set_line_base(0);
 	let result = dispatch::exec_input(body, slash);
+	// The handler runs before the function's variables are thrown away, so it can still see them
+	let result = slash.run_trap("RETURN").and(result);
+	let globals = slash.vars_mut().take_globals();
+	// The function's variables go away with the rest of its state, but its exports also changed the process environment
+	snapshot.vars().restore_env(slash.vars());
 	*slash = snapshot;
+	let in_func = slash.meta().call_stack().iter().any(|frame| !frame.is_source);
+	for (key,(val,flags)) in globals {
+		if in_func {
+			// Keep passing it up until it reaches the top level
+			slash.vars_mut().set_global(&key, val, flags);
+		} else {
+			slash.vars_mut().declare_var(&key, val, flags);
+		}
+	}
 
 	let code = helper::extract_return(&result);
 	if let Ok(code) = code {
@@ -39,3 +80,41 @@ pub fn exec_func(cmd: Pair<Rule>,slash: &mut Slash) -> SlashResult<()> {
 		result
 	}
 }
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute::dispatch, test_util::output_of};
+
+use super::*;
+
+	#[test]
+	fn test_recursion_limit() {
+		let mut slash = Slash::new();
+		let result = dispatch::exec_input("FUNCNEST=50; f() { f; }; f".to_string(), &mut slash);
+		assert!(result.is_err_and(|e| e.to_string().contains("function nesting too deep (50)")));
+		assert!(slash.meta().call_stack().is_empty());
+
+		// Without FUNCNEST the limit comes from core.max_recurse_depth, and sourced files count too
+		let path = env::temp_dir().join(format!("slash_self_source_{}",std::process::id()));
+		let path_str = path.to_str().unwrap();
+		std::fs::write(&path, format!("source {path_str}\n")).unwrap();
+		let mut slash = Slash::new();
+		slash.meta_mut().set_shopt("core.max_recurse_depth", "20").unwrap();
+		let result = dispatch::exec_input(format!("source {path_str}"), &mut slash);
+		assert!(result.is_err_and(|e| e.to_string().contains("function nesting too deep (20)")));
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn test_params_restored() {
+		let mut slash = Slash::new();
+		let output = output_of("set -- outer; f() { echo \"$1 $#\"; }; f a b; fn greet(name greeting) { echo \"$greeting $name $2\"; }; greet world hello; declare -f greet", &mut slash);
+
+		assert!(output.starts_with("a 2\nhello world hello\nfn greet(name greeting) {\n"), "{output}");
+		// The caller's positional parameters come back, and the named ones go away
+		assert_eq!(slash.vars().get_param("1"), Some("outer".into()));
+		assert_eq!(slash.vars().get_param("#"), Some("1".into()));
+		assert!(slash.vars().get_var("name").is_none());
+	}
+}
@@ -0,0 +1,32 @@
+use crate::{prelude::*, utils};
+
+use super::dispatch;
+
+/// Runs a brace group in the current shell, so that it can change variables and the working directory
+/// Redirections on the group apply to every command inside of it
+pub fn exec_group<'a>(group: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let body = group.scry(Rule::group_body).unpack()?.as_str().to_string();
+	let saved_fds = utils::save_fds()?;
+	let result = slash.activate_redirs().and_then(|_| dispatch::exec_input(body, slash));
+	utils::restore_fds(saved_fds, slash)?;
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{shellenv::SlashVal, test_util::output_of};
+
+use super::*;
+
+	#[test]
+	fn test_group_runs_in_current_shell() {
+		let mut slash = Slash::new();
+		let output = output_of("{ x=1; echo one; echo two; }", &mut slash);
+
+		assert_eq!(slash.vars().get_var("x"), Some(SlashVal::Int(1)));
+		assert_eq!(output, "one\ntwo\n");
+
+		// The closing brace has to follow a separator
+		assert!(dispatch::exec_input("{ echo hi }".to_string(), &mut slash).is_err());
+	}
+}
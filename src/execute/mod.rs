@@ -2,5 +2,7 @@ pub mod assignment;
 pub mod command;
 pub mod func;
 pub mod subshell;
+pub mod group;
 pub mod dispatch;
 pub mod pipeline;
+pub mod time;
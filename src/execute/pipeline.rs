@@ -1,8 +1,25 @@
-use crate::{helper, utils, prelude::*, shellenv::{ChildProc, JobBuilder}};
+use nix::sys::signal::SigSet;
+
+use crate::{helper, signal, utils, prelude::*, shellenv::{disable_reaping, enable_reaping, write_jobs, ChildProc, JobBuilder}};
 
 use super::dispatch;
 
 pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	// SIGCHLD is held back until the job has been waited on
+	// Otherwise the handler can reap a stage before the job exists, or interrupt the shell mid-allocation
+	let saved_mask = signal::block_sigchld();
+	// Each stage's exit status is needed for the pipeline's status, so the handler must not reap any of them
+	disable_reaping();
+	let result = fork_stages(pipeline, saved_mask, slash);
+	signal::restore_sigmask(saved_mask);
+	if result.is_err() {
+		// handle_fg() turns reaping back on when the job is waited on
+		enable_reaping()?;
+	}
+	result
+}
+
+fn fork_stages<'a>(pipeline: Pair<'a,Rule>, saved_mask: SigSet, slash: &mut Slash) -> SlashResult<()> {
 	let blame = pipeline.clone();
 	let (in_redirs,out_redirs) = slash.ctx_mut().sort_redirs();
 	let _ = slash.ctx_mut().take_redirs();
@@ -27,6 +44,8 @@ pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 
 		match unsafe { fork() } {
 			Ok(ForkResult::Child) => {
+				signal::restore_sigmask(saved_mask);
+				slash.vars().reseed_random();
 				if let Some(mut pipe) = r_pipe {
 					pipe.close()?
 				}
@@ -37,6 +56,8 @@ pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 					.map(|w| utils::Redir::from_raw(1, w.as_raw_fd()))
 					.and_then(|redir| Some(slash.ctx_mut().push_redir(redir)));
 				*slash.ctx_mut().flags_mut() |= utils::ExecFlags::NO_FORK;
+				// Only the pipeline as a whole goes to the background, so a stage must not background itself too
+				slash.ctx_mut().flags_mut().remove(utils::ExecFlags::BACKGROUND);
 				// These two if statements handle the case of existing i/o for the pipeline
 				// Stuff like shell functions in the middle of pipelines
 				if first {
@@ -48,8 +69,15 @@ pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 					slash.ctx_mut().extend_redirs(out_redirs.into());
 				}
 
-				dispatch::dispatch_exec(node, slash)?;
-				std::process::exit(1)
+				// Builtins and shell structures return here instead of exec'ing, so the stage exits with their status
+				let code = match dispatch::dispatch_exec(node, slash) {
+					Ok(_) => slash.get_status(),
+					Err(e) => {
+						eprintln!("{}",e);
+						1
+					}
+				};
+				std::process::exit(code)
 			}
 			Ok(ForkResult::Parent { child }) => {
 				if let Some(mut pipe) = w_pipe {
@@ -73,7 +101,15 @@ pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 						.with_children(children)
 						.build();
 
-					helper::handle_fg(slash,job)?;
+					if slash.ctx().flags().contains(utils::ExecFlags::BACKGROUND) {
+						// Like bash, `$!` is the last stage, whose status is the one `wait` reports
+						slash.vars_mut().set_param("!", &child.to_string());
+						write_jobs(|j| j.insert_job(job,false))??;
+						// Nothing waits on a background job here, so the handler has to reap it
+						enable_reaping()?;
+					} else {
+						helper::handle_fg(slash,job)?;
+					}
 				}
 			}
 			Err(e) => return Err(High(SlashErrHigh::exec_err("Command in pipeline failed", blame)))
@@ -82,6 +118,79 @@ pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 			first = false;
 		}
 	}
-	slash.set_code(0);
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{lock_process, output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_pipeline_streams_large_output() {
+		let mut slash = Slash::new();
+		let (out_path, err_path) = (temp_path("pipeline"), temp_path("pipeline_err"));
+
+		// Far more than a pipe buffer goes through each stage, and `yes` only stops once `head` closes its end
+		// Every stage is forked, so the redirections only apply in the children
+		let input = format!("yes 2> {} | head -n 100000 | wc -l > {}", err_path.to_str().unwrap(), out_path.to_str().unwrap());
+		execute::dispatch::exec_input(input, &mut slash).unwrap();
+
+		assert_eq!(std::fs::read_to_string(&out_path).unwrap().trim(), "100000");
+		// `yes` should be killed by SIGPIPE rather than seeing a write error
+		assert_eq!(std::fs::read_to_string(&err_path).unwrap(), "");
+		std::fs::remove_file(&out_path).unwrap();
+		std::fs::remove_file(&err_path).unwrap();
+	}
+
+	#[test]
+	fn test_pipelines_dont_leak_fds() {
+		// Tests that redirect in the shell itself open files while they hold the lock
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		let count_fds = || std::fs::read_dir("/proc/self/fd").unwrap().count();
+		let input = "echo a | cat > /dev/null; ".repeat(25);
+
+		execute::dispatch::exec_input("echo warmup | cat > /dev/null".to_string(), &mut slash).unwrap();
+		let before = count_fds();
+		let result = execute::dispatch::exec_input(input, &mut slash);
+		let after = count_fds();
+		result.unwrap();
+
+		// Other tests open files at the same time, so only a steady climb counts as a leak
+		assert!(after < before + 10, "fd count grew from {} to {}", before, after);
+	}
+
+	#[test]
+	fn test_background_pipeline_last_pid() {
+		use crate::shellenv::{read_jobs, JobID};
+
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("sh -c 'exit 3' | sh -c 'sleep 0.2; exit 4' &".to_string(), &mut slash).unwrap();
+		let last_pid = Pid::from_raw(slash.vars().get_param("!").unwrap().parse::<i32>().unwrap());
+		// The pipeline went to the background, and `$!` is its last stage rather than its process group
+		let (pgid, last_child) = read_jobs(|j| {
+			let job = j.query(JobID::Pid(last_pid)).unwrap();
+			(job.pgid(), job.get_children().last().unwrap().pid())
+		}).unwrap();
+		assert_eq!(last_child, last_pid);
+		assert_ne!(pgid, last_pid);
+
+		execute::dispatch::exec_input("wait $!".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 4);
+	}
+
+	#[test]
+	fn test_pipe_status() {
+		let mut slash = Slash::new();
+		let output = output_of("true | false | true; echo ${PIPESTATUS[@]}; sh -c 'exit 3'; echo ${PIPESTATUS[@]}; false; echo ${PIPESTATUS[@]}", &mut slash);
+
+		// A single command has one status, the same as `$?`, whether it is an external command or a builtin
+		assert_eq!(output, "0 1 0\n3\n1\n");
+
+		// Negating a pipeline changes `$?`, but not the status of its stages
+		let output = output_of("! true | false; echo $? ${PIPESTATUS[@]}", &mut slash);
+		assert_eq!(output, "0 0 1\n");
+	}
+}
@@ -1,6 +1,7 @@
 use crate::expand;
 use crate::helper;
 use crate::prelude::*;
+use crate::signal;
 
 use crate::shellenv::ChildProc;
 use crate::shellenv::JobBuilder;
@@ -34,19 +35,23 @@ pub fn exec_subshell<'a>(subsh: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult
 fn handle_external_subshell(script: String, argv: VecDeque<String>, slash: &mut Slash) -> SlashResult<()> {
 	let argv = argv.into_iter().map(|arg| CString::new(arg).unwrap()).collect::<Vec<_>>();
 	let envp = slash.get_cstring_evars()?;
+	// The interpreter opens the script through /proc/self/fd after the exec, so this one can't be close-on-exec
+	// The parent's copy is closed once the child is running
 	let mut memfd = utils::SmartFD::new_memfd("anonymous_subshell", true)?;
 	write!(memfd,"{}",script)?;
 
 	let fd_path = CString::new(format!("/proc/self/fd/{memfd}")).unwrap();
-	slash.ctx_mut().activate_redirs()?;
+	slash.activate_redirs()?;
 
 	if slash.in_pipe() {
+		signal::reset_ignored_signals();
 		execve(&fd_path, &argv, &envp).unwrap();
 		panic!("execve() failed in subshell execution");
 	}
 
 	match unsafe { fork() } {
 		Ok(ForkResult::Child) => {
+			signal::reset_ignored_signals();
 			execve(&fd_path, &argv, &envp).unwrap();
 			panic!("execve() failed in subshell execution");
 		}
@@ -62,18 +67,67 @@ fn handle_external_subshell(script: String, argv: VecDeque<String>, slash: &mut
 		}
 		Err(e) => panic!("Encountered fork error: {}",e)
 	}
-	memfd.close()?;
 	Ok(())
 }
 
 fn handle_internal_subshell(body: String, argv: VecDeque<String>, slash: &mut Slash) -> SlashResult<()> {
 	let snapshot = slash.clone();
-	slash.ctx_mut().activate_redirs()?;
+	// The snapshot still holds the old depth, so leaving the subshell puts it back
+	slash.set_subshell_depth(snapshot.subshell_depth() + 1);
+	// The subshell runs in this process, so its redirections have to be undone once it finishes
+	let saved_fds = utils::save_fds()?;
 	slash.vars_mut().reset_params();
-	for arg in argv {
-		slash.vars_mut().pos_param_pushback(&arg);
-	}
-	dispatch::exec_input(body.consume_escapes(), slash)?;
+	slash.vars_mut().set_pos_params(argv);
+	let result = slash.activate_redirs().and_then(|_| dispatch::exec_input(body.consume_escapes(), slash));
+	// An EXIT trap set in here goes off when the subshell ends, since the shell around it isn't exiting
+	let exit_trap = slash.logic().get_trap("EXIT");
+	let result = if exit_trap.is_some() && exit_trap != snapshot.logic().get_trap("EXIT") {
+		let trap_result = slash.run_trap("EXIT");
+		result.and(trap_result)
+	} else {
+		result
+	};
+	// Variables keep their attributes in the snapshot, but exports made in here also changed the process environment
+	snapshot.vars().restore_env(slash.vars());
+	utils::restore_fds(saved_fds, slash)?;
 	*slash = snapshot;
-	Ok(())
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{shellenv::SlashVal, test_util::{output_of, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_subshell_var_attributes() {
+		let mut slash = Slash::new();
+		let path = temp_path("subshell_attrs");
+		let path_str = path.to_str().unwrap();
+
+		dispatch::exec_input("declare -r SUB_RO=1; SUB_OUTER=outer".to_string(), &mut slash).unwrap();
+		let result = dispatch::exec_input("( SUB_RO=2 )".to_string(), &mut slash);
+		assert!(result.is_err_and(|e| e.to_string().contains("readonly variable")));
+		assert_eq!(slash.vars().get_var("SUB_RO"), Some(SlashVal::Int(1)));
+
+		let input = format!("( SUB_OUTER=inner; SUB_NEW=new; export SUB_EXPORTED=yes; sh -c 'echo $SUB_EXPORTED' > {path_str} )");
+		dispatch::exec_input(input, &mut slash).unwrap();
+
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "yes\n");
+		assert_eq!(slash.vars().get_var("SUB_OUTER"), Some(SlashVal::String("outer".into())));
+		assert_eq!(slash.vars().get_var("SUB_NEW"), None);
+		assert_eq!(slash.vars().get_evar("SUB_EXPORTED"), None);
+		assert!(env::var("SUB_EXPORTED").is_err());
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn test_subshell_depth() {
+		let mut slash = Slash::new();
+		let output = output_of("echo $OXIDE_SUBSHELL; ( echo $OXIDE_SUBSHELL; ( echo $BASH_SUBSHELL ) ); echo $(echo $OXIDE_SUBSHELL); echo $OXIDE_SUBSHELL", &mut slash);
+
+		assert_eq!(output, "0\n1\n2\n1\n0\n");
+		assert_eq!(slash.subshell_depth(), 0);
+	}
 }
@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+use crate::{builtin::times, prelude::*, utils};
+
+use super::dispatch;
+
+/// Runs the command after the `time` keyword, then prints how long it took to stderr
+/// A pipeline is timed as a whole, from before its first stage starts until its last stage exits
+/// The exit status is the command's own, and `time -p` prints the times in the POSIX format
+/// Without a command, the times are the shell's own since it started, like bash
+pub fn exec_timed<'a>(timed: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let posix = timed.scry(Rule::time_posix).is_some();
+	let Some(cmd) = timed.into_inner().find(|pair| !matches!(pair.as_rule(), Rule::time_kw | Rule::time_posix)) else {
+		let real = slash.meta().started().elapsed();
+		let (user, sys) = cpu_time()?;
+		print_times(posix, real, user, sys)?;
+		slash.set_code(0);
+		return Ok(())
+	};
+
+	let start = Instant::now();
+	let (start_user, start_sys) = cpu_time()?;
+	let result = dispatch::dispatch_exec(cmd, slash);
+	let real = start.elapsed();
+	let (end_user, end_sys) = cpu_time()?;
+	let (user, sys) = (end_user.saturating_sub(start_user), end_sys.saturating_sub(start_sys));
+
+	print_times(posix, real, user, sys)?;
+	result
+}
+
+/// The command's redirections are gone by now, so this goes to the shell's own stderr
+fn print_times(posix: bool, real: Duration, user: Duration, sys: Duration) -> SlashResult<()> {
+	let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+	if posix {
+		write!(stderr, "real {:.2}\nuser {:.2}\nsys {:.2}\n", real.as_secs_f64(), user.as_secs_f64(), sys.as_secs_f64())?;
+	} else {
+		write!(stderr, "\nreal\t{}\nuser\t{}\nsys\t{}\n", fmt_duration(real), fmt_duration(user), fmt_duration(sys))?;
+	}
+	Ok(())
+}
+
+/// The user and system time used so far by the shell and the children it has waited for
+fn cpu_time() -> SlashResult<(Duration, Duration)> {
+	let to_duration = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+	let mut user = Duration::ZERO;
+	let mut sys = Duration::ZERO;
+	for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+		let usage = times::read_rusage(who)?;
+		user += to_duration(usage.ru_utime);
+		sys += to_duration(usage.ru_stime);
+	}
+	Ok((user, sys))
+}
+
+/// Formats a duration like `1m2.345s`, the way bash prints times
+fn fmt_duration(time: Duration) -> String {
+	let secs = time.as_secs();
+	format!("{}m{}.{:03}s", secs / 60, secs % 60, time.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::test_util::output_of;
+
+use super::*;
+
+	#[test]
+	fn test_time_pipeline() {
+		let mut slash = Slash::new();
+		let output = output_of("{ time (sleep 0 | sleep 0); time -p sleep 0.2 | true; echo $?; set -o pipefail; time false | true; echo $?; } 2>&1", &mut slash);
+
+		let lines = output.lines().collect::<Vec<_>>();
+		assert_eq!(lines.len(), 13, "{}", output);
+		for (line, field) in lines[1..4].iter().zip(["real", "user", "sys"]) {
+			assert!(line.starts_with(&format!("{field}\t0m")) && line.ends_with('s'), "{}", output);
+		}
+		// The first stage is the slow one, so the whole pipeline has to be covered
+		let real = lines[4].strip_prefix("real ").and_then(|secs| secs.parse::<f64>().ok()).unwrap();
+		assert!(real >= 0.2, "{}", output);
+		// The status is the command's own, including pipefail
+		assert_eq!(lines[7], "0", "{}", output);
+		assert_eq!(lines[12], "1", "{}", output);
+	}
+
+	#[test]
+	fn test_time_bare() {
+		let mut slash = Slash::new();
+		let output = output_of("{ time; time -p\necho $?; } 2>&1", &mut slash);
+
+		let lines = output.lines().collect::<Vec<_>>();
+		assert_eq!(lines.len(), 8, "{}", output);
+		for (line, field) in lines[1..4].iter().zip(["real", "user", "sys"]) {
+			assert!(line.starts_with(&format!("{field}\t")) && line.ends_with('s'), "{}", output);
+		}
+		for (line, field) in lines[4..7].iter().zip(["real", "user", "sys"]) {
+			assert!(line.strip_prefix(&format!("{field} ")).is_some_and(|secs| secs.parse::<f64>().is_ok()), "{}", output);
+		}
+		assert_eq!(lines[7], "0", "{}", output);
+	}
+}
@@ -12,6 +12,7 @@ pub fn expand_cmd_sub(mut pair: Pair<Rule>,slash: &mut Slash) -> SlashResult<Str
 	let (mut r_pipe, mut w_pipe) = utils::SmartFD::pipe()?;
 	let redir = utils::Redir::from_raw(1,w_pipe.as_raw_fd());
 	let mut sub_slash = slash.clone();
+	sub_slash.set_subshell_depth(slash.subshell_depth() + 1);
 	let flags = sub_slash.ctx_mut().flags_mut();
 	*flags |= utils::ExecFlags::NO_FORK; // Tell the child proc to not fork since it's already in a fork
 	sub_slash.ctx_mut().push_redir(redir);
@@ -19,6 +20,7 @@ pub fn expand_cmd_sub(mut pair: Pair<Rule>,slash: &mut Slash) -> SlashResult<Str
 	match unsafe { fork() } {
 		Ok(ForkResult::Child) => {
 			r_pipe.close()?;
+			sub_slash.vars().reseed_random();
 			// Execute the subshell body with the ctx payload
 			execute::dispatch::exec_input(body.consume_escapes(), &mut sub_slash)?;
 			std::process::exit(1);
@@ -31,7 +33,6 @@ pub fn expand_cmd_sub(mut pair: Pair<Rule>,slash: &mut Slash) -> SlashResult<Str
 
 	let mut buffer = String::new();
 	r_pipe.read_to_string(&mut buffer)?;
-	r_pipe.close()?;
 
 	Ok(buffer.trim().to_string())
 }
@@ -63,7 +64,6 @@ pub fn cmd_sub_from_str(input: &str,slash: &mut Slash) -> SlashResult<String> {
 
 	let mut buffer = String::new();
 	r_pipe.read_to_string(&mut buffer)?;
-	r_pipe.close()?;
 
 	Ok(buffer.trim().to_string())
 }
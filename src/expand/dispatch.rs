@@ -67,6 +67,10 @@ pub fn expand_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<Stri
 	let mut buffer = cmd.as_str().to_string();
 	// Order matters
 	let expand_rules = [
+		Rule::var_assoc,
+		Rule::var_indirect,
+		Rule::var_transform,
+		Rule::var_default,
 		Rule::var_sub,
 		Rule::param_sub,
 		Rule::glob_word,
@@ -77,7 +81,6 @@ pub fn expand_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<Stri
 		Rule::brace_word,
 		Rule::tilde_sub
 	];
-	buffer = alias_pass(buffer,slash)?;
 	for rule in expand_rules {
 		// Expand each rule in order
 		buffer = rule_pass(rule,buffer,slash)?;
@@ -85,43 +88,34 @@ pub fn expand_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<Stri
 	Ok(buffer)
 }
 
-pub fn alias_pass<'a>(buffer: String, slash: &mut Slash) -> SlashResult<String> {
-	let mut result = buffer.clone();
-	let mut list = SlashParse::parse(Rule::find_expansions, &buffer).unwrap().next().unpack()?.to_vec();
-	let logic = slash.logic().clone();
+/// Replaces each command name in the input that has an alias with the alias body
+/// Only words that the parser puts in command position are looked at, so `echo ls` leaves `ls` alone
+/// The commands in an alias body are expanded too, except for the aliases that are already being expanded, so `alias ls='ls -l'` stops after one step
+pub fn expand_aliases(input: String, expanding: &mut Vec<String>, slash: &Slash) -> SlashResult<String> {
+	let mut result = input.clone();
+	let mut tree = match SlashParse::parse(Rule::main, &input) {
+		Ok(tree) => tree,
+		// An alias body doesn't have to be a whole command on its own, like `alias s='sudo '`
+		Err(_) if !expanding.is_empty() => return Ok(input),
+		Err(e) => return Err(e.into())
+	};
 
-	while let Some(word) = list.pop() {
-		if let Some(body) = logic.get_alias(word.as_str()) {
-			let span = word.as_span();
-			result.replace_span(span, &body);
+	let mut cmd_names = Vec::from(tree.next().unwrap().seek_all(Rule::cmd_name));
+	// Replacing from the end of the input keeps the spans of the earlier names valid
+	cmd_names.sort_by_key(|cmd_name| cmd_name.as_span().start());
+	while let Some(cmd_name) = cmd_names.pop() {
+		let name = cmd_name.as_str();
+		if expanding.iter().any(|alias| alias == name) {
+			continue
 		}
-	}
-	Ok(result)
-}
-
-pub fn expand_aliases(input: String, depth: usize, mut cached: Vec<String>, slash: &mut Slash) -> SlashResult<String> {
-	if depth > 10 {
-		return Ok(input)
-	}
-	let mut result = input.clone();
-	let mut alias_pass = SlashParse::parse(Rule::main, &input)?;
-	let logic = slash.logic().clone();
-
-	let mut cmd_names = alias_pass.next().unwrap().seek_all(Rule::cmd_name);
-	while let Some(cmd_name) = cmd_names.pop_back() {
-		if let Some(alias) = logic.get_alias(cmd_name.as_str()) {
-			if !cached.contains(&alias) {
-				let span = cmd_name.as_span();
-				result.replace_span(span,&alias);
-				cached.push(alias);
-			}
+		if let Some(body) = slash.logic().get_alias(name) {
+			expanding.push(name.to_string());
+			let body = expand_aliases(body, expanding, slash)?;
+			expanding.pop();
+			result.replace_span(cmd_name.as_span(), &body);
 		}
 	}
-	if result != input {
-		return expand_aliases(result, depth + 1, cached, slash)
-	} else {
-		Ok(result)
-	}
+	Ok(result)
 }
 
 pub fn rule_pass<'a>(rule: Rule, buffer: String, slash: &mut Slash) -> SlashResult<String> {
@@ -132,6 +126,7 @@ pub fn rule_pass<'a>(rule: Rule, buffer: String, slash: &mut Slash) -> SlashResu
 		Rule::assignment, // These rules signal the expansion logic to break up the pair
 		Rule::arg_assign, // and push the inner pairs back onto the stack
 		Rule::std_assign,
+		Rule::elem_assign,
 		Rule::plus_assign,
 		Rule::minus_assign,
 		Rule::increment,
@@ -146,13 +141,12 @@ pub fn rule_pass<'a>(rule: Rule, buffer: String, slash: &mut Slash) -> SlashResu
 		if word.contains_rules(rule) {
 			let span = word.as_span();
 			let expanded = match rule {
-				Rule::var_sub => {
-					slash.vars().get_var(&word.as_str()[1..]).unwrap_or_default().to_string()
-				}
-				Rule::param_sub => {
-					let param = slash.vars().get_param(&word.as_str()[1..]).unwrap_or_default().to_string();
-					param
-				}
+				Rule::var_sub => expand::misc::expand_var(&word.as_str()[1..],slash),
+				Rule::var_assoc => expand::misc::expand_assoc(word.as_str(),slash)?,
+				Rule::var_indirect => expand::misc::expand_indirect(word.as_str(),slash),
+				Rule::var_transform => expand::misc::expand_transform(word.as_str(),slash),
+				Rule::var_default => expand::misc::expand_default(word.as_str(),slash)?,
+				Rule::param_sub => expand::misc::expand_param(&word.as_str()[1..], false, slash),
 				Rule::dquoted => expand::string::expand_string(word,slash)?,
 				Rule::arr_index => expand::index::expand_index(word,slash)?,
 				Rule::glob_word => expand::glob::expand_glob(word),
@@ -174,6 +168,10 @@ pub fn rule_queue() -> Vec<Rule> {
 		Rule::cmd_sub,
 		Rule::param_sub,
 		Rule::var_sub,
+		Rule::var_assoc,
+		Rule::var_indirect,
+		Rule::var_transform,
+		Rule::var_default,
 		Rule::dquoted
 	]
 }
@@ -194,15 +192,14 @@ pub fn expand_word<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<St
 			let expanded = match rule {
 				Rule::cmd_sub => expand::cmdsub::expand_cmd_sub(pair,slash)?,
 				Rule::param_sub => {
-					let param_name = &pair.as_str()[1..];
-					let param = slash.vars().get_param(param_name).unwrap_or_default().to_string();
-					param
-				}
-				Rule::var_sub => {
-					let var_name = &pair.as_str()[1..];
-					let result = slash.vars().get_var(var_name).unwrap_or_default().to_string();
-					result
+					let quoted = expand::misc::in_dquotes(word, span.start());
+					expand::misc::expand_param(&pair.as_str()[1..], quoted, slash)
 				}
+				Rule::var_sub => expand::misc::expand_var(&pair.as_str()[1..],slash),
+				Rule::var_assoc => expand::misc::expand_assoc(pair.as_str(),slash)?,
+				Rule::var_indirect => expand::misc::expand_indirect(pair.as_str(),slash),
+				Rule::var_transform => expand::misc::expand_transform(pair.as_str(),slash),
+				Rule::var_default => expand::misc::expand_default(pair.as_str(),slash)?,
 				Rule::dquoted => expand::string::expand_string(pair,slash)?,
 				_ => unreachable!()
 			};
@@ -215,3 +212,71 @@ pub fn expand_word<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<St
 
 	Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, shellenv::SlashVal, test_util::lock_process};
+
+use super::*;
+
+	#[test]
+	fn test_expand_exported_var() {
+		let _lock = lock_process();
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("export EXPAND_EXPORTED=\"foo\"".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("plain=$EXPAND_EXPORTED; braced=${EXPAND_EXPORTED}".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("plain"), Some(SlashVal::String("foo".into())));
+		assert_eq!(slash.vars().get_var("braced"), Some(SlashVal::String("foo".into())));
+
+		// Reassigning an exported variable updates the environment too
+		execute::dispatch::exec_input("EXPAND_EXPORTED=\"baz\"".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_evar("EXPAND_EXPORTED"), Some("baz".into()));
+	}
+
+	#[test]
+	fn test_expand_plain_var() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("plain_var=\"bar\"".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("copy=$plain_var".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("copy"), Some(SlashVal::String("bar".into())));
+	}
+
+	#[test]
+	fn test_random() {
+		let slash = Slash::new();
+		let values = (0..8).map(|_| slash.vars().get_var("RANDOM").unwrap().to_string().parse::<i32>().unwrap()).collect::<Vec<i32>>();
+		assert!(values.iter().all(|val| (0..32768).contains(val)));
+		assert!(values.windows(2).any(|pair| pair[0] != pair[1]));
+	}
+
+	#[test]
+	fn test_random_per_shell() {
+		let mut slash = Slash::new();
+		let mut other = Slash::new();
+		let draw = |slash: &Slash| (0..4).map(|_| slash.vars().get_var("RANDOM").unwrap().to_string()).collect::<Vec<_>>();
+
+		// Seeding one shell leaves the other alone, and the same seed gives the same values
+		execute::dispatch::exec_input("RANDOM=42".to_string(), &mut slash).unwrap();
+		let seeded = draw(&slash);
+		execute::dispatch::exec_input("RANDOM=42".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("RANDOM=7".to_string(), &mut other).unwrap();
+		draw(&other);
+		assert_eq!(draw(&slash), seeded);
+
+		// A command substitution is reseeded in the child instead of repeating what the parent draws next
+		execute::dispatch::exec_input("RANDOM=42; sub=$(echo $RANDOM $RANDOM $RANDOM $RANDOM)".to_string(), &mut slash).unwrap();
+		assert_ne!(slash.vars().get_var("sub").unwrap().to_string(), seeded.join(" "));
+	}
+
+	#[test]
+	fn test_seconds() {
+		let mut slash = Slash::new();
+		let seconds = |slash: &Slash| slash.vars().get_var("SECONDS").unwrap().to_string().parse::<u64>().unwrap();
+		let before = seconds(&slash);
+		std::thread::sleep(std::time::Duration::from_millis(1100));
+		assert!(seconds(&slash) > before);
+
+		execute::dispatch::exec_input("SECONDS=100".to_string(), &mut slash).unwrap();
+		assert!((100..102).contains(&seconds(&slash)));
+	}
+}
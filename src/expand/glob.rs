@@ -10,3 +10,326 @@ pub fn expand_glob(pair: Pair<Rule>) -> String {
 	}
 	result.trim().to_string()
 }
+
+#[derive(Debug,Clone)]
+enum PatToken {
+	Literal(char),
+	AnyChar,
+	AnyString,
+	Class { negated: bool, ranges: Vec<(char,char)> },
+	/// An extglob group like `@(foo|bar)`, holding the operator and the alternatives
+	Group(char, Vec<Vec<PatToken>>)
+}
+
+/// Returns true if `word` contains an extglob operator like `!(...)`
+pub fn has_extglob(word: &str) -> bool {
+	let chars = word.chars().collect::<Vec<_>>();
+	chars.windows(2).enumerate().any(|(i,pair)| {
+		"?*+@!".contains(pair[0]) && pair[1] == '(' && (i == 0 || chars[i - 1] != '\\')
+	})
+}
+
+fn parse_pattern(pattern: &[char], extglob: bool) -> Vec<PatToken> {
+	let mut tokens = vec![];
+	let mut i = 0;
+	while i < pattern.len() {
+		let ch = pattern[i];
+		if extglob && "?*+@!".contains(ch) && pattern.get(i + 1) == Some(&'(') {
+			if let Some(close) = find_group_end(pattern, i + 1) {
+				let alts = split_alternatives(&pattern[i + 2..close]).into_iter()
+					.map(|alt| parse_pattern(alt, extglob))
+					.collect();
+				tokens.push(PatToken::Group(ch, alts));
+				i = close + 1;
+				continue
+			}
+		}
+		match ch {
+			'\\' if i + 1 < pattern.len() => {
+				tokens.push(PatToken::Literal(pattern[i + 1]));
+				i += 1;
+			}
+			'?' => tokens.push(PatToken::AnyChar),
+			'*' => tokens.push(PatToken::AnyString),
+			'[' => {
+				match parse_class(pattern, i) {
+					Some((token, end)) => {
+						tokens.push(token);
+						i = end;
+					}
+					None => tokens.push(PatToken::Literal('['))
+				}
+			}
+			_ => tokens.push(PatToken::Literal(ch))
+		}
+		i += 1;
+	}
+	tokens
+}
+
+/// Finds the paren that closes the group opened at `open`
+fn find_group_end(pattern: &[char], open: usize) -> Option<usize> {
+	let mut depth = 0;
+	let mut i = open;
+	while i < pattern.len() {
+		match pattern[i] {
+			'\\' => i += 1,
+			'(' => depth += 1,
+			')' => {
+				depth -= 1;
+				if depth == 0 {
+					return Some(i)
+				}
+			}
+			_ => {}
+		}
+		i += 1;
+	}
+	None
+}
+
+/// Splits the inside of a group on the `|` characters that are not in a nested group
+fn split_alternatives(body: &[char]) -> Vec<&[char]> {
+	let mut alts = vec![];
+	let mut depth = 0;
+	let mut start = 0;
+	let mut i = 0;
+	while i < body.len() {
+		match body[i] {
+			'\\' => i += 1,
+			'(' => depth += 1,
+			')' => depth -= 1,
+			'|' if depth == 0 => {
+				alts.push(&body[start..i]);
+				start = i + 1;
+			}
+			_ => {}
+		}
+		i += 1;
+	}
+	alts.push(&body[start..]);
+	alts
+}
+
+/// Parses a bracket expression starting at `open`, returning the token and the index of the closing bracket
+fn parse_class(pattern: &[char], open: usize) -> Option<(PatToken,usize)> {
+	let mut i = open + 1;
+	let negated = matches!(pattern.get(i), Some('!' | '^'));
+	if negated {
+		i += 1;
+	}
+	let mut ranges = vec![];
+	let start = i;
+	while i < pattern.len() {
+		let ch = pattern[i];
+		// A `]` right after the opening bracket is part of the set
+		if ch == ']' && i > start {
+			return Some((PatToken::Class { negated, ranges }, i))
+		}
+		if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|end| *end != ']') {
+			ranges.push((ch, pattern[i + 2]));
+			i += 3;
+		} else {
+			ranges.push((ch, ch));
+			i += 1;
+		}
+	}
+	None
+}
+
+/// Results of matching a run of tokens against a piece of text, so that no pair is tried twice
+/// Slices are keyed by where they start and how long they are, which is unique within one call to `pattern_match()`
+/// Without this, patterns like `*a*a*a*b` or `+(+(a))b` take exponential time on text that almost matches
+#[derive(Default)]
+struct MatchMemo {
+	tokens: HashMap<(usize,usize,usize,usize),bool>,
+	repeats: HashMap<(usize,usize,usize,bool),bool>
+}
+
+fn match_tokens(tokens: &[PatToken], text: &[char], memo: &mut MatchMemo) -> bool {
+	let key = (tokens.as_ptr() as usize, tokens.len(), text.as_ptr() as usize, text.len());
+	if let Some(matched) = memo.tokens.get(&key) {
+		return *matched
+	}
+	let matched = match_tokens_uncached(tokens, text, memo);
+	memo.tokens.insert(key, matched);
+	matched
+}
+
+fn match_tokens_uncached(tokens: &[PatToken], text: &[char], memo: &mut MatchMemo) -> bool {
+	let Some((token, rest)) = tokens.split_first() else {
+		return text.is_empty()
+	};
+	match token {
+		PatToken::Literal(ch) => text.first() == Some(ch) && match_tokens(rest, &text[1..], memo),
+		PatToken::AnyChar => !text.is_empty() && match_tokens(rest, &text[1..], memo),
+		PatToken::AnyString => (0..=text.len()).any(|i| match_tokens(rest, &text[i..], memo)),
+		PatToken::Class { negated, ranges } => {
+			let Some(ch) = text.first() else {
+				return false
+			};
+			let in_class = ranges.iter().any(|(low,high)| low <= ch && ch <= high);
+			in_class != *negated && match_tokens(rest, &text[1..], memo)
+		}
+		PatToken::Group(op, alts) => {
+			match op {
+				'@' => (0..=text.len()).any(|i| matches_alt(alts, &text[..i], memo) && match_tokens(rest, &text[i..], memo)),
+				'?' => match_tokens(rest, text, memo) || (0..=text.len()).any(|i| matches_alt(alts, &text[..i], memo) && match_tokens(rest, &text[i..], memo)),
+				'*' => match_repeat(alts, text, rest, 0, memo),
+				'+' => match_repeat(alts, text, rest, 1, memo),
+				'!' => (0..=text.len()).any(|i| !matches_alt(alts, &text[..i], memo) && match_tokens(rest, &text[i..], memo)),
+				_ => unreachable!()
+			}
+		}
+	}
+}
+
+fn matches_alt(alts: &[Vec<PatToken>], text: &[char], memo: &mut MatchMemo) -> bool {
+	alts.iter().any(|alt| match_tokens(alt, text, memo))
+}
+
+/// Matches at least `min` repetitions of the alternatives, followed by `rest`
+/// `rest` always follows the same group, so the group's alternatives are enough to key the result on
+fn match_repeat(alts: &[Vec<PatToken>], text: &[char], rest: &[PatToken], min: usize, memo: &mut MatchMemo) -> bool {
+	let key = (alts.as_ptr() as usize, text.as_ptr() as usize, text.len(), min > 0);
+	if let Some(matched) = memo.repeats.get(&key) {
+		return *matched
+	}
+	// Each repetition has to consume something, or this would never end
+	let matched = (min == 0 && match_tokens(rest, text, memo)) || (1..=text.len()).any(|i| {
+		matches_alt(alts, &text[..i], memo) && match_repeat(alts, &text[i..], rest, min.saturating_sub(1), memo)
+	});
+	memo.repeats.insert(key, matched);
+	matched
+}
+
+/// Matches `text` against a shell pattern
+/// The extglob operators are only recognized if `extglob` is true, and are otherwise matched literally
+pub fn pattern_match(pattern: &str, text: &str, extglob: bool) -> bool {
+	let pattern = pattern.chars().collect::<Vec<_>>();
+	let text = text.chars().collect::<Vec<_>>();
+	match_tokens(&parse_pattern(&pattern, extglob), &text, &mut MatchMemo::default())
+}
+
+/// Filename expansion for patterns that use extglob operators, which the glob crate does not understand
+/// Returns the matching paths in sorted order, or nothing if there are no matches
+pub fn expand_extglob(word: &str, dotglob: bool, nocase: bool) -> Vec<String> {
+	let is_absolute = word.starts_with('/');
+	let mut paths = vec![if is_absolute { "/".to_string() } else { String::new() }];
+	for component in word.split('/').filter(|comp| !comp.is_empty()) {
+		let is_pattern = has_extglob(component) || component.contains(['*', '?', '[']);
+		let mut next_paths = vec![];
+		for path in paths {
+			if !is_pattern {
+				next_paths.push(format!("{}{}",path,component));
+				continue
+			}
+			let dir = if path.is_empty() { ".".to_string() } else { path.clone() };
+			let Ok(entries) = std::fs::read_dir(&dir) else {
+				continue
+			};
+			let mut names = entries.filter_map(|entry| entry.ok())
+				.map(|entry| entry.file_name().to_string_lossy().to_string())
+				.filter(|name| !name.starts_with('.') || dotglob || component.starts_with('.'))
+				.filter(|name| if nocase {
+					pattern_match(&component.to_lowercase(), &name.to_lowercase(), true)
+				} else {
+					pattern_match(component, name, true)
+				})
+				.collect::<Vec<_>>();
+			names.sort();
+			next_paths.extend(names.into_iter().map(|name| format!("{}{}",path,name)));
+		}
+		paths = next_paths.into_iter().map(|path| format!("{}/",path)).collect();
+	}
+	paths.into_iter()
+		.map(|path| path.trim_end_matches('/').to_string())
+		.filter(|path| !path.is_empty() && Path::new(path).exists())
+		.collect()
+}
+
+/// Removes the matches of a filename expansion that `$GLOBIGNORE` names, and puts the rest in `core.globsort` order
+/// If `$GLOBIGNORE` is set, `.` and `..` are always ignored as well. `core.globsort` sorts by name, or by newest or largest first
+pub fn filter_matches(mut paths: Vec<String>, slash: &Slash) -> Vec<String> {
+	let ignore = slash.vars().get_var("GLOBIGNORE").map(|val| val.to_string()).unwrap_or_default();
+	let patterns = ignore.split(':').filter(|pat| !pat.is_empty()).collect::<Vec<_>>();
+	if !patterns.is_empty() {
+		let extglob = slash.meta().get_shopt("core.extglob").is_ok_and(|opt| opt.parse::<bool>().unwrap_or(false));
+		paths.retain(|path| {
+			let name = Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+			name != "." && name != ".." && !patterns.iter().any(|pat| pattern_match(pat, path, extglob))
+		});
+	}
+	match slash.meta().get_shopt("core.globsort").unwrap_or_default().as_str() {
+		"mtime" => paths.sort_by_cached_key(|path| std::cmp::Reverse(std::fs::metadata(path).and_then(|meta| meta.modified()).ok())),
+		"size" => paths.sort_by_cached_key(|path| std::cmp::Reverse(std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0))),
+		_ => paths.sort()
+	}
+	paths
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_extglob_operators() {
+		let files = ["main.c", "main.o", "util.o", "README", "foo.tar.gz", "foofoo", "foo", "bar"];
+		let matching = |pattern: &str| files.iter().filter(|file| pattern_match(pattern, file, true)).cloned().collect::<Vec<_>>();
+
+		assert_eq!(matching("!(*.o)"), ["main.c", "README", "foo.tar.gz", "foofoo", "foo", "bar"]);
+		assert_eq!(matching("@(foo|bar)"), ["foo", "bar"]);
+		assert_eq!(matching("?(main).[co]"), ["main.c", "main.o"]);
+		assert_eq!(matching("*(foo)"), ["foofoo", "foo"]);
+		assert_eq!(matching("+(foo)"), ["foofoo", "foo"]);
+		assert_eq!(matching("foo+(.tar|.gz)"), ["foo.tar.gz"]);
+		assert!(pattern_match("?(x)", "", true));
+		assert!(!pattern_match("+(x)", "", true));
+		assert!(pattern_match("*.@(c|h)", "main.h", true));
+
+		// Without extglob the operators are literal
+		assert!(!pattern_match("@(foo|bar)", "foo", false));
+		assert!(pattern_match("@(foo|bar)", "@(foo|bar)", false));
+	}
+
+	#[test]
+	fn test_basic_patterns() {
+		assert!(pattern_match("*.rs", "main.rs", false));
+		assert!(pattern_match("ma?n.rs", "main.rs", false));
+		assert!(pattern_match("[a-c]at", "bat", false));
+		assert!(!pattern_match("[!a-c]at", "bat", false));
+		assert!(pattern_match("\\*", "*", false));
+		assert!(!pattern_match("\\*", "a", false));
+	}
+
+	#[test]
+	fn test_pattern_backtracking() {
+		// Each of these backtracks through every way of splitting the text before failing, which is only fast with the memo
+		let text = "a".repeat(40);
+		assert!(!pattern_match("*a*a*a*a*a*a*a*a*a*a*b", &text, false));
+		assert!(!pattern_match("+(+(a|aa))b", &text, true));
+		assert!(!pattern_match("*(a|aa)*(a|aa)*(a|aa)b", &text, true));
+		assert!(pattern_match("+(+(a|aa))", &text, true));
+	}
+
+	#[test]
+	fn test_globignore_and_globsort() {
+		use std::time::{Duration, SystemTime};
+		let mut slash = Slash::new();
+		let dir = crate::test_util::temp_path("globignore");
+		let dir_str = dir.to_str().unwrap();
+		std::fs::create_dir_all(&dir).unwrap();
+		let now = SystemTime::now();
+		for (name, age) in [("a.txt", 20), ("b.txt", 30), ("c.txt", 10)] {
+			let file = std::fs::File::create(dir.join(name)).unwrap();
+			file.set_modified(now - Duration::from_secs(age)).unwrap();
+		}
+
+		let input = format!("GLOBIGNORE='*/b.txt'; echo {dir_str}/*.txt; setopt core.globsort=\"mtime\"; GLOBIGNORE=; echo {dir_str}/*.txt");
+		let output = crate::test_util::output_of(&input, &mut slash);
+
+		let expected = format!("{dir_str}/a.txt {dir_str}/c.txt\n{dir_str}/c.txt {dir_str}/a.txt {dir_str}/b.txt\n");
+		assert_eq!(output, expected);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,4 +1,4 @@
-use crate::{helper, prelude::*};
+use crate::{helper::{self, StrExtension}, prelude::*, shellenv::SlashVal};
 
 pub fn expand_tilde(pair: Pair<Rule>) -> SlashResult<String> {
 	debug_assert!(pair.as_rule() == Rule::tilde_sub, "Found this: {:?}",pair.as_rule());
@@ -20,6 +20,576 @@ pub fn expand_tilde(pair: Pair<Rule>) -> SlashResult<String> {
 	Ok(word.replacen("~", &home, 1))
 }
 
+/// Joins lines ending in an unquoted backslash
+/// `echo foo \<newline>bar` becomes `echo foo bar`. The backslash-newline is kept as-is inside single quotes and `$'...'`
+/// The dropped newlines go back in after the end of the joined line, so everything after it stays on the same line for $LINENO
+pub fn join_continuations(input: &str) -> String {
+	let mut result = String::new();
+	let mut dropped = 0;
+	let mut chars = input.chars().peekable();
+	let mut in_squote = false;
+	let mut in_dquote = false;
+	let mut in_ansi_quote = false;
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' if in_ansi_quote => {
+				result.push(ch);
+				result.extend(chars.next());
+			}
+			'\\' if !in_squote => {
+				match chars.next() {
+					Some('\n') => dropped += 1, // Drop both
+					Some(esc_ch) => {
+						result.push(ch);
+						result.push(esc_ch);
+					}
+					None => result.push(ch)
+				}
+			}
+			'$' if !in_squote && !in_dquote && !in_ansi_quote && chars.peek() == Some(&'\'') => {
+				in_ansi_quote = true;
+				result.push(ch);
+				result.extend(chars.next());
+			}
+			'\'' if in_ansi_quote => {
+				in_ansi_quote = false;
+				result.push(ch);
+			}
+			'\'' if !in_dquote => {
+				in_squote = !in_squote;
+				result.push(ch);
+			}
+			'"' if !in_squote && !in_ansi_quote => {
+				in_dquote = !in_dquote;
+				result.push(ch);
+			}
+			'\n' if !in_squote && !in_dquote && !in_ansi_quote => {
+				result.push(ch);
+				result.extend(std::iter::repeat_n('\n', dropped));
+				dropped = 0;
+			}
+			_ => result.push(ch)
+		}
+	}
+	result
+}
+
+/// Removes `#` comments from the input
+/// A `#` only starts a comment at the start of a word, so `a#b` and quoted `#` are left alone
+/// A `#!` directly after an opening paren is a subshell shebang and is also left alone
+/// Inside `$'...'` a backslash escapes the closing quote, so those are tracked apart from plain single quotes
+pub fn strip_comments(input: &str) -> String {
+	let mut result = String::new();
+	let mut chars = input.chars().peekable();
+	let mut prev: Option<char> = None;
+	let mut in_squote = false;
+	let mut in_dquote = false;
+	let mut in_ansi_quote = false;
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' if !in_squote => {
+				result.push(ch);
+				if let Some(esc_ch) = chars.next() {
+					result.push(esc_ch);
+				}
+				prev = Some('\\');
+				continue
+			}
+			'$' if !in_squote && !in_dquote && !in_ansi_quote && chars.peek() == Some(&'\'') => {
+				in_ansi_quote = true;
+				result.push(ch);
+				result.extend(chars.next());
+				prev = Some('\'');
+				continue
+			}
+			'\'' if in_ansi_quote => in_ansi_quote = false,
+			'\'' if !in_dquote => in_squote = !in_squote,
+			'"' if !in_squote && !in_ansi_quote => in_dquote = !in_dquote,
+			'#' if !in_squote && !in_dquote && !in_ansi_quote => {
+				let word_start = prev.is_none_or(|prev_ch| prev_ch.is_whitespace() || ";|&()".contains(prev_ch));
+				let subsh_shebang = prev == Some('(') && chars.peek() == Some(&'!');
+				if word_start && !subsh_shebang {
+					while chars.peek().is_some_and(|next_ch| *next_ch != '\n') {
+						chars.next();
+					}
+					continue
+				}
+			}
+			_ => { /* Do nothing */ }
+		}
+		result.push(ch);
+		prev = Some(ch);
+	}
+	result
+}
+
+/// A `<<` redirection found on a command line, waiting for its body
+struct HereDocOp {
+	/// Where the operator starts and the delimiter word ends, in bytes from the start of the input
+	span: (usize, usize),
+	delim: String,
+	quoted: bool,
+	strip_tabs: bool
+}
+
+/// Moves here-doc bodies from the lines after their command into the redirections themselves
+/// Each `<<delim` queues its delimiter, and once the line ends the lines after it are read as bodies in that order,
+/// so `cat <<A <<B` takes the lines up to `A` and then the lines up to `B` before anything else is read.
+/// The body takes the place of the delimiter as a double-quoted word, so it is expanded like a string,
+/// or as an ANSI-C quoted word if any part of the delimiter was quoted, so that it is left as-is.
+/// `<<-` strips leading tabs from the body and the closing line.
+/// Also returns the delimiters that never found their closing line, in the order that they are waiting in
+pub fn collect_heredocs(input: &str) -> (String, VecDeque<String>) {
+	let chars = input.char_indices().collect::<Vec<_>>();
+	let mut result = String::new();
+	let mut ops = VecDeque::new();
+	let mut copied = 0; // How much of the input is already in the result
+	let mut i = 0;
+	let mut prev: Option<char> = None;
+	let mut in_squote = false;
+	let mut in_dquote = false;
+	let mut in_ansi_quote = false; // `$'...'`, where a backslash can escape the closing quote
+	let mut in_comment = false;
+	while let Some(&(pos, ch)) = chars.get(i) {
+		i += 1;
+		if in_comment && ch != '\n' {
+			continue
+		}
+		let quoted = in_squote || in_dquote || in_ansi_quote;
+		match ch {
+			'\\' if !in_squote => {
+				i += 1;
+				prev = Some(ch);
+				continue
+			}
+			'$' if !quoted && chars.get(i).is_some_and(|(_, next_ch)| *next_ch == '\'') => {
+				in_ansi_quote = true;
+				i += 1;
+				prev = Some('\'');
+				continue
+			}
+			'\'' if in_ansi_quote => in_ansi_quote = false,
+			'\'' if !in_dquote => in_squote = !in_squote,
+			'"' if !in_squote && !in_ansi_quote => in_dquote = !in_dquote,
+			'#' if !quoted && prev.is_none_or(|prev_ch| prev_ch.is_whitespace() || ";|&()".contains(prev_ch)) => {
+				in_comment = true;
+			}
+			'<' if !quoted && chars.get(i).is_some_and(|(_, next_ch)| *next_ch == '<') => {
+				let (op, next_i) = read_heredoc_op(input, &chars, i - 1);
+				ops.extend(op);
+				i = next_i;
+				prev = chars.get(i - 1).map(|(_, ch)| *ch);
+				continue
+			}
+			'\n' if !quoted => {
+				in_comment = false;
+				if !ops.is_empty() {
+					let line_end = pos + 1;
+					let (bodies, body_len, pending) = read_heredoc_bodies(&input[line_end..], take(&mut ops));
+					result.push_str(&fill_heredocs(&input[copied..line_end], copied, bodies));
+					if !pending.is_empty() {
+						return (result, pending)
+					}
+					copied = line_end + body_len;
+					while chars.get(i).is_some_and(|(next_pos, _)| *next_pos < copied) {
+						i += 1;
+					}
+				}
+			}
+			_ => { /* Do nothing */ }
+		}
+		prev = Some(ch);
+	}
+	let pending = ops.iter().map(|op| op.delim.clone()).collect::<VecDeque<_>>();
+	let bodies = ops.into_iter().map(|op| (op, String::new())).collect();
+	result.push_str(&fill_heredocs(&input[copied..], copied, bodies));
+	(result, pending)
+}
+
+/// Reads the operator and delimiter of a here-doc starting at `chars[start]`, and returns the index of the char after them
+/// `<<<` is a here-string, and a quoted word spanning lines is a body that was already moved into place, so neither is returned
+fn read_heredoc_op(input: &str, chars: &[(usize, char)], start: usize) -> (Option<HereDocOp>, usize) {
+	let mut i = start + 2;
+	if chars.get(i).is_some_and(|(_, ch)| *ch == '<') {
+		return (None, i + 1)
+	}
+	let strip_tabs = chars.get(i).is_some_and(|(_, ch)| *ch == '-');
+	if strip_tabs {
+		i += 1;
+	}
+	while chars.get(i).is_some_and(|(_, ch)| *ch == ' ' || *ch == '\t') {
+		i += 1;
+	}
+	let word_start = i;
+	let mut delim = String::new();
+	let mut quoted = false;
+	let mut quote: Option<char> = None; // `$` stands for ANSI-C quotes, where a backslash escapes the closing quote
+	while let Some(&(_, ch)) = chars.get(i) {
+		i += 1;
+		match ch {
+			'$' if quote.is_none() && chars.get(i).is_some_and(|(_, next_ch)| *next_ch == '\'') => {
+				quoted = true;
+				quote = Some('$');
+				i += 1;
+			}
+			'\\' if quote != Some('\'') => {
+				quoted = true;
+				if let Some(&(_, esc_ch)) = chars.get(i) {
+					delim.push(esc_ch);
+					i += 1;
+				}
+			}
+			'\'' if quote == Some('$') => quote = None,
+			'\'' | '"' if quote.is_none() => {
+				quoted = true;
+				quote = Some(ch);
+			}
+			'\'' | '"' if quote == Some(ch) => quote = None,
+			_ if quote.is_none() && (ch.is_whitespace() || ";|&<>()".contains(ch)) => {
+				i -= 1;
+				break
+			}
+			_ => delim.push(ch)
+		}
+	}
+	let byte_at = |i: usize| chars.get(i).map(|(pos, _)| *pos).unwrap_or(input.len());
+	let word = &input[byte_at(word_start)..byte_at(i)];
+	// An empty body is written as `$''`, which would be an odd delimiter to choose
+	if word.is_empty() || word.contains('\n') || word == "$''" {
+		return (None, i)
+	}
+	let op = HereDocOp { span: (byte_at(start), byte_at(i)), delim, quoted, strip_tabs };
+	(Some(op), i)
+}
+
+/// Reads a body for each operator from the lines of `input`, in order
+/// Returns the bodies, how many bytes of input they took up, and the delimiters that the input ran out on
+fn read_heredoc_bodies(input: &str, mut ops: VecDeque<HereDocOp>) -> (Vec<(HereDocOp, String)>, usize, VecDeque<String>) {
+	let mut bodies = vec![];
+	let mut lines = input.split_inclusive('\n');
+	let mut len = 0;
+	while let Some(op) = ops.pop_front() {
+		let mut body = String::new();
+		let mut closed = false;
+		for line in lines.by_ref() {
+			len += line.len();
+			let line = if op.strip_tabs { line.trim_start_matches('\t') } else { line };
+			if line.trim_end_matches('\n') == op.delim {
+				closed = true;
+				break
+			}
+			body.push_str(line);
+		}
+		if !closed {
+			let mut pending = VecDeque::from([op.delim.clone()]);
+			pending.extend(ops.iter().map(|op| op.delim.clone()));
+			bodies.push((op, body));
+			bodies.extend(ops.into_iter().map(|op| (op, String::new())));
+			return (bodies, len, pending)
+		}
+		bodies.push((op, body));
+	}
+	(bodies, len, VecDeque::new())
+}
+
+/// Replaces each operator and delimiter in `segment`, which starts at `offset` in the input, with `<<` and the quoted body
+fn fill_heredocs(segment: &str, offset: usize, bodies: Vec<(HereDocOp, String)>) -> String {
+	let mut result = segment.to_string();
+	for (op, body) in bodies.into_iter().rev() {
+		let word = if op.quoted || body.is_empty() {
+			format!("$'{}'", body.replace('\\', "\\\\").replace('\'', "\\'"))
+		} else {
+			let mut word = String::from("\"");
+			let mut chars = body.chars().peekable();
+			while let Some(ch) = chars.next() {
+				match ch {
+					// A quote is just a character in a here-doc, so the escapes have to keep it that way
+					'"' => word.push_str("\\\""),
+					'\\' if chars.peek() == Some(&'"') => word.push_str("\\\\"),
+					_ => word.push(ch)
+				}
+			}
+			word.push('"');
+			word
+		};
+		result.replace_range(op.span.0 - offset..op.span.1 - offset, &format!("<<{}", word));
+	}
+	result
+}
+
+/// Expands `$name`
+/// `$FUNCNAME` is only the innermost function, like bash; `${FUNCNAME[@]}` gives the whole stack
+pub fn expand_var(name: &str, slash: &Slash) -> String {
+	let bare_name = name.strip_prefix('{').and_then(|name| name.strip_suffix('}')).unwrap_or(name);
+	match slash.vars().get_var(name) {
+		Some(SlashVal::Array(arr)) if bare_name == "FUNCNAME" => arr.first().map(|val| val.to_string()).unwrap_or_default(),
+		val => val.unwrap_or_default().to_string()
+	}
+}
+
+/// Expands `${!name}` to the value of the variable named by `name`,
+/// and `${!prefix*}` or `${!prefix@}` to the names of all variables starting with `prefix`
+pub fn expand_indirect(word: &str, slash: &Slash) -> String {
+	let body = word.trim_start_matches("${!").trim_end_matches('}');
+	match body.strip_suffix(['*','@']) {
+		Some(prefix) => prefix_names(prefix, slash).join(" "),
+		None => {
+			let target = slash.vars().get_var(body).unwrap_or_default().to_string();
+			if target.is_empty() {
+				return String::new()
+			}
+			slash.vars().get_var(&target).unwrap_or_default().to_string()
+		}
+	}
+}
+
+/// Expands `${name[key]}`, and `${name[@]}` or `${!name[@]}` for all of the values or keys
+/// Indexed arrays work the same way, with each element's index as its key
+pub fn expand_assoc(word: &str, slash: &mut Slash) -> SlashResult<String> {
+	let body = word.trim_start_matches("${").strip_suffix("]}").unwrap_or_default();
+	let (keys_only, body) = match body.strip_prefix('!') {
+		Some(body) => (true, body),
+		None => (false, body)
+	};
+	let (name, key) = body.split_once('[').unwrap_or((body, ""));
+	let Some(val) = slash.vars().get_var(name) else {
+		return Ok(String::new())
+	};
+	if key == "@" || key == "*" {
+		let entries: Vec<String> = match (&val, keys_only) {
+			(SlashVal::Dict(dict), true) => dict.keys().cloned().collect(),
+			(SlashVal::Dict(dict), false) => dict.values().map(|val| val.to_string()).collect(),
+			(SlashVal::Array(arr), true) => (0..arr.len()).map(|i| i.to_string()).collect(),
+			(SlashVal::Array(arr), false) => arr.iter().map(|val| val.to_string()).collect(),
+			(_, true) => vec!["0".into()],
+			(val, false) => vec![val.to_string()]
+		};
+		return Ok(entries.join(" "))
+	}
+	let key = expand_key(key, slash)?;
+	let elem = match val {
+		SlashVal::Dict(dict) => dict.get(&key).cloned(),
+		SlashVal::Array(arr) => key.parse::<usize>().ok().and_then(|i| arr.get(i).cloned()),
+		_ => None
+	};
+	Ok(elem.map(|val| val.to_string()).unwrap_or_default())
+}
+
+/// Expands the key inside of `name[key]`, which may be quoted or contain expansions
+pub fn expand_key(key: &str, slash: &mut Slash) -> SlashResult<String> {
+	let key = key.replace("\\]", "]");
+	match key.as_pair_from_rule(Rule::word) {
+		Some(pair) => Ok(helper::try_expansion(slash, pair)?.trim_quotes()),
+		None => Ok(key)
+	}
+}
+
+/// Expands `${name@op}`, which transforms the value of `name`
+/// `Q` quotes it so that it reads back as the same string, `E` expands ANSI-C escapes in it, and `A` gives an assignment that recreates the variable.
+/// `U` and `L` change the case of the whole value, and `u` only of the first character
+pub fn expand_transform(word: &str, slash: &Slash) -> String {
+	let body = word.trim_start_matches("${").strip_suffix('}').unwrap_or_default();
+	let Some((name, op)) = body.rsplit_once('@') else {
+		return String::new()
+	};
+	let Some(val) = slash.vars().get_var(name) else {
+		return String::new()
+	};
+	let value = val.to_string();
+	match op {
+		"Q" => quote_for_reuse(&value),
+		"E" => super::string::expand_ansi_c_escapes(&value),
+		"A" => {
+			let flags = slash.vars().get_flags(name);
+			if flags.is_empty() {
+				format!("{}={}", name, quote_for_reuse(&value))
+			} else {
+				crate::builtin::declare::render_var(name, &val, flags)
+			}
+		}
+		"U" => value.to_uppercase(),
+		"L" => value.to_lowercase(),
+		"u" => {
+			let mut chars = value.chars();
+			chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+		}
+		_ => value
+	}
+}
+
+/// Single-quotes a value, or ANSI-C quotes it if it has single quotes, backslashes, or control characters in it
+pub fn quote_for_reuse(value: &str) -> String {
+	// A backslash before the closing quote would escape it, so backslashes need ANSI-C quoting too
+	if !value.contains(|ch: char| ch == '\'' || ch == '\\' || ch.is_control()) {
+		return format!("'{}'", value)
+	}
+	let mut quoted = String::from("$'");
+	for ch in value.chars() {
+		match ch {
+			'\'' | '\\' => {
+				quoted.push('\\');
+				quoted.push(ch);
+			}
+			'\n' => quoted.push_str("\\n"),
+			'\t' => quoted.push_str("\\t"),
+			'\r' => quoted.push_str("\\r"),
+			'\x1b' => quoted.push_str("\\E"),
+			_ if ch.is_control() => quoted.push_str(&format!("\\x{:02x}", ch as u32)),
+			_ => quoted.push(ch)
+		}
+	}
+	quoted.push('\'');
+	quoted
+}
+
+/// Expands `${name:-word}`, `${name:=word}`, `${name:+word}`, and `${name:?word}`
+/// Without the colon, only an unset variable counts as missing, rather than an unset or empty one
+pub fn expand_default(word: &str, slash: &mut Slash) -> SlashResult<String> {
+	let body = word.trim_start_matches("${").strip_suffix('}').unwrap_or_default();
+	let name_len = body.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '.')).unwrap_or(body.len());
+	let (name, rest) = body.split_at(name_len);
+	let (check_empty, rest) = match rest.strip_prefix(':') {
+		Some(rest) => (true, rest),
+		None => (false, rest)
+	};
+	let op = rest.chars().next().unwrap_or_default();
+	let operand = &rest[op.len_utf8()..];
+
+	let value = slash.vars().get_var(name).map(|val| val.to_string());
+	let missing = match &value {
+		Some(val) => check_empty && val.is_empty(),
+		None => true
+	};
+	let expand_operand = |slash: &mut Slash| -> SlashResult<String> {
+		match operand.as_pair_from_rule(Rule::word) {
+			Some(pair) => Ok(helper::try_expansion(slash, pair)?.trim_quotes()),
+			None => Ok(operand.to_string())
+		}
+	};
+	match op {
+		'-' if missing => expand_operand(slash),
+		'=' if missing => {
+			let new_val = expand_operand(slash)?;
+			slash.vars_mut().set_var(name, SlashVal::parse(&new_val).unwrap_or(SlashVal::String(new_val.clone())));
+			Ok(new_val)
+		}
+		'+' if missing => Ok(String::new()),
+		'+' => expand_operand(slash),
+		'?' if missing => {
+			let msg = expand_operand(slash)?;
+			let msg = if msg.is_empty() { "parameter null or not set".to_string() } else { msg };
+			Err(Low(SlashErrLow::ExecFailed(format!("{}: {}",name,msg))))
+		}
+		_ => Ok(value.unwrap_or_default())
+	}
+}
+
+/// Expands a special or positional parameter like `$1` or `$#`
+/// In double quotes, `$*` is joined on the first character of `$IFS`
+/// A `"$@"` in a command's arguments never gets here, since `take_word_lists()` turns each parameter into its own word
+pub fn expand_param(name: &str, quoted: bool, slash: &Slash) -> String {
+	let params = slash.vars().borrow_pos_params();
+	match name {
+		"*" if quoted => {
+			let ifs = slash.vars().get_var("IFS").map(|ifs| ifs.to_string()).unwrap_or_else(|| " ".into());
+			let sep = ifs.chars().next().map(String::from).unwrap_or_default();
+			params.iter().cloned().collect::<Vec<_>>().join(&sep)
+		}
+		"*" | "@" => params.iter().cloned().collect::<Vec<_>>().join(" "),
+		_ => slash.vars().get_param(name).unwrap_or_default()
+	}
+}
+
+/// Whether the byte offset `pos` in `input` falls inside of double quotes
+pub fn in_dquotes(input: &str, pos: usize) -> bool {
+	let mut dquoted = false;
+	let mut squoted = false;
+	let mut chars = input[..pos].chars();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' if !squoted => { chars.next(); }
+			'"' if !squoted => dquoted = !dquoted,
+			'\'' if !dquoted => squoted = !squoted,
+			_ => {}
+		}
+	}
+	dquoted
+}
+
+/// The names of all variables that start with `prefix`, in sorted order
+fn prefix_names(prefix: &str, slash: &Slash) -> Vec<String> {
+	let vars = slash.vars();
+	let mut names = vars.vars().keys()
+		.chain(vars.borrow_evars().keys())
+		.filter(|name| name.starts_with(prefix))
+		.cloned()
+		.collect::<Vec<String>>();
+	names.sort();
+	names.dedup();
+	names
+}
+
+/// The length of the `${!prefix@}` at the start of `input`, if there is one
+fn prefix_at_len(input: &str) -> Option<usize> {
+	let prefix = input.strip_prefix("${!")?;
+	let name_len = prefix.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_')).unwrap_or(prefix.len());
+	prefix[name_len..].starts_with("@}").then_some(3 + name_len + 2)
+}
+
+/// Takes the place of an expansion that gives a list of words while the rest of its word is expanded
+const WORD_LIST_MARK: char = '\u{E000}';
+
+/// Finds each `$@` and `${!prefix@}` in double quotes in `word`, which expand to one word per parameter or variable name
+/// Returns the word with a mark in place of each one, along with the words that go there,
+/// or None if there aren't any, in which case the word is expanded like any other
+pub fn take_word_lists(word: &str, slash: &Slash) -> Option<(String, Vec<Vec<String>>)> {
+	let mut marked = String::new();
+	let mut lists = vec![];
+	let mut rest = word;
+	while let Some(i) = rest.find('$') {
+		let pos = word.len() - rest.len() + i;
+		let (before, after) = rest.split_at(i);
+		marked.push_str(before);
+		let quoted = in_dquotes(word, pos) && !before.ends_with('\\');
+		if quoted && after.starts_with("$@") {
+			marked.push(WORD_LIST_MARK);
+			lists.push(slash.vars().borrow_pos_params().iter().cloned().collect());
+			rest = &after[2..];
+		} else if let Some(len) = prefix_at_len(after).filter(|_| quoted) {
+			marked.push(WORD_LIST_MARK);
+			lists.push(prefix_names(&after[3..len - 2], slash));
+			rest = &after[len..];
+		} else {
+			marked.push('$');
+			rest = &after[1..];
+		}
+	}
+	marked.push_str(rest);
+	(!lists.is_empty()).then_some((marked, lists))
+}
+
+/// Puts the lists from `take_word_lists()` back into the expanded word
+/// The text before a list sticks to its first word and the text after it to its last,
+/// so `"-$@-"` with the parameters `a b` gives `-a` and `b-`, and a lone `"$@"` with no parameters gives no words at all
+pub fn fill_word_lists(expanded: &str, lists: Vec<Vec<String>>) -> Vec<String> {
+	let mut segments = expanded.split(WORD_LIST_MARK);
+	let mut words = vec![segments.next().unwrap_or_default().to_string()];
+	let mut any_items = false;
+	for (list, segment) in lists.into_iter().zip(segments) {
+		let mut items = list.into_iter();
+		if let Some(first) = items.next() {
+			any_items = true;
+			words.last_mut().unwrap().push_str(&first);
+			words.extend(items);
+		}
+		words.last_mut().unwrap().push_str(segment);
+	}
+	if !any_items && words.iter().all(|word| word.is_empty()) {
+		return vec![]
+	}
+	words
+}
+
 pub fn expand_shebang(slash: &mut Slash,shebang: &str) -> String {
 	let mut command = shebang.trim_start_matches("#!").trim().to_string();
 	if command.has_unescaped("/") {
@@ -98,6 +668,220 @@ pub fn expand_esc<'a>(slash: &mut Slash,pair: Pair<'a,Rule>) -> SlashResult<Stri
 		Rule::esc_exit_code => helper::escseq_exitcode(slash)?,
 		Rule::esc_success_symbol => helper::escseq_success(slash)?,
 		Rule::esc_failure_symbol => helper::escseq_fail(slash)?,
+		Rule::esc_job_count => crate::shellenv::read_jobs(helper::escseq_job_count)?,
+		Rule::esc_hist_number => helper::escseq_hist_number(slash)?,
+		Rule::esc_cmd_number => slash.meta().cmd_number().to_string(),
 		_ => unreachable!("Got this rule in prompt expansion: {:?}",pair.as_rule())
 	})
 }
+
+#[cfg(test)]
+mod tests {
+use super::*;
+
+	#[test]
+	fn test_full_line_comment() {
+		assert_eq!(strip_comments("# a comment\necho foo"), "\necho foo");
+	}
+
+	#[test]
+	fn test_trailing_comment() {
+		assert_eq!(strip_comments("echo foo # a comment"), "echo foo ");
+		assert_eq!(strip_comments("echo \"foo # not a comment\""), "echo \"foo # not a comment\"");
+	}
+
+	#[test]
+	fn test_comment_mid_word() {
+		assert_eq!(strip_comments("echo a#b"), "echo a#b");
+	}
+
+	#[test]
+	fn test_comment_in_ansi_quote() {
+		// The escaped quote doesn't close the word, so the `#` is still quoted
+		assert_eq!(strip_comments("echo $'it\\'s # x' # comment"), "echo $'it\\'s # x' ");
+		assert_eq!(strip_comments("echo 'a\\' # comment"), "echo 'a\\' ");
+	}
+
+	#[test]
+	fn test_continuation_top_level() {
+		assert_eq!(join_continuations("echo foo \\\nbar"), "echo foo bar");
+		assert_eq!(join_continuations("echo foo \\\nbar\necho \\\n\\\nbaz\ntrue"), "echo foo bar\n\necho baz\n\n\ntrue");
+	}
+
+	#[test]
+	fn test_continuation_dquote() {
+		assert_eq!(join_continuations("echo \"foo \\\nbar\""), "echo \"foo bar\"");
+		assert_eq!(join_continuations("echo \"foo \\\nbar\nbaz\"\ntrue"), "echo \"foo bar\nbaz\"\n\ntrue");
+	}
+
+	#[test]
+	fn test_continuation_squote() {
+		assert_eq!(join_continuations("echo 'foo \\\nbar'"), "echo 'foo \\\nbar'");
+	}
+
+	#[test]
+	fn test_indirect_expansion() {
+		let mut slash = Slash::new();
+		crate::execute::dispatch::exec_input("y=z; x=y; result=${!x}".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("result"), Some(crate::shellenv::SlashVal::String("z".into())));
+	}
+
+	#[test]
+	fn test_prompt_job_count() {
+		use crate::shellenv::{ChildProc, JobBuilder, JobTable};
+		// Other tests put jobs in the shell's job table, so this counts the jobs in a table of its own
+		let mut table = JobTable::new();
+		assert_eq!(helper::escseq_job_count(&table), "0");
+		for i in 0..2 {
+			// Pids that can't exist, so nothing real gets touched
+			let pid = Pid::from_raw(1_000_000_000 + i);
+			let child = ChildProc::new(pid, Some("sleep 10"), None).unwrap();
+			table.insert_job(JobBuilder::new().with_pgid(pid).with_children(vec![child]).build(), true).unwrap();
+		}
+		assert_eq!(helper::escseq_job_count(&table), "2");
+
+		let _lock = crate::test_util::lock_process();
+		let mut slash = Slash::new();
+		slash.vars_mut().export_var("PS1", "\\#>");
+		assert_eq!(expand_prompt(None, &mut slash).unwrap(), "1>");
+	}
+
+	#[test]
+	fn test_prompt_hist_number() {
+		use crate::prompt::history::{write_hist_file, HistEntry};
+		let _lock = crate::test_util::lock_process();
+		let mut slash = Slash::new();
+		let saved_hist = env::var("HIST_FILE").ok();
+		let path = crate::test_util::temp_path("prompt_hist");
+		let entries = ["ls", "pwd"].map(|command| HistEntry { time: None, dir: None, command: command.into() });
+		write_hist_file(&path, &entries).unwrap();
+		slash.vars_mut().export_var("HIST_FILE", path.to_str().unwrap());
+		slash.vars_mut().export_var("PS1", "\\!>");
+		assert_eq!(expand_prompt(None, &mut slash).unwrap(), "3>");
+
+		// A change to the file from elsewhere is picked up
+		std::fs::write(&path, "#V2\nls\n").unwrap();
+		assert_eq!(expand_prompt(None, &mut slash).unwrap(), "2>");
+
+		match saved_hist {
+			Some(hist) => slash.vars_mut().export_var("HIST_FILE", &hist),
+			None => slash.vars_mut().unset_evar("HIST_FILE")
+		}
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn test_prompt_trunc_path() {
+		let _lock = crate::test_util::lock_process();
+		let mut slash = Slash::new();
+		let saved_pwd = slash.vars().get_evar("PWD").unwrap_or_default();
+		let home = slash.vars().get_evar("HOME").unwrap_or_default();
+		slash.vars_mut().export_var("PS1", "\\w");
+		slash.meta_mut().set_shopt("prompt.trunc_prompt_path", "3").unwrap();
+
+		slash.vars_mut().export_var("PWD", "/tmp/deep/path/to/last/two/dirs");
+		let deep = expand_prompt(None, &mut slash).unwrap();
+		slash.vars_mut().export_var("PWD", "/tmp/two/dirs");
+		let shallow = expand_prompt(None, &mut slash).unwrap();
+		slash.vars_mut().export_var("PWD", &format!("{}/a/b/c/d",home));
+		let in_home = expand_prompt(None, &mut slash).unwrap();
+		slash.meta_mut().set_shopt("prompt.trunc_prompt_path", "0").unwrap();
+		let untruncated = expand_prompt(None, &mut slash).unwrap();
+		slash.vars_mut().export_var("PWD", &saved_pwd);
+
+		assert_eq!(deep, ".../last/two/dirs");
+		assert_eq!(shallow, "/tmp/two/dirs");
+		assert_eq!(in_home, ".../b/c/d");
+		assert_eq!(untruncated, "~/a/b/c/d");
+	}
+
+	#[test]
+	fn test_prefix_listing() {
+		let mut slash = Slash::new();
+		crate::execute::dispatch::exec_input("PFX_b=1; PFX_a=2; other=3".to_string(), &mut slash).unwrap();
+		crate::execute::dispatch::exec_input("star=\"${!PFX_*}\"; at=\"${!PFX_@}\"".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("star").unwrap().to_string(), "PFX_a PFX_b");
+		assert_eq!(slash.vars().get_var("at").unwrap().to_string(), "PFX_a PFX_b");
+
+		// As an argument, the quoted `@` form gives one word per name like "$@" does, and the `*` form gives one word
+		let output = crate::test_util::output_of("count() { echo $#; }; count \"${!PFX_@}\"; count \"${!PFX_*}\"; count \"${!NO_SUCH_PFX@}\"", &mut slash);
+		assert_eq!(output, "2\n1\n0\n");
+	}
+
+	#[test]
+	fn test_quoted_at_words() {
+		let mut slash = Slash::new();
+		// The parameters go into the arguments as they are, so quotes inside of them are just characters
+		let input = "count() { echo $#; }\nset -- 'a\"b' c; count \"$@\"; printf '%s|' \"$@\"; echo; count \"-$@-\"; set --; count \"$@\"; count \"x$@y\"";
+		assert_eq!(crate::test_util::output_of(input, &mut slash), "2\na\"b|c|\n2\n0\n1\n");
+
+		assert_eq!(fill_word_lists("-\u{E000}-", vec![vec!["a".into(), "b".into()]]), ["-a", "b-"]);
+		assert!(fill_word_lists("\u{E000}", vec![vec![]]).is_empty());
+		assert_eq!(fill_word_lists("\u{E000}", vec![vec!["".into()]]), [""]);
+	}
+
+	#[test]
+	fn test_quoted_params_ifs() {
+		let mut slash = Slash::new();
+		let input = "count() { echo $#; }\nset -- a b c; IFS=,; star=\"$*\"; count \"$@\"; count \"$*\"; IFS=:-; colon=\"$*\"";
+		let output = crate::test_util::output_of(input, &mut slash);
+
+		assert_eq!(slash.vars().get_var("star").unwrap().to_string(), "a,b,c");
+		// Only the first character of $IFS is used
+		assert_eq!(slash.vars().get_var("colon").unwrap().to_string(), "a:b:c");
+		// "$@" is three words, "$*" is one
+		assert_eq!(output, "3\n1\n");
+	}
+
+	#[test]
+	fn test_last_arg_param() {
+		let mut slash = Slash::new();
+		assert!(slash.vars().get_param("_").is_some_and(|shell| !shell.is_empty()));
+
+		let output = crate::test_util::output_of("echo a b c 2> /dev/null; echo $_; true x && echo \"[$_]\"", &mut slash);
+
+		// The redirection isn't an argument, so it doesn't count as the last one
+		assert_eq!(output, "a b c\nc\n[x]\n");
+		assert_eq!(slash.vars().get_param("_"), Some("[x]".into()));
+	}
+
+	#[test]
+	fn test_collect_heredocs() {
+		// Both bodies follow the command line, in the order of their operators
+		let (input, pending) = collect_heredocs("cat <<A 3<<-'B'; echo done\none $X\nA\n\ttwo $X\n\tB\necho next\n");
+		assert_eq!(input, "cat <<\"one $X\n\" 3<<$'two $X\n'; echo done\necho next\n");
+		assert!(pending.is_empty());
+		// Collecting again leaves the bodies alone, since function bodies and the like are executed again later
+		assert_eq!(collect_heredocs(&input).0, input);
+
+		assert_eq!(collect_heredocs("cat <<<word <<\"E\" # <<C\n\"hi\"\nE\n").0, "cat <<<word <<$'\"hi\"\n' # <<C\n");
+		assert_eq!(collect_heredocs("cat <<E\nE\n").0, "cat <<$''\n");
+
+		let (_, pending) = collect_heredocs("cat <<A <<B\none\nA\ntwo\n");
+		assert_eq!(pending, VecDeque::from(["B".to_string()]));
+		let (_, pending) = collect_heredocs("cat <<A <<B");
+		assert_eq!(pending, VecDeque::from(["A".to_string(), "B".to_string()]));
+	}
+
+	#[test]
+	fn test_transform_expansions() {
+		let mut slash = Slash::new();
+		crate::execute::dispatch::exec_input("x='hello World'; up=${x@U}; low=${x@L}; first=${x@u}; assign=${x@A}; e='a\\tb'; tab=${e@E}".to_string(), &mut slash).unwrap();
+		let var = |name: &str, slash: &Slash| slash.vars().get_var(name).unwrap().to_string();
+		assert_eq!(var("up", &slash), "HELLO WORLD");
+		assert_eq!(var("low", &slash), "hello world");
+		assert_eq!(var("first", &slash), "Hello World");
+		assert_eq!(var("assign", &slash), "x='hello World'");
+		assert_eq!(var("tab", &slash), "a\tb");
+
+		// Quoting gives back the same string once it is parsed again
+		for value in ["two words", "it's a \"quoted\" value\\"] {
+			slash.vars_mut().set_var("x", SlashVal::String(value.into()));
+			let quoted = expand_transform("${x@Q}", &slash);
+			crate::execute::dispatch::exec_input(format!("y={quoted}"), &mut slash).unwrap();
+			assert_eq!(var("y", &slash), value, "{}", quoted);
+		}
+		assert_eq!(expand_transform("${x@Q}", &slash), "$'it\\'s a \"quoted\" value\\\\'");
+		assert_eq!(expand_transform("${unset_var@Q}", &slash), "");
+	}
+}
@@ -17,13 +17,12 @@ pub fn expand_string(pair: Pair<Rule>, slash: &mut Slash) -> SlashResult<String>
 		} else {
 			let sub_type = inner.next().unpack()?;
 			let expanded = match sub_type.as_rule() {
-				Rule::var_sub => {
-					slash.vars().get_var(&word.as_str()[1..]).unwrap_or_default().to_string()
-				}
-				Rule::param_sub => {
-					let param = slash.vars().get_param(&word.as_str()[1..]).unwrap_or_default().to_string();
-					param
-				}
+				Rule::var_sub => super::misc::expand_var(&word.as_str()[1..],slash),
+				Rule::var_assoc => super::misc::expand_assoc(word.as_str(),slash)?,
+				Rule::var_indirect => super::misc::expand_indirect(word.as_str(),slash),
+				Rule::var_transform => super::misc::expand_transform(word.as_str(),slash),
+				Rule::var_default => super::misc::expand_default(word.as_str(),slash)?,
+				Rule::param_sub => super::misc::expand_param(&word.as_str()[1..], true, slash),
 				Rule::cmd_sub => {
 					let result = super::cmdsub::expand_cmd_sub(word,slash)?;
 					result
@@ -38,3 +37,150 @@ pub fn expand_string(pair: Pair<Rule>, slash: &mut Slash) -> SlashResult<String>
 	result = format!("\"{}\"",result);
 	Ok(result)
 }
+
+/// Expands ANSI-C quoted strings like `$'a\tb'`
+/// The result is a literal, and is not subject to any further expansion
+pub fn expand_ansi_c(pair: Pair<Rule>) -> String {
+	let body = pair.scry(Rule::ansi_body).map(|body| body.as_str()).unwrap_or_default();
+	expand_ansi_c_escapes(body)
+}
+
+/// Expands the escape sequences of ANSI-C quoting in a string that isn't quoted itself, like for `${name@E}`
+pub fn expand_ansi_c_escapes(body: &str) -> String {
+	unescape(body, false).0
+}
+
+/// Expands a here-doc body from the word that `collect_heredocs` moved it into
+/// A body behind a quoted delimiter is ANSI-C quoted and only has its escapes undone. Any other body is expanded like a double-quoted string
+pub fn expand_heredoc(word: &str, slash: &mut Slash) -> SlashResult<String> {
+	if let Some(body) = word.strip_prefix("$'").and_then(|word| word.strip_suffix('\'')) {
+		return Ok(unescape(body, false).0)
+	}
+	let expanded = match SlashParse::parse(Rule::word, word) {
+		Ok(mut pairs) => super::dispatch::expand_word(pairs.next().unwrap(), slash)?,
+		Err(_) => return Ok(word.to_string())
+	};
+	let body = expanded.strip_prefix('"').and_then(|body| body.strip_suffix('"')).unwrap_or(&expanded);
+	// Only the escapes that mean something in double quotes are undone, so a lone backslash stays
+	let mut result = String::new();
+	let mut chars = body.chars();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			result.push(ch);
+			continue
+		}
+		match chars.next() {
+			Some(esc_ch @ ('"' | '\\' | '$' | '`')) => result.push(esc_ch),
+			Some(esc_ch) => {
+				result.push(ch);
+				result.push(esc_ch);
+			}
+			None => result.push(ch)
+		}
+	}
+	Ok(result)
+}
+
+/// Expands the escape sequences that `echo -e` understands, which are the ANSI-C ones apart from `\'`, `\"` and `\?`
+/// Octal values are written as `\0nnn`, and `\c` ends the output, which is signalled by the returned bool
+pub fn expand_echo_escapes(arg: &str) -> (String, bool) {
+	unescape(arg, true)
+}
+
+fn unescape(body: &str, echo: bool) -> (String, bool) {
+	let mut result = String::new();
+	let mut chars = body.chars().peekable();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			result.push(ch);
+			continue
+		}
+		let Some(esc_ch) = chars.next() else {
+			result.push(ch);
+			break
+		};
+		match esc_ch {
+			'n' => result.push('\n'),
+			't' => result.push('\t'),
+			'r' => result.push('\r'),
+			'a' => result.push('\x07'),
+			'b' => result.push('\x08'),
+			'e' | 'E' => result.push('\x1b'),
+			'f' => result.push('\x0c'),
+			'v' => result.push('\x0b'),
+			'\\' => result.push(esc_ch),
+			'\'' | '"' | '?' if !echo => result.push(esc_ch),
+			'c' if echo => return (result, true),
+			'x' => push_code_point(&mut result, &mut chars, 16, 2).unwrap_or_else(|| result.push_str("\\x")),
+			'u' => push_code_point(&mut result, &mut chars, 16, 4).unwrap_or_else(|| result.push_str("\\u")),
+			'U' => push_code_point(&mut result, &mut chars, 16, 8).unwrap_or_else(|| result.push_str("\\U")),
+			'0'..='7' => {
+				// A leading zero is followed by up to three more digits, like `\0101`, otherwise up to three digits are taken in all
+				let mut octal = esc_ch.to_string();
+				let max_len = if esc_ch == '0' { 4 } else { 3 };
+				while octal.len() < max_len && chars.peek().is_some_and(|ch| ch.is_digit(8)) {
+					octal.push(chars.next().unwrap());
+				}
+				let code = u32::from_str_radix(&octal, 8).unwrap();
+				result.push(char::from_u32(code).unwrap_or_default());
+			}
+			_ => {
+				result.push(ch);
+				result.push(esc_ch);
+			}
+		}
+	}
+	(result, false)
+}
+
+/// Consumes up to `max_len` digits in the given radix, and pushes the resulting character
+/// Returns None if there were no digits to consume
+fn push_code_point(result: &mut String, chars: &mut std::iter::Peekable<std::str::Chars>, radix: u32, max_len: usize) -> Option<()> {
+	let mut digits = String::new();
+	while digits.len() < max_len && chars.peek().is_some_and(|ch| ch.is_digit(radix)) {
+		digits.push(chars.next().unwrap());
+	}
+	if digits.is_empty() {
+		return None
+	}
+	let code = u32::from_str_radix(&digits, radix).ok()?;
+	result.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+	Some(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute;
+
+use super::*;
+
+	fn ansi_c(input: &str) -> String {
+		let pair = SlashParse::parse(Rule::ansi_quoted, input).unwrap().next().unwrap();
+		expand_ansi_c(pair)
+	}
+
+	#[test]
+	fn test_ansi_c_hex() {
+		assert_eq!(ansi_c("$'\\x41\\x42'"), "AB");
+	}
+
+	#[test]
+	fn test_octal_escapes() {
+		assert_eq!(ansi_c("$'\\0101\\101\\0'"), "AA\0");
+		assert_eq!(expand_echo_escapes("\\0101\\101"), ("AA".to_string(), false));
+	}
+
+	#[test]
+	fn test_ansi_c_tab() {
+		assert_eq!(ansi_c("$'a\\tb'"), "a\tb");
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("tabbed=$'a\\tb'".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("tabbed").unwrap().to_string(), "a\tb");
+	}
+
+	#[test]
+	fn test_ansi_c_unterminated() {
+		let mut slash = Slash::new();
+		assert!(execute::dispatch::exec_input("echo $'abc".to_string(), &mut slash).is_err());
+	}
+}
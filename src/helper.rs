@@ -4,8 +4,8 @@ use std::os::unix::fs::PermissionsExt;
 use io::Read;
 use nix::unistd::getpgrp;
 
-use crate::{expand, prelude::*, utils};
-use crate::{utils::REGEX, error::{SlashErr, SlashErrHigh, SlashErrLow}, shellenv::{self, attach_tty, disable_reaping, enable_reaping, write_jobs, DisplayWaitStatus, HashFloat, Job, Slash, SlashVal}, SlashResult};
+use crate::{builtin, expand, prelude::*, prompt, utils};
+use crate::{utils::REGEX, error::{SlashErr, SlashErrHigh, SlashErrLow}, shellenv::{self, attach_tty, disable_reaping, enable_reaping, read_jobs, write_jobs, DisplayWaitStatus, EnvFlags, HashFloat, Job, JobTable, Slash, SlashVal}, SlashResult};
 
 
 #[macro_export]
@@ -125,8 +125,8 @@ impl StrExtension for str {
 	/// If some other string is longer than this one, extend this string by the difference by taking characters from the other
 	fn fill_from(&self, other: &str) -> String {
 		let mut result = self.to_string();
-		if self.len() < other.len() {
-			let clipped = other[self.len()..].to_string();
+		// `self` is usually styled, so its length may not land on a char boundary in `other`
+		if let Some(clipped) = other.get(self.len()..).filter(|clipped| !clipped.is_empty()) {
 			result = format!("{}{}",self,clipped);
 		}
 		result
@@ -394,29 +394,34 @@ impl StrExtension for str {
 }
 
 pub fn validate_autocd(slash: &mut Slash,argv: &VecDeque<String>) -> SlashResult<bool> {
-	if slash.meta().get_shopt("core.autocd").is_ok_and(|opt| opt.parse::<bool>().unwrap()) && argv.len() == 1 {
+	if slash.meta().get_shopt("core.autocd").is_ok_and(|opt| opt.parse::<bool>().unwrap_or(false)) && argv.len() == 1 {
 		let candidate = argv.front().unwrap();
-		Ok(Path::new(candidate).is_dir())
+		// Commands found in PATH take precedence over directories of the same name
+		let is_cmd = !candidate.contains('/') && which(slash, candidate).is_some();
+		Ok(!is_cmd && Path::new(candidate).is_dir())
 	} else {
 		Ok(false)
 	}
 }
 
 pub fn try_expansion<'a>(slash: &mut Slash,pair: Pair<'a,Rule>) -> SlashResult<String> {
-	if pair.contains_rules(&[Rule::expand_word,Rule::dquoted][..]) {
+	if let Some(ansi_quoted) = pair.scry(Rule::ansi_quoted) {
+		Ok(expand::string::expand_ansi_c(ansi_quoted))
+	} else if pair.contains_rules(&[Rule::expand_word,Rule::dquoted][..]) {
 		expand::dispatch::expand_word(pair,slash)
 	} else {
 		Ok(pair.as_str().to_string())
 	}
 }
 
-pub fn try_glob(words: VecDeque<String>) -> VecDeque<String> {
+pub fn try_glob(words: VecDeque<String>, nocase: bool) -> VecDeque<String> {
 	let mut globs = VecDeque::new();
+	let options = glob::MatchOptions { case_sensitive: !nocase, ..glob::MatchOptions::new() };
 	for word in &words {
 		if !word.has_unescaped("*") && !word.has_unescaped("?") && !REGEX["glob_braces"].is_match(&word) {
 			return words
 		}
-		if let Ok(results) = glob::glob(&word) {
+		if let Ok(results) = glob::glob_with(&word, options) {
 			for entry in results {
 				if let Ok(path) = entry {
 					globs.push_back(path.to_str().unwrap().to_string());
@@ -432,8 +437,8 @@ pub fn try_tilde(words: VecDeque<String>) -> VecDeque<String> {
 	for word in &words {
 		if !word.starts_with('~') {
 			if let Some(pair) = word.as_pair_from_rule(Rule::arg_assign) {
-				let value = pair.scry(Rule::word).unwrap();
-				if !value.as_str().starts_with('~') {
+				// Array values don't have a word to expand
+				if !pair.scry(Rule::word).is_some_and(|value| value.as_str().starts_with('~')) {
 					return words
 				}
 			} else {
@@ -456,23 +461,71 @@ pub fn prepare_argv<'a>(pair: Pair<'a,Rule>,slash: &mut Slash) -> SlashResult<Ve
 	let mut args = VecDeque::new();
 	let mut inner = pair.into_inner().filter(|pr| matches!(pr.as_rule(), Rule::cmd_name | Rule::arg_assign | Rule::word));
 	while let Some(pair) = inner.next() {
+		if pair.as_rule() == Rule::word && pair.contains_rules(Rule::ansi_quoted) {
+			// The result of ANSI-C quoting is already a literal
+			args.push_back(try_expansion(slash,pair)?);
+			continue
+		}
+		if let Some((marked, lists)) = expand::misc::take_word_lists(pair.as_str(), slash) {
+			// Each item of "$@" or "${!prefix@}" is its own word, so the items go in after the rest of the word is expanded and unquoted
+			let expanded = match marked.as_pair_from_rule(Rule::word) {
+				Some(marked) => try_expansion(slash,marked)?,
+				None => marked.clone()
+			};
+			args.extend(expand::misc::fill_word_lists(&expanded.trim_quotes(), lists));
+			continue
+		}
 		let word = pair.as_str().trim_quotes().to_string();
+		let is_quoted = pair.contains_rules(&[Rule::dquoted,Rule::squoted][..]);
 		let expanded = VecDeque::from(vec![try_expansion(slash,pair)?]);
-		let expanded_ext = try_glob(expanded.clone());
+		let extglob = slash.meta().get_shopt("core.extglob")?.parse::<bool>().unwrap_or(false);
+		let nocaseglob = slash.meta().get_shopt("core.nocaseglob")?.parse::<bool>().unwrap_or(false);
+		let expanded_ext = if extglob && !is_quoted && expand::glob::has_extglob(&expanded[0]) {
+			let dotglob = slash.meta().get_shopt("core.dotglob")?.parse::<bool>().unwrap_or(false);
+			expand::glob::filter_matches(expand::glob::expand_extglob(&expanded[0], dotglob, nocaseglob), slash).into()
+		} else {
+			let globbed = try_glob(expanded.clone(), nocaseglob);
+			if globbed == expanded {
+				globbed
+			} else {
+				expand::glob::filter_matches(globbed.into(), slash).into()
+			}
+		};
 		let expanded_ext = try_tilde(expanded_ext);
 		if !expanded_ext.is_empty() {
 			for word in expanded_ext {
 				args.push_back(word.trim_quotes());
 			}
+		} else if !is_quoted && slash.meta().get_shopt("core.nullglob")?.parse::<bool>().unwrap_or(false) {
+			// A pattern that matched nothing goes away entirely
+			continue
 		} else {
 			for word in expanded {
 				args.push_back(word.trim_quotes());
 			}
 		}
 	}
+	// `$_` has to be set after the expansion, so that the arguments that use it still get the last command's argument
+	if let Some(last) = args.back() {
+		slash.vars_mut().set_param("_", last);
+	}
+	if slash.meta().flags().contains(EnvFlags::STACK_TRACE) && !args.is_empty() {
+		let traced = args.iter().map(|arg| quote_traced(arg)).collect::<Vec<_>>();
+		let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+		writeln!(stderr, "+ {}", traced.join(" "))?;
+	}
 	Ok(args)
 }
 
+/// Quotes an argument for `set -x` output if it wouldn't come back as the same word without quotes
+fn quote_traced(arg: &str) -> String {
+	if arg.is_empty() || arg.contains(|ch: char| ch.is_whitespace() || "'\"\\$`|&;<>(){}*?[]#~".contains(ch)) {
+		expand::misc::quote_for_reuse(arg)
+	} else {
+		arg.to_string()
+	}
+}
+
 pub fn get_pipeline_cmd<'a>(pair: Pair<'a,Rule>) -> SlashResult<String> {
 	Ok(match pair.as_rule() {
 		Rule::simple_cmd => {
@@ -492,6 +545,7 @@ pub fn get_pipeline_cmd<'a>(pair: Pair<'a,Rule>) -> SlashResult<String> {
 					loop_kind.into()
 				}
 				Rule::subshell => "anonymous subshell".into(),
+				Rule::cmd_group => "brace group".into(),
 				_ => todo!("shell cmd kind '{:?}'", shell_cmd.as_rule())
 			}
 		}
@@ -509,6 +563,132 @@ pub fn prepare_redirs<'a>(pair: Pair<'a,Rule>) -> SlashResult<VecDeque<utils::Re
 	Ok(redirs)
 }
 
+/// Whether `name` can be a variable name, which is a letter or underscore followed by letters, digits, and underscores
+/// This is the same set of names that `var_ident_plain` in the grammar accepts
+pub fn is_valid_ident(name: &str) -> bool {
+	let mut chars = name.chars();
+	chars.next().is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_') && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// The variable name on the left of a `name=value` argument
+/// A dotted option path only means something to `setopt`, so anywhere else it is an invalid identifier
+pub fn assign_target<'a>(arg: &Pair<'a,Rule>, blame: Pair<'a,Rule>) -> SlashResult<Pair<'a,Rule>> {
+	if let Some(path) = arg.scry(Rule::opt_path) {
+		check_ident(path.as_str(), blame)?;
+	}
+	arg.scry(Rule::var_ident).unpack()
+}
+
+/// Fails if `name` can't be a variable name, so that it never ends up in the variable table or the environment
+pub fn check_ident<'a>(name: &str, blame: Pair<'a,Rule>) -> SlashResult<()> {
+	if is_valid_ident(name) {
+		Ok(())
+	} else {
+		Err(High(SlashErrHigh::syntax_err(format!("`{}': not a valid identifier",name), blame)))
+	}
+}
+
+/// The short flags given to a builtin, in the order they were given, as parsed by `parse_flags`
+#[derive(Debug, Default, PartialEq)]
+pub struct ParsedFlags {
+	flags: Vec<(char, Option<String>)>,
+	/// Whether each flag was given as `+x` instead of `-x`
+	plus: Vec<bool>
+}
+
+impl ParsedFlags {
+	pub fn has(&self, flag: char) -> bool {
+		self.flags.iter().any(|(found, _)| *found == flag)
+	}
+	/// The argument given to the last occurrence of `flag`
+	pub fn value(&self, flag: char) -> Option<&str> {
+		self.flags.iter().rev().find(|(found, _)| *found == flag).and_then(|(_, val)| val.as_deref())
+	}
+	pub fn iter(&self) -> impl Iterator<Item = &(char, Option<String>)> {
+		self.flags.iter()
+	}
+	/// Like `iter`, but also yields whether each flag was given with `-` rather than `+`
+	pub fn iter_signed(&self) -> impl Iterator<Item = (bool, &(char, Option<String>))> {
+		self.plus.iter().map(|plus| !plus).zip(self.flags.iter())
+	}
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FlagErr {
+	Unknown(char),
+	MissingArg(char)
+}
+
+impl FlagErr {
+	/// Turns this into the error that the builtin `name` reports
+	pub fn into_err<'a>(self, name: &str, blame: Pair<'a,Rule>) -> SlashErr {
+		let msg = match self {
+			FlagErr::Unknown(flag) => format!("Invalid flag in `{}' call: -{}",name,flag),
+			FlagErr::MissingArg(flag) => format!("{}: -{}: option requires an argument",name,flag)
+		};
+		High(SlashErrHigh::syntax_err(msg, blame))
+	}
+}
+
+/// Splits the leading flags off of a builtin's arguments, returning them along with the operands that follow
+/// `spec` lists the accepted short flags like getopts does, with a `:` after each flag that takes an argument, e.g. `"rsn:"`
+/// Flags can be bundled like `-rs`, and an argument can be attached like `-n3` or given as the next word
+/// The operands start at the first word that isn't a flag, or after `--`
+/// If `strict` is false, a word with an unknown flag in it starts the operands instead of being an error, and `--` is an operand too, like with `echo`
+/// If `spec` starts with `+`, flags can also be given like `+x`, for builtins like `set` that use that to turn things off
+pub fn parse_flags(spec: &str, mut argv: VecDeque<String>, strict: bool) -> Result<(ParsedFlags, VecDeque<String>), FlagErr> {
+	let mut parsed = ParsedFlags::default();
+	let (allow_plus, spec) = match spec.strip_prefix('+') {
+		Some(spec) => (true, spec),
+		None => (false, spec)
+	};
+	while let Some(arg) = argv.front() {
+		if strict && arg == "--" {
+			argv.pop_front();
+			break
+		}
+		let plus = allow_plus && arg.starts_with('+');
+		let Some(cluster) = arg.strip_prefix(if plus { '+' } else { '-' }).filter(|cluster| !cluster.is_empty()) else {
+			break
+		};
+		let mut found = vec![];
+		let mut needs_arg = None;
+		let mut error = None;
+		for (i, flag) in cluster.char_indices() {
+			let Some(pos) = spec.find(flag).filter(|_| flag != ':') else {
+				error = Some(FlagErr::Unknown(flag));
+				break
+			};
+			if !spec[pos + flag.len_utf8()..].starts_with(':') {
+				found.push((flag, None));
+				continue
+			}
+			let attached = &cluster[i + flag.len_utf8()..];
+			if attached.is_empty() {
+				needs_arg = Some(flag);
+			} else {
+				found.push((flag, Some(attached.to_string())));
+			}
+			break
+		}
+		if needs_arg.is_some() && argv.len() < 2 {
+			error = needs_arg.map(FlagErr::MissingArg);
+		}
+		match error {
+			Some(e) if strict => return Err(e),
+			Some(_) => break,
+			None => {}
+		}
+		argv.pop_front();
+		if let Some(flag) = needs_arg {
+			found.push((flag, argv.pop_front()));
+		}
+		parsed.plus.extend(std::iter::repeat_n(plus, found.len()));
+		parsed.flags.extend(found);
+	}
+	Ok((parsed, argv))
+}
+
 pub fn handle_nested(open: &str, close: &str, haystack: &mut VecDeque<char>) -> String {
 	let mut count = 1;
 	let mut result = String::new();
@@ -670,27 +850,83 @@ pub fn suppress_err<F: FnOnce() -> T, T>(f: F) -> T {
 	result
 }
 
-pub fn slice_completion(line: &str, candidate: &str) -> String {
-	let mut buffer = String::new();
-	let mut chars = candidate.chars();
-	let mut found = false;
-	while let Some(ch) = chars.next() {
-		buffer.push(ch);
-		if line.ends_with(&buffer) {
-			found = true;
-			break
+/// The word that the end of `line` is in, as far as completion is concerned
+#[derive(Debug, PartialEq)]
+pub struct CompWord {
+	/// Where the part of the word that a candidate replaces starts, which is after the last `/` or `$` in it
+	pub seg_start: usize,
+	/// The quote that is still open at the end of the line, if there is one
+	pub quote: Option<char>,
+	/// The word with its quotes and escapes taken out
+	pub unquoted: String
+}
+
+/// Finds the word being completed at the end of `line`
+/// Quoted and backslash-escaped spaces don't end the word, so `cd "My Doc` and `cd My\ Doc` are both completing `My Doc`
+pub fn comp_word(line: &str) -> CompWord {
+	let mut word = CompWord { seg_start: 0, quote: None, unquoted: String::new() };
+	let mut chars = line.char_indices();
+	while let Some((i, ch)) = chars.next() {
+		let next = i + ch.len_utf8();
+		match (word.quote, ch) {
+			(Some(quote), _) if ch == quote => word.quote = None,
+			(Some('"'), '\\') | (None, '\\') => {
+				if let Some((_, escaped)) = chars.next() {
+					word.unquoted.push(escaped);
+				}
+			}
+			(None, '"' | '\'') => {
+				word.quote = Some(ch);
+				word.seg_start = next;
+			}
+			(None, _) if ch.is_whitespace() => {
+				word.unquoted.clear();
+				word.seg_start = next;
+			}
+			(Some('\''), '$') => word.unquoted.push(ch),
+			(_, '/' | '$') => {
+				word.unquoted.push(ch);
+				word.seg_start = next;
+			}
+			_ => word.unquoted.push(ch)
 		}
 	}
-	let result = chars.collect::<String>();
-	if result.is_empty() && !found {
-		buffer
-	} else {
-		result
+	word
+}
+
+/// The characters that mean something to the shell outside of quotes, so a completion outside of quotes escapes them
+const COMP_SPECIAL_CHARS: &str = "'\"\\$`()&;|<>*?[]{}!#~";
+
+/// Escapes a candidate so that it reads back as itself in the word it is put into
+/// Inside of quotes only the quote itself and what double quotes expand need escaping, and outside of them whitespace and anything special to the shell does
+pub fn escape_completion(candidate: &str, quote: Option<char>) -> String {
+	let mut escaped = String::new();
+	for ch in candidate.chars() {
+		match (quote, ch) {
+			(Some('\''), '\'') => escaped.push_str("'\\''"),
+			(Some('"'), '"' | '\\' | '$' | '`') => {
+				escaped.push('\\');
+				escaped.push(ch);
+			}
+			(None, _) if ch.is_whitespace() || COMP_SPECIAL_CHARS.contains(ch) => {
+				escaped.push('\\');
+				escaped.push(ch);
+			}
+			_ => escaped.push(ch)
+		}
 	}
+	escaped
 }
 
-pub fn which(slash: &mut Slash,command: &str) -> Option<String> {
-	if let Some(env_path) = slash.vars().get_evar("PATH") {
+/// Works out where the word being completed starts in `line` and what to put in its place
+/// The candidate replaces the whole word, so it doesn't need to start with what was typed
+pub fn slice_completion(line: &str, candidate: &str) -> (usize, String) {
+	let word = comp_word(line);
+	(word.seg_start, escape_completion(candidate, word.quote))
+}
+
+pub fn which(slash: &Slash,command: &str) -> Option<String> {
+	if let Some(env_path) = slash.vars().get_evar("PATH").or_else(|| env::var("PATH").ok()) {
 		for path in env::split_paths(&env_path) {
 			let full_path = path.join(command);
 			if full_path.is_file() && is_exec(&full_path) {
@@ -701,6 +937,82 @@ pub fn which(slash: &mut Slash,command: &str) -> Option<String> {
 	None
 }
 
+/// Levenshtein distance between two strings, counted in chars
+/// With `transpose`, swapping two adjacent chars counts as a single edit, so `gti` is one edit away from `git`
+pub fn edit_distance(a: &str, b: &str, transpose: bool) -> usize {
+	let a = a.chars().collect::<Vec<char>>();
+	let b = b.chars().collect::<Vec<char>>();
+	let mut dist = vec![vec![0; b.len() + 1]; a.len() + 1];
+	for (i, row) in dist.iter_mut().enumerate() {
+		row[0] = i;
+	}
+	for j in 0..=b.len() {
+		dist[0][j] = j;
+	}
+	for i in 1..=a.len() {
+		for j in 1..=b.len() {
+			let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+			dist[i][j] = (dist[i - 1][j - 1] + cost).min(dist[i - 1][j] + 1).min(dist[i][j - 1] + 1);
+			if transpose && i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+				dist[i][j] = dist[i][j].min(dist[i - 2][j - 2] + 1);
+			}
+		}
+	}
+	dist[a.len()][b.len()]
+}
+
+/// Finds the candidate closest to `word`, as long as it is within `max_dist` edits
+/// Ties go to whichever candidate comes first
+pub fn closest_match<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>, max_dist: usize, transpose: bool) -> Option<&'a str> {
+	candidates.into_iter()
+		.map(|candidate| (edit_distance(word, candidate, transpose), candidate))
+		.filter(|(dist, _)| *dist <= max_dist)
+		.min_by_key(|(dist, _)| *dist)
+		.map(|(_, candidate)| candidate)
+}
+
+/// Lays out items in as many columns as fit within `width`, left to right and then top to bottom
+/// Every column is as wide as the longest item plus two spaces, so a terminal too narrow for two of them gets one item per line
+/// Widths are measured in terminal columns, so wide characters and color codes don't throw the columns off
+pub fn fmt_columns<S: AsRef<str>>(items: &[S], width: usize) -> String {
+	let col_width = items.iter().map(|item| utils::display_width(item.as_ref())).max().unwrap_or(0) + 2;
+	let num_cols = (width / col_width).max(1);
+	let mut output = String::new();
+	for row in items.chunks(num_cols) {
+		let mut line = String::new();
+		for item in row {
+			let item = item.as_ref();
+			line.push_str(item);
+			line.push_str(&" ".repeat(col_width - utils::display_width(item)));
+		}
+		output.push_str(line.trim_end());
+		output.push('\n');
+	}
+	output
+}
+
+/// Suggests a builtin, function, alias, or command in PATH that is spelled like `name`
+pub fn suggest_cmd(slash: &Slash, name: &str) -> Option<String> {
+	let mut candidates = builtin::BUILTINS.iter().map(|builtin| builtin.to_string()).collect::<Vec<String>>();
+	candidates.extend(slash.logic().borrow_functions().keys().cloned());
+	candidates.extend(slash.logic().borrow_aliases().keys().cloned());
+	if let Some(env_path) = slash.vars().get_evar("PATH").or_else(|| env::var("PATH").ok()) {
+		for path in env::split_paths(&env_path) {
+			let Ok(entries) = fs::read_dir(path) else { continue };
+			for entry in entries.flatten() {
+				if is_exec(&entry.path()) {
+					candidates.extend(entry.file_name().into_string().ok());
+				}
+			}
+		}
+	}
+	candidates.sort();
+	candidates.dedup();
+	// Anything further than this would suggest unrelated commands for short names
+	let max_dist = name.chars().count().saturating_sub(1).min(2);
+	closest_match(name, candidates.iter().map(String::as_str), max_dist, true).map(str::to_string)
+}
+
 pub fn is_exec(path: &Path) -> bool {
 	fs::metadata(path)
 		.map(|meta| meta.is_file() && (meta.permissions().mode() & 0o111) != 0)
@@ -735,29 +1047,44 @@ pub fn unset_var_conflicts(slash: &mut Slash,key: &str) -> SlashResult<()> {
 	Ok(())
 }
 
+/// The exit status that a foreground process's wait status gives, with signals reported as 128 plus the signal number
+/// Statuses that don't end the wait on a process, like `Continued`, give None
+pub fn fg_wait_code(status: &WaitStatus) -> Option<i32> {
+	match status {
+		WaitStatus::Exited(_, code) => Some(*code),
+		WaitStatus::Stopped(_, sig) | WaitStatus::Signaled(_, sig, _) => Some(utils::SIG_EXIT_OFFSET + *sig as i32),
+		_ => None
+	}
+}
+
 pub fn handle_fg(slash: &mut Slash, job: Job) -> SlashResult<()> {
 	let mut code = 0;
 	attach_tty(job.pgid())?;
 	disable_reaping();
-	let statuses = write_jobs(|j| j.new_fg(job))??;
+	// The table is only locked to put the job in and take the result back, so signal handlers that use it can still run during the wait
+	// The wait is retried if a signal interrupts it, and a SIGINT that reaches the shell is passed on to the job's group
+	let mut waited = job.clone();
+	write_jobs(|j| j.new_fg(job))?;
+	let statuses = waited.wait_pgrp()?;
+	write_jobs(|j| j.finish_fg(waited))?;
 	for status in statuses {
 		match status {
-			WaitStatus::Exited(_, exit_code) => {
-				code = exit_code;
-			}
-			WaitStatus::Stopped(pid, sig) => {
-				crate::signal::handle_child_stop(pid, sig)?;
-				code = utils::SIG_EXIT_OFFSET + sig as i32;
-			},
-			WaitStatus::Signaled(pid, sig, _) => {
-				crate::signal::handle_child_signal(pid, sig)?;
-				code = utils::SIG_EXIT_OFFSET + sig as i32;
-			},
-			_ => { /* Do nothing */ }
+			WaitStatus::Stopped(pid, sig) => crate::signal::handle_child_stop(pid, sig)?,
+			WaitStatus::Signaled(pid, sig, _) => crate::signal::handle_child_signal(pid, sig)?,
+			_ => {}
 		}
+		code = fg_wait_code(&status).unwrap_or(code);
 	}
 	attach_tty(getpgrp())?;
-	slash.set_code(code);
+	// A job that is still in the foreground has finished, so every stage has a status to go by
+	let pipefail = slash.meta().flags().contains(EnvFlags::PIPEFAIL);
+	let fg_codes = read_jobs(|j| j.get_fg().map(|job| {
+		(job.get_exit_code(pipefail), job.get_children().iter().map(|child| child.exit_code()).collect::<Vec<_>>())
+	}))?;
+	slash.set_code(fg_codes.as_ref().map(|(job_code,_)| *job_code).unwrap_or(code));
+	if let Some((_,stage_codes)) = fg_codes {
+		slash.set_pipe_status(&stage_codes);
+	}
 	write_jobs(|j| {
 		j.update_job_statuses().unwrap();
 		j.reset_fg();
@@ -1077,6 +1404,16 @@ pub fn escseq_custom(slash: &mut Slash,query: &str) -> SlashResult<String> {
 	expand::cmdsub::expand_cmd_sub(parsed,slash)
 }
 
+pub fn escseq_job_count(jobs: &JobTable) -> String {
+	jobs.job_count().to_string()
+}
+
+/// The number that the next command will have in the history
+pub fn escseq_hist_number(slash: &mut Slash) -> SlashResult<String> {
+	let entries = prompt::history::cached_entries(&prompt::history::hist_path(slash));
+	Ok((entries.len() + 1).to_string())
+}
+
 pub fn escseq_exitcode<'a>(slash: &mut Slash) -> SlashResult<String> {
 	Ok(slash.vars().get_param("?").unwrap_or_default())
 }
@@ -1154,21 +1491,18 @@ pub fn escseq_non_printing_sequence(chars: &mut VecDeque<char>, result: &mut Str
 }
 
 /// Handles the current working directory.
+/// `$HOME` is shortened to `~`, and if the path is longer than `prompt.trunc_prompt_path` components,
+/// the leading ones are collapsed into `...`. A length of 0 never truncates.
 pub fn escseq_working_directory<'a>(slash: &mut Slash) -> SlashResult<String> {
-	let mut cwd = env::var("PWD").unwrap_or_default();
-	let home = env::var("HOME").unwrap_or_default();
-	if cwd.starts_with(&home) {
-		cwd = cwd.replacen(&home, "~", 1); // Use `replacen` to replace only the first occurrence
+	let mut cwd = slash.vars().get_evar("PWD").unwrap_or_default();
+	let home = slash.vars().get_evar("HOME").unwrap_or_default();
+	if !home.is_empty() && (cwd == home || cwd.starts_with(&format!("{}/",home.trim_end_matches('/')))) {
+		cwd = cwd.replacen(&home, "~", 1);
 	}
-	let trunc_len = slash.meta().get_shopt("prompt.trunc_prompt_path").unwrap_or("0".into()).parse::<usize>().unwrap();
-	if trunc_len > 0 {
-		let mut path = PathBuf::from(cwd);
-		let mut cwd_components: Vec<_> = path.components().collect();
-		if cwd_components.len() > trunc_len {
-			cwd_components = cwd_components.split_off(cwd_components.len() - trunc_len);
-			path = cwd_components.iter().collect(); // Rebuild the PathBuf
-		}
-		cwd = path.to_string_lossy().to_string();
+	let trunc_len = slash.meta().get_shopt("prompt.trunc_prompt_path").unwrap_or("0".into()).parse::<usize>().unwrap_or(0);
+	let components = cwd.split('/').filter(|comp| !comp.is_empty()).collect::<Vec<_>>();
+	if trunc_len > 0 && components.len() > trunc_len {
+		cwd = format!(".../{}",components[components.len() - trunc_len..].join("/"));
 	}
 	Ok(cwd)
 }
@@ -1498,3 +1832,90 @@ pub fn add_vars<'a>(left: SlashVal, right: SlashVal) -> SlashResult<SlashVal> {
 		SlashVal::Dict(btree_map) => todo!(),
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use nix::{sys::signal::Signal, unistd::Pid};
+
+	use super::*;
+
+	fn args(words: &[&str]) -> VecDeque<String> {
+		words.iter().map(|word| word.to_string()).collect()
+	}
+
+	#[test]
+	fn test_is_valid_ident() {
+		assert!(is_valid_ident("foo") && is_valid_ident("_foo") && is_valid_ident("FOO_2"));
+		assert!(!is_valid_ident("a.b") && !is_valid_ident("1abc") && !is_valid_ident("a-b") && !is_valid_ident("a b") && !is_valid_ident(".a") && !is_valid_ident(""));
+	}
+
+	#[test]
+	fn test_fg_wait_code() {
+		let pid = Pid::from_raw(1);
+		assert_eq!(fg_wait_code(&WaitStatus::Exited(pid, 3)), Some(3));
+		assert_eq!(fg_wait_code(&WaitStatus::Stopped(pid, Signal::SIGTSTP)), Some(148));
+		assert_eq!(fg_wait_code(&WaitStatus::Signaled(pid, Signal::SIGINT, false)), Some(130));
+		assert_eq!(fg_wait_code(&WaitStatus::Continued(pid)), None);
+	}
+
+	#[test]
+	fn test_fmt_columns() {
+		let items = ["alpha", "be", "gamma", "d", "epsilon"];
+		// The longest item sets every column at nine wide, so twenty columns fit two of them
+		assert_eq!(fmt_columns(&items, 20), "alpha    be\ngamma    d\nepsilon\n");
+		assert_eq!(fmt_columns(&items, 45), "alpha    be       gamma    d        epsilon\n");
+		assert_eq!(fmt_columns(&items, 5), "alpha\nbe\ngamma\nd\nepsilon\n");
+		assert_eq!(fmt_columns::<&str>(&[], 80), "");
+
+		// The CJK name takes four columns and the color codes none, so both line up with the plain name
+		let items = ["漢字", "\x1b[1mab\x1b[0m", "abcd"];
+		assert_eq!(fmt_columns(&items, 12), "漢字  \x1b[1mab\x1b[0m\nabcd\n");
+	}
+
+	#[test]
+	fn test_parse_flags() {
+		let (flags, operands) = parse_flags("rsn:", args(&["-rs", "-n3", "-n", "5", "file", "-r"]), true).unwrap();
+		assert!(flags.has('r') && flags.has('s'));
+		assert_eq!(flags.value('n'), Some("5"));
+		// Flags stop at the first operand
+		assert_eq!(operands, args(&["file", "-r"]));
+
+		let (flags, operands) = parse_flags("rs", args(&["-r", "--", "-s"]), true).unwrap();
+		assert!(!flags.has('s'));
+		assert_eq!(operands, args(&["-s"]));
+
+		assert_eq!(parse_flags("rs", args(&["-rx"]), true), Err(FlagErr::Unknown('x')));
+		assert_eq!(parse_flags("n:", args(&["-n"]), true), Err(FlagErr::MissingArg('n')));
+
+		// Without strict, the word with the unknown flag is where the operands start, and it keeps all of its flags
+		let (flags, operands) = parse_flags("ne", args(&["-e", "-nx", "--", "-"]), false).unwrap();
+		assert_eq!(flags.iter().cloned().collect::<Vec<_>>(), vec![('e', None)]);
+		assert_eq!(operands, args(&["-nx", "--", "-"]));
+
+		// `+` flags are only accepted when the spec asks for them
+		let (flags, operands) = parse_flags("+exo:", args(&["-e", "+xo", "posix", "+"]), true).unwrap();
+		let signed = flags.iter_signed().map(|(on, (flag, val))| (on, *flag, val.clone())).collect::<Vec<_>>();
+		assert_eq!(signed, vec![(true, 'e', None), (false, 'x', None), (false, 'o', Some("posix".to_string()))]);
+		assert_eq!(operands, args(&["+"]));
+		let (_, operands) = parse_flags("ex", args(&["+x"]), true).unwrap();
+		assert_eq!(operands, args(&["+x"]));
+	}
+
+	#[test]
+	fn test_slice_completion() {
+		// Prefix match
+		assert_eq!(slice_completion("echo gi", "git-checkout"), (5, "git-checkout".to_string()));
+		// Case differs from what was typed
+		assert_eq!(slice_completion("cat src/MA", "main.rs"), (8, "main.rs".to_string()));
+		// Subsequence match
+		assert_eq!(slice_completion("echo $hme", "HOME"), (6, "HOME".to_string()));
+		assert_eq!(slice_completion("", "ls"), (0, "ls".to_string()));
+		// Spaces that are quoted or escaped are part of the word, and the candidate is escaped to match
+		assert_eq!(slice_completion("cd \"My Doc", "My Documents/"), (4, "My Documents/".to_string()));
+		assert_eq!(slice_completion("cd My\\ Doc", "My Documents/"), (3, "My\\ Documents/".to_string()));
+		assert_eq!(slice_completion("cat \"dir/say \\\"hi", "say \"hi\""), (9, "say \\\"hi\\\"".to_string()));
+		assert_eq!(slice_completion("cat a", "a b$c"), (4, "a\\ b\\$c".to_string()));
+		assert_eq!(slice_completion("cat 'a", "it's"), (5, "it'\\''s".to_string()));
+		assert_eq!(comp_word("ls 'a b'/c\\ d"), CompWord { seg_start: 9, quote: None, unquoted: "a b/c d".into() });
+	}
+}
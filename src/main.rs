@@ -1,4 +1,4 @@
-use std::{os::fd::AsRawFd, path::PathBuf};
+use std::{os::fd::AsRawFd, path::{Path, PathBuf}};
 
 use clap::{ArgAction, Parser as ClapParser};
 use error::{SlashErr, SlashErrExt, SlashErrLow, SlashResult};
@@ -19,6 +19,8 @@ pub mod prelude;
 pub mod utils;
 pub mod script;
 pub mod pest_ext;
+#[cfg(test)]
+pub mod test_util;
 
 
 #[derive(Debug,ClapParser)]
@@ -29,12 +31,21 @@ pub mod pest_ext;
 struct SlashArgs {
 	script: Option<PathBuf>,
 
-	#[arg(long = "no-rc", action = ArgAction::SetTrue, help = "Run without executing .slashrc")]
+	#[arg(short = 'l', long = "login", action = ArgAction::SetTrue, help = "Act as a login shell, executing .slash_profile instead of .slashrc")]
+	login: bool,
+
+	#[arg(long = "no-profile", visible_alias = "noprofile", action = ArgAction::SetTrue, help = "Run a login shell without executing .slash_profile")]
+	no_profile: bool,
+
+	#[arg(long = "no-rc", visible_alias = "norc", action = ArgAction::SetTrue, help = "Run without executing .slashrc")]
 	no_rc: bool,
 
-	#[arg(long = "rc-path", value_name = "FILE", help = "Set a custom path to .slashrc")]
+	#[arg(long = "rc-path", visible_aliases = ["rcfile", "init-file"], value_name = "FILE", help = "Execute FILE instead of .slashrc")]
 	rc_path: Option<PathBuf>,
 
+	#[arg(long = "posix", action = ArgAction::SetTrue, help = "Follow the POSIX standard where the default behavior differs from it, like `set -o posix`")]
+	posix: bool,
+
 	#[arg(long = "no-history", action = ArgAction::SetTrue, help = "Run without loading .slash_hist" )]
 	no_hist: bool,
 
@@ -63,20 +74,69 @@ fn restore_termios(orig: &Option<Termios>) {
 	}
 }
 
+/// The startup files to source, in the order that bash uses
+/// A login shell only reads `~/.slash_profile`, which can source the rc file itself, and `--noprofile` skips it.
+/// Any other shell reads `~/.slashrc`, or the file given by `--rcfile`, and `--norc` skips it.
+/// Missing default files are skipped, but a file given by `--rcfile` has to exist
+fn startup_files(args: &SlashArgs, home: &Path) -> SlashResult<Vec<PathBuf>> {
+	if args.login {
+		let profile = home.join(".slash_profile");
+		return Ok(if args.no_profile || !profile.exists() { vec![] } else { vec![profile] })
+	}
+	if args.no_rc {
+		return Ok(vec![])
+	}
+	match &args.rc_path {
+		Some(path) if !path.is_file() => Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("rc file not found: {}",path.display())))),
+		Some(path) => Ok(vec![path.clone()]),
+		None => {
+			let rc = home.join(".slashrc");
+			Ok(if rc.exists() { vec![rc] } else { vec![] })
+		}
+	}
+}
+
+/// Runs the EXIT trap, then leaves with `code`
+fn exit_shell(slash: &mut Slash, termios: &Option<Termios>, code: i32) -> ! {
+	slash.run_trap("EXIT").catch();
+	restore_termios(termios);
+	std::process::exit(code)
+}
+
 fn main() {
 
 	let mut slash = Slash::new(); // The shell environment
 
-	let args = SlashArgs::parse();
+	let mut args = SlashArgs::parse();
+	// Like bash, a shell started as `-slash` is a login shell
+	if std::env::args().next().is_some_and(|arg0| arg0.starts_with('-')) {
+		args.login = true;
+	}
 	if args.no_rc {
 		slash.vars_mut().export_var("PS1", "$> ");
 	}
+	if args.posix {
+		slash.meta_mut().mod_flags(|flags| *flags |= shellenv::EnvFlags::POSIX);
+	}
 
-	if !args.no_rc {
-		slash.source_rc(args.rc_path).catch();
+	// Set before the rc file runs, so that it can be turned back off there
+	slash.meta_mut().set_shopt("core.expand_aliases", "true").catch();
+
+	let home = PathBuf::from(std::env::var("HOME").unwrap_or_default());
+	match startup_files(&args, &home) {
+		Ok(files) => for file in files {
+			slash.source_rc(&file).catch();
+		}
+		Err(e) => {
+			eprintln!("slash: {}",e);
+			std::process::exit(2)
+		}
 	}
 
 	let termios = set_termios();
+	signal::interactive_sig_setup();
+	slash.meta_mut().mod_flags(|flags| *flags |= shellenv::EnvFlags::INTERACTIVE);
+	slash.export_term_size();
 	loop {
 		let input = prompt::prompt::run_prompt(&mut slash).catch().unwrap_or_default();
 
@@ -84,7 +144,7 @@ fn main() {
 		slash.ctx_mut().push_state().catch();
 		let saved_fds = utils::save_fds().unwrap();
 
-		let result = dispatch::exec_input(input, &mut slash);
+		let result = dispatch::exec_read_input(input, &mut slash);
 
 		utils::restore_fds(saved_fds,&mut slash).catch();
 		slash.ctx_mut().pop_state().catch();
@@ -93,16 +153,13 @@ fn main() {
 			Ok(_) => continue,
 			Err(e) => {
 				match e {
-					SlashErr::Low(SlashErrLow::CleanExit(code)) => {
-						restore_termios(&termios);
-						std::process::exit(code)
-					}
+					SlashErr::Low(SlashErrLow::CleanExit(code)) => exit_shell(&mut slash, &termios, code),
+					SlashErr::Low(SlashErrLow::Interrupted) => println!(),
 					SlashErr::High(ref high) => {
-						if let SlashErrLow::CleanExit(code) = high.get_err() {
-							restore_termios(&termios);
-							std::process::exit(*code)
-						} else {
-							eprintln!("{}",e)
+						match high.get_err() {
+							SlashErrLow::CleanExit(code) => exit_shell(&mut slash, &termios, *code),
+							SlashErrLow::Interrupted => println!(),
+							_ => eprintln!("{}",e)
 						}
 					}
 					_ => eprintln!("{}",e)
@@ -111,3 +168,41 @@ fn main() {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::shellenv::SlashVal;
+
+use super::*;
+
+	#[test]
+	fn test_startup_files() {
+		let home = std::env::temp_dir().join(format!("slash_startup_{}",std::process::id()));
+		std::fs::create_dir_all(&home).unwrap();
+		let (rc, profile, custom) = (home.join(".slashrc"), home.join(".slash_profile"), home.join("custom_rc"));
+		std::fs::write(&rc, "").unwrap();
+		std::fs::write(&profile, "").unwrap();
+		std::fs::write(&custom, "RCFILE_TEST=loaded\n").unwrap();
+		let files = |args: &[&str]| {
+			let args = SlashArgs::try_parse_from(["slash"].iter().chain(args)).unwrap();
+			startup_files(&args, &home)
+		};
+
+		assert_eq!(files(&[]).unwrap(), [rc.clone()]);
+		assert_eq!(files(&["--login"]).unwrap(), [profile.clone()]);
+		assert!(files(&["-l", "--noprofile", "--norc"]).unwrap().is_empty());
+		assert!(files(&["--norc"]).unwrap().is_empty());
+		// --rcfile takes the place of the default rc file, and login shells don't read either of them
+		let custom_str = custom.to_str().unwrap();
+		assert_eq!(files(&["--rcfile", custom_str]).unwrap(), [custom.clone()]);
+		assert_eq!(files(&["--init-file", custom_str]).unwrap(), [custom.clone()]);
+		assert_eq!(files(&["--login", "--rcfile", custom_str]).unwrap(), [profile.clone()]);
+
+		let mut slash = Slash::new();
+		slash.source_rc(&custom).unwrap();
+		assert_eq!(slash.vars().get_var("RCFILE_TEST"), Some(SlashVal::String("loaded".into())));
+
+		std::fs::remove_dir_all(&home).unwrap();
+		assert!(files(&["--rcfile", custom_str]).is_err_and(|e| e.to_string().contains("rc file not found")));
+	}
+}
@@ -133,7 +133,6 @@ impl<'a> PairExt<'a> for Pair<'a,Rule> {
 #[grammar_inline = r##"
 // Helper rules
 WHITESPACE        = _{ " " | "\t" }
-COMMENT           = _{ !"#!" ~ "#" ~ (!(NEWLINE | "#") ~ ANY)* }
 number            =  { ASCII_DIGIT+ }
 parameter         =  { "#" | ASCII_DIGIT+ | "@" | "*" | "?" | "$" | "!" | "_" | "-" }
 glob_brackets     = @{ !"\\[" ~ "[" ~ (!("]" | "," | WHITESPACE | NEWLINE) ~ ANY)* ~ "]" }
@@ -162,6 +161,11 @@ glob_word       = @{ ident? ~ glob_pat+ ~ ident? }
 tilde_sub       = @{ pwd | prev_pwd | (home ~ path_seg?) }
 brace_word      = @{ ident? ~ brace_expand+ ~ ident? }
 var_sub         = @{ !"\\$" ~ "$" ~ var_ident }
+assoc_key       = @{ ("\\]" | !"]" ~ ANY)* }
+var_assoc       = @{ !"\\$" ~ "${" ~ "!"? ~ var_ident_plain ~ "[" ~ assoc_key ~ "]" ~ "}" } // ${name[key]}, ${name[@]} and ${!name[@]}
+var_indirect    = @{ !"\\$" ~ "${!" ~ var_ident_plain ~ ("*" | "@")? ~ "}" } // ${!name} and ${!prefix*}
+var_transform   = @{ !"\\$" ~ "${" ~ var_ident_plain ~ "@" ~ ("Q" | "E" | "A" | "U" | "u" | "L") ~ "}" } // ${name@Q} and friends
+var_default     = @{ !"\\$" ~ "${" ~ var_ident_plain ~ (":-" | ":=" | ":+" | ":?" | "-" | "=" | "+" | "?") ~ ("\\}" | !"}" ~ ANY)* ~ "}" } // ${name:-word} and friends
 index           =  { ASCII_DIGIT+ }
 slice           = ${ index ~ ".." ~ index }
 key             =  { dquoted | squoted }
@@ -171,6 +175,10 @@ param_sub       = @{ !"\\$" ~ "$" ~ parameter }
 expansion       =  {
     tilde_sub
   | brace_word
+  | var_assoc
+  | var_indirect
+  | var_transform
+  | var_default
   | var_sub
   | arr_index
   | cmd_sub
@@ -192,19 +200,25 @@ prev_pwd =  { "~-" }
 dquote_body        =  { ("\\\"" | !"\"" ~ ANY)* }
 squote_body        =  { ("\\'" | !"'" ~ ANY)* }
 dquoted            =  { dqt ~ dquote_body ~ dqt }
+ansi_body          =  { ("\\" ~ ANY | !"'" ~ ANY)* }
+ansi_quoted        =  { "$'" ~ ansi_body ~ "'" } // ANSI-C quoting, e.g. $'\t'
 squoted            =  { sqt ~ squote_body ~ sqt }
-var_ident_plain    = @{ NEWLINE* ~ !parameter ~ ASCII_ALPHA ~ (ASCII_ALPHANUMERIC | "." | "_")* }
+var_ident_plain    = @{ NEWLINE* ~ (ASCII_ALPHA | "_" ~ &(ASCII_ALPHANUMERIC | "_")) ~ (ASCII_ALPHANUMERIC | "_")* } // A lone `_` is the parameter
 var_ident_brackets = @{ !"\\{" ~ "{" ~ var_ident_plain ~ !"\\}" ~ "}" }
 var_ident          =  { var_ident_brackets | var_ident_plain }
+opt_path           = @{ var_ident_plain ~ ("." ~ var_ident_plain)+ } // Option paths like `core.autocd' for setopt
+extglob_body       = _{ (extglob_group | "\\" ~ ANY | !(")" | WHITESPACE | NEWLINE) ~ ANY)* }
+extglob_group      = @{ ("?" | "*" | "+" | "@" | "!") ~ "(" ~ extglob_body ~ ")" } // Rejected after parsing unless extglob is on
 ident              = _{
 	"[" |
     "]" |
-    (("\\" ~ ANY) | // 'out' and 'in' refer to redir operators '>' and '<'
-  	(!out ~ !in ~ ASCII_ALPHANUMERIC | "\"" | "'" | "[" | "]" | "*" | "?" | "_" | "-" | "!" | "%" | "+" | "=" | "\\" | "/" | "," | "." | ":" | "@"))+
+    (extglob_group | ("\\" ~ ANY) | // 'out' and 'in' refer to redir operators '>' and '<'
+  	(!out ~ !in ~ ASCII_ALPHANUMERIC | "\"" | "'" | "[" | "]" | "*" | "?" | "_" | "-" | "!" | "%" | "+" | "=" | "\\" | "/" | "," | "." | ":" | "@" | "#"))+
 }
 cmd_name           = @{ word }
 word               = ${
-    dquoted
+    ansi_quoted
+  | dquoted
   | squoted
   | expand_word
   | ident
@@ -217,18 +231,24 @@ plus_assign        =  { var_ident ~ "+=" ~ word? ~ (!sep ~ cmd_list)? }
 increment          =  @{ var_ident ~ "++" ~ (!sep ~ WHITESPACE+ ~ cmd_list)? }
 decrement          =  @{ var_ident ~ "--" ~ (!sep ~ WHITESPACE+ ~ cmd_list)? }
 minus_assign       =  { var_ident ~ "-=" ~ word? ~ (!sep ~ cmd_list)? }
-assignment         =  { increment | decrement | std_assign | plus_assign | minus_assign }
-arg_assign         =  { var_ident ~ "=" ~ (array|word)? }
+elem_assign        =  ${ var_ident_plain ~ "[" ~ assoc_key ~ "]" ~ "=" ~ word? }
+assignment         =  { elem_assign | increment | decrement | std_assign | plus_assign | minus_assign }
+arg_assign         =  { (opt_path | var_ident) ~ "=" ~ (array|word)? }
 sep                = _{ (";" | NEWLINE)+ }
 
 // Types of commands
 cmd_list   =  { (bg_cmd | expr) ~ (#op = op ~ (bg_cmd | expr))* }
-simple_cmd =  { !reserved ~ (redir | cmd_name) ~ (arg_assign | word | redir)* }
+simple_cmd =  { !reserved ~ (redir | cmd_name) ~ (arg_assign | redir | word)* }
 bg_cmd     =  { expr ~ !"&&" ~ "&" ~ word_bound }
 pipeline   =  { (shell_cmd | simple_cmd) ~ ("|" ~ (shell_cmd | simple_cmd))+ }
-expr       = _{ pipeline | shell_cmd | assignment | simple_cmd }
+expr       = _{ timed | negation | pipeline | shell_cmd | assignment | simple_cmd }
+time_kw    = @{ "time" ~ &(WHITESPACE | sep | EOI) }
+time_posix = @{ "-p" ~ &(WHITESPACE | sep | EOI) }
+timed      =  { time_kw ~ time_posix? ~ (negation | pipeline | shell_cmd | assignment | simple_cmd)? } // A bare `time' prints the shell's own times
+bang       = @{ "!" ~ &(WHITESPACE | NEWLINE) }
+negation   =  { bang ~ bang? ~ (pipeline | shell_cmd | assignment | simple_cmd) } // A second `!' is caught at execution
 shell_cmd  =  {
-    (for_cmd | match_cmd | loop_cmd | if_cmd | subshell | brace_grp | assignment | func_def) ~ redir*
+    (for_cmd | match_cmd | loop_cmd | if_cmd | subshell | cmd_group | assignment | func_def) ~ redir*
 }
 
 
@@ -268,7 +288,7 @@ or  = { "||" }
 op  = { (and | or) }
 
 redir_list =  { redir ~ (redir)* }
-fd_out     = @{ number }
+fd_out     = @{ number ~ &("<" | ">") } // Must touch the operator, `echo 3 >file` is not a redirection of fd 3
 fd_target  = @{ number }
 file       = { proc_sub|word }
 pipe       =  { "|" }
@@ -293,9 +313,8 @@ redir      =  {
   | (fd_out ~ in ~ "&" ~ fd_target)
   | (out ~ "&" ~ fd_target)
   | (fd_out ~ out ~ "&" ~ fd_target)
-  | (out ~ "&" ~ "-")
-  | (fd_out ~ out ~ "&" ~ "-")
-  | (fd_out ~ out ~ "&" ~ "-")
+  | (out ~ close_fd)
+  | (fd_out ~ out ~ close_fd)
   | ("&" ~ out ~ file)
   | (fd_out ~ in_out ~ file)
   | (in_out ~ file)
@@ -336,6 +355,9 @@ esc_sequence       =  {
   | esc_exit_code
   | esc_success_symbol
   | esc_failure_symbol
+  | esc_job_count
+  | esc_hist_number
+  | esc_cmd_number
 }
 esc_pwd            =  { "\\w" }
 esc_pwd_short      =  { "\\W" }
@@ -347,6 +369,9 @@ esc_prompt_symbol  = @{ "\\$" }
 esc_exit_code      =  { "\\?" }
 esc_success_symbol =  { "\\S" }
 esc_failure_symbol =  { "\\F" }
+esc_job_count      =  { "\\j" }
+esc_hist_number    =  { "\\!" }
+esc_cmd_number     =  { "\\#" }
 esc_bell           =  { "\\a" }
 esc_newline        =  { "\\n" }
 esc_return         =  { "\\r" }
@@ -475,13 +500,18 @@ words = { (!shell_struct ~ (hl_redir | hl_brace_word | hl_glob | hl_word))+ }
 syntax_hl = { (loud_sep | shell_struct | loud_operator | words)* }
 
 // Misc rules
-func_name = @{ word ~ "()" }
-func_def  =  {
+func_name   = @{ word ~ "()" }
+// `fn greet(name greeting) { ... }` binds the arguments to these names as well as to $1, $2, ...
+func_params =  { "(" ~ var_ident_plain* ~ ")" }
+func_def    =  {
     (func_name ~ NEWLINE* ~ brace_grp)
-  | ("fn" ~ (func_name | word) ~ NEWLINE* ~ brace_grp)
+  | ("fn" ~ (func_name | word ~ func_params | word) ~ NEWLINE* ~ brace_grp)
 }
 
 brace_grp = { "{" ~ sub_main ~ "}" }
+// A brace group run as a command, where the closing brace has to come after a separator like in bash
+cmd_group  = { "{" ~ group_body ~ "}" }
+group_body = { NEWLINE* ~ (cmd_list? ~ sep)+ }
 
 // sub_main is used for brace groups, it essentially allows for holding a script in the brace group
 // It's stored as a static string, but using sub_main makes sure that it actually parses first
@@ -1,29 +1,15 @@
-use crossterm::{cursor::{self, MoveTo}, execute, terminal::{Clear, ClearType}};
-use rustyline::{completion::{Candidate, Completer, FilenameCompleter}, error::ReadlineError, Context};
-use skim::{prelude::{Key, SkimItemReader, SkimOptionsBuilder}, Skim};
+use crossterm::{cursor::{self, MoveTo}, execute, terminal::{self, Clear, ClearType}};
+use rustyline::{completion::{Candidate, Completer}, config::BellStyle, error::ReadlineError, Context};
+use skim::{prelude::{unbounded, Cow, Key, SkimItemReader, SkimItemReceiver, SkimItemSender, SkimOptions, SkimOptionsBuilder}, ItemPreview, PreviewContext, Skim, SkimItem};
 
-use crate::{helper, prelude::*};
+use once_cell::sync::Lazy;
+use std::{process::{Command, Stdio}, sync::RwLock, time::{Duration, Instant, SystemTime}};
 
-use super::prompt::SlashHelper;
+use crate::{builtin::BUILTINS, helper, prelude::*, shellenv, utils};
 
-pub struct CompRegistry {
-	path_completer: FilenameCompleter,
-	cmds: HashMap<String, Vec<CompOption>>
-}
-
-impl CompRegistry {
-	pub fn new() -> Self {
-		let cmds = HashMap::new();
-		let path_completer = FilenameCompleter::new();
-		Self { cmds, path_completer }
-	}
-	pub fn get_cmd(&self, key: String) -> Option<Vec<CompOption>> {
-		let result = self.cmds.get(&key).map(|comp_vec| comp_vec.to_vec());
-		result
-	}
-}
+use super::{prompt::SlashHelper, rl_init};
 
-#[derive(Clone,Debug)]
+#[derive(Clone,Debug,PartialEq)]
 pub enum CompType {
 	Variables,
 	EnvVars,
@@ -87,58 +73,395 @@ impl CompOption {
 	}
 }
 
+impl CompType {
+	/// The kind of name that an `-A` action of `complete` refers to
+	pub fn from_action(action: &str) -> Option<Self> {
+		match action {
+			"file" => Some(Self::Paths),
+			"directory" => Some(Self::Directories),
+			"command" => Some(Self::Commands),
+			"function" => Some(Self::Functions),
+			"variable" => Some(Self::Variables),
+			_ => None
+		}
+	}
+}
+
+/// How `complete` says to complete a command's arguments: a fixed list of words, plus the kinds of names in `actions`
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct CompSpec {
+	pub words: Vec<String>,
+	pub actions: Vec<CompType>
+}
+
+impl CompSpec {
+	pub fn add_action(&mut self, action: CompType) {
+		if !self.actions.contains(&action) {
+			self.actions.push(action);
+		}
+	}
+	/// Everything the spec offers for `typed`, before it is matched against what was typed
+	/// Paths are listed from the directory that `typed` points into
+	pub fn candidates(&self, typed: &str, slash: &Slash) -> Vec<String> {
+		let mut candidates = self.words.clone();
+		for action in &self.actions {
+			match action {
+				CompType::Paths => candidates.extend(path_candidates(typed, false)),
+				CompType::Directories => candidates.extend(path_candidates(typed, true)),
+				CompType::Commands => candidates.extend(command_names(slash)),
+				CompType::Functions => candidates.extend(slash.logic().borrow_functions().keys().cloned()),
+				CompType::Variables => {
+					let vars = slash.vars();
+					candidates.extend(vars.vars().keys().chain(vars.borrow_evars().keys()).cloned());
+				}
+				_ => {}
+			}
+		}
+		candidates
+	}
+}
+
+/// The options that would register this spec again, as printed by `complete -p`
+impl Display for CompSpec {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let mut opts = vec![];
+		if !self.words.is_empty() {
+			opts.push(format!("-W '{}'",self.words.join(" ")));
+		}
+		for action in &self.actions {
+			let opt = match action {
+				CompType::Paths => "-f",
+				CompType::Directories => "-d",
+				CompType::Commands => "-c",
+				CompType::Functions => "-A function",
+				CompType::Variables => "-A variable",
+				_ => continue
+			};
+			opts.push(opt.to_string());
+		}
+		write!(f,"{}",opts.join(" "))
+	}
+}
+
+/// The entries of the directory that `typed` points into, with the directory part of `typed` kept in front
+/// Hidden entries are only offered once a `.` has been typed
+fn path_candidates(typed: &str, dirs_only: bool) -> Vec<String> {
+	let (dir, name) = match typed.rsplit_once('/') {
+		Some((dir, name)) => (format!("{}/",dir), name),
+		None => (String::new(), typed)
+	};
+	let Ok(entries) = std::fs::read_dir(if dir.is_empty() { "." } else { dir.as_str() }) else {
+		return vec![]
+	};
+	entries.flatten().filter_map(|entry| {
+		let file_name = entry.file_name().to_string_lossy().to_string();
+		if file_name.starts_with('.') && !name.starts_with('.') {
+			return None
+		}
+		let is_dir = entry.path().is_dir();
+		if dirs_only && !is_dir {
+			return None
+		}
+		Some(format!("{}{}{}",dir,file_name,if is_dir { "/" } else { "" }))
+	}).collect()
+}
+
+/// Builtins, functions, aliases, and the executables in `$PATH`
+fn command_names(slash: &Slash) -> Vec<String> {
+	let mut names = BUILTINS.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+	names.extend(slash.logic().borrow_functions().keys().cloned());
+	names.extend(slash.logic().borrow_aliases().keys().cloned());
+	let path = slash.vars().get_evar("PATH").unwrap_or_default();
+	for dir in env::split_paths(&path) {
+		let Ok(entries) = std::fs::read_dir(dir) else {
+			continue
+		};
+		names.extend(entries.flatten()
+			.filter(|entry| helper::is_exec(&entry.path()))
+			.map(|entry| entry.file_name().to_string_lossy().to_string()));
+	}
+	names
+}
+
 impl Display for CompOption {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		write!(f,"{}",self.value)
 	}
 }
 
-impl<'a> Completer for SlashHelper<'a> {
-	type Candidate = CompOption;
+/// Long options read from `--help` output, keyed by command path and modification time
+/// The mtime is part of the key so that reinstalling a command invalidates its entry
+static HELP_OPTS: Lazy<RwLock<HashMap<(PathBuf,SystemTime),Vec<String>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
 
-	fn complete(
+/// Pulls every `--option` out of a command's help text, in the order they first appear
+pub fn scrape_long_opts(help_text: &str) -> Vec<String> {
+	let mut opts = vec![];
+	for token in help_text.split(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')) {
+		let Some(name) = token.strip_prefix("--") else {
+			continue
+		};
+		let opt = format!("--{}",name.trim_end_matches('-'));
+		if name.starts_with(|ch: char| ch.is_ascii_alphanumeric()) && !opts.contains(&opt) {
+			opts.push(opt);
+		}
+	}
+	opts
+}
+
+/// Runs `cmd_path --help` and returns the long options it mentions
+/// Commands that take longer than a second to respond are given up on
+pub fn help_options(cmd_path: &Path) -> Vec<String> {
+	let Some(mtime) = std::fs::metadata(cmd_path).and_then(|meta| meta.modified()).ok() else {
+		return vec![]
+	};
+	let key = (cmd_path.to_path_buf(), mtime);
+	if let Some(opts) = HELP_OPTS.read().ok().and_then(|cache| cache.get(&key).cloned()) {
+		return opts
+	}
+
+	shellenv::disable_reaping();
+	let output = Command::new(cmd_path)
+		.arg("--help")
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.ok()
+		.and_then(|mut child| {
+			let deadline = Instant::now() + Duration::from_secs(1);
+			while child.try_wait().ok()?.is_none() {
+				if Instant::now() >= deadline {
+					child.kill().ok();
+					child.wait().ok();
+					return None
+				}
+				std::thread::sleep(Duration::from_millis(10));
+			}
+			child.wait_with_output().ok()
+		});
+	shellenv::enable_reaping().ok();
+
+	let opts = output.map(|output| {
+		// Some commands print their help to stderr
+		let text = format!("{}{}",String::from_utf8_lossy(&output.stdout),String::from_utf8_lossy(&output.stderr));
+		scrape_long_opts(&text)
+	}).unwrap_or_default();
+	if let Ok(mut cache) = HELP_OPTS.write() {
+		cache.insert(key, opts.clone());
+	}
+	opts
+}
+
+impl<'a> SlashHelper<'a> {
+	/// Completes a word starting with `--` using the command's `--help` output, if `prompt.comp_help_opts` is enabled
+	/// Commands with a spec from `complete` are left to `complete_spec()`
+	fn complete_long_opt(&self, line: &str, pos: usize) -> Option<(usize, Vec<CompOption>)> {
+		let before = &line[..pos];
+		let word = before.rsplit(char::is_whitespace).next()?;
+		let cmd = before.split_whitespace().next()?;
+		if !word.starts_with("--") || cmd == word {
+			return None
+		}
+		if self.slash.logic().get_comp_spec(cmd).is_some() {
+			return None
+		}
+		let enabled = self.slash.meta().get_shopt("prompt.comp_help_opts").ok()?.parse::<bool>().unwrap_or(false);
+		if !enabled {
+			return None
+		}
+		let cmd_path = if cmd.contains('/') { Some(cmd.to_string()) } else { helper::which(self.slash, cmd) }?;
+		let candidates = help_options(Path::new(&cmd_path));
+		let mut comp_opts = candidates.into_iter()
+			.filter(|opt| opt.starts_with(word))
+			.map(|opt| CompOption { value: opt, desc: None, comp_type: CompType::Params, priority: 0 })
+			.collect::<Vec<_>>();
+		if comp_opts.is_empty() {
+			return None
+		}
+		let start = pos - word.len();
+		if comp_opts.len() > 1 && self.menu_style() {
+			let values = comp_opts.into_iter().map(|opt| opt.value).collect();
+			return Some((start, cycle_order(values).iter().map(|opt| CompOption::path(opt)).collect()))
+		}
+		if comp_opts.len() > 1 {
+			if let Some(selected) = skim_comp(comp_opts.clone(), None) {
+				return Some((start, vec![CompOption::path(&selected)]))
+			}
+		}
+		comp_opts.truncate(1);
+		Some((start, comp_opts))
+	}
+	/// Completes the arguments of a command that has a spec registered with `complete`
+	/// Once a command has a spec, nothing else is offered for its arguments
+	fn complete_spec(&self, line: &str, pos: usize) -> Option<(usize, Vec<CompOption>)> {
+		let before = &line[..pos];
+		let cmd = before.split_whitespace().next()?;
+		if before.trim_start().strip_prefix(cmd)?.is_empty() {
+			// Still typing the command name
+			return None
+		}
+		let spec = self.slash.logic().get_comp_spec(cmd)?;
+		let word = before.rsplit(char::is_whitespace).next()?;
+		let start = pos - word.len();
+		let matching = CompMatching::from_slash(self.slash);
+		let mut candidates = cycle_order(spec.candidates(word, self.slash).into_iter()
+			.filter(|candidate| matching.matches(word, candidate))
+			.collect());
+		if candidates.len() > 1 && self.menu_style() {
+			return Some((start, candidates.iter().map(|candidate| CompOption::path(candidate)).collect()))
+		}
+		let selected = match candidates.len() {
+			0 => return Some((pos, vec![])),
+			1 => candidates.pop()?,
+			_ => skim_comp(candidates.iter().map(|candidate| CompOption::path(candidate)).collect(), None)?
+		};
+		Some((start, vec![CompOption::path(&selected)]))
+	}
+	/// Completes variable names after a `$`
+	fn complete_var(&self, line: &str, pos: usize) -> Option<(usize, Vec<CompOption>)> {
+		let before = &line[..pos];
+		let word = before.rsplit(char::is_whitespace).next()?;
+		let typed = word.strip_prefix('$')?;
+		let word_start = pos - typed.len();
+		let matching = CompMatching::from_slash(self.slash);
+		let vars = self.slash.vars();
+		let mut names = vars.vars().keys().chain(vars.borrow_evars().keys())
+			.filter(|name| matching.matches(typed, name))
+			.cloned()
+			.collect::<Vec<_>>();
+		names = cycle_order(names);
+		if names.is_empty() {
+			return None
+		}
+		if names.len() > 1 && self.menu_style() {
+			return Some((word_start, names.iter().map(|name| CompOption::path(name)).collect()))
+		}
+		let selected = if names.len() > 1 {
+			skim_comp(names.iter().map(|name| CompOption::path(name)).collect(), None)?
+		} else {
+			names.pop()?
+		};
+		let (start, replacement) = helper::slice_completion(&line[..pos], &selected);
+		Some((start, vec![CompOption::path(&replacement)]))
+	}
+
+	/// Whether `prompt.completion_style` asks for Tab to cycle through candidates instead of opening the fuzzy finder
+	pub fn menu_style(&self) -> bool {
+		self.slash.meta().get_shopt("prompt.completion_style").is_ok_and(|style| style.trim_matches('"') == "menu")
+	}
+}
+
+/// How typed text is compared against completion candidates, set by `prompt.completion_matching`
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum CompMatching {
+	Exact,
+	IgnoreCase,
+	Fuzzy
+}
+
+impl CompMatching {
+	pub fn from_slash(slash: &Slash) -> Self {
+		match slash.meta().get_shopt("prompt.completion_matching").unwrap_or_default().trim_matches('"') {
+			"ignore-case" => Self::IgnoreCase,
+			"fuzzy" => Self::Fuzzy,
+			_ => Self::Exact
+		}
+	}
+	/// Exact and ignore-case matching compare prefixes, fuzzy matching looks for the typed chars in order anywhere in the candidate
+	pub fn matches(&self, typed: &str, candidate: &str) -> bool {
+		match self {
+			Self::Exact => candidate.starts_with(typed),
+			Self::IgnoreCase => candidate.to_lowercase().starts_with(&typed.to_lowercase()),
+			Self::Fuzzy => {
+				let mut candidate_chars = candidate.chars().flat_map(char::to_lowercase);
+				typed.chars().flat_map(char::to_lowercase).all(|ch| candidate_chars.any(|cand_ch| cand_ch == ch))
+			}
+		}
+	}
+}
+
+/// The order that menu completion cycles through candidates in
+pub fn cycle_order(mut candidates: Vec<String>) -> Vec<String> {
+	candidates.sort();
+	candidates.dedup();
+	candidates
+}
+
+impl<'a> SlashHelper<'a> {
+	fn complete_line(
 		&self,
 		line: &str,
 		pos: usize,
 		ctx: &Context<'_>,
-	) -> Result<(usize, Vec<Self::Candidate>), ReadlineError> {
+	) -> Result<(usize, Vec<CompOption>), ReadlineError> {
+		// Inside of `$(` or `(`, the words are completed as a command line of their own
+		let cmd_start = innermost_cmd_start(&line[..pos]);
+		if cmd_start > 0 {
+			let (start, candidates) = self.complete_line(&line[cmd_start..], pos - cmd_start, ctx)?;
+			return Ok((start + cmd_start, candidates))
+		}
+
 		let mut completions = Vec::new();
 		let line = line.to_string();
 		let num_words = line.split_whitespace().count();
 
+		if let Some(long_opts) = self.complete_long_opt(&line, pos) {
+			return Ok(long_opts)
+		}
+		if let Some(vars) = self.complete_var(&line, pos) {
+			return Ok(vars)
+		}
+		if let Some(spec) = self.complete_spec(&line, pos) {
+			return Ok(spec)
+		}
+
 		// Determine if this is a file path or a command completion
 		if !line.is_empty() && (num_words > 1 || line.split(" ").into_iter().next().is_some_and(|wrd| wrd.starts_with(['.','/','~']))) {
 			//TODO: Handle these unwraps
 			let (start, matches) = self.filename_comp.complete(&line, pos, ctx)?;
-			completions.extend(matches.iter().map(|c| c.display().to_string()));
+			if matches.len() > 1 && self.menu_style() {
+				// Rustyline swaps each candidate in for the word as Tab is pressed
+				let replacements = matches.iter().map(|c| c.replacement().to_string()).collect();
+				return Ok((start, cycle_order(replacements).iter().map(|opt| CompOption::path(opt)).collect()))
+			}
+			// The displayed names are bare, so directories get their slash back here, and the escaping is left to `slice_completion`
+			let typed_dir = preview_dir(&helper::comp_word(&line[..pos]).unquoted);
+			completions.extend(matches.iter().map(|c| {
+				let name = c.display().trim_end_matches('/');
+				if typed_dir.join(name).is_dir() { format!("{}/",name) } else { name.to_string() }
+			}));
 			let mut comp_opts = completions.into_iter().map(|opt| {
 				CompOption::path(&opt)
 			}).collect::<Vec<CompOption>>();
 
 			// Invoke fuzzyfinder if there are matches
 			if !comp_opts.is_empty() && comp_opts.len() > 1 {
-				if let Some(selected) = skim_comp(comp_opts.clone()) {
-					let result = helper::slice_completion(&line, &selected);
-					let unfinished = line.split_whitespace().last().unwrap();
-					let result = CompOption::path(&format!("{unfinished}{result}"));
-					return Ok((start, vec![result]));
+				let unfinished = helper::comp_word(&line[..pos]).unquoted;
+				let preview_dir = self.slash.meta().get_shopt("prompt.comp_preview").ok()
+					.and_then(|opt| opt.parse::<bool>().ok())
+					.unwrap_or(false)
+					.then(|| preview_dir(&unfinished));
+				if let Some(selected) = skim_comp(comp_opts.clone(), preview_dir) {
+					let (start, replacement) = helper::slice_completion(&line[..pos], &selected);
+					return Ok((start, vec![CompOption::path(&replacement)]));
 				}
 			}
 
-			// Return completions, starting from the beginning of the word
+			// Return completions, replacing the last segment of the path
 			if let Some(candidate) = comp_opts.pop() {
-				let result = CompOption::path(&helper::slice_completion(&line, &candidate.to_string()));
-				comp_opts.push(result);
+				let (start, replacement) = helper::slice_completion(&line[..pos], &candidate.to_string());
+				return Ok((start, vec![CompOption::path(&replacement)]))
 			}
 			return Ok((pos, comp_opts))
 		}
 
 		// Command completion
 		let prefix = &line[..pos]; // The part of the line to match
+		let matching = CompMatching::from_slash(self.slash);
 		completions.extend(
 			self.commands
 			.iter()
-			.filter(|cmd| cmd.starts_with(prefix)) // Match prefix
+			.filter(|cmd| matching.matches(prefix, cmd))
 			.cloned(), // Clone matched command names
 		);
 
@@ -150,42 +473,238 @@ impl<'a> Completer for SlashHelper<'a> {
 				priority: 0
 			}
 		}).collect::<Vec<CompOption>>();
+		if comp_opts.len() > 1 && self.menu_style() {
+			let values = comp_opts.into_iter().map(|opt| opt.value).collect();
+			return Ok((0, cycle_order(values).iter().map(|opt| CompOption::path(opt)).collect()))
+		}
 		// Invoke fuzzyfinder if there are matches
 		if comp_opts.len() > 1 {
-			if let Some(selected) = skim_comp(comp_opts.clone()) {
-				let result = CompOption::path(&helper::slice_completion(&line, &selected));
-				return Ok((pos, vec![result]));
+			if let Some(selected) = skim_comp(comp_opts.clone(), None) {
+				let (start, replacement) = helper::slice_completion(&line[..pos], &selected);
+				return Ok((start, vec![CompOption::path(&replacement)]));
 			}
 		}
 		if let Some(candidate) = comp_opts.pop() {
-			let expanded = helper::slice_completion(&line, &candidate.to_string());
-			let result = CompOption::path(&expanded);
-			comp_opts.push(result);
+			let (start, replacement) = helper::slice_completion(&line[..pos], &candidate.to_string());
+			return Ok((start, vec![CompOption::path(&replacement)]))
 		}
-		// Return completions, starting from the beginning of the word
 		Ok((pos, comp_opts))
 	}
 }
 
-pub fn skim_comp(options: Vec<CompOption>) -> Option<String> {
+impl<'a> Completer for SlashHelper<'a> {
+	type Candidate = CompOption;
+
+	/// With a visible bell, the screen flashes when nothing matches, where rustyline would have beeped
+	/// Menu completion lists the candidates it is about to cycle through
+	fn complete(
+		&self,
+		line: &str,
+		pos: usize,
+		ctx: &Context<'_>,
+	) -> Result<(usize, Vec<Self::Candidate>), ReadlineError> {
+		let result = self.complete_line(line, pos, ctx)?;
+		if result.1.is_empty() && rl_init::bell_style(self.slash.meta()).is_ok_and(|style| style == BellStyle::Visible) {
+			flash_screen();
+		}
+		if result.1.len() > 1 && self.menu_style() {
+			list_candidates(&result.1, self.slash.term_width());
+		}
+		Ok(result)
+	}
+}
+
+/// Prints the candidates in columns under the line, and rustyline redraws the prompt below them
+fn list_candidates(candidates: &[CompOption], width: usize) {
+	if !isatty(STDOUT_FILENO).unwrap_or(false) {
+		return
+	}
+	let values = candidates.iter().map(|opt| opt.display()).collect::<Vec<_>>();
+	// The terminal is in raw mode here, so each line has to return to the first column itself
+	let columns = helper::fmt_columns(&values, width).replace('\n', "\r\n");
 	let mut stdout = io::stdout();
+	let _ = write!(stdout, "\r\n{}", columns).and_then(|_| stdout.flush());
+}
 
-	let (init_col, _) = cursor::position().unwrap();
+/// Shows a visible bell by switching the terminal to reverse video for a moment
+/// Switching back happens on another thread, so completion returns without waiting for it
+fn flash_screen() {
+	let mut stdout = io::stdout();
+	if write!(stdout, "\x1b[?5h").and_then(|_| stdout.flush()).is_ok() {
+		std::thread::spawn(|| {
+			std::thread::sleep(Duration::from_millis(100));
+			let mut stdout = io::stdout();
+			let _ = write!(stdout, "\x1b[?5l").and_then(|_| stdout.flush());
+		});
+	}
+}
 
-	// Get terminal dimensions
-	let height = options.len().min(10) as u16; // Set maximum number of options to display
+/// Where the innermost command around the end of `line` starts, after the last `$(` or `(` that is still open
+/// A `(` only opens a subshell where a command could start, so `arr=(` is left alone, and `$((` or `((` open arithmetic, where no command starts
+/// Zero means the line itself
+fn innermost_cmd_start(line: &str) -> usize {
+	let mut openers = vec![];
+	let mut quote = None;
+	let mut escaped = false;
+	let mut prev = None;
+	for (i, ch) in line.char_indices() {
+		if escaped {
+			escaped = false;
+			prev = Some(ch);
+			continue
+		}
+		match (quote, ch) {
+			(Some('\''), '\'') => quote = None,
+			(Some('\''), _) => {}
+			(_, '\\') => escaped = true,
+			(None, '\'' | '"') => quote = Some(ch),
+			(Some('"'), '"') => quote = None,
+			(_, '(') if prev == Some('$') => openers.push(Some(i + 1)),
+			// Grouping parens inside of arithmetic
+			(_, '(') if openers.last().is_some_and(|opener: &Option<usize>| opener.is_none()) => openers.push(None),
+			(_, '(') if prev == Some('(') && openers.last() == Some(&Some(i)) => {
+				openers.pop();
+				openers.extend([None, None]);
+			}
+			(None, '(') if prev.is_none_or(|prev: char| prev.is_whitespace() || ";|&(".contains(prev)) => openers.push(Some(i + 1)),
+			(_, ')') => { openers.pop(); }
+			_ => {}
+		}
+		prev = Some(ch);
+	}
+	let Some(Some(start)) = openers.pop() else {
+		return 0
+	};
+	let rest = &line[start..];
+	start + rest.len() - rest.trim_start().len()
+}
 
-	// Prepare options for skim
-	let options_join = options.iter().map(|opt| opt.to_string()).collect::<Vec<String>>().join("\n");
-	let input = SkimItemReader::default().of_bufread(std::io::Cursor::new(options_join));
+/// The directory that the path being completed lives in
+fn preview_dir(unfinished: &str) -> PathBuf {
+	let dir = match unfinished.rfind('/') {
+		Some(index) => &unfinished[..=index],
+		None => "."
+	};
+	match dir.strip_prefix('~') {
+		Some(rest) => PathBuf::from(format!("{}{}",env::var("HOME").unwrap_or_default(),rest)),
+		None => PathBuf::from(dir)
+	}
+}
+
+/// Describes a path for the completion preview pane
+/// Directories list their contents, text files show their first lines, and anything else shows its type
+pub fn preview_path(path: &Path) -> String {
+	if path.is_dir() {
+		return match std::fs::read_dir(path) {
+			Ok(entries) => {
+				let mut names = entries.filter_map(|entry| entry.ok())
+					.map(|entry| {
+						let name = entry.file_name().to_string_lossy().to_string();
+						if entry.path().is_dir() { format!("{}/",name) } else { name }
+					})
+					.collect::<Vec<_>>();
+				names.sort();
+				names.join("\n")
+			}
+			Err(e) => format!("cannot read {}: {}",path.display(),e)
+		}
+	}
+	let mut head = vec![0u8; 4096];
+	let bytes_read = match std::fs::File::open(path).and_then(|mut file| file.read(&mut head)) {
+		Ok(bytes_read) => bytes_read,
+		Err(e) => return format!("cannot read {}: {}",path.display(),e)
+	};
+	head.truncate(bytes_read);
+	let text = match std::str::from_utf8(&head) {
+		Ok(text) => Some(text),
+		// The read can stop partway through a multibyte character, which doesn't make the file binary
+		Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok(),
+		Err(_) => None
+	};
+	match text {
+		Some(text) if !text.contains('\0') => text.lines().take(50).collect::<Vec<_>>().join("\n"),
+		_ => file_type(&head).to_string()
+	}
+}
+
+/// Guesses what kind of binary file this is from its first few bytes
+fn file_type(head: &[u8]) -> &'static str {
+	const MAGIC: [(&[u8], &str); 7] = [
+		(b"\x7fELF", "ELF executable"),
+		(b"\x89PNG", "PNG image"),
+		(b"\xff\xd8\xff", "JPEG image"),
+		(b"GIF8", "GIF image"),
+		(b"%PDF", "PDF document"),
+		(b"PK\x03\x04", "Zip archive"),
+		(b"\x1f\x8b", "gzip compressed data"),
+	];
+	MAGIC.iter()
+		.find(|(magic,_)| head.starts_with(magic))
+		.map(|(_,desc)| *desc)
+		.unwrap_or("binary data")
+}
+
+/// A completion candidate that can describe the path it refers to
+struct PreviewItem {
+	text: String,
+	path: PathBuf
+}
+
+impl SkimItem for PreviewItem {
+	fn text(&self) -> Cow<'_, str> {
+		Cow::Borrowed(&self.text)
+	}
+	fn preview(&self, _context: PreviewContext) -> ItemPreview {
+		ItemPreview::Text(preview_path(&self.path))
+	}
+}
 
-	let skim_options = SkimOptionsBuilder::default()
+pub fn build_skim_options(height: u16, preview: bool) -> SkimOptions {
+	let mut builder = SkimOptionsBuilder::default();
+	builder
 		.prompt(String::new())
 		.height(format!("{height}")) // Adjust height based on the options
 		.reverse(true)
-		.multi(false)
-		.build()
-		.unwrap();
+		.multi(false);
+	if preview {
+		// The items provide their own preview text, so no preview command is needed
+		builder.preview(Some(String::new())).preview_window("right:50%".to_string());
+	}
+	builder.build().unwrap()
+}
+
+/// Opens the fuzzy finder over `options`
+/// If `preview_dir` is given, the options are treated as paths in that directory and previewed
+pub fn skim_comp(options: Vec<CompOption>, preview_dir: Option<PathBuf>) -> Option<String> {
+	let mut stdout = io::stdout();
+
+	let (init_col, _) = cursor::position().unwrap();
+
+	// Get terminal dimensions
+	let (cols, _) = terminal::size().unwrap_or((80,24));
+	// Options wider than the terminal wrap onto extra rows
+	let rows = options.iter()
+		.map(|opt| utils::display_width(&opt.to_string()).max(1).div_ceil(cols.max(1) as usize))
+		.sum::<usize>();
+	let height = rows.min(10) as u16; // Set maximum number of options to display
+
+	// Prepare options for skim
+	let input: SkimItemReceiver = match &preview_dir {
+		Some(dir) => {
+			let (sender, receiver): (SkimItemSender, SkimItemReceiver) = unbounded();
+			for opt in &options {
+				let item = PreviewItem { text: opt.to_string(), path: dir.join(opt.to_string()) };
+				sender.send(Arc::new(item)).ok();
+			}
+			receiver
+		}
+		None => {
+			let options_join = options.iter().map(|opt| opt.to_string()).collect::<Vec<String>>().join("\n");
+			SkimItemReader::default().of_bufread(std::io::Cursor::new(options_join))
+		}
+	};
+
+	let skim_options = build_skim_options(height, preview_dir.is_some());
 
 		let selected = Skim::run_with(&skim_options, Some(input))
 			.and_then(|out| {
@@ -215,3 +734,170 @@ pub fn skim_comp(options: Vec<CompOption>) -> Option<String> {
 
 		selected
 }
+
+#[cfg(test)]
+mod tests {
+	use std::os::unix::fs::PermissionsExt;
+
+	use rustyline::{completion::Completer, history::DefaultHistory, Context};
+
+use super::*;
+
+	#[test]
+	fn test_completion_matching() {
+		let candidates = ["git", "git-checkout", "gcc", "Makefile", "make"];
+		let matching = |mode: CompMatching, typed: &str| candidates.iter().filter(|cand| mode.matches(typed, cand)).cloned().collect::<Vec<_>>();
+
+		assert_eq!(matching(CompMatching::Exact, "gi"), ["git", "git-checkout"]);
+		assert!(matching(CompMatching::Exact, "GIT").is_empty());
+		assert_eq!(matching(CompMatching::IgnoreCase, "GIT"), ["git", "git-checkout"]);
+		assert_eq!(matching(CompMatching::IgnoreCase, "mak"), ["Makefile", "make"]);
+		assert_eq!(matching(CompMatching::Fuzzy, "gco"), ["git-checkout"]);
+		assert_eq!(matching(CompMatching::Fuzzy, "mkf"), ["Makefile"]);
+
+		let mut slash = Slash::new();
+		assert_eq!(CompMatching::from_slash(&slash), CompMatching::Exact);
+		slash.meta_mut().set_shopt("prompt.completion_matching", "ignore-case").unwrap();
+		assert_eq!(CompMatching::from_slash(&slash), CompMatching::IgnoreCase);
+		assert!(slash.meta_mut().set_shopt("prompt.completion_matching", "bogus").is_err());
+	}
+
+	#[test]
+	fn test_cycle_order() {
+		let candidates = ["git", "gcc", "git", "gawk", "g++"].map(String::from).to_vec();
+		assert_eq!(cycle_order(candidates), ["g++", "gawk", "gcc", "git"]);
+
+		let mut slash = Slash::new();
+		assert!(!SlashHelper::new(&mut slash).menu_style());
+		slash.meta_mut().set_shopt("prompt.completion_style", "menu").unwrap();
+		assert!(SlashHelper::new(&mut slash).menu_style());
+		assert!(slash.meta_mut().set_shopt("prompt.completion_style", "bogus").is_err());
+	}
+
+	#[test]
+	fn test_preview_options() {
+		assert!(build_skim_options(10, true).preview.is_some());
+		assert!(build_skim_options(10, false).preview.is_none());
+
+		let dir = env::temp_dir().join(format!("slash_preview_{}",std::process::id()));
+		std::fs::create_dir_all(dir.join("sub")).unwrap();
+		std::fs::write(dir.join("notes.txt"), "first line\nsecond line\n").unwrap();
+		std::fs::write(dir.join("prog"), b"\x7fELF\x02\x01\x00\x00").unwrap();
+		// The first 4096 bytes end in the middle of the 'é'
+		std::fs::write(dir.join("wide.txt"), format!("{}é", "a".repeat(4095))).unwrap();
+
+		assert_eq!(preview_path(&dir), "notes.txt\nprog\nsub/\nwide.txt");
+		assert_eq!(preview_path(&dir.join("notes.txt")), "first line\nsecond line");
+		assert_eq!(preview_path(&dir.join("prog")), "ELF executable");
+		assert_eq!(preview_path(&dir.join("wide.txt")), "a".repeat(4095));
+		assert!(preview_path(&dir.join("missing")).starts_with("cannot read"));
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_help_options() {
+		let path = env::temp_dir().join(format!("slash_fake_cmd_{}",std::process::id()));
+		let runs = env::temp_dir().join(format!("slash_fake_cmd_runs_{}",std::process::id()));
+		let script = format!("#!/bin/sh\necho run >> {}\necho 'Usage: fake [OPTIONS]'\necho '  -v, --verbose      say more'\necho '      --output=FILE  write to FILE'\n", runs.display());
+		std::fs::write(&path, script).unwrap();
+		std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+		let opts = help_options(&path);
+		assert_eq!(opts, ["--verbose", "--output"]);
+		// Later lookups come from the cache, so the command only ever runs once
+		assert_eq!(help_options(&path), opts);
+
+		let mut slash = Slash::new();
+		let line = format!("{} --verb", path.to_str().unwrap());
+		assert!(SlashHelper::new(&mut slash).complete_long_opt(&line, line.len()).is_none());
+		slash.meta_mut().set_shopt("prompt.comp_help_opts", "true").unwrap();
+		let (start, candidates) = SlashHelper::new(&mut slash).complete_long_opt(&line, line.len()).unwrap();
+		assert_eq!(start, line.len() - "--verb".len());
+		assert_eq!(candidates[0].to_string(), "--verbose");
+		SlashHelper::new(&mut slash).complete_long_opt(&line, line.len()).unwrap();
+		assert_eq!(std::fs::read_to_string(&runs).unwrap(), "run\n");
+		std::fs::remove_file(&path).unwrap();
+		std::fs::remove_file(&runs).unwrap();
+	}
+
+	#[test]
+	fn test_complete_path_with_spaces() {
+		let mut slash = Slash::new();
+		let dir = env::temp_dir().join(format!("slash_comp_spaces_{}",std::process::id()));
+		std::fs::create_dir_all(dir.join("My Documents")).unwrap();
+		let dir_str = dir.to_str().unwrap();
+
+		let helper = SlashHelper::new(&mut slash);
+		let history = DefaultHistory::new();
+		let ctx = Context::new(&history);
+		let complete = |line: &str| {
+			let (start, candidates) = helper.complete(line, line.len(), &ctx).unwrap();
+			(start, candidates.iter().map(|candidate| candidate.replacement().to_string()).collect::<Vec<_>>())
+		};
+		// Only the part after the last slash is replaced, and the open quote is left alone
+		let line = format!("cd \"{dir_str}/My Doc");
+		assert_eq!(complete(&line), (line.len() - 6, vec!["My Documents/".to_string()]));
+		let line = format!("cd {dir_str}/My\\ Doc");
+		assert_eq!(complete(&line), (line.len() - 7, vec!["My\\ Documents/".to_string()]));
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_complete_escapes_special_chars() {
+		let mut slash = Slash::new();
+		let dir = env::temp_dir().join(format!("slash_comp_escape_{}",std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("a b$c"), "").unwrap();
+		let dir_str = dir.to_str().unwrap();
+
+		let helper = SlashHelper::new(&mut slash);
+		let history = DefaultHistory::new();
+		let ctx = Context::new(&history);
+		let complete = |line: &str| {
+			let (start, candidates) = helper.complete(line, line.len(), &ctx).unwrap();
+			(start, candidates.iter().map(|candidate| candidate.replacement().to_string()).collect::<Vec<_>>())
+		};
+		let line = format!("cat {dir_str}/a");
+		assert_eq!(complete(&line), (line.len() - 1, vec!["a\\ b\\$c".to_string()]));
+		// Inside of double quotes the space is fine as it is
+		let line = format!("cat \"{dir_str}/a");
+		assert_eq!(complete(&line), (line.len() - 1, vec!["a b\\$c".to_string()]));
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_complete_in_cmd_sub() {
+		let mut slash = Slash::new();
+		let dir = env::temp_dir().join(format!("slash_comp_cmdsub_{}",std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("notes.txt"), "").unwrap();
+		let dir_str = dir.to_str().unwrap();
+
+		// The fuzzy finder needs a terminal
+		slash.meta_mut().set_shopt("prompt.completion_style", "menu").unwrap();
+		let helper = SlashHelper::new(&mut slash);
+		let history = DefaultHistory::new();
+		let ctx = Context::new(&history);
+		let complete = |line: &str| {
+			let (start, candidates) = helper.complete(line, line.len(), &ctx).unwrap();
+			(start, candidates.iter().map(|candidate| candidate.replacement().to_string()).collect::<Vec<_>>())
+		};
+		for line in ["cat $(ech", "cat \"$( ech", "(ech", "ls; (true) && (ech"] {
+			let (start, candidates) = complete(line);
+			assert_eq!(start, line.len() - 3, "{}", line);
+			assert!(candidates.contains(&"echo".to_string()), "{}: {:?}", line, candidates);
+		}
+		// Past the command name, the words inside are paths again
+		let line = format!("echo $(cat {dir_str}/no");
+		assert_eq!(complete(&line), (line.len() - 2, vec!["notes.txt".to_string()]));
+		assert_eq!(innermost_cmd_start("echo $(a) b"), 0);
+		assert_eq!(innermost_cmd_start("arr=(ech"), 0);
+		assert_eq!(innermost_cmd_start("cat '$(ech"), 0);
+		assert_eq!(innermost_cmd_start("echo $((1+"), 0);
+		assert_eq!(innermost_cmd_start("(( (x+"), 0);
+		assert_eq!(innermost_cmd_start("echo $(( $(ech"), 11);
+		let (_, candidates) = complete("echo $((1+");
+		assert!(!candidates.contains(&"echo".to_string()), "{:?}", candidates);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
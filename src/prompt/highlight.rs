@@ -295,7 +295,7 @@ impl<'a> SlashHighlighter<'a> {
 				let sub_type = sub_type.unwrap();
 				let span = sub_type.as_span();
 				match sub_type.as_rule() {
-					Rule::loud_ident => { /* Pass */ }
+					Rule::loud_ident | Rule::extglob_group => { /* Pass */ }
 					Rule::dquoted => {
 						let styled = self.highlight_dquote(sub_type);
 						buffer.replace_span(span,&styled);
@@ -32,13 +32,15 @@ impl Hint for SlashHint {
 
 impl<'a> Hinter for SlashHelper<'a> {
 	fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<Self::Hint> {
-		if line.is_empty() {
+		// The hint is drawn after the cursor, so it only makes sense at the end of the line
+		if line.is_empty() || pos < line.len() {
 			return None
 		}
 		let history = ctx.history();
 		let result = self.hist_substr_search(line, history);
 		if let Some(hist_line) = result {
-			let window = hist_line[line.len()..].to_string();
+			// Stripping the typed text instead of slicing by its length can't split a multibyte char
+			let window = hist_line.strip_prefix(line)?.to_string();
 			let hint = SlashHint::new(window);
 			Some(hint)
 		} else {
@@ -48,3 +50,58 @@ impl<'a> Hinter for SlashHelper<'a> {
 
 	type Hint = SlashHint;
 }
+
+#[cfg(test)]
+mod tests {
+	use rustyline::{completion::Completer, highlight::Highlighter, history::{DefaultHistory, History}};
+
+	use crate::prompt::history::HistEntry;
+
+use super::*;
+
+	#[test]
+	fn test_multibyte_input() {
+		let mut slash = Slash::new();
+		// The fuzzy finder needs a terminal
+		slash.meta_mut().set_shopt("prompt.completion_style", "menu").unwrap();
+		let helper = SlashHelper::new(&mut slash);
+		let mut history = DefaultHistory::new();
+		history.add("echo café au lait").unwrap();
+		history.add("echo naïve").unwrap();
+		let ctx = Context::new(&history);
+
+		let hint = helper.hint("echo caf", 8, &ctx).unwrap();
+		assert_eq!(hint.completion(), Some("é au lait"));
+		let hint = helper.hint("echo café", "echo café".len(), &ctx).unwrap();
+		assert_eq!(hint.completion(), Some(" au lait"));
+		assert!(helper.hint("echo café", 4, &ctx).is_none());
+
+		// Every char boundary is somewhere the cursor can be
+		for line in ["echo café", "echo \"$(echo é)ü\" | grép ñ", "ls ~/dïr/\u{1F600}"] {
+			helper.highlight(line, line.len());
+			for (pos, _) in line.char_indices() {
+				helper.hint(&line[..pos], pos, &ctx);
+				helper.complete(line, pos, &ctx).ok();
+			}
+		}
+	}
+
+	#[test]
+	fn test_hint_prefers_current_dir() {
+		let mut slash = Slash::new();
+		slash.meta_mut().set_shopt("prompt.completion_style", "menu").unwrap();
+		let mut helper = SlashHelper::new(&mut slash);
+		let mut history = DefaultHistory::new();
+		for (dir, command) in [("/srv/a", "make a-target"), ("/srv/b", "make b-target")] {
+			history.add(command).unwrap();
+			helper.dir_history.push(HistEntry { time: None, dir: Some(dir.into()), command: command.into() });
+		}
+		let ctx = Context::new(&history);
+
+		helper.cwd = Some("/srv/a".into());
+		assert_eq!(helper.hint("make ", 5, &ctx).unwrap().completion(), Some("a-target"));
+		// Nothing was run here, so the latest entry from anywhere is used
+		helper.cwd = Some("/srv/c".into());
+		assert_eq!(helper.hint("make ", 5, &ctx).unwrap().completion(), Some("b-target"));
+	}
+}
@@ -0,0 +1,310 @@
+use chrono::TimeZone;
+use rustyline::history::History;
+
+use crate::{expand, prelude::*, signal};
+
+/// The first line of rustyline's history files, which escape newlines and backslashes in each entry
+const FILE_VERSION_V2: &str = "#V2";
+/// Starts the line that gives the directory of the entry after it
+const DIR_PREFIX: &str = "#dir ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistEntry {
+	/// Seconds since the epoch, if the entry was saved with `core.hist_timestamps`
+	pub time: Option<i64>,
+	/// The directory the command was run in, if the entry was saved with `core.hist_dirs`
+	pub dir: Option<String>,
+	pub command: String
+}
+
+/// The entries of the last history file that was read or written, and the modification time and size it had then
+struct HistCache {
+	path: PathBuf,
+	stamp: (std::time::SystemTime, u64),
+	entries: Arc<Vec<HistEntry>>
+}
+
+static HIST_CACHE: Mutex<Option<HistCache>> = Mutex::new(None);
+
+fn file_stamp(path: &Path) -> Option<(std::time::SystemTime, u64)> {
+	let meta = std::fs::metadata(path).ok()?;
+	Some((meta.modified().ok()?, meta.len()))
+}
+
+fn store_cache(path: &Path, entries: Arc<Vec<HistEntry>>) {
+	if let (Some(stamp), Ok(mut cache)) = (file_stamp(path), HIST_CACHE.lock()) {
+		*cache = Some(HistCache { path: path.to_path_buf(), stamp, entries });
+	}
+}
+
+/// The entries of a history file, which is only parsed again once it has changed since the last time
+/// The prompt asks for the history several times before each command, so this keeps it from rereading the file for each one
+pub fn cached_entries(path: &Path) -> Arc<Vec<HistEntry>> {
+	let stamp = file_stamp(path);
+	if let Ok(cache) = HIST_CACHE.lock() {
+		if let Some(cached) = cache.as_ref().filter(|cached| cached.path == path && Some(cached.stamp) == stamp) {
+			return cached.entries.clone()
+		}
+	}
+	let entries = Arc::new(read_hist_file(path).unwrap_or_default());
+	store_cache(path, entries.clone());
+	entries
+}
+
+/// The history file, from `$HIST_FILE` or `~/.slash_hist`
+pub fn hist_path(slash: &Slash) -> PathBuf {
+	slash.vars().get_evar("HIST_FILE").map(PathBuf::from).unwrap_or_else(|| {
+		let home = slash.vars().get_evar("HOME").unwrap_or_default();
+		PathBuf::from(format!("{}/.slash_hist",home))
+	})
+}
+
+/// Reads the entries of a history file
+/// A `#<epoch>` line gives the time of the entry after it, like in bash's history files, and a `#dir <path>` line gives its directory
+pub fn read_hist_file(path: &Path) -> SlashResult<Vec<HistEntry>> {
+	let contents = std::fs::read_to_string(path)?;
+	let mut lines = contents.lines().peekable();
+	let escaped = lines.next_if_eq(&FILE_VERSION_V2).is_some();
+	let mut entries = vec![];
+	let mut time = None;
+	let mut dir = None;
+	for line in lines {
+		if line.is_empty() {
+			continue
+		}
+		if let Some(epoch) = line.strip_prefix('#').and_then(|epoch| epoch.parse::<i64>().ok()) {
+			time = Some(epoch);
+			continue
+		}
+		if let Some(path) = line.strip_prefix(DIR_PREFIX) {
+			dir = Some(unescape(path));
+			continue
+		}
+		// A command that starts with `#` was written with a backslash in front, so it can't be taken for one of the lines above
+		let command = if escaped {
+			unescape(line.strip_prefix('\\').filter(|rest| rest.starts_with('#')).unwrap_or(line))
+		} else {
+			line.to_string()
+		};
+		entries.push(HistEntry { time: time.take(), dir: dir.take(), command });
+	}
+	Ok(entries)
+}
+
+/// Writes `entries` to a history file, putting a `#<epoch>` line before each one that has a time, and a `#dir <path>` line before each one that has a directory
+pub fn write_hist_file(path: &Path, entries: &[HistEntry]) -> SlashResult<()> {
+	let mut contents = format!("{}\n",FILE_VERSION_V2);
+	for entry in entries {
+		if let Some(time) = entry.time {
+			contents.push_str(&format!("#{}\n",time));
+		}
+		if let Some(dir) = &entry.dir {
+			contents.push_str(&format!("{}{}\n",DIR_PREFIX,escape(dir)));
+		}
+		if entry.command.starts_with('#') {
+			contents.push('\\');
+		}
+		contents.push_str(&escape(&entry.command));
+		contents.push('\n');
+	}
+	// The file is truncated before it is written, so a signal that exits the shell in between would lose the history
+	signal::with_signals_blocked(&signal::CRITICAL_SIGNALS, || std::fs::write(path, contents))?;
+	// What was just written is already known, so the next lookup doesn't have to parse it again
+	store_cache(path, Arc::new(entries.to_vec()));
+	Ok(())
+}
+
+/// Adds `entry` to the end of the history file, keeping only the last `max_entries`
+/// If `erase_dups` is true, any earlier copies of the command are removed first
+pub fn append_entry(path: &Path, entry: HistEntry, erase_dups: bool, max_entries: usize) -> SlashResult<()> {
+	let mut entries = cached_entries(path).to_vec();
+	if erase_dups {
+		entries.retain(|old| old.command != entry.command);
+	}
+	entries.push(entry);
+	let excess = entries.len().saturating_sub(max_entries);
+	entries.drain(..excess);
+	write_hist_file(path, &entries)
+}
+
+/// Removes every entry whose command comes up again later, so each command is only left at its most recent spot
+pub fn dedup_entries(entries: &mut Vec<HistEntry>) {
+	let mut seen = HashSet::new();
+	let mut kept = entries.drain(..).rev().filter(|entry| seen.insert(entry.command.clone())).collect::<Vec<_>>();
+	kept.reverse();
+	*entries = kept;
+}
+
+/// The rules from `$HISTCONTROL` and `$HISTIGNORE` for which commands are saved
+#[derive(Debug, Default)]
+pub struct HistFilter {
+	pub ignore_space: bool,
+	pub ignore_dups: bool,
+	pub erase_dups: bool,
+	/// Glob patterns for commands that are never saved
+	pub ignore_patterns: Vec<String>,
+	extglob: bool
+}
+
+impl HistFilter {
+	pub fn from_slash(slash: &Slash) -> Self {
+		let mut filter = Self::default();
+		let control = slash.vars().get_var("HISTCONTROL").map(|val| val.to_string()).unwrap_or_default();
+		for opt in control.split(':') {
+			match opt {
+				"ignorespace" => filter.ignore_space = true,
+				"ignoredups" => filter.ignore_dups = true,
+				"ignoreboth" => {
+					filter.ignore_space = true;
+					filter.ignore_dups = true;
+				}
+				"erasedups" => filter.erase_dups = true,
+				_ => {}
+			}
+		}
+		let ignore = slash.vars().get_var("HISTIGNORE").map(|val| val.to_string()).unwrap_or_default();
+		filter.ignore_patterns = ignore.split(':').filter(|pat| !pat.is_empty()).map(|pat| pat.to_string()).collect();
+		filter.extglob = slash.meta().get_shopt("core.extglob").is_ok_and(|opt| opt.parse::<bool>().unwrap_or(false));
+		filter
+	}
+	/// Whether `command` should be left out of the history, given the entry before it
+	pub fn ignores(&self, command: &str, last: Option<&str>) -> bool {
+		(self.ignore_space && command.starts_with(' ')) ||
+		(self.ignore_dups && last == Some(command)) ||
+		self.ignore_patterns.iter().any(|pat| expand::glob::pattern_match(pat, command, self.extglob))
+	}
+}
+
+/// Loads the commands in a history file into the line editor's history, leaving the timestamps behind
+pub fn load_into<H: History>(path: &Path, history: &mut H) -> SlashResult<()> {
+	if !path.exists() {
+		return Err(Low(SlashErrLow::InternalErr(format!("{}: no such file",path.display()))))
+	}
+	for entry in cached_entries(path).iter() {
+		history.add_owned(entry.command.clone())
+			.map_err(|e| Low(SlashErrLow::InternalErr(format!("Failed to load history: {}",e))))?;
+	}
+	Ok(())
+}
+
+/// Formats an entry's time with a strftime format string, like `$HISTTIMEFORMAT`
+pub fn format_time(time: i64, fmt: &str) -> String {
+	let Some(time) = chrono::Local.timestamp_opt(time, 0).single() else {
+		return String::new()
+	};
+	// chrono reports bad format strings as a formatting error
+	let mut result = String::new();
+	match fmt::write(&mut result, format_args!("{}",time.format(fmt))) {
+		Ok(_) => result,
+		Err(_) => String::new()
+	}
+}
+
+fn escape(command: &str) -> String {
+	command.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+	let mut result = String::new();
+	let mut chars = line.chars();
+	while let Some(ch) = chars.next() {
+		match (ch, chars.clone().next()) {
+			('\\', Some('n')) => {
+				result.push('\n');
+				chars.next();
+			}
+			('\\', Some('\\')) => {
+				result.push('\\');
+				chars.next();
+			}
+			_ => result.push(ch)
+		}
+	}
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{shellenv::SlashVal, test_util::temp_path};
+
+	use super::*;
+
+	#[test]
+	fn test_timestamp_round_trip() {
+		let path = temp_path("hist");
+		let entries = vec![
+			HistEntry { time: None, dir: None, command: "ls".into() },
+			HistEntry { time: Some(1700000000), dir: None, command: "echo \"a\\b\"".into() },
+			HistEntry { time: Some(1700000060), dir: Some("/tmp/with space".into()), command: "for i in 1 2; do\n\techo $i\ndone".into() },
+		];
+		write_hist_file(&path, &entries).unwrap();
+
+		let contents = std::fs::read_to_string(&path).unwrap();
+		assert!(contents.contains("\n#1700000000\necho \"a\\\\b\"\n"));
+		assert_eq!(read_hist_file(&path).unwrap(), entries);
+
+		append_entry(&path, HistEntry { time: Some(chrono::Local::now().timestamp()), dir: None, command: "pwd".into() }, false, 3).unwrap();
+		let entries = read_hist_file(&path).unwrap();
+		assert_eq!(entries.len(), 3);
+		assert_eq!(entries[0].command, "echo \"a\\b\"");
+		assert!(entries[2].time.is_some_and(|time| time > 1700000060));
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(format_time(0, "%s "), "0 ");
+	}
+
+	#[test]
+	fn test_comment_commands() {
+		let path = temp_path("hist_comments");
+		// Commands that look like the lines that give an entry's time or directory
+		let entries = ["#dir /tmp", "#1700000000", "\\#dir", "# a note"].map(|command| HistEntry { time: None, dir: None, command: command.into() });
+		write_hist_file(&path, &entries).unwrap();
+		assert_eq!(read_hist_file(&path).unwrap(), entries);
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn test_hist_filter() {
+		let mut slash = Slash::new();
+		slash.vars_mut().set_var("HISTIGNORE", SlashVal::String("ls*:exit".into()));
+		slash.vars_mut().set_var("HISTCONTROL", SlashVal::String("ignorespace:erasedups".into()));
+		let filter = HistFilter::from_slash(&slash);
+		assert!(filter.ignores("ls -la", None));
+		assert!(filter.ignores("exit", None));
+		assert!(filter.ignores(" echo secret", None));
+		assert!(!filter.ignores("echo ls", None));
+		assert!(!filter.ignores("echo foo", Some("echo foo")));
+		assert!(filter.erase_dups);
+
+		let path = temp_path("hist_dups");
+		let entries = ["make", "cd src", "make test"].map(|cmd| HistEntry { time: None, dir: None, command: cmd.into() });
+		write_hist_file(&path, &entries).unwrap();
+		append_entry(&path, HistEntry { time: None, dir: None, command: "cd src".into() }, filter.erase_dups, 1000).unwrap();
+		let commands = read_hist_file(&path).unwrap().into_iter().map(|entry| entry.command).collect::<Vec<_>>();
+		assert_eq!(commands, ["make", "make test", "cd src"]);
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn test_multiline_entry() {
+		use rustyline::history::DefaultHistory;
+
+		// The validator keeps reading until the block parses, so the whole block arrives as one line
+		let block = "if true; then\n\techo yes\nfi";
+		let path = temp_path("hist_multiline");
+
+		let mut saved = DefaultHistory::new();
+		saved.add(block).unwrap();
+		saved.save(&path).unwrap();
+		let mut loaded = DefaultHistory::new();
+		load_into(&path, &mut loaded).unwrap();
+		assert_eq!(loaded.iter().collect::<Vec<_>>(), [block]);
+
+		// The timestamped format keeps the block together as well
+		write_hist_file(&path, &[HistEntry { time: Some(1700000000), dir: None, command: block.into() }]).unwrap();
+		let mut loaded = DefaultHistory::new();
+		load_into(&path, &mut loaded).unwrap();
+		assert_eq!(loaded.iter().collect::<Vec<_>>(), [block]);
+		std::fs::remove_file(&path).unwrap();
+	}
+}
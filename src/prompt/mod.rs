@@ -1,6 +1,7 @@
 pub mod comp;
 pub mod highlight;
 pub mod hint;
+pub mod history;
 pub mod prompt;
 pub mod rl_init;
 pub mod validate;
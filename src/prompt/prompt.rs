@@ -1,18 +1,22 @@
-use std::{env, path::Path};
+use std::env;
 
 use nix::{sys::signal::{kill, Signal}, unistd::Pid};
 use rustyline::{completion::FilenameCompleter, error::ReadlineError, history::History, Helper};
 
 use crate::prelude::*;
-use crate::{error::{SlashErr::*, SlashErrLow}, expand, shellenv::Slash, SlashResult};
+use crate::{error::{SlashErr::*, SlashErrLow}, expand, shellenv::Slash, signal, utils, SlashResult};
 
-use super::rl_init;
+use super::{history, rl_init};
 
 #[derive(Helper)]
 pub struct SlashHelper<'a> {
 	pub filename_comp: FilenameCompleter,
 	pub slash: &'a mut Slash,
-	pub commands: Vec<String>
+	pub commands: Vec<String>,
+	/// The history entries that were saved with a directory, which hints prefer when it is the current one
+	/// These are only loaded with `core.hist_dirs`, since the line editor's own history doesn't keep directories
+	pub dir_history: Vec<history::HistEntry>,
+	pub cwd: Option<String>
 }
 
 impl<'a> SlashHelper<'a> {
@@ -25,16 +29,35 @@ impl<'a> SlashHelper<'a> {
 			"exit".to_string(),
 		];
 
+		let hist_dirs = slash.meta().get_shopt("core.hist_dirs").is_ok_and(|opt| opt.parse::<bool>().unwrap_or(false));
+		let dir_history = if hist_dirs {
+			history::cached_entries(&history::hist_path(slash)).iter()
+				.filter(|entry| entry.dir.is_some())
+				.cloned()
+				.collect()
+		} else {
+			vec![]
+		};
+		let cwd = env::current_dir().ok().map(|dir| dir.to_string_lossy().to_string());
+
 		let mut helper = SlashHelper {
 			filename_comp: FilenameCompleter::new(),
 			slash,
 			commands,
+			dir_history,
+			cwd
 		};
 		helper.update_commands_from_path();
 		helper
 	}
 
+	/// Finds the latest history entry that starts with `term`, preferring ones that were run in the current directory
 	pub fn hist_substr_search(&self, term: &str, hist: &dyn History) -> Option<String> {
+		let same_dir = self.dir_history.iter().rev()
+			.find(|entry| entry.dir.is_some() && entry.dir == self.cwd && entry.command.starts_with(term));
+		if let Some(entry) = same_dir {
+			return Some(entry.command.clone())
+		}
 		let limit = hist.len();
 		let mut latest_match = None;
 		for i in 0..limit {
@@ -65,14 +88,28 @@ impl<'a> SlashHelper<'a> {
 	}
 }
 
+/// Throws away the input after Ctrl-C at the prompt, including any lines of an unfinished block
+fn abort_input(slash: &mut Slash) -> String {
+	slash.meta_mut().leave_prompt();
+	// rustyline reads the ^C itself, but a SIGINT from before the prompt shouldn't stop the next command
+	signal::take_interrupt();
+	slash.set_code(utils::SIG_EXIT_OFFSET + Signal::SIGINT as i32);
+	String::new()
+}
+
 pub fn run_prompt(slash: &mut Slash) -> SlashResult<String> {
 	slash.stop_timer()?;
 	slash.meta_mut().enter_prompt();
 
-	let hist_path = slash.vars().get_evar("HIST_FILE").unwrap_or_else(|| -> String {
-		let home = slash.vars().get_evar("HOME").unwrap_or_default();
-		format!("{}/.slash_hist",home)
-	});
+	let hist_path = history::hist_path(slash);
+	let timestamps = slash.meta().get_shopt("core.hist_timestamps")?.parse::<bool>().unwrap_or(false);
+	let hist_dirs = slash.meta().get_shopt("core.hist_dirs")?.parse::<bool>().unwrap_or(false);
+	let max_hist = slash.meta().get_shopt("core.max_hist")?.parse::<usize>().unwrap_or(1000);
+	// rustyline redraws the line itself if the terminal is resized while it is reading,
+	// but a resize while a command was running has to be picked up before the prompt is expanded
+	if signal::take_resize() || slash.vars().get_var("COLUMNS").is_none() {
+		slash.update_term_size();
+	}
 	let prompt = match expand::misc::expand_prompt(None,slash) {
 		Ok(expanded) => expanded,
 		Err(e) => {
@@ -86,23 +123,31 @@ pub fn run_prompt(slash: &mut Slash) -> SlashResult<String> {
 	match rl.readline(&prompt) {
 		Ok(line) => {
 			slash.meta_mut().leave_prompt();
-			if !line.is_empty() {
-				rl.history_mut()
+			let filter = history::HistFilter::from_slash(slash);
+			let last_entry = rl.history().iter().last().cloned();
+			if !line.is_empty() && !filter.ignores(&line, last_entry.as_deref()) {
+				let added = rl.history_mut()
 					.add(&line)
 					.map_err(|_| Low(SlashErrLow::InternalErr("Failed to write to history file".into())))?;
-					rl.history_mut()
-						.save(Path::new(&hist_path))
-						.map_err(|_| Low(SlashErrLow::InternalErr("Failed to write to history file".into())))?;
-					slash.meta_mut().set_last_input(&line);
+				// rustyline can't store timestamps or directories, remove entries, or tell a command like `#dir x` from those lines,
+				// so the file is kept up to date by hand
+				if added {
+					let time = timestamps.then(|| chrono::Local::now().timestamp());
+					let dir = hist_dirs.then(|| env::current_dir().ok()).flatten().map(|dir| dir.to_string_lossy().to_string());
+					let entry = history::HistEntry { time, dir, command: line.clone() };
+					history::append_entry(&hist_path, entry, filter.erase_dups, max_hist)?;
+				}
+			}
+			if !line.is_empty() {
+				slash.meta_mut().set_last_input(&line);
+				slash.meta_mut().next_cmd_number();
 			}
 			Ok(line)
 		}
-		Err(ReadlineError::Interrupted) => {
-			slash.meta_mut().leave_prompt();
-			Ok(String::new())
-		}
+		Err(ReadlineError::Interrupted) => Ok(abort_input(slash)),
 		Err(ReadlineError::Eof) => {
 			slash.meta_mut().leave_prompt();
+			slash.run_trap("EXIT").catch();
 			kill(Pid::this(), Signal::SIGQUIT).map_err(|_| Low(SlashErrLow::from_io()))?;
 			Ok(String::new())
 		}
@@ -112,3 +157,48 @@ pub fn run_prompt(slash: &mut Slash) -> SlashResult<String> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{lock_process, output_of}};
+
+use super::*;
+
+	#[test]
+	fn test_interrupted_prompt() {
+		let mut slash = Slash::new();
+		slash.meta_mut().enter_prompt();
+		let input = abort_input(&mut slash);
+		assert!(input.is_empty());
+
+		// The empty input runs like any other, and the shell carries on with $? set
+		execute::dispatch::exec_input(input, &mut slash).unwrap();
+		assert_eq!(output_of("echo $?", &mut slash), "130\n");
+	}
+
+	#[test]
+	fn test_term_size_vars() {
+		let _lock = lock_process();
+		let before = ["COLUMNS", "LINES"].map(|key| env::var(key).ok());
+		let mut slash = Slash::new();
+		// Making a shell doesn't touch the environment of the process
+		assert_eq!(env::var("COLUMNS").ok(), before[0]);
+		let cols = slash.vars().get_var("COLUMNS").unwrap().to_string().parse::<i32>().unwrap();
+		assert!(cols > 0);
+		assert!(slash.vars().get_var("LINES").unwrap().to_string().parse::<i32>().unwrap() > 0);
+
+		// Child processes see the size once it is exported, like the interactive shell does at startup,
+		// and an assignment holds until the next resize
+		slash.export_term_size();
+		let output = output_of("sh -c 'echo $COLUMNS'; COLUMNS=50", &mut slash);
+		assert_eq!(output.trim(), cols.to_string());
+		assert_eq!(slash.term_width(), 50);
+
+		for (key, val) in ["COLUMNS", "LINES"].into_iter().zip(before) {
+			match val {
+				Some(val) => env::set_var(key, val),
+				None => env::remove_var(key)
+			}
+		}
+	}
+}
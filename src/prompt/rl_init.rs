@@ -1,11 +1,11 @@
-use rustyline::{config::Configurer, history::DefaultHistory, ColorMode, Config, EditMode, Editor};
+use rustyline::{config::{BellStyle, Configurer}, history::DefaultHistory, ColorMode, Config, EditMode, Editor};
 
 use crate::{prelude::*, shellenv::EnvMeta};
 
-use super::prompt::SlashHelper;
+use super::{history, prompt::SlashHelper};
 
 pub fn load_history(path: &Path, rl: &mut Editor<SlashHelper, DefaultHistory>) -> SlashResult<()> {
-	if let Err(e) = rl.load_history(path) {
+	if let Err(e) = history::load_into(path, rl.history_mut()) {
 		eprintln!("No previous history found or failed to load history: {}", e);
 	}
 	Ok(())
@@ -13,10 +13,9 @@ pub fn load_history(path: &Path, rl: &mut Editor<SlashHelper, DefaultHistory>) -
 
 pub fn init_prompt<'a>(slash: &'a mut Slash) -> SlashResult<Editor<SlashHelper<'a>, DefaultHistory>> {
 	let config = build_editor_config(slash.meta())?;
-	let path = format!("{}/.slash_hist",env::var("HOME").unwrap_or_default());
-	let hist_path = Path::new(&path);
+	let hist_path = history::hist_path(slash);
 	let mut rl = initialize_editor(slash,config)?;
-	load_history(hist_path,&mut rl)?;
+	load_history(&hist_path,&mut rl)?;
 	Ok(rl)
 }
 
@@ -25,7 +24,12 @@ pub fn initialize_editor<'a>(slash: &'a mut Slash,config: Config) -> SlashResult
 		eprintln!("Failed to initialize Rustyline editor: {}", e);
 		std::process::exit(1);
 	});
-	rl.set_completion_type(rustyline::CompletionType::List);
+	// Menu completion relies on rustyline cycling through the candidates on Tab and Shift-Tab
+	let completion_type = match slash.meta().get_shopt("prompt.completion_style")?.trim_matches('"') {
+		"menu" => rustyline::CompletionType::Circular,
+		_ => rustyline::CompletionType::List
+	};
+	rl.set_completion_type(completion_type);
 	rl.set_helper(Some(SlashHelper::new(slash)));
 	Ok(rl)
 }
@@ -49,6 +53,7 @@ pub fn build_editor_config(meta: &EnvMeta) -> SlashResult<Config> {
 		false => ColorMode::Disabled,
 	};
 	let tab_stop = meta.get_shopt("prompt.tab_stop")?.parse::<usize>().unwrap();
+	let bell_style = bell_style(meta)?;
 
 	config = config
 		.max_history_size(max_size)
@@ -61,7 +66,34 @@ pub fn build_editor_config(meta: &EnvMeta) -> SlashResult<Config> {
 		.edit_mode(edit_mode)
 		.auto_add_history(auto_hist)
 		.color_mode(prompt_highlight)
-		.tab_stop(tab_stop);
+		.tab_stop(tab_stop)
+		.bell_style(bell_style);
 
 	Ok(config.build())
 }
+
+/// The bell from `core.bell_style`, which is 0 for none, 1 for audible, and 2 for visible
+/// rustyline doesn't draw a visible bell itself, so completion flashes the screen for it
+pub fn bell_style(meta: &EnvMeta) -> SlashResult<BellStyle> {
+	match meta.get_shopt("core.bell_style")?.parse::<usize>() {
+		Ok(0) => Ok(BellStyle::None),
+		Ok(1) => Ok(BellStyle::Audible),
+		Ok(2) => Ok(BellStyle::Visible),
+		_ => Err(Low(SlashErrLow::InternalErr("Invalid shopts.core.bell_style value".into())))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_bell_style_config() {
+		let mut slash = Slash::new();
+		for (value, style) in [("0", BellStyle::None), ("1", BellStyle::Audible), ("2", BellStyle::Visible)] {
+			slash.meta_mut().set_shopt("core.bell_style", value).unwrap();
+			assert_eq!(build_editor_config(slash.meta()).unwrap().bell_style(), style);
+		}
+		assert!(slash.meta_mut().set_shopt("core.bell_style", "3").is_err());
+	}
+}
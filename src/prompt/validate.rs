@@ -4,15 +4,26 @@ use crate::prelude::*;
 
 use super::prompt::SlashHelper;
 
-fn try_parse(input: &str) -> bool {
-	SlashParse::parse(Rule::main, input).is_ok()
+fn try_parse(input: &str, comments: bool) -> bool {
+	// The prompt keeps reading lines while a here-doc is waiting for its delimiter
+	let (input, pending) = crate::expand::misc::collect_heredocs(input);
+	if !pending.is_empty() {
+		return false
+	}
+	let input = input.as_str();
+	if comments {
+		let input = crate::expand::misc::strip_comments(input);
+		SlashParse::parse(Rule::main, &input).is_ok()
+	} else {
+		SlashParse::parse(Rule::main, input).is_ok()
+	}
 }
 
 impl<'a> Validator for SlashHelper<'a> {
 	fn validate(&self, ctx: &mut rustyline::validate::ValidationContext) -> rustyline::Result<rustyline::validate::ValidationResult> {
 	    let input = ctx.input();
 
-			match try_parse(input) {
+			match try_parse(input, self.slash.comments_enabled()) {
 				true => Ok(rustyline::validate::ValidationResult::Valid(None)),
 				false => Ok(rustyline::validate::ValidationResult::Incomplete),
 			}
@@ -15,6 +15,10 @@ pub fn exec_for_cmd<'a>(cmd: Pair<'a,Rule>,slash: &mut Slash) -> SlashResult<()>
 		.map(|elem| SlashVal::parse(elem.as_str()).unwrap())
 		.collect::<Vec<SlashVal>>();
 
+	if let Some(var) = loop_vars.iter().find(|var| slash.vars().is_readonly(var)) {
+		return Err(High(SlashErrHigh::exec_err(format!("{}: readonly variable",var), cmd)))
+	}
+
 	let vars_len = loop_vars.len();
 	for var in &loop_vars {
 		let existing_val = slash.vars().get_var(var).unwrap_or_default();
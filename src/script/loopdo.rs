@@ -1,12 +1,35 @@
-use crate::prelude::*;
+use crate::{prelude::*, utils};
 
+/// Redirections on the loop are opened once for the whole loop, so `while read line; do ...; done < file` reads the file line by line
 pub fn exec_loop_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let loop_kind = cmd.scry(Rule::loop_kind).unpack()?.as_str();
 	let loop_cond = cmd.scry(Rule::loop_cond).unpack()?.as_str().to_string();
 	let loop_body = cmd.scry(Rule::loop_body).unpack()?.as_str().to_string();
 
+	// Fds past the standard ones that only the loop's redirections open are closed again once it is done
+	let mut redirs = slash.ctx_mut().take_redirs();
+	let opened_fds = redirs.iter()
+		.map(|redir| redir.our_fd())
+		.filter(|fd| *fd > 2 && fcntl(*fd, F_GETFD).is_err())
+		.collect::<Vec<_>>();
+	slash.expand_heredocs(&mut redirs)?;
+
+	let saved_fds = utils::save_fds()?;
+	// Dropping the redirections would close those fds before the loop reads from them, so they are kept open until it is done
+	let mut loop_redirs = utils::CmdRedirs::new(redirs);
+	let activated = loop_redirs.activate(slash.noclobber());
+	loop_redirs.persist();
+	let result = activated.and_then(|_| run_loop(loop_kind, &loop_cond, &loop_body, slash));
+	utils::restore_fds(saved_fds, slash)?;
+	for fd in opened_fds {
+		let _ = close(fd);
+	}
+	result
+}
+
+fn run_loop(loop_kind: &str, loop_cond: &str, loop_body: &str, slash: &mut Slash) -> SlashResult<()> {
 	loop {
-		slash.exec_as_cond(&loop_cond)?;
+		slash.exec_as_cond(loop_cond)?;
 		let is_success = slash.get_status() == 0;
 		match loop_kind {
 			"while" => {
@@ -21,7 +44,7 @@ pub fn exec_loop_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 			}
 			_ => unreachable!()
 		}
-		let result = slash.exec_as_body(&loop_body);
+		let result = slash.exec_as_body(loop_body);
 		match result {
 			Err(High(err)) => {
 				match err.get_err() {
@@ -1,20 +1,30 @@
-use crate::prelude::*;
+use crate::{expand, helper, prelude::*};
 
 pub fn exec_match_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let mut inner = cmd.into_inner();
 	let match_pat = inner.next().unpack()?;
+	let subject = helper::try_expansion(slash, match_pat)?;
+	let extglob = slash.meta().get_shopt("core.extglob")?.parse::<bool>().unwrap_or(false);
 	let mut arms = VecDeque::new();
 
 	while let Some(arm) = inner.next() {
-		arms.push_back(arm);
+		if arm.as_rule() == Rule::match_arm {
+			arms.push_back(arm);
+		}
 	}
 
 	while let Some(arm) = arms.pop_front() {
 		let mut inner = arm.into_inner();
 		let arm_pat = inner.next().unpack()?;
-		let arm_body = inner.next().unpack()?.as_str();
+		let arm_body = inner.next().unpack()?.as_str().trim_end_matches(',');
 
-		if arm_pat.as_str().trim() == match_pat.as_str().trim() {
+		// Arms are only patterns with extglob on, and are otherwise compared as they are written
+		let is_match = if extglob {
+			expand::glob::pattern_match(arm_pat.as_str().trim(), subject.trim(), true)
+		} else {
+			arm_pat.as_str().trim() == subject.trim()
+		};
+		if is_match {
 			slash.exec_as_body(arm_body)?;
 			break
 		}
@@ -22,3 +32,24 @@ pub fn exec_match_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 	slash.set_code(0);
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::output_of};
+
+use super::*;
+
+	#[test]
+	fn test_match_extglob() {
+		let mut slash = Slash::new();
+		// Like in bash, the option has to be on before the line that uses it is parsed
+		execute::dispatch::exec_input("setopt core.extglob=true".to_string(), &mut slash).unwrap();
+		let output = output_of("match main.c < *.o => echo object, !(*.o) => echo source, done", &mut slash);
+		assert_eq!(output, "source\n");
+
+		execute::dispatch::exec_input("setopt core.extglob=false".to_string(), &mut slash).unwrap();
+		let output = output_of("match foo < f* => echo pattern, foo => echo literal, done", &mut slash);
+		assert_eq!(output, "literal\n");
+		assert!(execute::dispatch::exec_input("match foo < @(foo|bar) => echo extglob, done".to_string(), &mut slash).is_err());
+	}
+}
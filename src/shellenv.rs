@@ -1,4 +1,4 @@
-use std::{collections::{BTreeMap, VecDeque}, env, ffi::{CString, OsStr}, fmt, hash::Hash, io::{self, Read}, mem::take, os::fd::BorrowedFd, path::{Path, PathBuf}, sync::{Arc, LazyLock}, time::{Duration, Instant}};
+use std::{collections::{BTreeMap, BTreeSet, VecDeque}, env, ffi::{CString, OsStr}, fmt, hash::Hash, io::{self, Read}, mem::take, os::fd::BorrowedFd, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc, LazyLock}, time::{Duration, Instant}};
 use std::collections::HashMap;
 
 use bitflags::bitflags;
@@ -6,7 +6,7 @@ use nix::{sys::{signal::{kill, killpg, signal, SigHandler, SigmaskHow, Signal::{
 use once_cell::sync::Lazy;
 use std::sync::RwLock;
 
-use crate::{execute::dispatch, prelude::*, utils::{self, Redir}};
+use crate::{execute::dispatch, expand, prelude::*, prompt::comp::CompSpec, utils::{self, Redir}};
 use crate::{error::{SlashErr::*, SlashErrLow}, helper::{self, VecDequeExtension}, shopt::ShOpts, SlashResult};
 
 
@@ -90,6 +90,9 @@ bitflags! {
 		const INHERIT_RET      = 0b00000100000000000000000000000000; // set -T
 		const SOURCING         = 0b00001000000000000000000000000000;
 		const INITIALIZED      = 0b00010000000000000000000000000000;
+		const PIPEFAIL         = 0b00100000000000000000000000000000; // set -o pipefail
+		const IN_TRAP          = 0b01000000000000000000000000000000; // Running a trap handler, which must not set off traps itself
+		const POSIX            = 0b10000000000000000000000000000000; // set -o posix
 	}
 	#[derive(Debug,Copy,Clone)]
 	pub struct JobCmdFlags: i8 { // Options for the jobs builtin
@@ -100,6 +103,14 @@ bitflags! {
 		const STOPPED   = 0b00010000;
 		const INIT      = 0b00100000;
 	}
+	#[derive(Debug,Copy,Clone,PartialEq)]
+	pub struct VarFlags: u8 { // Variable attributes set by declare
+		const INTEGER   = 0b00000001; // -i
+		const READONLY  = 0b00000010; // -r
+		const EXPORT    = 0b00000100; // -x
+		const ARRAY     = 0b00001000; // -a
+		const ASSOC     = 0b00010000; // -A
+	}
 }
 
 #[derive(Debug,Clone)]
@@ -118,7 +129,59 @@ impl Slash {
 		let meta = EnvMeta::new(EnvFlags::empty());
 		let ctx = ExecCtx::new();
 
-		Self { vars, logic, meta, ctx }
+		let mut slash = Self { vars, logic, meta, ctx };
+		// Until a command has run, `$_` is the path of the shell itself
+		slash.vars.set_param("_", &env::current_exe().unwrap_or_default().to_string_lossy());
+		slash.set_subshell_depth(0);
+		slash.update_term_size();
+		slash
+	}
+	/// Sets `$COLUMNS` and `$LINES` to the size of the terminal, updating the environment too if they are exported
+	/// Without a terminal, sizes from the environment are kept, or 80x24 is assumed
+	pub fn update_term_size(&mut self) {
+		let from_env = |key: &str, default: u16| env::var(key).ok()
+			.and_then(|val| val.parse::<u16>().ok())
+			.filter(|val| *val > 0)
+			.unwrap_or(default);
+		// Unlike `terminal::size()`, this never falls back to running `tput`
+		let (cols, rows) = match crossterm::terminal::window_size() {
+			Ok(size) if size.columns > 0 && size.rows > 0 => (size.columns, size.rows),
+			_ => (from_env("COLUMNS", 80), from_env("LINES", 24))
+		};
+		for (key, val) in [("COLUMNS", cols), ("LINES", rows)] {
+			if self.vars.get_evar(key).is_some() {
+				self.vars.export_var(key, &val.to_string());
+			}
+			self.vars.set_var(key, SlashVal::Int(val as i32));
+		}
+	}
+	/// Exports `$COLUMNS` and `$LINES`, so that programs run from the prompt can lay themselves out by the terminal size
+	pub fn export_term_size(&mut self) {
+		self.update_term_size();
+		for key in ["COLUMNS", "LINES"] {
+			if let Some(val) = self.vars.get_var(key) {
+				self.vars.export_var(key, &val.to_string());
+			}
+		}
+	}
+	/// How many subshells deep the shell is, from `$OXIDE_SUBSHELL`
+	pub fn subshell_depth(&self) -> i32 {
+		self.vars.get_var("OXIDE_SUBSHELL")
+			.and_then(|depth| depth.to_string().parse::<i32>().ok())
+			.unwrap_or(0)
+	}
+	/// Sets `$OXIDE_SUBSHELL`, and `$BASH_SUBSHELL` along with it for scripts written for bash
+	pub fn set_subshell_depth(&mut self, depth: i32) {
+		for key in ["OXIDE_SUBSHELL", "BASH_SUBSHELL"] {
+			self.vars.set_var(key, SlashVal::Int(depth));
+		}
+	}
+	/// How many columns wide output should be laid out, from `$COLUMNS`
+	pub fn term_width(&self) -> usize {
+		self.vars.get_var("COLUMNS")
+			.and_then(|cols| cols.to_string().parse::<usize>().ok())
+			.filter(|cols| *cols > 0)
+			.unwrap_or(80)
 	}
 	pub fn vars(&self) -> &VarTable {
 		&self.vars
@@ -156,7 +219,12 @@ impl Slash {
 		//let col = call_info.column();
 		//eprintln!("called set code from file: {file}, on line {line} col {col}");
 
-		self.vars.set_param("?", &code.to_string())
+		self.vars.set_param("?", &code.to_string());
+		self.set_pipe_status(&[code]);
+	}
+	/// Sets `$PIPESTATUS` to the exit code of each stage of the last pipeline, or just the last command's code
+	pub fn set_pipe_status(&mut self, codes: &[i32]) {
+		self.vars.set_var("PIPESTATUS", SlashVal::Array(codes.iter().map(|code| SlashVal::Int(*code)).collect()));
 	}
 	pub fn in_pipe(&self) -> bool {
 		self.meta.flags().contains(EnvFlags::IN_SUB_PROC)
@@ -169,7 +237,27 @@ impl Slash {
 	}
 	pub fn consume_redirs(&mut self, redirs: VecDeque<Redir>) -> SlashResult<()> {
 		self.ctx_mut().extend_redirs(redirs);
-		self.ctx_mut().activate_redirs()?;
+		self.activate_redirs()
+	}
+	pub fn activate_redirs(&mut self) -> SlashResult<()> {
+		let noclobber = self.noclobber();
+		let mut redirs = self.ctx.take_redirs();
+		self.expand_heredocs(&mut redirs)?;
+		utils::CmdRedirs::new(redirs).activate(noclobber)
+	}
+	/// Either `set -C` or the `core.noclobber` shopt stops `>` from overwriting existing files
+	pub fn noclobber(&self) -> bool {
+		self.meta.flags().contains(EnvFlags::NO_OVERWRITE) ||
+		self.meta.get_shopt("core.noclobber").is_ok_and(|opt| opt.parse::<bool>().unwrap_or(false))
+	}
+	/// Here-doc bodies are expanded when their command runs, so a body in a loop or a function sees the current values
+	pub fn expand_heredocs(&mut self, redirs: &mut VecDeque<Redir>) -> SlashResult<()> {
+		for redir in redirs.iter_mut() {
+			if let Some(word) = redir.heredoc_word() {
+				let body = expand::string::expand_heredoc(&word.to_string(), self)?;
+				redir.set_body(body);
+			}
+		}
 		Ok(())
 	}
 	pub fn start_timer(&mut self) {
@@ -192,28 +280,104 @@ impl Slash {
 		Ok(())
 	}
 
-	pub fn source_rc(&mut self, path: Option<PathBuf>) -> SlashResult<()> {
-		let path = if let Some(path) = path {
-			path
-		} else {
-			let home = env::var("HOME").unwrap();
-			PathBuf::from(format!("{home}/.slashrc"))
-		};
+	/// With `set -v`, writes input to stderr exactly as it was read, before it is parsed or expanded
+	pub fn print_input(&self, input: &str) -> SlashResult<()> {
+		if !self.meta.flags().contains(EnvFlags::PRINT_INPUT) || input.is_empty() {
+			return Ok(())
+		}
+		let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+		write!(stderr, "{}", input)?;
+		if !input.ends_with('\n') {
+			writeln!(stderr)?;
+		}
+		Ok(())
+	}
+
+	/// Sources a startup file like `~/.slashrc`, reporting any error in it without stopping the shell
+	pub fn source_rc(&mut self, path: &Path) -> SlashResult<()> {
 		if let Err(e) = self.source_file(path.to_str().unwrap()) {
 			self.set_code(1);
-			eprintln!("Failed to source slashrc: {}",e);
+			eprintln!("Failed to source {}: {}",path.display(),e);
 		}
 		Ok(())
 	}
 
+	/// Pushes a function call or sourced file onto the call stack
+	/// Fails instead if the stack is already `$FUNCNEST` frames deep, or `core.max_recurse_depth` if it is unset,
+	/// so runaway recursion stops with an error before it overflows the stack
+	pub fn enter_frame(&mut self, frame: StackFrame) -> SlashResult<()> {
+		let limit = self.vars.get_var("FUNCNEST")
+			.and_then(|val| val.to_string().parse::<usize>().ok())
+			.filter(|limit| *limit > 0)
+			.unwrap_or_else(|| self.meta.get_shopt("core.max_recurse_depth").ok().and_then(|opt| opt.parse::<usize>().ok()).unwrap_or(500));
+		if self.meta.call_stack().len() >= limit {
+			return Err(Low(SlashErrLow::ExecFailed(format!("{}: function nesting too deep ({})",frame.name,limit))))
+		}
+		self.meta.push_frame(frame);
+		Ok(())
+	}
 
 	pub fn source_file<'a>(&mut self, path: &str) -> SlashResult<()> {
-		let mut file = utils::SmartFD::std_open(Path::new(path))?;
 		let mut buffer = String::new();
-		file.read_to_string(&mut buffer).map_err(|_| Low(SlashErrLow::from_io()))?;
-		file.close()?;
-
-		dispatch::exec_input(buffer, self)
+		// The file is closed at the end of this statement, before its commands run
+		utils::SmartFD::std_open(Path::new(path))?.read_to_string(&mut buffer).map_err(|_| Low(SlashErrLow::from_io()))?;
+
+		let frame = StackFrame {
+			name: path.to_string(),
+			line: self.vars.get_param("LINENO").and_then(|line| line.parse::<usize>().ok()).unwrap_or(0),
+			file: self.meta.current_file(),
+			is_source: true,
+			shadowed: HashMap::new()
+		};
+		self.enter_frame(frame)?;
+		// Sourced files always get comments, regardless of core.int_comments
+		let sourcing = self.meta.flags().contains(EnvFlags::SOURCING);
+		self.meta.mod_flags(|flags| *flags |= EnvFlags::SOURCING);
+		// $LINENO counts from the top of the sourced file
+		let line_base = self.meta.line_base();
+		self.meta.set_line_base(0);
+		let result = dispatch::exec_read_input(buffer, self);
+		let trap_result = self.run_trap("RETURN");
+		self.meta.set_line_base(line_base);
+		self.meta.pop_frame();
+		if !sourcing {
+			self.meta.mod_flags(|flags| *flags &= !EnvFlags::SOURCING);
+		}
+		result.and(trap_result)
+	}
+	/// Runs the handler that `trap` set for `signal`, if there is one, leaving `$?` as it was
+	/// Nothing run by a handler sets off a trap, so a handler can't end up calling itself
+	pub fn run_trap(&mut self, signal: &str) -> SlashResult<()> {
+		if self.meta.flags().contains(EnvFlags::IN_TRAP) {
+			return Ok(())
+		}
+		let Some(handler) = self.logic.get_trap(signal) else {
+			return Ok(())
+		};
+		let status = self.get_status();
+		self.meta.mod_flags(|flags| *flags |= EnvFlags::IN_TRAP);
+		let result = dispatch::exec_input(handler, self);
+		self.meta.mod_flags(|flags| *flags &= !EnvFlags::IN_TRAP);
+		self.set_code(status);
+		result
+	}
+	/// Runs the handlers for trapped signals that have arrived since the last check
+	pub fn run_pending_traps(&mut self) -> SlashResult<()> {
+		let trapped = self.logic.borrow_traps().keys()
+			.filter_map(|name| format!("SIG{}",name).parse::<Signal>().ok())
+			.collect::<Vec<_>>();
+		for sig in crate::signal::take_pending_traps(&trapped) {
+			self.run_trap(&sig.as_str()["SIG".len()..])?;
+		}
+		Ok(())
+	}
+	/// Whether or not `#` starts a comment in the current context
+	/// `core.int_comments` only applies to input typed at the prompt
+	pub fn comments_enabled(&self) -> bool {
+		let flags = self.meta.flags();
+		!flags.contains(EnvFlags::INTERACTIVE) ||
+		flags.contains(EnvFlags::SOURCING) ||
+		self.meta.get_shopt("core.int_comments").is_ok_and(|opt| opt.parse::<bool>().unwrap_or(true))
 	}
 
 	pub fn get_cstring_evars<'a>(&self) -> SlashResult<Vec<CString>> {
@@ -283,6 +447,10 @@ impl Slash {
 		env::set_var("SHELL", pathbuf_to_string(std::env::current_exe()));
 		env_vars.insert("HIST_FILE".into(),format!("{}/.slash_hist",home));
 		env::set_var("HIST_FILE",format!("{}/.slash_hist",home));
+		// PATH is always exported, so that changing it, even with `local`, changes where commands are looked up
+		let path = env::var("PATH").unwrap_or_else(|_| "/usr/local/bin:/usr/bin:/bin".into());
+		env_vars.insert("PATH".into(), path.clone());
+		env::set_var("PATH", path);
 
 		env_vars
 	}
@@ -331,6 +499,7 @@ impl ExecCtx {
 		let mut clone = self.clone();
 		let (cond_redirs,_) = self.sort_redirs();
 		clone.redir_queue = cond_redirs.into();
+		clone.flags |= utils::ExecFlags::IN_COND;
 		clone
 	}
 	/// Creates a new instance of ExecCtx which retains only the stdout and stderr of the original
@@ -352,20 +521,25 @@ impl ExecCtx {
 		for redir in self.redir_queue.clone() {
 			match redir.redir_type() {
 				Rule::r#in => in_redirs.push(redir.clone()),
-				Rule::out | Rule::append => out_redirs.push(redir.clone()),
+				Rule::out | Rule::force_out | Rule::append | Rule::close_fd => out_redirs.push(redir.clone()),
 				_ => unimplemented!()
 			}
 		}
 		(in_redirs,out_redirs)
 	}
 	pub fn push_state(&mut self) -> SlashResult<()> {
+		// The saved states leave out the stack, since each one would otherwise hold a copy of every state below it
+		let stack = std::mem::take(&mut self.state_stack);
 		let saved_state = Box::new(self.clone());
+		self.state_stack = stack;
 		self.state_stack.push(saved_state);
 		Ok(())
 	}
 	pub fn pop_state(&mut self) -> SlashResult<()> {
 		if let Some(state) = self.state_stack.pop() {
+			let stack = std::mem::take(&mut self.state_stack);
 			*self = *state;
+			self.state_stack = stack;
 		}
 		Ok(())
 	}
@@ -410,10 +584,6 @@ impl ExecCtx {
 	pub fn consume_redirs(&mut self) -> utils::CmdRedirs {
 		utils::CmdRedirs::new(self.take_redirs())
 	}
-	pub fn activate_redirs(&mut self) -> SlashResult<()> {
-		let mut redirs = self.consume_redirs();
-		redirs.activate()
-	}
 }
 
 #[derive(Debug,Clone)]
@@ -460,6 +630,14 @@ impl<'a> ChildProc {
 	pub fn status(&self) -> WaitStatus {
 		self.status
 	}
+	/// The exit code that the process's status corresponds to, or zero if it hasn't finished
+	pub fn exit_code(&self) -> i32 {
+		match self.status {
+			WaitStatus::Exited(_, code) => code,
+			WaitStatus::Signaled(_, sig, _) | WaitStatus::Stopped(_, sig) => utils::SIG_EXIT_OFFSET + sig as i32,
+			_ => 0
+		}
+	}
 	pub fn waitpid(&mut self, flags: Option<WaitPidFlag>) -> Result<WaitStatus, nix::errno::Errno>  {
 		let result = waitpid(self.pid(), flags);
 		if let Ok(status) = result {
@@ -485,7 +663,10 @@ impl<'a> ChildProc {
 		matches!(self.status, WaitStatus::Stopped(_, _))
 	}
 	pub fn is_done(&self) -> bool {
-		matches!(self.status, WaitStatus::Exited(_, _))
+		matches!(self.status, WaitStatus::Exited(_, _) | WaitStatus::Signaled(_, _, _))
+	}
+	pub fn is_running(&self) -> bool {
+		matches!(self.status, WaitStatus::StillAlive | WaitStatus::Continued(_))
 	}
 }
 
@@ -624,6 +805,25 @@ impl Job {
 	pub fn get_statuses(&self) -> Vec<WaitStatus> {
 		self.children.iter().map(|chld| chld.status()).collect::<Vec<WaitStatus>>()
 	}
+	pub fn is_running(&self) -> bool {
+		self.children.iter().any(|chld| chld.is_running())
+	}
+	/// True once every process in the job has exited or been killed
+	/// A stopped job can still be continued, so it isn't done
+	pub fn is_done(&self) -> bool {
+		self.children.iter().all(|chld| chld.is_done())
+	}
+	/// The exit code of the job, taken from the last process in the group
+	/// With `pipefail`, it is taken from the rightmost process that failed instead, if there is one
+	pub fn get_exit_code(&self, pipefail: bool) -> i32 {
+		let codes = self.children.iter().map(|chld| chld.exit_code());
+		if pipefail {
+			if let Some(code) = codes.clone().filter(|code| *code != 0).last() {
+				return code
+			}
+		}
+		codes.last().unwrap_or(0)
+	}
 	pub fn get_children(&self) -> &[ChildProc] {
 		&self.children
 	}
@@ -649,18 +849,27 @@ impl Job {
 	pub fn wait_pgrp<'a>(&mut self) -> SlashResult<Vec<WaitStatus>> {
 		let mut statuses = Vec::new();
 
-		for child in self.children.iter_mut() {
-			let result = child.waitpid(Some(WaitPidFlag::WUNTRACED));
-			match result {
-				Ok(status) => {
-					statuses.push(status);
-				}
-				Err(nix::errno::Errno::ECHILD) => {
-					// No more child processes in the group
-					break;
-				}
-				Err(_) => {
-					return Err(Low(SlashErrLow::from_io()));
+		let pgid = self.pgid;
+		'children: for child in self.children.iter_mut() {
+			loop {
+				match child.waitpid(Some(WaitPidFlag::WUNTRACED)) {
+					Ok(status) => {
+						statuses.push(status);
+						break
+					}
+					Err(nix::errno::Errno::EINTR) => {
+						// The shell got the SIGINT instead of the job, so pass it on
+						if crate::signal::take_interrupt() {
+							killpg(pgid, Signal::SIGINT).ok();
+						}
+					}
+					Err(nix::errno::Errno::ECHILD) => {
+						// No more child processes in the group
+						break 'children
+					}
+					Err(_) => {
+						return Err(Low(SlashErrLow::from_io()));
+					}
 				}
 			}
 		}
@@ -721,6 +930,9 @@ impl JobTable {
 	pub fn mut_jobs(&mut self) -> &mut Vec<Option<Job>> {
 		&mut self.jobs
 	}
+	pub fn job_count(&self) -> usize {
+		self.jobs.iter().flatten().count()
+	}
 	pub fn curr_job(&self) -> Option<usize> {
 		self.order.last().copied()
 	}
@@ -751,13 +963,16 @@ impl JobTable {
 	pub fn job_order(&self) -> &[usize] {
 		&self.order
 	}
-	pub fn new_fg<'a>(&mut self, job: Job) -> SlashResult<Vec<WaitStatus>> {
-		let pgid = job.pgid();
+	/// Makes `job` the foreground job
+	/// The caller waits on a copy of it without holding the table, then hands that back to `finish_fg()`
+	pub fn new_fg(&mut self, job: Job) {
 		self.fg = Some(job);
-		attach_tty(pgid)?;
-		let statuses = self.fg.as_mut().unwrap().wait_pgrp()?;
-		attach_tty(getpgrp())?;
-		Ok(statuses)
+	}
+	/// Replaces the foreground job with the copy that was waited on, which has the statuses of its children
+	pub fn finish_fg(&mut self, job: Job) {
+		if self.fg.as_ref().is_some_and(|fg| fg.pgid() == job.pgid()) {
+			self.fg = Some(job);
+		}
 	}
 
 	pub fn fg_to_bg(&mut self, status: WaitStatus) -> SlashResult<()> {
@@ -776,6 +991,7 @@ impl JobTable {
 	pub fn remove_job(&mut self, id: JobID) -> Option<Job> {
 		let table_id = self.query(id).map(|job| job.table_id()).unwrap();
 		if let Some(table_id) = table_id {
+			self.order.retain(|id| *id != table_id);
 			self.jobs.get_mut(table_id).and_then(Option::take)
 		} else {
 			None
@@ -899,6 +1115,40 @@ impl JobTable {
 			}
 		}
 	}
+	/// Resolves a job specifier like `%1`, `%+`, `%%`, `%-`, `%cmd`, or `%?cmd` into a table id
+	///
+	/// `%string` matches jobs whose command starts with `string`, and `%?string` matches jobs whose command
+	/// contains it. Both are errors if more than one job matches.
+	pub fn resolve_jobspec(&self, spec: &str) -> SlashResult<usize> {
+		let Some(body) = spec.strip_prefix('%') else {
+			return Err(Low(SlashErrLow::ExecFailed(format!("Invalid job spec: {}",spec))))
+		};
+		let no_such_job = || Low(SlashErrLow::ExecFailed(format!("{}: no such job",spec)));
+		let matches = |pred: &dyn Fn(&str) -> bool| -> SlashResult<usize> {
+			let found = self.jobs.iter().flatten()
+				.filter(|job| pred(&job.get_commands().join(" | ")))
+				.filter_map(|job| job.table_id())
+				.collect::<Vec<usize>>();
+			match found.as_slice() {
+				[id] => Ok(*id),
+				[] => Err(no_such_job()),
+				_ => Err(Low(SlashErrLow::ExecFailed(format!("{}: ambiguous job spec",spec))))
+			}
+		};
+		match body {
+			"" | "+" | "%" => self.curr_job().ok_or_else(no_such_job),
+			"-" => self.prev_job().or(self.curr_job()).ok_or_else(no_such_job),
+			_ if body.chars().all(|ch| ch.is_ascii_digit()) => {
+				// Job numbers are displayed starting from 1
+				let id = body.parse::<usize>().map_err(|_| no_such_job())?.checked_sub(1).ok_or_else(no_such_job)?;
+				self.query(JobID::TableID(id)).and(Some(id)).ok_or_else(no_such_job)
+			}
+			_ => match body.strip_prefix('?') {
+				Some(substr) => matches(&|cmd| cmd.contains(substr)),
+				None => matches(&|cmd| cmd.starts_with(body))
+			}
+		}
+	}
 	pub fn hang_up(&mut self) {
 		for job in self.jobs.iter_mut() {
 			if let Some(ref mut job) = job {
@@ -921,7 +1171,8 @@ impl JobTable {
 	pub fn reset_recents(&mut self) {
 		self.new_updates.clear()
 	}
-	pub fn print_jobs(&self, flags: &JobCmdFlags, mut fmt: impl Write) -> SlashResult<()> {
+	/// Lists the jobs in as many columns as fit within `width`
+	pub fn print_jobs(&self, flags: &JobCmdFlags, width: usize, mut fmt: impl Write) -> SlashResult<()> {
 		let jobs = if flags.contains(JobCmdFlags::NEW_ONLY) {
 			&self.jobs
 				.iter()
@@ -934,6 +1185,7 @@ impl JobTable {
 				.map(|job| job.as_ref())
 				.collect::<Vec<Option<&Job>>>()
 		};
+		let mut lines = vec![];
 		for job in jobs.iter().flatten() {
 			// Skip foreground job
 			let id = job.table_id().unwrap();
@@ -944,9 +1196,10 @@ impl JobTable {
 			if flags.contains(JobCmdFlags::STOPPED) && !matches!(job.get_statuses().get(id).unwrap(), WaitStatus::Stopped(_,_)) {
 				continue;
 			}
-			// Print the job in the selected format
-			writeln!(fmt,"{}",job.display(&self.order,*flags))?;
+			// Tabs have no fixed width, so they would throw the columns off
+			lines.push(job.display(&self.order,*flags).replace('\t', " "));
 		}
+		write!(fmt,"{}",helper::fmt_columns(&lines, width))?;
 		Ok(())
 	}
 	pub fn update_job_statuses<'a>(&mut self) -> SlashResult<()> {
@@ -1219,12 +1472,39 @@ impl fmt::Display for SlashVal {
 
 
 
+fn next_random(random_state: &AtomicU64) -> i32 {
+	let mut state = random_state.load(Ordering::Relaxed);
+	if state == 0 {
+		let nanos = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.map(|dur| dur.as_nanos() as u64)
+			.unwrap_or_default();
+		state = (nanos ^ ((std::process::id() as u64) << 32)) | 1;
+	}
+	// xorshift64*
+	state ^= state >> 12;
+	state ^= state << 25;
+	state ^= state >> 27;
+	random_state.store(state, Ordering::Relaxed);
+	((state.wrapping_mul(0x2545F4914F6CDD1D) >> 33) % 32768) as i32
+}
+
+fn seed_random(random_state: &AtomicU64, seed: u64) {
+	// Zero would leave the generator stuck, and also means "unseeded"
+	random_state.store(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1, Ordering::Relaxed);
+}
+
 #[derive(Debug,Clone)]
 pub struct VarTable {
 	env: HashMap<String,String>,
 	params: HashMap<String,String>,
 	pos_params: VecDeque<String>,
-	vars: HashMap<String,SlashVal>
+	vars: HashMap<String,SlashVal>,
+	flags: HashMap<String,VarFlags>,
+	globals: HashMap<String,(SlashVal,VarFlags)>, // Set with `declare -g`, and carried out of the function when it returns
+	seconds_base: (Instant, u64), // $SECONDS counts up from the second value, starting at the instant
+	random_state: Arc<AtomicU64>, // State for $RANDOM, shared between clones so that restoring a snapshot doesn't replay old values
+	current_command: String // The text of the command being run, for $BASH_COMMAND and $OX_COMMAND
 }
 
 impl VarTable {
@@ -1233,7 +1513,12 @@ impl VarTable {
 			env,
 			params: HashMap::new(),
 			pos_params: VecDeque::new(),
-			vars: HashMap::new()
+			vars: HashMap::new(),
+			flags: HashMap::new(),
+			globals: HashMap::new(),
+			seconds_base: (Instant::now(), 0),
+			random_state: Arc::new(AtomicU64::new(0)),
+			current_command: String::new()
 		}
 	}
 
@@ -1258,14 +1543,32 @@ impl VarTable {
 		self.env.remove(key);
 		std::env::remove_var(key);
 	}
+	/// Puts the process environment back the way this table had it, undoing whatever `scoped` exported since it was cloned from this one
+	pub fn restore_env(&self, scoped: &VarTable) {
+		for key in scoped.env.keys().filter(|key| !self.env.contains_key(*key)) {
+			std::env::remove_var(key);
+		}
+		for (key,val) in self.env.iter().filter(|(key,val)| scoped.env.get(*key) != Some(*val)) {
+			std::env::set_var(key, val);
+		}
+	}
 
 	// Getters, setters, and unsetters for `params`
+	/// `$#`, `$@`, and `$*` are always computed from the positional parameters,
+	/// so that `shift`, `set --`, and function calls can't leave them out of date
 	pub fn get_param(&self, key: &str) -> Option<String> {
 		if let Ok(index) = key.parse::<usize>() {
-			self.pos_params.get(index).cloned().map(|param| param.to_string())
+			// $0 is not a positional parameter, $1 is the first one
+			if index == 0 {
+				return self.params.get(key).cloned()
+			}
+			self.pos_params.get(index - 1).cloned().map(|param| param.to_string())
 		} else {
-			let result = self.params.get(key).cloned().map(|param| param.to_string());
-			result
+			match key {
+				"#" => Some(self.pos_params.len().to_string()),
+				"@" | "*" => Some(self.pos_params.iter().cloned().collect::<Vec<_>>().join(" ")),
+				_ => self.params.get(key).cloned()
+			}
 		}
 	}
 	pub fn borrow_pos_params(&self) -> &VecDeque<String> {
@@ -1275,41 +1578,117 @@ impl VarTable {
 		self.get_param(key.to_string().as_str())
 	}
 	pub fn pos_param_popfront(&mut self) -> Option<String> {
-		let popped_param = self.pos_params.pop_front();
-		self.set_param("@".into(), &self.pos_params.clone().to_vec().join(" "));
-		self.set_param("#".into(), &self.pos_params.len().to_string());
-		popped_param
+		self.pos_params.pop_front()
 	}
 	pub fn pos_param_pushback(&mut self, param: &str) {
 		self.pos_params.push_back(param.to_string());
-		self.set_param("@".into(), &self.pos_params.clone().to_vec().join(" "));
-		self.set_param("#".into(), &self.pos_params.len().to_string());
+	}
+	/// Replaces all of the positional parameters, like `set -- args...`
+	pub fn set_pos_params<I: IntoIterator<Item = String>>(&mut self, params: I) {
+		self.pos_params = params.into_iter().collect();
 	}
 	pub fn set_param(&mut self, key: &str, value: &str) {
 		self.params.insert(key.into(), value.into());
 	}
 	pub fn reset_params(&mut self) {
 		self.params.clear();
+		self.pos_params.clear();
 	}
 	pub fn unset_param(&mut self, key: &str) {
 		self.params.remove(key);
 	}
 
 	pub fn set_var(&mut self, key: &str, val: SlashVal) {
+		// Assigning to a dynamic variable changes how it is computed instead of storing the value
+		match key {
+			"RANDOM" => {
+				let seed = val.to_string();
+				seed_random(&self.random_state, seed.parse::<u64>().unwrap_or_else(|_| seed.bytes().fold(0, |acc, byte| acc.wrapping_mul(31).wrapping_add(byte as u64))));
+				return
+			}
+			"SECONDS" => {
+				self.seconds_base = (Instant::now(), val.to_string().parse::<u64>().unwrap_or(0));
+				return
+			}
+			_ => {}
+		}
+		if self.env.contains_key(key) {
+			// Keep exported variables in sync, so that expansions and child processes agree
+			self.export_var(key, &val.to_string());
+		}
 		self.vars.insert(key.to_string(),val);
 	}
 	pub fn unset_var(&mut self, key: &str) {
 		self.vars.remove(key);
+		self.flags.remove(key);
+	}
+
+	// Getters and setters for variable attributes
+	/// The attributes of `key`, including the ones that follow from its value and whether it is exported
+	pub fn get_flags(&self, key: &str) -> VarFlags {
+		let mut flags = self.flags.get(key).copied().unwrap_or(VarFlags::empty());
+		if self.env.contains_key(key) {
+			flags |= VarFlags::EXPORT;
+		}
+		match self.vars.get(key) {
+			Some(SlashVal::Array(_)) => flags |= VarFlags::ARRAY,
+			Some(SlashVal::Dict(_)) => flags |= VarFlags::ASSOC,
+			_ => {}
+		}
+		flags
+	}
+	pub fn add_flags(&mut self, key: &str, flags: VarFlags) {
+		*self.flags.entry(key.to_string()).or_insert(VarFlags::empty()) |= flags;
+	}
+	pub fn is_readonly(&self, key: &str) -> bool {
+		self.get_flags(key).contains(VarFlags::READONLY)
+	}
+	/// Sets a variable and gives it `flags`, exporting it if they include `EXPORT`
+	pub fn declare_var(&mut self, key: &str, val: SlashVal, flags: VarFlags) {
+		if flags.contains(VarFlags::EXPORT) {
+			self.export_var(key, &val.to_string());
+		}
+		self.set_var(key, val);
+		// Whether a variable is exported is tracked by `env` instead
+		self.add_flags(key, flags - VarFlags::EXPORT);
+	}
+	/// Declares a variable that should survive the function it was set in, like `declare -g`
+	pub fn set_global(&mut self, key: &str, val: SlashVal, flags: VarFlags) {
+		self.declare_var(key, val.clone(), flags);
+		self.globals.insert(key.to_string(), (val, flags));
+	}
+	/// Records the command that is about to run, after alias expansion but before any other expansion
+	pub fn set_current_command(&mut self, command: &str) {
+		self.current_command = command.to_string();
+	}
+	/// Called in a forked child that keeps running shell code, so that it doesn't repeat the parent's $RANDOM values
+	/// The generator is seeded again from the time and the child's pid the next time it is used
+	pub fn reseed_random(&self) {
+		self.random_state.store(0, Ordering::Relaxed);
+	}
+	pub fn take_globals(&mut self) -> HashMap<String,(SlashVal,VarFlags)> {
+		std::mem::take(&mut self.globals)
 	}
+	/// The single lookup used for variable resolution
+	/// Checks dynamic variables like $RANDOM, then shell variables, then environment variables, then special parameters
+	/// Accepts both `VAR` and `{VAR}`
 	pub fn get_var(&self, key: &str) -> Option<SlashVal> {
+		let key = key.strip_prefix('{').and_then(|key| key.strip_suffix('}')).unwrap_or(key);
+		match key {
+			"RANDOM" => return Some(SlashVal::Int(next_random(&self.random_state))),
+			"SECONDS" => {
+				let (start, offset) = self.seconds_base;
+				return Some(SlashVal::Int((offset + start.elapsed().as_secs()) as i32))
+			}
+			"BASH_COMMAND" | "OX_COMMAND" => return Some(SlashVal::String(self.current_command.clone())),
+			_ => {}
+		}
 		if let Some(var) = self.vars.get(key).cloned() {
 			Some(var)
-		} else if let Some(var) = self.params.get(key).cloned() {
-			let val = SlashVal::String(var);
-			Some(val)
+		} else if let Some(var) = self.env.get(key).cloned() {
+			Some(SlashVal::String(var))
 		} else {
-			let var = self.env.get(key).cloned().map(SlashVal::String);
-			var
+			self.get_param(key).map(SlashVal::String)
 		}
 	}
 	pub fn get_var_mut(&mut self, key: &str) -> Option<&mut SlashVal> {
@@ -1336,14 +1715,20 @@ impl VarTable {
 #[derive(Debug,Clone)]
 pub struct LogicTable {
 	functions: HashMap<String,String>,
-	aliases: HashMap<String,String>
+	func_params: HashMap<String,Vec<String>>, // The named parameters of functions defined like `fn name(a b)`
+	aliases: HashMap<String,String>,
+	comp_specs: HashMap<String,CompSpec>, // Completion specs from `complete`, keyed by command name
+	traps: HashMap<String,String> // Handlers from `trap`, keyed by signal name
 }
 
 impl LogicTable {
 	pub fn new() -> Self {
 		Self {
 			functions: HashMap::new(),
-			aliases: HashMap::new()
+			func_params: HashMap::new(),
+			aliases: HashMap::new(),
+			comp_specs: HashMap::new(),
+			traps: HashMap::new()
 		}
 	}
 	pub fn new_alias(&mut self, name: &str, value: String) {
@@ -1360,6 +1745,13 @@ impl LogicTable {
 	}
 	pub fn new_func(&mut self, name: &str, instructions: &str) {
 		self.functions.insert(name.to_string(),instructions.to_string());
+		self.func_params.remove(name);
+	}
+	pub fn set_func_params(&mut self, name: &str, params: Vec<String>) {
+		self.func_params.insert(name.to_string(),params);
+	}
+	pub fn get_func_params(&self, name: &str) -> Vec<String> {
+		self.func_params.get(name).cloned().unwrap_or_default()
 	}
 	pub fn get_func(&self, name: &str) -> Option<String> {
 		self.functions.get(name).cloned()
@@ -1369,6 +1761,34 @@ impl LogicTable {
 	}
 	pub fn remove_func(&mut self, name: &str) {
 		self.functions.remove(name);
+		self.func_params.remove(name);
+	}
+	pub fn set_comp_spec(&mut self, name: &str, spec: CompSpec) {
+		self.comp_specs.insert(name.to_string(),spec);
+	}
+	pub fn get_comp_spec(&self, name: &str) -> Option<CompSpec> {
+		self.comp_specs.get(name).cloned()
+	}
+	pub fn borrow_comp_specs(&self) -> &HashMap<String,CompSpec> {
+		&self.comp_specs
+	}
+	pub fn remove_comp_spec(&mut self, name: &str) {
+		self.comp_specs.remove(name);
+	}
+	pub fn clear_comp_specs(&mut self) {
+		self.comp_specs.clear();
+	}
+	pub fn set_trap(&mut self, signal: &str, handler: &str) {
+		self.traps.insert(signal.to_string(),handler.to_string());
+	}
+	pub fn get_trap(&self, signal: &str) -> Option<String> {
+		self.traps.get(signal).cloned()
+	}
+	pub fn borrow_traps(&self) -> &HashMap<String,String> {
+		&self.traps
+	}
+	pub fn remove_trap(&mut self, signal: &str) {
+		self.traps.remove(signal);
 	}
 }
 
@@ -1378,16 +1798,31 @@ impl Default for LogicTable {
 	}
 }
 
+/// An entry in the call stack, used by `caller` and $FUNCNAME
+#[derive(Debug,Clone)]
+pub struct StackFrame {
+	pub name: String, // The function name, or the path of the sourced file
+	pub line: usize, // The line this frame was called from
+	pub file: Option<String>, // The file this frame was called from, if it was called from a sourced file
+	pub is_source: bool,
+	pub shadowed: HashMap<String,Option<SlashVal>> // The values that this function's locals hid when they were declared
+}
+
 #[derive(Debug,Clone)]
 pub struct EnvMeta {
 	last_input: String,
 	last_command: Option<String>,
 	timer_start: Option<Instant>,
 	cmd_duration: Option<Duration>,
-	dir_stack: Vec<PathBuf>,
+	dir_stack: Vec<PathBuf>, // Directories saved by `pushd`, most recent last. The current directory is always the top of the stack
 	shopts: ShOpts,
 	flags: EnvFlags,
-	in_prompt: bool
+	open_fds: BTreeSet<RawFd>, // File descriptors opened with `exec`, e.g. `exec 3>file`
+	line_base: usize, // Added to line numbers in the input being executed, for $LINENO
+	call_stack: Vec<StackFrame>, // Function calls and sourced files, innermost last
+	cmd_number: usize, // Counts the commands entered at the prompt, for `\#`
+	in_prompt: bool,
+	started: Instant // When the shell started, for a bare `time`
 }
 
 impl EnvMeta {
@@ -1398,17 +1833,28 @@ impl EnvMeta {
 			last_command: None,
 			timer_start: None,
 			cmd_duration: None,
-			dir_stack: vec![std::env::current_dir().unwrap()],
+			dir_stack: vec![],
 			shopts: ShOpts::new(),
 			flags,
+			open_fds: BTreeSet::new(),
+			line_base: 0,
+			call_stack: vec![],
+			cmd_number: 1,
 			in_prompt,
+			started: Instant::now()
 		}
 	}
+	pub fn started(&self) -> Instant {
+		self.started
+	}
 	pub fn get_cmd_duration(&self) -> Option<Duration> {
 		self.cmd_duration
 	}
-	pub fn reset_dir_stack(&mut self, path: PathBuf) {
-		self.dir_stack = vec![path]
+	pub fn dir_stack(&self) -> &[PathBuf] {
+		&self.dir_stack
+	}
+	pub fn set_dir_stack(&mut self, stack: Vec<PathBuf>) {
+		self.dir_stack = stack
 	}
 	pub fn push_dir(&mut self, path: PathBuf) {
 		self.dir_stack.push(path)
@@ -1420,14 +1866,48 @@ impl EnvMeta {
 		self.last_command.clone()
 	}
 	pub fn pop_dir(&mut self) -> Option<PathBuf> {
-		if self.dir_stack.len() > 1 {
-			self.dir_stack.pop()
-		} else {
-			None
+		self.dir_stack.pop()
+	}
+	pub fn open_fds(&self) -> &BTreeSet<RawFd> {
+		&self.open_fds
+	}
+	pub fn track_fd(&mut self, fd: RawFd) {
+		self.open_fds.insert(fd);
+	}
+	pub fn untrack_fd(&mut self, fd: RawFd) {
+		self.open_fds.remove(&fd);
+	}
+	pub fn line_base(&self) -> usize {
+		self.line_base
+	}
+	pub fn set_line_base(&mut self, base: usize) {
+		self.line_base = base
+	}
+	pub fn call_stack(&self) -> &[StackFrame] {
+		&self.call_stack
+	}
+	pub fn push_frame(&mut self, frame: StackFrame) {
+		self.call_stack.push(frame)
+	}
+	pub fn pop_frame(&mut self) -> Option<StackFrame> {
+		self.call_stack.pop()
+	}
+	/// Remembers the value that a new local hides, if the function hasn't already declared it
+	pub fn shadow_var(&mut self, key: &str, prev: Option<SlashVal>) {
+		if let Some(frame) = self.call_stack.iter_mut().rev().find(|frame| !frame.is_source) {
+			frame.shadowed.entry(key.to_string()).or_insert(prev);
 		}
 	}
-	pub fn top_dir(&self) -> Option<&PathBuf> {
-		self.dir_stack.last()
+	/// Forgets the innermost local named `key`, returning the value it hid
+	/// The outer `None` means that no function on the stack declared `key` as a local
+	pub fn unshadow_var(&mut self, key: &str) -> Option<Option<SlashVal>> {
+		self.call_stack.iter_mut().rev()
+			.filter(|frame| !frame.is_source)
+			.find_map(|frame| frame.shadowed.remove(key))
+	}
+	/// The file currently being sourced, if any
+	pub fn current_file(&self) -> Option<String> {
+		self.call_stack.iter().rev().find(|frame| frame.is_source).map(|frame| frame.name.clone())
 	}
 	pub fn leave_prompt(&mut self) {
 		self.in_prompt = false
@@ -1435,6 +1915,12 @@ impl EnvMeta {
 	pub fn enter_prompt(&mut self) {
 		self.in_prompt = true
 	}
+	pub fn cmd_number(&self) -> usize {
+		self.cmd_number
+	}
+	pub fn next_cmd_number(&mut self) {
+		self.cmd_number += 1
+	}
 	pub fn set_last_input(&mut self,input: &str) {
 		self.last_input = input.to_string()
 	}
@@ -1475,16 +1961,22 @@ pub fn enable_reaping<'a>() -> SlashResult<()> {
 	Ok(())
 }
 
+/// The job table is only touched with the signals blocked whose handlers write to it,
+/// since a handler waiting on a lock that its own thread holds would never get it
 pub fn read_jobs<'a,F,T>(f: F) -> SlashResult<T>
 where F: FnOnce(&JobTable) -> T {
-	let lock = JOBS.read().map_err(|_| Low(SlashErrLow::InternalErr("Failed to obtain write lock; lock might be poisoned".into())))?;
-	Ok(f(&lock))
+	crate::signal::with_signals_blocked(&crate::signal::CRITICAL_SIGNALS, || {
+		let lock = JOBS.read().map_err(|_| Low(SlashErrLow::InternalErr("Failed to obtain write lock; lock might be poisoned".into())))?;
+		Ok(f(&lock))
+	})
 }
 
 pub fn write_jobs<'a,F,T>(f: F) -> SlashResult<T>
 where F: FnOnce(&mut JobTable) -> T {
-	let mut lock = JOBS.write().map_err(|_| Low(SlashErrLow::InternalErr("Failed to obtain write lock; lock might be poisoned".into())))?;
-	Ok(f(&mut lock))
+	crate::signal::with_signals_blocked(&crate::signal::CRITICAL_SIGNALS, || {
+		let mut lock = JOBS.write().map_err(|_| Low(SlashErrLow::InternalErr("Failed to obtain write lock; lock might be poisoned".into())))?;
+		Ok(f(&mut lock))
+	})
 }
 
 pub fn attach_tty<'a>(pgid: Pid) -> SlashResult<()> {
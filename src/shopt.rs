@@ -2,6 +2,40 @@ use std::collections::{BTreeMap, VecDeque};
 
 use crate::{error::{SlashErr, SlashErrLow}, shellenv::SlashVal, SlashResult};
 
+/// The dotted path of every option with a fixed place in `ShOpts`
+pub const SHOPT_KEYS: [&str; 30] = [
+	"core.dotglob",
+	"core.autocd",
+	"core.hist_ignore_dupes",
+	"core.max_hist",
+	"core.int_comments",
+	"core.auto_hist",
+	"core.bell_style",
+	"core.max_recurse_depth",
+	"core.noclobber",
+	"core.cdspell",
+	"core.cmd_suggest",
+	"core.nullglob",
+	"core.nocaseglob",
+	"core.extglob",
+	"core.hist_timestamps",
+	"core.hist_dirs",
+	"core.expand_aliases",
+	"core.globsort",
+	"core.xpg_echo",
+	"prompt.trunc_prompt_path",
+	"prompt.edit_mode",
+	"prompt.comp_limit",
+	"prompt.prompt_highlight",
+	"prompt.tab_stop",
+	"prompt.comp_help_opts",
+	"prompt.comp_preview",
+	"prompt.completion_style",
+	"prompt.completion_matching",
+	"prompt.exit_status.success",
+	"prompt.exit_status.failure",
+];
+
 #[derive(Clone, Debug)]
 pub struct ShOpts {
 	pub core: ShOptsCore,
@@ -20,6 +54,17 @@ impl ShOpts {
 			auto_hist: true,
 			bell_style: 1,
 			max_recurse_depth: 500,
+			noclobber: false,
+			cdspell: false,
+			cmd_suggest: true,
+			nullglob: false,
+			nocaseglob: false,
+			extglob: false,
+			hist_timestamps: false,
+			hist_dirs: false,
+			expand_aliases: false,
+			globsort: "name".into(),
+			xpg_echo: false,
 		};
 		let prompt = ShOptsPrompt {
 			trunc_prompt_path: 4,
@@ -27,6 +72,10 @@ impl ShOpts {
 			comp_limit: 100,
 			prompt_highlight: true,
 			tab_stop: 8,
+			comp_help_opts: false,
+			comp_preview: false,
+			completion_style: "fuzzy".into(),
+			completion_matching: "exact".into(),
 			exit_status: PromptStatus {
 				success: " ".into(),
 				failure: "✗".into(),
@@ -78,6 +127,17 @@ pub struct ShOptsCore {
 	pub auto_hist: bool,
 	pub bell_style: usize,
 	pub max_recurse_depth: usize,
+	pub noclobber: bool,
+	pub cdspell: bool,
+	pub cmd_suggest: bool,
+	pub nullglob: bool, // Patterns that match nothing expand to nothing instead of themselves
+	pub nocaseglob: bool, // Filename expansion ignores case
+	pub extglob: bool,
+	pub hist_timestamps: bool,
+	pub hist_dirs: bool, // Record the working directory of each history entry, and prefer entries from the current one for hints
+	pub expand_aliases: bool, // Turned on when the shell starts interactively, like in bash
+	pub globsort: String, // "name", "mtime", or "size"
+	pub xpg_echo: bool, // echo expands escapes without -e
 }
 
 impl ShOptsCore {
@@ -92,6 +152,17 @@ impl ShOptsCore {
 			"auto_hist" => Ok(SlashVal::Bool(self.auto_hist)),
 			"bell_style" => Ok(SlashVal::Int(self.bell_style as i32)),
 			"max_recurse_depth" => Ok(SlashVal::Int(self.max_recurse_depth as i32)),
+			"noclobber" => Ok(SlashVal::Bool(self.noclobber)),
+			"cdspell" => Ok(SlashVal::Bool(self.cdspell)),
+			"cmd_suggest" => Ok(SlashVal::Bool(self.cmd_suggest)),
+			"nullglob" => Ok(SlashVal::Bool(self.nullglob)),
+			"nocaseglob" => Ok(SlashVal::Bool(self.nocaseglob)),
+			"extglob" => Ok(SlashVal::Bool(self.extglob)),
+			"hist_timestamps" => Ok(SlashVal::Bool(self.hist_timestamps)),
+			"hist_dirs" => Ok(SlashVal::Bool(self.hist_dirs)),
+			"expand_aliases" => Ok(SlashVal::Bool(self.expand_aliases)),
+			"globsort" => Ok(SlashVal::String(self.globsort.clone())),
+			"xpg_echo" => Ok(SlashVal::Bool(self.xpg_echo)),
 			_ => Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid core opts key: {}",key))))
 		}
 	}
@@ -129,8 +200,10 @@ impl ShOptsCore {
 				};
 			}
 			"bell_style" => {
-				self.bell_style = if let SlashVal::Int(val) = value { val as usize } else {
-					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.bell_style: {:?}", value))))
+				// 0 is no bell, 1 is audible, and 2 is visible
+				self.bell_style = match value {
+					SlashVal::Int(val @ 0..=2) => val as usize,
+					_ => return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.bell_style: {:?}", value))))
 				};
 			}
 			"max_recurse_depth" => {
@@ -138,6 +211,62 @@ impl ShOptsCore {
 					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.max_recurse_depth: {:?}", value))))
 				};
 			}
+			"noclobber" => {
+				self.noclobber = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.noclobber: {:?}", value))))
+				};
+			}
+			"cdspell" => {
+				self.cdspell = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.cdspell: {:?}", value))))
+				};
+			}
+			"cmd_suggest" => {
+				self.cmd_suggest = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.cmd_suggest: {:?}", value))))
+				};
+			}
+			"nullglob" => {
+				self.nullglob = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.nullglob: {:?}", value))))
+				};
+			}
+			"nocaseglob" => {
+				self.nocaseglob = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.nocaseglob: {:?}", value))))
+				};
+			}
+			"extglob" => {
+				self.extglob = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.extglob: {:?}", value))))
+				};
+			}
+			"hist_timestamps" => {
+				self.hist_timestamps = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.hist_timestamps: {:?}", value))))
+				};
+			}
+			"hist_dirs" => {
+				self.hist_dirs = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.hist_dirs: {:?}", value))))
+				};
+			}
+			"expand_aliases" => {
+				self.expand_aliases = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.expand_aliases: {:?}", value))))
+				};
+			}
+			"globsort" => {
+				self.globsort = match value {
+					SlashVal::String(val) if matches!(val.as_str(), "name" | "mtime" | "size") => val,
+					_ => return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.globsort, expected name, mtime, or size: {:?}", value))))
+				};
+			}
+			"xpg_echo" => {
+				self.xpg_echo = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.xpg_echo: {:?}", value))))
+				};
+			}
 			_ => {
 				return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid core opts key: {}", key))))
 			}
@@ -153,6 +282,10 @@ pub struct ShOptsPrompt {
 	pub comp_limit: usize,
 	pub prompt_highlight: bool,
 	pub tab_stop: usize,
+	pub comp_help_opts: bool, // Complete long options by reading `--help`
+	pub comp_preview: bool, // Preview paths while picking a completion
+	pub completion_style: String, // "fuzzy" opens skim, "menu" cycles through candidates with Tab
+	pub completion_matching: String, // "exact", "ignore-case", or "fuzzy"
 	pub exit_status: PromptStatus, // Sub-group for exit status symbols
 	pub custom: PromptCustom
 }
@@ -166,6 +299,10 @@ impl ShOptsPrompt {
 			"comp_limit" => Ok(SlashVal::Int(self.comp_limit as i32)),
 			"prompt_highlight" => Ok(SlashVal::Bool(self.prompt_highlight)),
 			"tab_stop" => Ok(SlashVal::Int(self.tab_stop as i32)),
+			"comp_help_opts" => Ok(SlashVal::Bool(self.comp_help_opts)),
+			"comp_preview" => Ok(SlashVal::Bool(self.comp_preview)),
+			"completion_style" => Ok(SlashVal::String(self.completion_style.clone())),
+			"completion_matching" => Ok(SlashVal::String(self.completion_matching.clone())),
 			"exit_status" => Ok(self.exit_status.get(query)?),
 			"custom" => Ok(self.custom.get(query)?),
 			_ => Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid key for prompt opts: {}",key))))
@@ -200,6 +337,28 @@ impl ShOptsPrompt {
 					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.tab_stop: {:?}", value))))
 				};
 			}
+			"comp_help_opts" => {
+				self.comp_help_opts = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for prompt.comp_help_opts: {:?}", value))))
+				};
+			}
+			"comp_preview" => {
+				self.comp_preview = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for prompt.comp_preview: {:?}", value))))
+				};
+			}
+			"completion_style" => {
+				self.completion_style = match value {
+					SlashVal::String(val) if matches!(val.as_str(), "fuzzy" | "menu") => val,
+					_ => return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for prompt.completion_style, expected fuzzy or menu: {:?}", value))))
+				};
+			}
+			"completion_matching" => {
+				self.completion_matching = match value {
+					SlashVal::String(val) if matches!(val.as_str(), "exact" | "ignore-case" | "fuzzy") => val,
+					_ => return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for prompt.completion_matching, expected exact, ignore-case, or fuzzy: {:?}", value))))
+				};
+			}
 			"exit_status" => self.exit_status.set(query, value)?,
 			"custom" => self.custom.set(query,value)?,
 			_ => {
@@ -275,6 +434,22 @@ impl PromptCustom {
 		}
 		Ok(())
 	}
+	/// Every value set under `prompt.custom`, with the dotted path that leads to it
+	pub fn entries(&self) -> Vec<(String, SlashVal)> {
+		fn walk(path: String, val: &SlashVal, entries: &mut Vec<(String, SlashVal)>) {
+			match val {
+				SlashVal::Dict(map) => {
+					for (key, inner) in map {
+						walk(format!("{}.{}", path, key), inner, entries);
+					}
+				}
+				_ => entries.push((path, val.clone()))
+			}
+		}
+		let mut entries = vec![];
+		walk("prompt.custom".into(), &self.opts, &mut entries);
+		entries
+	}
 	pub fn insert_top_level(&mut self, key: String, val: SlashVal) {
 		if let SlashVal::Dict(map) = &mut self.opts {
 			map.insert(key,val);
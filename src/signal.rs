@@ -1,7 +1,16 @@
-use nix::{sys::{signal::{killpg, signal, SigHandler, Signal} , wait::{waitpid, WaitPidFlag, WaitStatus}}, unistd::{getpgid, getpgrp, Pid}};
+use nix::{sys::{signal::{killpg, pthread_sigmask, sigaction, signal, SaFlags, SigAction, SigHandler, SigSet, SigmaskHow, Signal} , wait::{waitpid, WaitPidFlag, WaitStatus}}, unistd::{getpgid, getpgrp, Pid}};
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use crate::{error::{SlashErr, SlashErrLow}, helper, shellenv::{self, read_jobs, write_jobs, JobCmdFlags, JobID}, SlashResult};
 
+/// Set by the SIGWINCH handler, and cleared when the prompt picks up the new terminal size
+static WINDOW_RESIZED: AtomicBool = AtomicBool::new(false);
+/// Set by the SIGINT handler, and cleared by whichever of the executor or the foreground wait notices it first
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// One bit per signal number, set when a signal with a `trap` handler arrives and cleared when the executor runs the handler
+static PENDING_TRAPS: AtomicU64 = AtomicU64::new(0);
+
 pub fn sig_handler_setup() {
 	unsafe {
 		signal(Signal::SIGCHLD, SigHandler::Handler(handle_sigchld)).unwrap();
@@ -14,6 +23,121 @@ pub fn sig_handler_setup() {
 	}
 }
 
+/// Handlers that only the interactive shell needs
+/// SIGINT is installed without SA_RESTART, so that a foreground wait wakes up and can pass it on to the job
+pub fn interactive_sig_setup() {
+	unsafe {
+		signal(Signal::SIGWINCH, SigHandler::Handler(handle_sigwinch)).unwrap();
+		let action = SigAction::new(SigHandler::Handler(handle_sigint), SaFlags::empty(), SigSet::empty());
+		sigaction(Signal::SIGINT, &action).unwrap();
+	}
+}
+
+/// Catches `sig` for a `trap` handler, or ignores it if `ignore` is true, as with `trap '' sig`
+pub fn set_trap_handler(sig: Signal, ignore: bool) {
+	let handler = if ignore { SigHandler::SigIgn } else { SigHandler::Handler(handle_trapped) };
+	unsafe { signal(sig, handler) }.ok();
+}
+
+/// Gives `sig` back the handling it has when no trap is set for it
+pub fn reset_trap_handler(sig: Signal, interactive: bool) {
+	let handler = match sig {
+		Signal::SIGHUP => SigHandler::Handler(handle_sighup),
+		Signal::SIGQUIT => SigHandler::Handler(handle_sigquit),
+		Signal::SIGTSTP => SigHandler::Handler(handle_sigtstp),
+		Signal::SIGINT => SigHandler::Handler(handle_sigint),
+		Signal::SIGWINCH if interactive => SigHandler::Handler(handle_sigwinch),
+		Signal::SIGTTIN | Signal::SIGTTOU => SigHandler::SigIgn,
+		_ => SigHandler::SigDfl
+	};
+	// SIGINT goes back to being able to interrupt a wait, like in interactive_sig_setup()
+	let flags = if sig == Signal::SIGINT && interactive { SaFlags::empty() } else { SaFlags::SA_RESTART };
+	unsafe { sigaction(sig, &SigAction::new(handler, flags, SigSet::empty())) }.ok();
+}
+
+/// Takes the signals in `signals` that have arrived since they were last taken
+/// Other bits are left alone, since they belong to whoever trapped those signals
+pub fn take_pending_traps(signals: &[Signal]) -> Vec<Signal> {
+	let mask = signals.iter().fold(0u64, |mask, sig| mask | 1 << *sig as i32);
+	let pending = PENDING_TRAPS.fetch_and(!mask, Ordering::SeqCst) & mask;
+	signals.iter().copied().filter(|sig| pending & 1 << *sig as i32 != 0).collect()
+}
+
+extern "C" fn handle_trapped(sig: libc::c_int) {
+	PENDING_TRAPS.fetch_or(1 << sig, Ordering::SeqCst);
+}
+
+/// Returns true if the terminal has been resized since the last time this was called
+pub fn take_resize() -> bool {
+	WINDOW_RESIZED.swap(false, Ordering::Relaxed)
+}
+
+/// Returns true if the shell has received SIGINT since the last time this was called
+pub fn take_interrupt() -> bool {
+	INTERRUPTED.swap(false, Ordering::Relaxed)
+}
+
+/// Puts the signals that the shell ignores back to their default handling, right before another program is executed
+/// The Rust runtime ignores SIGPIPE, so without this a command writing into a pipeline would not be stopped when its reader exits
+pub fn reset_ignored_signals() {
+	unsafe {
+		signal(Signal::SIGPIPE, SigHandler::SigDfl).ok();
+		signal(Signal::SIGTTIN, SigHandler::SigDfl).ok();
+		signal(Signal::SIGTTOU, SigHandler::SigDfl).ok();
+	}
+}
+
+/// The signals whose handlers take the job table lock, or exit the shell
+/// One of them arriving while the table is locked or the history file is half written would deadlock or leave a mess.
+/// SIGINT only sets a flag, and has to stay deliverable so that it can interrupt a foreground wait, which holds the lock
+pub const CRITICAL_SIGNALS: [Signal; 4] = [Signal::SIGCHLD, Signal::SIGTSTP, Signal::SIGHUP, Signal::SIGQUIT];
+
+/// Blocks SIGCHLD for the calling thread, returning the previous mask to give back to `restore_sigmask()`
+pub fn block_sigchld() -> SigSet {
+	block_signals(&[Signal::SIGCHLD])
+}
+
+/// Blocks `signals` for the calling thread, returning the previous mask to give back to `restore_sigmask()`
+pub fn block_signals(signals: &[Signal]) -> SigSet {
+	let mut mask = SigSet::empty();
+	let mut mask_backup = SigSet::empty();
+	for sig in signals {
+		mask.add(*sig);
+	}
+	pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&mask), Some(&mut mask_backup)).ok();
+	mask_backup
+}
+
+/// Runs `f` with `signals` blocked, and puts the previous mask back afterwards, even if `f` panics
+/// Signals that arrive in the meantime are delivered once the mask is restored
+pub fn with_signals_blocked<T>(signals: &[Signal], f: impl FnOnce() -> T) -> T {
+	struct Restore(SigSet);
+	impl Drop for Restore {
+		fn drop(&mut self) {
+			restore_sigmask(self.0);
+		}
+	}
+	let _restore = Restore(block_signals(signals));
+	f()
+}
+
+/// Sleeps until a signal arrives that `mask` doesn't block, with SIGCHLD unblocked
+/// The caller blocks SIGCHLD before checking on its children, so one that changes state after the check still wakes this up
+pub fn suspend_until_sigchld(mask: SigSet) {
+	let mut suspend_mask = mask;
+	suspend_mask.remove(Signal::SIGCHLD);
+	suspend_mask.suspend().ok();
+}
+
+pub fn restore_sigmask(mask: SigSet) {
+	pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&mask), None).ok();
+}
+
+extern "C" fn handle_sigwinch(_: libc::c_int) {
+	// Querying the new size isn't async-signal-safe, so that is left to the prompt
+	WINDOW_RESIZED.store(true, Ordering::Relaxed);
+}
+
 extern "C" fn handle_sighup(_: libc::c_int) {
 	write_jobs(|j| {
 		for job in j.mut_jobs().iter_mut().flatten() {
@@ -32,11 +156,8 @@ extern "C" fn handle_sigtstp(_: libc::c_int) {
 }
 
 extern "C" fn handle_sigint(_: libc::c_int) {
-	write_jobs(|j| {
-		if let Some(job) = j.get_fg_mut() {
-			job.killpg(Signal::SIGINT).unwrap();
-		}
-	}).unwrap();
+	// Taking the job table lock here could deadlock, so the foreground wait passes the signal on instead
+	INTERRUPTED.store(true, Ordering::Relaxed);
 }
 
 pub extern "C" fn ignore_sigchld(_: libc::c_int) {
@@ -166,3 +287,43 @@ pub fn handle_child_continue<'a>(pid: Pid) -> SlashResult<()> {
 	})?;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_with_signals_blocked() {
+		let blocked = |sig: Signal| SigSet::thread_get_mask().unwrap().contains(sig);
+		assert!(!blocked(Signal::SIGUSR1));
+		let inner = with_signals_blocked(&[Signal::SIGUSR1, Signal::SIGUSR2], || {
+			// Nesting keeps whatever the outer section blocked
+			with_signals_blocked(&[Signal::SIGUSR1], || ());
+			(blocked(Signal::SIGUSR1), blocked(Signal::SIGUSR2))
+		});
+		assert_eq!(inner, (true, true));
+		assert!(!blocked(Signal::SIGUSR1) && !blocked(Signal::SIGUSR2));
+
+		// A signal that was blocked before stays blocked afterwards
+		let saved = block_signals(&[Signal::SIGUSR2]);
+		with_signals_blocked(&[Signal::SIGUSR2], || ());
+		assert!(blocked(Signal::SIGUSR2));
+		restore_sigmask(saved);
+		assert!(!blocked(Signal::SIGUSR2));
+
+		let result = std::panic::catch_unwind(|| with_signals_blocked(&[Signal::SIGUSR1], || panic!("in a critical section")));
+		assert!(result.is_err());
+		assert!(!blocked(Signal::SIGUSR1));
+	}
+
+	#[test]
+	fn test_sigwinch_sets_flag() {
+		take_resize();
+		assert!(!take_resize());
+		handle_sigwinch(libc::SIGWINCH);
+		handle_sigwinch(libc::SIGWINCH);
+		// Any number of resizes between prompts only needs one redraw
+		assert!(take_resize());
+		assert!(!take_resize());
+	}
+}
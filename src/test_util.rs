@@ -0,0 +1,64 @@
+//! Fixtures shared by tests that run shell input
+//!
+//! The shell applies redirections to its own file descriptors, and builtins like `cd` and `export` change the working directory
+//! and environment of the whole process. Cargo runs tests on parallel threads, so any test that does one of those things
+//! holds the process lock while it runs.
+
+use std::{cell::Cell, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::{execute::dispatch, prelude::*};
+
+static PROCESS_LOCK: Mutex<()> = Mutex::new(());
+
+thread_local! {
+	static HOLDING_LOCK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Held while a test changes the standard fds, the working directory, or environment variables
+/// Taking it again on the same thread is a no-op, so `capture()` can be used while holding it
+pub struct ProcessGuard {
+	guard: Option<MutexGuard<'static, ()>>
+}
+
+impl Drop for ProcessGuard {
+	fn drop(&mut self) {
+		if self.guard.take().is_some() {
+			HOLDING_LOCK.with(|held| held.set(false));
+		}
+	}
+}
+
+pub fn lock_process() -> ProcessGuard {
+	if HOLDING_LOCK.with(|held| held.get()) {
+		return ProcessGuard { guard: None }
+	}
+	// A test that panicked while holding the lock has already failed, so there's no reason to fail the rest too
+	let guard = PROCESS_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+	HOLDING_LOCK.with(|held| held.set(true));
+	ProcessGuard { guard: Some(guard) }
+}
+
+/// A path in the temp directory that no other test uses
+pub fn temp_path(name: &str) -> PathBuf {
+	static COUNTER: AtomicUsize = AtomicUsize::new(0);
+	let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+	env::temp_dir().join(format!("slash_{}_{}_{}", name, std::process::id(), n))
+}
+
+/// Runs `input` in a brace group whose stdout the shell redirects to a file, and returns the result and everything written there
+/// The group puts the shell's own file descriptors back when it finishes, so nothing here touches them directly
+pub fn capture(input: &str, slash: &mut Slash) -> (SlashResult<()>, String) {
+	let _lock = lock_process();
+	let path = temp_path("capture");
+	let result = dispatch::exec_input(format!("{{ {}\n}} > {}", input, path.display()), slash);
+	let output = std::fs::read_to_string(&path).unwrap_or_default();
+	let _ = std::fs::remove_file(&path);
+	(result, output)
+}
+
+/// Like `capture()`, for input that is expected to succeed
+pub fn output_of(input: &str, slash: &mut Slash) -> String {
+	let (result, output) = capture(input, slash);
+	result.unwrap();
+	output
+}
@@ -1,8 +1,10 @@
 use libc::{S_IRGRP, S_IROTH, S_IRUSR, S_IWUSR};
+use nix::{fcntl::FcntlArg, unistd::{lseek, pipe2, Whence}};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use unicode_width::UnicodeWidthChar;
 
-use crate::{helper, prelude::*, shellenv::{ChildProc, JobBuilder}};
+use crate::{helper, prelude::*, shellenv::{write_jobs, ChildProc, JobBuilder}};
 
 pub const SIG_EXIT_OFFSET: i32 = 128;
 
@@ -51,6 +53,7 @@ bitflags::bitflags! {
 		const NO_RESET_IN   = 0b00000000000000000000000000010000;
 		const NO_RESET_OUT  = 0b00000000000000000000000000100000;
 		const NO_RESET_ERR  = 0b00000000000000000000000001000000;
+		const IN_COND       = 0b00000000000000000000000010000000; // Failures here do not trigger errexit
 	}
 }
 
@@ -60,7 +63,9 @@ pub struct Redir {
 	redir_type: Rule,
 	our_fd: i32,
 	their_fd: Option<i32>,
-	file_target: Option<PathBuf>
+	file_target: Option<PathBuf>,
+	/// The expanded body of a here-doc, which is filled in right before the redirection is activated
+	body: Option<String>
 }
 
 impl Redir {
@@ -85,6 +90,7 @@ impl Redir {
 						let fd = pair.as_str().parse::<i32>().unwrap();
 						their_fd = Some(fd);
 					}
+					Rule::close_fd => redir_type = Some(Rule::close_fd),
 					Rule::r#in |
 					Rule::out |
 					Rule::force_out |
@@ -107,7 +113,8 @@ impl Redir {
 					redir_type: redir_type.unwrap(),
 					our_fd,
 					their_fd,
-					file_target
+					file_target,
+					body: None
 				}
 			)
 		} else {
@@ -119,77 +126,120 @@ impl Redir {
 			0 => Rule::r#in,
 			_ => Rule::out
 		};
-		Self { redir_type, our_fd, their_fd: Some(their_fd), file_target: None }
+		Self { redir_type, our_fd, their_fd: Some(their_fd), file_target: None, body: None }
 	}
 	pub fn redir_type(&self) -> Rule {
 		self.redir_type
 	}
+	pub fn our_fd(&self) -> RawFd {
+		self.our_fd
+	}
+	/// The word that a here-doc body was moved into by `collect_heredocs`, if it hasn't been expanded yet
+	pub fn heredoc_word(&self) -> Option<&str> {
+		if self.redir_type != Rule::heredoc || self.body.is_some() {
+			return None
+		}
+		self.file_target.as_ref().and_then(|word| word.to_str())
+	}
+	pub fn set_body(&mut self, body: String) {
+		self.body = Some(body)
+	}
 }
 
 #[derive(Debug)]
 pub struct CmdRedirs {
 	open_fds: Vec<SmartFD>,
-	targets_fd: Vec<Redir>,
-	targets_file: Vec<Redir>
+	redirs: Vec<Redir>
 }
 
 impl CmdRedirs {
-	pub fn new(mut redirs: VecDeque<Redir>) -> Self {
-		let mut targets_fd = vec![];
-		let mut targets_file = vec![];
-		while let Some(redir) = redirs.pop_back() {
-			let Redir { redir_type: _, our_fd: _, their_fd, file_target: _ } = &redir;
-			if their_fd.is_some() {
-				targets_fd.push(redir);
+	pub fn new(redirs: VecDeque<Redir>) -> Self {
+		Self { open_fds: vec![], redirs: redirs.into() }
+	}
+	/// Applies the redirections from left to right, each one against the file descriptors left by the ones before it
+	/// So `> out 2>&1` sends both streams to `out`, while `2>&1 > out` leaves stderr where stdout was
+	pub fn activate(&mut self, noclobber: bool) -> SlashResult<()> {
+		for redir in &self.redirs {
+			let src_fd = if redir.their_fd.is_some() {
+				Self::dup_their_fd(redir)?
 			} else {
-				targets_file.push(redir);
-			}
+				Self::open_file_target(redir, noclobber)?
+			};
+			self.open_fds.extend(src_fd);
 		}
-		Self { open_fds: vec![], targets_fd, targets_file }
-	}
-	pub fn activate(&mut self) -> SlashResult<()> {
-		self.open_file_targets()?;
-		self.open_their_fds()?;
 		Ok(())
 	}
-	pub fn close_all(mut self) -> SlashResult<()> {
-		for fd in self.open_fds.iter_mut() {
-			fd.close()?
+	/// Releases ownership of the redirected file descriptors, so that they stay open after this is dropped
+	/// Used by `exec` to keep numbered file descriptors open for the rest of the session
+	pub fn persist(mut self) -> Vec<RawFd> {
+		take(&mut self.open_fds).into_iter().map(|fd| fd.into_raw_fd()).collect()
+	}
+	/// If `noclobber` is set, `>` will refuse to truncate an existing regular file. `>|` always truncates.
+	fn open_file_target(redir: &Redir, noclobber: bool) -> SlashResult<Option<SmartFD>> {
+		let Redir { redir_type, our_fd, file_target, body, .. } = redir;
+		if *redir_type == Rule::close_fd {
+			close(*our_fd).ok();
+			return Ok(None)
 		}
-		Ok(())
-	}
-	pub fn open_file_targets(&mut self) -> SlashResult<()> {
-		for redir in &self.targets_file {
-			let Redir { redir_type, our_fd, their_fd: _, file_target } = redir;
-			let src_fd = SmartFD::new(*our_fd)?;
-			let path = file_target.as_ref().unwrap(); // We know that there's a file target so unwrap is safe
+		let src_fd = SmartFD::new(*our_fd)?;
+		if *redir_type == Rule::heredoc {
+			// The body is written to memory and read back from the start, so it can be any size
+			let mut body_fd = SmartFD::new_memfd("heredoc", false)?;
+			write!(body_fd, "{}", body.as_deref().unwrap_or_default())?;
+			lseek(body_fd.as_raw_fd(), 0, Whence::SeekSet).map_err(|_| Low(SlashErrLow::from_io()))?;
+			body_fd.dup2(&src_fd)?;
+			return Ok(Some(src_fd))
+		}
+		let path = file_target.as_ref().unwrap(); // We know that there's a file target so unwrap is safe
+		let mode = Mode::from_bits(0o644).unwrap();
+		let file_fd = if *redir_type == Rule::out && noclobber {
+			Self::open_noclobber(path, mode)?
+		} else {
 			let flags = match redir_type {
 				Rule::r#in => OFlag::O_RDONLY,
-				Rule::out => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
+				Rule::out | Rule::force_out => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
 				Rule::append => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND,
 				_ => unreachable!(),
 			};
-			let mode = Mode::from_bits(0o644).unwrap();
-			let mut file_fd = SmartFD::open(path, flags, mode)?;
+			SmartFD::open(path, flags, mode)?
+		};
+		if file_fd.as_raw_fd() == *our_fd {
+			// The file was opened on the target fd already, so there is nothing to duplicate
+			let _ = file_fd.into_raw_fd();
+		} else {
 			file_fd.dup2(&src_fd)?;
-			file_fd.close()?;
-			self.open_fds.push(src_fd);
 		}
-		Ok(())
-	}
-	pub fn open_their_fds(&mut self) -> SlashResult<()> {
-		for redir in &self.targets_fd {
-			let Redir { redir_type: _, our_fd, their_fd, file_target: _ } = redir;
-			let mut tgt_fd = SmartFD::new(their_fd.unwrap())?;
-			let src_fd = SmartFD::new(*our_fd)?;
-			tgt_fd.dup2(&src_fd)?;
-			tgt_fd.close()?;
-			self.open_fds.push(src_fd);
+		Ok(Some(src_fd))
+	}
+	/// Opens the target of `>` without ever truncating a regular file
+	/// The file is created with O_EXCL, so one that appears between a check and the open can't be clobbered.
+	/// A target that already exists is only opened if it isn't a regular file, like /dev/null or a fifo
+	fn open_noclobber(path: &Path, mode: Mode) -> SlashResult<SmartFD> {
+		match open(path, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL, mode) {
+			Ok(fd) => return Ok(SmartFD { fd }),
+			Err(Errno::EEXIST) => { /* Check what is there below */ }
+			Err(_) => return Err(Low(SlashErrLow::BadFD(format!("Attempted to open non-existant file '{}'",path.to_str().unwrap()))))
 		}
-		Ok(())
+		// The type is checked on the opened file, since the path could have been replaced in the meantime
+		let file_fd = SmartFD::open(path, OFlag::O_WRONLY, mode)?;
+		let is_regular = fstat(file_fd.as_raw_fd()).is_ok_and(|stat| stat.st_mode & libc::S_IFMT == libc::S_IFREG);
+		if is_regular {
+			return Err(Low(SlashErrLow::ExecFailed(format!("cannot overwrite existing file '{}'",path.to_str().unwrap()))))
+		}
+		Ok(file_fd)
+	}
+	fn dup_their_fd(redir: &Redir) -> SlashResult<Option<SmartFD>> {
+		let Redir { our_fd, their_fd, .. } = redir;
+		let tgt_fd = SmartFD::new(their_fd.unwrap())?;
+		let src_fd = SmartFD::new(*our_fd)?;
+		tgt_fd.dup2(&src_fd)?;
+		let _ = tgt_fd.into_raw_fd(); // The target stays open, e.g. `>&3` must not close fd 3
+		Ok(Some(src_fd))
 	}
 }
 
+/// An owned file descriptor, which is closed when it is dropped
+/// The standard streams are never closed this way, and `into_raw_fd()` leaks the descriptor for things that must outlive it, like fds handed to a child
 #[derive(Hash, Eq, PartialEq, Debug)]
 pub struct SmartFD {
 	fd: RawFd,
@@ -320,19 +370,23 @@ impl<'a> SmartFD {
 
 	/// Create a `SmartFD` from a duplicate of `stdin` (FD 0)
 	pub fn from_stdin() -> SlashResult<Self> {
-		let fd = dup(0).map_err(|_| Low(SlashErrLow::from_io()))?;
-		Ok(Self { fd })
+		Self::dup_cloexec(0)
 	}
 
 	/// Create a `SmartFD` from a duplicate of `stdout` (FD 1)
 	pub fn from_stdout() -> SlashResult<Self> {
-		let fd = dup(1).map_err(|_| Low(SlashErrLow::from_io()))?;
-		Ok(Self { fd })
+		Self::dup_cloexec(1)
 	}
 
 	/// Create a `SmartFD` from a duplicate of `stderr` (FD 2)
 	pub fn from_stderr() -> SlashResult<Self> {
-		let fd = dup(2).map_err(|_| Low(SlashErrLow::from_io()))?;
+		Self::dup_cloexec(2)
+	}
+
+	/// Duplicates `fd` with `FD_CLOEXEC` set, so that the copy is not inherited by programs the shell executes
+	/// Like bash, the copy is put at 10 or above, leaving 3 through 9 free for scripts to open with `exec`
+	pub fn dup_cloexec(fd: RawFd) -> SlashResult<Self> {
+		let fd = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(10)).map_err(|_| Low(SlashErrLow::from_io()))?;
 		Ok(Self { fd })
 	}
 
@@ -363,12 +417,13 @@ impl<'a> SmartFD {
 			MemFdCreateFlag::MFD_CLOEXEC
 		};
 		let fd = memfd_create(&c_name, flags).map_err(|_| Low(SlashErrLow::from_io()))?;
-		Ok(SmartFD { fd: fd.as_raw_fd() })
+		SmartFD::from_owned_fd(fd)
 	}
 
-	/// Wrapper for nix::unistd::pipe(), simply produces two `SmartFDs` that point to a read and write pipe respectfully
+	/// Wrapper for nix::unistd::pipe2(), simply produces two `SmartFDs` that point to a read and write pipe respectfully
+	/// Both ends are close-on-exec. Children get the ends they need by duplicating them onto stdin or stdout, which clears the flag
 	pub fn pipe() -> SlashResult<(Self,Self)> {
-		let (r_pipe,w_pipe) = pipe().map_err(|_| Low(SlashErrLow::from_io()))?;
+		let (r_pipe,w_pipe) = pipe2(OFlag::O_CLOEXEC).map_err(|_| Low(SlashErrLow::from_io()))?;
 		let r_fd = SmartFD::from_owned_fd(r_pipe)?;
 		let w_fd = SmartFD::from_owned_fd(w_pipe)?;
 		Ok((r_fd,w_fd))
@@ -394,7 +449,7 @@ impl<'a> SmartFD {
 			return Err(Low(SlashErrLow::BadFD("Attempted to call `dup2()` on an invalid SmartFD".into())))
 		}
 
-		dup2(self.fd, target_fd).unwrap();
+		dup2(self.fd, target_fd).map_err(|_| Low(SlashErrLow::BadFD(format!("Bad file descriptor: {}",self.fd))))?;
 		Ok(())
 	}
 
@@ -428,9 +483,8 @@ impl<'a> SmartFD {
 			return Ok(())
 		}
 
-		close(self.fd).unwrap();
-		self.fd = -1;
-		Ok(())
+		let fd = std::mem::replace(&mut self.fd, -1);
+		close(fd).map_err(|_| Low(SlashErrLow::BadFD(format!("Bad file descriptor: {}",fd))))
 	}
 
 	pub fn mk_shared(self) -> Arc<Mutex<Self>> {
@@ -438,7 +492,7 @@ impl<'a> SmartFD {
 	}
 
 	pub fn is_valid(&self) -> bool {
-		self.fd > 0
+		self.fd >= 0
 	}
 }
 
@@ -449,10 +503,8 @@ impl Display for SmartFD {
 }
 
 impl Drop for SmartFD {
-	#[track_caller]
 	fn drop(&mut self) {
-		if self.fd >= 0 && self.close().is_err() {
-		}
+		let _ = self.close();
 	}
 }
 
@@ -476,20 +528,38 @@ impl FromRawFd for SmartFD {
 	}
 }
 
-pub fn exec_external(command: CString, argv: Vec<CString>, envp: Vec<CString>,blame: Pair<Rule>) -> ! {
+/// `suggestion` looks for a similarly spelled command to mention, and is only called if this one is not found
+pub fn exec_external(command: CString, argv: Vec<CString>, envp: Vec<CString>, suggestion: impl FnOnce() -> Option<String>, blame: Pair<Rule>) -> ! {
+	crate::signal::reset_ignored_signals();
 	let Err(e) = execvpe(&command, &argv, &envp);
 	match e {
 		Errno::ENOENT => {
-			let error = High(SlashErrHigh::cmd_not_found(command.to_str().unwrap(), blame));
-			eprintln!("{}",error);
+			let name = match suggestion() {
+				Some(suggestion) => format!("{} (did you mean: {}?)",command.to_str().unwrap(),suggestion),
+				None => command.to_str().unwrap().to_string()
+			};
+			let error = High(SlashErrHigh::cmd_not_found(name, blame));
+			write_stderr(&error);
 		}
 		Errno::EACCES => {
 			let error = High(SlashErrHigh::no_permission(command.to_str().unwrap(), blame));
-			eprintln!("{}",error);
+			write_stderr(&error);
 		}
 		_ => unimplemented!("Case for `{}` not implemented", e.to_string())
 	}
-	std::process::exit(e as i32)
+	match e {
+		Errno::ENOENT => std::process::exit(127),
+		Errno::EACCES => std::process::exit(126),
+		_ => std::process::exit(e as i32)
+	}
+}
+
+/// Writes straight to fd 2, since the stderr handle in a forked child may not point at it
+fn write_stderr(msg: &impl Display) {
+	if let Ok(mut stderr) = SmartFD::new(STDERR_FILENO) {
+		let _ = writeln!(stderr, "{}", msg);
+		let _ = stderr.into_raw_fd();
+	}
 }
 
 pub fn handle_parent_process<'a>(child: Pid, command: String, slash: &mut Slash) -> SlashResult<()> {
@@ -501,10 +571,52 @@ pub fn handle_parent_process<'a>(child: Pid, command: String, slash: &mut Slash)
 		.with_pgid(child)
 		.build();
 
-	helper::handle_fg(slash,job)?;
+	if slash.ctx().flags().contains(ExecFlags::BACKGROUND) {
+		slash.vars_mut().set_param("!", &child.to_string());
+		write_jobs(|j| j.insert_job(job,false))??;
+	} else {
+		helper::handle_fg(slash,job)?;
+	}
 	Ok(())
 }
 
+/// Returns the number of terminal columns a string occupies
+/// ANSI escape sequences are zero width, and wide characters like CJK glyphs take two columns
+pub fn display_width(s: &str) -> usize {
+	let mut width = 0;
+	let mut chars = s.chars().peekable();
+	while let Some(ch) = chars.next() {
+		if ch != '\x1b' {
+			width += ch.width().unwrap_or(0);
+			continue
+		}
+		match chars.next() {
+			// CSI sequence, ends at the first byte in the range @ to ~
+			Some('[') => {
+				for esc_ch in chars.by_ref() {
+					if ('@'..='~').contains(&esc_ch) {
+						break
+					}
+				}
+			}
+			// OSC sequence, ends at BEL or ST
+			Some(']') => {
+				while let Some(esc_ch) = chars.next() {
+					if esc_ch == '\x07' {
+						break
+					}
+					if esc_ch == '\x1b' && chars.peek() == Some(&'\\') {
+						chars.next();
+						break
+					}
+				}
+			}
+			_ => {}
+		}
+	}
+	width
+}
+
 pub fn save_fds() -> SlashResult<(SmartFD,SmartFD,SmartFD)> {
 	Ok((
 		SmartFD::from_stdin()?,
@@ -513,19 +625,106 @@ pub fn save_fds() -> SlashResult<(SmartFD,SmartFD,SmartFD)> {
 	))
 }
 
-pub fn restore_fds(mut stdio: (SmartFD,SmartFD,SmartFD), slash: &mut Slash) -> SlashResult<()> {
+pub fn restore_fds(stdio: (SmartFD,SmartFD,SmartFD), slash: &mut Slash) -> SlashResult<()> {
 	let flags = slash.ctx().flags();
 	if !flags.contains(ExecFlags::NO_RESET_IN) {
 		stdio.0.dup2(&0)?;
-		stdio.0.close()?;
 	}
 	if !flags.contains(ExecFlags::NO_RESET_OUT) {
 		stdio.1.dup2(&1)?;
-		stdio.1.close()?;
 	}
 	if !flags.contains(ExecFlags::NO_RESET_ERR) {
 		stdio.2.dup2(&2)?;
-		stdio.2.close()?;
 	}
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, test_util::{capture, temp_path}};
+
+use super::*;
+
+	#[test]
+	fn test_display_width() {
+		// The CJK glyph takes two columns, the color codes take none
+		assert_eq!(display_width("\x1b[1;32mab漢\x1b[0m"), 4);
+		assert_eq!(display_width("\x1b]0;title\x07$> "), 3);
+	}
+
+	#[test]
+	fn test_smartfd_drop() {
+		// Another test can reuse a closed fd number right away, so the close is checked from the other end of a pipe
+		let (mut r_end, w_end) = SmartFD::pipe().unwrap();
+		fcntl(r_end.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+		let dup = SmartFD::dup_cloexec(w_end.as_raw_fd()).unwrap();
+		drop(w_end);
+		let mut byte = [0u8; 1];
+		let open_err = r_end.read(&mut byte).unwrap_err();
+		assert_eq!(open_err.kind(), io::ErrorKind::WouldBlock);
+		drop(dup);
+		assert_eq!(r_end.read(&mut byte).unwrap(), 0);
+
+		// Dropping the only write end of a pipe is what lets the reader see the end of the input
+		let (mut r_pipe, mut w_pipe) = SmartFD::pipe().unwrap();
+		write!(w_pipe, "done").unwrap();
+		drop(w_pipe);
+		let mut buffer = String::new();
+		r_pipe.read_to_string(&mut buffer).unwrap();
+		assert_eq!(buffer, "done");
+
+		drop(SmartFD::new(STDOUT_FILENO).unwrap());
+		assert!(fcntl(STDOUT_FILENO, FcntlArg::F_GETFD).is_ok());
+	}
+
+	#[test]
+	fn test_noclobber() {
+		let mut slash = Slash::new();
+		let path = temp_path("noclobber");
+		std::fs::write(&path, "original\n").unwrap();
+		let path_str = path.to_str().unwrap();
+
+		execute::dispatch::exec_input("setopt core.noclobber=true".to_string(), &mut slash).unwrap();
+
+		let (clobber, _) = capture(&format!("echo x > {path_str}"), &mut slash);
+		assert!(clobber.is_err());
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "original\n");
+
+		let (forced, _) = capture(&format!("echo x >| {path_str}"), &mut slash);
+		assert!(forced.is_ok());
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "x\n");
+
+		// `set -C` does the same thing as the shopt, and files that aren't regular can still be written to
+		execute::dispatch::exec_input("setopt core.noclobber=false; set -C".to_string(), &mut slash).unwrap();
+		let (clobber, _) = capture(&format!("echo y > {path_str}"), &mut slash);
+		assert!(clobber.is_err());
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "x\n");
+		capture("echo y > /dev/null", &mut slash).0.unwrap();
+
+		execute::dispatch::exec_input("set +C".to_string(), &mut slash).unwrap();
+		capture(&format!("echo y > {path_str}"), &mut slash).0.unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "y\n");
+
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn test_redir_order() {
+		let mut slash = Slash::new();
+		let dir = temp_path("redir_order");
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+		let (both, out, term, piped) = (path("both"), path("out"), path("term"), path("piped"));
+		let cmd = "sh -c 'echo out; echo err >&2'";
+
+		// The group's file stands in for the terminal that stdout pointed to before the command's own redirections
+		let input = format!("{cmd} > {both} 2>&1; {{ {cmd} 2>&1 > {out}; }} > {term}; {cmd} 2>&1 > /dev/null | cat > {piped}");
+		capture(&input, &mut slash).0.unwrap();
+
+		assert_eq!(std::fs::read_to_string(&both).unwrap(), "out\nerr\n");
+		assert_eq!(std::fs::read_to_string(&out).unwrap(), "out\n");
+		assert_eq!(std::fs::read_to_string(&term).unwrap(), "err\n");
+		assert_eq!(std::fs::read_to_string(&piped).unwrap(), "err\n");
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}